@@ -126,6 +126,7 @@ void main() {
             .with_cone(RayCone::from_spread_angle(1.0))
             .with_cull_back_faces(false)
             .with_path_length(1)
+            .with_instance_mask(RT_INSTANCE_MASK_GI)
             .trace(acceleration_structure);
 
         if (primary_hit.is_hit) {
@@ -164,7 +165,7 @@ void main() {
                                     to_sun_norm,
                                     1e-4,
                                     SKY_DIST
-                            ));
+                            ), RT_INSTANCE_MASK_GI);
                         const float3 light_radiance = is_sun_shadowed ? 0.0 : SUN_COLOR;
 
                         radiance_contribution +=
@@ -203,7 +204,7 @@ void main() {
                                                 to_light_norm_ws,
                                                 1e-3,
                                                 sqrt(dist_to_light2) - 2e-3
-                                        ));
+                                        ), RT_INSTANCE_MASK_GI);
 
                                     radiance_contribution +=
                                         !is_shadowed ?
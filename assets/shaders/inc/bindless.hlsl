@@ -2,4 +2,45 @@
 
 [[vk::binding(0, 1)]] StructuredBuffer<Mesh> meshes;
 [[vk::binding(1, 1)]] ByteAddressBuffer vertices;
+
+// Vertex streams are decoded according to `Mesh::vertex_encoding`
+
+Vertex load_mesh_vertex(Mesh mesh, uint vid) {
+    if ((mesh.vertex_encoding & VERTEX_ENCODING_POSITION_MASK) == VERTEX_ENCODING_POSITION_F32) {
+        // TODO: replace with Load<float4> once there's a fast path for NV
+        // https://github.com/microsoft/DirectXShaderCompiler/issues/2193
+        return unpack_vertex(VertexPacked(asfloat(vertices.Load4(vid * sizeof(float4) + mesh.vertex_core_offset))));
+    } else {
+        return unpack_quantized_vertex(mesh, vertices.Load3(vid * sizeof(uint3) + mesh.vertex_core_offset));
+    }
+}
+
+// `stream_offset` is either `vertex_uv_offset` or `vertex_uv1_offset`
+float2 load_mesh_uv(Mesh mesh, uint stream_offset, uint vid) {
+    if ((mesh.vertex_encoding & VERTEX_ENCODING_UV_F16) != 0) {
+        return unpack_2x16f_uint(vertices.Load(vid * sizeof(uint) + stream_offset));
+    } else {
+        return asfloat(vertices.Load2(vid * sizeof(float2) + stream_offset));
+    }
+}
+
+float4 load_mesh_tangent(Mesh mesh, uint vid) {
+    if (mesh.vertex_tangent_offset == 0) {
+        return float4(1, 0, 0, 1);
+    } else if ((mesh.vertex_encoding & VERTEX_ENCODING_TANGENT_OCTAHEDRAL) != 0) {
+        return unpack_octahedral_tangent(vertices.Load(vid * sizeof(uint) + mesh.vertex_tangent_offset));
+    } else {
+        return asfloat(vertices.Load4(vid * sizeof(float4) + mesh.vertex_tangent_offset));
+    }
+}
+
+float4 load_mesh_color(Mesh mesh, uint vid) {
+    if (mesh.vertex_aux_offset == 0) {
+        return 1.0.xxxx;
+    } else if ((mesh.vertex_encoding & VERTEX_ENCODING_COLOR_RGBA8) != 0) {
+        return unpack_color_rgba8(vertices.Load(vid * sizeof(uint) + mesh.vertex_aux_offset));
+    } else {
+        return asfloat(vertices.Load4(vid * sizeof(float4) + mesh.vertex_aux_offset));
+    }
+}
 #include "bindless_textures.hlsl"
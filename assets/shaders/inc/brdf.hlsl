@@ -20,6 +20,7 @@ struct BrdfValue {
     static BrdfValue invalid() {
         BrdfValue res;
         res.value_over_pdf = 0.0;
+        res.value = 0.0;
         res.pdf = 0.0;
         res.transmission_fraction = 0.0;
         return res;
@@ -36,6 +37,7 @@ struct BrdfSample: BrdfValue {
     static BrdfSample invalid() {
         BrdfSample res;
         res.value_over_pdf = 0.0;
+        res.value = 0.0;
         res.pdf = 0.0;
         res.wi = float3(0.0, 0.0, -1.0);
         res.transmission_fraction = 0.0;
@@ -43,8 +45,9 @@ struct BrdfSample: BrdfValue {
         return res;
     }
 
+    // Transmitted samples point below the surface; invalid ones have a zero pdf.
     bool is_valid() {
-        return wi.z > 1e-6;
+        return pdf > 0.0 && abs(wi.z) > 1e-6;
     }
 };
 
@@ -292,6 +295,50 @@ struct SpecularBrdf {
 	}
 };
 
+// Retro-reflective fuzz of cloth-like materials: the "Charlie" distribution with the visibility of Neubelt and Pettineo.
+// https://blog.selfshadow.com/publications/s2017-shading-course/imageworks/s2017_pbs_imageworks_sheen.pdf
+struct SheenBrdf {
+    float3 albedo;
+    float roughness;
+
+    BrdfValue evaluate(float3 wo, float3 wi) {
+        if (wo.z <= 0 || wi.z <= 0) {
+            return BrdfValue::invalid();
+        }
+
+        const float3 m = normalize(wo + wi);
+        const float inv_r = 1.0 / max(roughness, 1e-3);
+        const float sin_theta_m = sqrt(max(0.0, 1.0 - m.z * m.z));
+        const float ndf = (2.0 + inv_r) * pow(sin_theta_m, inv_r) / M_TAU;
+        const float visibility = 1.0 / (4.0 * (wi.z + wo.z - wi.z * wo.z));
+
+        BrdfValue res;
+        res.value = albedo * (ndf * visibility);
+        res.pdf = M_FRAC_1_PI;
+        res.value_over_pdf = res.value / res.pdf;
+        res.transmission_fraction = 1.0;
+        return res;
+    }
+
+    // The lobe is wide, so it's sampled like a diffuse one.
+    BrdfSample sample(float3 wo, float2 urand) {
+        DiffuseBrdf cosine_brdf;
+        cosine_brdf.albedo = 1.0;
+        BrdfSample res = cosine_brdf.sample(wo, urand);
+
+        const BrdfValue value = evaluate(wo, res.wi);
+        if (value.pdf <= 0.0) {
+            return BrdfSample::invalid();
+        }
+
+        res.value = value.value;
+        res.value_over_pdf = value.value_over_pdf;
+        res.pdf = value.pdf;
+        res.transmission_fraction = value.transmission_fraction;
+        return res;
+    }
+};
+
 // https://seblagarde.files.wordpress.com/2015/07/course_notes_moving_frostbite_to_pbr_v32.pdf
 float3 specular_dominant_direction(float3 n, float3 v, float roughness) {
     float3 r = reflect(-v, n);
@@ -47,7 +47,8 @@ struct FrameConstants {
     float4 sky_ambient;
 
     float world_gi_scale;
-	uint pad0;
+    // Offset of GI ray origins per unit of distance from the eye, clearing the coarser GI LODs
+    float gi_ray_lod_bias;
 	uint pad1;
 	uint pad2;
 
@@ -27,6 +27,22 @@ struct GbufferData {
     float roughness;
     float metalness;
 
+    // Specular reflectance at normal incidence of the non-metallic part
+    float dielectric_f0;
+
+    // Strength and roughness of a dielectric coating layered on top
+    float clearcoat;
+    float clearcoat_roughness;
+
+    // Fraction of the non-metallic part which lets light through, tinted by the albedo.
+    // Surfaces are thin, so there's no refraction.
+    float transmission;
+
+    // Intensity and roughness of the retro-reflective fuzz of cloth-like materials.
+    // Only the strongest channel of the sheen color is kept.
+    float sheen;
+    float sheen_roughness;
+
     static GbufferData create_zero() {
         GbufferData res;
         res.albedo = 0;
@@ -34,6 +50,12 @@ struct GbufferData {
         res.normal = 0;
         res.roughness = 0;
         res.metalness = 0;
+        res.dielectric_f0 = 0.04;
+        res.clearcoat = 0;
+        res.clearcoat_roughness = 0;
+        res.transmission = 0;
+        res.sheen = 0;
+        res.sheen_roughness = 0;
         return res;
     }
 
@@ -48,13 +70,47 @@ float perceptual_roughness_to_roughness(float r) {
     return r * r;
 }
 
+uint pack_unorm_rounded(float val, uint bit_count) {
+    return uint(round(saturate(val) * float((1u << bit_count) - 1)));
+}
+
+// The top 16 bits of `data0.z` hold at most one optional layer: its kind in the top two bits,
+// and two 7-bit parameters below. Clearcoat takes precedence over transmission, and that over sheen.
+static const uint GBUFFER_LAYER_NONE = 0;
+// Strength, and perceptual roughness
+static const uint GBUFFER_LAYER_CLEARCOAT = 1;
+// Transmission
+static const uint GBUFFER_LAYER_TRANSMISSION = 2;
+// Intensity, and perceptual roughness
+static const uint GBUFFER_LAYER_SHEEN = 3;
+
 GbufferDataPacked GbufferData::pack() {
     float4 res = 0.0.xxxx;
-    res.x = asfloat(pack_color_888(albedo));
+    // F0 is stored in the otherwise unused top byte, with more precision for the common low values.
+    res.x = asfloat(pack_color_888(albedo) | (pack_unorm_rounded(sqrt(dielectric_f0), 8) << 24));
     res.y = pack_normal_11_10_11(normal);
 
-    float2 roughness_metalness = float2(roughness_to_perceptual_roughness(roughness), metalness);
-    res.z = asfloat(pack_2x16f_uint(roughness_metalness));
+    // Perceptual roughness gets 8 bits, like the maps it mostly comes from. Relative steps in roughness
+    // stay under 3% above a perceptual roughness of 0.3, but reach 8% at 0.1, where smooth gradients
+    // (from filtering, or scaling by the roughness factor) can show banding in sharp highlights.
+    uint layer = GBUFFER_LAYER_NONE << 14;
+    if (clearcoat > 0) {
+        layer = (GBUFFER_LAYER_CLEARCOAT << 14)
+            | pack_unorm_rounded(clearcoat, 7)
+            | (pack_unorm_rounded(roughness_to_perceptual_roughness(clearcoat_roughness), 7) << 7);
+    } else if (transmission > 0) {
+        layer = (GBUFFER_LAYER_TRANSMISSION << 14) | pack_unorm_rounded(transmission, 7);
+    } else if (sheen > 0) {
+        layer = (GBUFFER_LAYER_SHEEN << 14)
+            | pack_unorm_rounded(sheen, 7)
+            | (pack_unorm_rounded(roughness_to_perceptual_roughness(sheen_roughness), 7) << 7);
+    }
+
+    res.z = asfloat(
+        pack_unorm_rounded(roughness_to_perceptual_roughness(roughness), 8)
+        | (pack_unorm_rounded(metalness, 8) << 8)
+        | (layer << 16)
+    );
     res.w = asfloat(float3_to_rgb9e5(emissive));
 
    GbufferDataPacked packed;
@@ -67,9 +123,33 @@ GbufferData GbufferDataPacked::unpack() {
     res.albedo = unpack_albedo();
     res.normal = unpack_normal();
 
-    float2 roughness_metalness = unpack_2x16f_uint(data0.z);
-    res.roughness = perceptual_roughness_to_roughness(roughness_metalness.x);
-    res.metalness = roughness_metalness.y;
+    const float f0_sqrt = unpack_unorm(data0.x >> 24, 8);
+    res.dielectric_f0 = f0_sqrt * f0_sqrt;
+
+    res.roughness = perceptual_roughness_to_roughness(unpack_unorm(data0.z, 8));
+    res.metalness = unpack_unorm(data0.z >> 8, 8);
+
+    const uint layer = data0.z >> 16;
+    const uint layer_kind = layer >> 14;
+    const float layer_param0 = unpack_unorm(layer, 7);
+    const float layer_param1 = unpack_unorm(layer >> 7, 7);
+
+    res.clearcoat = 0;
+    res.clearcoat_roughness = 0;
+    res.transmission = 0;
+    res.sheen = 0;
+    res.sheen_roughness = 0;
+
+    if (layer_kind == GBUFFER_LAYER_CLEARCOAT) {
+        res.clearcoat = layer_param0;
+        res.clearcoat_roughness = perceptual_roughness_to_roughness(layer_param1);
+    } else if (layer_kind == GBUFFER_LAYER_TRANSMISSION) {
+        res.transmission = layer_param0;
+    } else if (layer_kind == GBUFFER_LAYER_SHEEN) {
+        res.sheen = layer_param0;
+        res.sheen_roughness = perceptual_roughness_to_roughness(layer_param1);
+    }
+
     res.emissive = unpack_emissive();
 
     return res;
@@ -32,17 +32,35 @@ void apply_metalness_to_brdfs(inout SpecularBrdf specular_brdf, inout DiffuseBrd
     diffuse_brdf.albedo = min(1.0, diffuse_brdf.albedo * albedo_boost);
 }
 
+// Reflectance at normal incidence of a clear coat with the IOR of 1.5
+static const float CLEARCOAT_F0 = 0.04;
+
+// Rough directional albedo of a white sheen lobe, used to pick it when sampling.
+static const float SHEEN_APPROX_ALBEDO = 0.2;
+
 struct LayeredBrdf {
     SpecularBrdf specular_brdf;
     DiffuseBrdf diffuse_brdf;
     SpecularBrdfEnergyPreservation energy_preservation;
 
+    // Optional dielectric coating on top of the specular and diffuse layers
+    SpecularBrdf clearcoat_brdf;
+    SpecularBrdfEnergyPreservation clearcoat_energy_preservation;
+    float clearcoat;
+
+    // Optional thin transmission lobe, mirroring the specular lobe through the surface
+    SpecularBrdf transmission_brdf;
+    float3 transmission_albedo;
+
+    // Optional fuzz on top. It reflects little light, so the layers below aren't attenuated.
+    SheenBrdf sheen_brdf;
+
     static LayeredBrdf from_gbuffer_ndotv(
         GbufferData gbuffer,
         float ndotv
     ) {
         SpecularBrdf specular_brdf;
-        specular_brdf.albedo = 0.04;
+        specular_brdf.albedo = gbuffer.dielectric_f0;
         specular_brdf.roughness = gbuffer.roughness;
 
         DiffuseBrdf diffuse_brdf;
@@ -50,20 +68,91 @@ struct LayeredBrdf {
 
         apply_metalness_to_brdfs(specular_brdf, diffuse_brdf, gbuffer.metalness);
 
+        // Transmission takes light away from the diffuse layer, passing it through the surface instead.
+        diffuse_brdf.albedo *= 1.0 - gbuffer.transmission;
+
+        SpecularBrdf transmission_brdf;
+        transmission_brdf.albedo = 1.0;
+        transmission_brdf.roughness = gbuffer.roughness;
+
+        SpecularBrdf clearcoat_brdf;
+        clearcoat_brdf.albedo = CLEARCOAT_F0;
+        clearcoat_brdf.roughness = gbuffer.clearcoat_roughness;
+
         LayeredBrdf res;
         res.energy_preservation =
             SpecularBrdfEnergyPreservation::from_brdf_ndotv(specular_brdf, ndotv);
 
         res.specular_brdf = specular_brdf;
         res.diffuse_brdf = diffuse_brdf;
+
+        res.clearcoat = gbuffer.clearcoat;
+        res.clearcoat_brdf = clearcoat_brdf;
+        if (res.clearcoat > 0.0) {
+            res.clearcoat_energy_preservation =
+                SpecularBrdfEnergyPreservation::from_brdf_ndotv(clearcoat_brdf, ndotv);
+        } else {
+            res.clearcoat_energy_preservation.preintegrated_reflection = 0.0;
+            res.clearcoat_energy_preservation.preintegrated_reflection_mult = 1.0;
+            res.clearcoat_energy_preservation.preintegrated_transmission_fraction = 1.0;
+        }
+
+        res.transmission_brdf = transmission_brdf;
+        res.transmission_albedo = gbuffer.albedo * (gbuffer.transmission * max(0.0, 1.0 - gbuffer.metalness));
+
+        res.sheen_brdf.albedo = gbuffer.sheen;
+        res.sheen_brdf.roughness = gbuffer.sheen_roughness;
+
         return res;
     }
 
+    // Fraction of light making it through the coating to the layers below, and back.
+    float3 clearcoat_transmission() {
+        return lerp(1.0, clearcoat_energy_preservation.preintegrated_transmission_fraction, clearcoat);
+    }
+
+    float3 add_sheen(float3 base_value, float3 wo, float3 wi) {
+        if (all(sheen_brdf.albedo <= 0.0)) {
+            return base_value;
+        }
+
+        return base_value + sheen_brdf.evaluate(wo, wi).value;
+    }
+
+    float3 add_clearcoat(float3 base_value, float3 wo, float3 wi) {
+        if (clearcoat <= 0.0) {
+            return base_value;
+        }
+
+        const BrdfValue coat = clearcoat_brdf.evaluate(wo, wi);
+        return base_value * clearcoat_transmission()
+            + coat.value * clearcoat_energy_preservation.preintegrated_reflection_mult * clearcoat;
+    }
+
+    // Light arriving from below the surface, passing through it.
+    // The surface is thin, so the lobe is the specular one mirrored to the other side.
+    float3 evaluate_transmission(float3 wo, float3 wi) {
+        if (all(transmission_albedo <= 0.0)) {
+            return 0;
+        }
+
+        const BrdfValue trans = transmission_brdf.evaluate(wo, float3(wi.xy, -wi.z));
+        return trans.value
+            * transmission_albedo
+            * energy_preservation.preintegrated_transmission_fraction
+            * clearcoat_transmission();
+    }
+
+    // Light from below the surface (`wi.z < 0`) is transmitted; callers must use `abs(wi.z)` as the cosine term.
     float3 evaluate(float3 wo, float3 wi) {
-        if (wo.z <= 0 || wi.z <= 0) {
+        if (wo.z <= 0 || wi.z == 0) {
             return 0;
         }
 
+        if (wi.z < 0) {
+            return evaluate_transmission(wo, wi);
+        }
+
         const BrdfValue diff = diffuse_brdf.evaluate(wo, wi);
 
         #if LAYERED_BRDF_FORCE_DIFFUSE_ONLY
@@ -76,17 +165,26 @@ struct LayeredBrdf {
             return spec.value;
         #endif
 
-        return (
-            spec.value * energy_preservation.preintegrated_reflection_mult +
-            diff.value * spec.transmission_fraction
+        return add_clearcoat(
+            add_sheen(
+                spec.value * energy_preservation.preintegrated_reflection_mult +
+                diff.value * spec.transmission_fraction,
+                wo, wi
+            ),
+            wo, wi
         );
     }
 
+    // Light from below the surface (`wi.z < 0`) is transmitted; callers must use `abs(wi.z)` as the cosine term.
     float3 evaluate_directional_light(float3 wo, float3 wi) {
-        if (wo.z <= 0 || wi.z <= 0) {
+        if (wo.z <= 0 || wi.z == 0) {
             return 0;
         }
 
+        if (wi.z < 0) {
+            return evaluate_transmission(wo, wi);
+        }
+
         const BrdfValue diff = diffuse_brdf.evaluate(wo, wi);
 
         #if LAYERED_BRDF_FORCE_DIFFUSE_ONLY
@@ -108,9 +206,13 @@ struct LayeredBrdf {
             //energy_preservation.preintegrated_reflection_mult;
             lerp(1.0, energy_preservation.preintegrated_reflection_mult, sqrt(abs(wi.z)));
 
-        return (
-            spec.value * preintegrated_reflection_mult_directional +
-            diff.value * spec.transmission_fraction
+        return add_clearcoat(
+            add_sheen(
+                spec.value * preintegrated_reflection_mult_directional +
+                diff.value * spec.transmission_fraction,
+                wo, wi
+            ),
+            wo, wi
         );
     }
 
@@ -129,12 +231,29 @@ struct LayeredBrdf {
         // and reflect with the complement of that. However since we use a single ray,
         // we toss a coin, and choose between reflection and transmission.
 
-        const float spec_wt = calculate_luma(energy_preservation.preintegrated_reflection);
-        const float diffuse_wt = calculate_luma(energy_preservation.preintegrated_transmission_fraction * diffuse_brdf.albedo);
-        const float transmission_p = diffuse_wt / (spec_wt + diffuse_wt);
+        // The clear coat is a third lobe on top, attenuating the other two.
+        const float3 coat_transmission = clearcoat_transmission();
+        const float coat_wt = clearcoat * calculate_luma(clearcoat_energy_preservation.preintegrated_reflection);
+        const float spec_wt = calculate_luma(energy_preservation.preintegrated_reflection * coat_transmission);
+        const float diffuse_wt = calculate_luma(energy_preservation.preintegrated_transmission_fraction * diffuse_brdf.albedo * coat_transmission);
+        const float thin_transmission_wt = calculate_luma(energy_preservation.preintegrated_transmission_fraction * transmission_albedo * coat_transmission);
+        const float sheen_wt = SHEEN_APPROX_ALBEDO * calculate_luma(sheen_brdf.albedo * coat_transmission);
+        const float total_wt = coat_wt + spec_wt + diffuse_wt + thin_transmission_wt + sheen_wt;
+        const float coat_p = coat_wt / total_wt;
+        const float transmission_p = diffuse_wt / total_wt;
+        const float thin_transmission_p = thin_transmission_wt / total_wt;
+        const float sheen_p = sheen_wt / total_wt;
 
         const float lobe_xi = urand.z;
-        if (lobe_xi < transmission_p) {
+        if (lobe_xi < coat_p) {
+            // The coating wins!
+
+            brdf_sample = clearcoat_brdf.sample(wo, urand.xy);
+
+            const float lobe_pdf = coat_p;
+            brdf_sample.value_over_pdf /= lobe_pdf;
+            brdf_sample.value_over_pdf *= clearcoat * clearcoat_energy_preservation.preintegrated_reflection_mult;
+        } else if (lobe_xi < coat_p + transmission_p) {
             // Transmission wins! Now sample the bottom layer (diffuse)
 
             brdf_sample = diffuse_brdf.sample(wo, urand.xy);
@@ -143,17 +262,35 @@ struct LayeredBrdf {
             brdf_sample.value_over_pdf /= lobe_pdf;
 
             // Account for the masking that the top level exerts on the bottom.
-            brdf_sample.value_over_pdf *= energy_preservation.preintegrated_transmission_fraction;
+            brdf_sample.value_over_pdf *= energy_preservation.preintegrated_transmission_fraction * coat_transmission;
+        } else if (lobe_xi < coat_p + transmission_p + thin_transmission_p) {
+            // Light goes through the surface. Sample the mirrored specular lobe, and flip it to the other side.
+
+            brdf_sample = transmission_brdf.sample(wo, urand.xy);
+            brdf_sample.wi.z = -brdf_sample.wi.z;
+
+            const float lobe_pdf = thin_transmission_p;
+            brdf_sample.value_over_pdf /= lobe_pdf;
+            brdf_sample.value_over_pdf *=
+                energy_preservation.preintegrated_transmission_fraction * transmission_albedo * coat_transmission;
+        } else if (lobe_xi < coat_p + transmission_p + thin_transmission_p + sheen_p) {
+            // The fuzz wins!
+
+            brdf_sample = sheen_brdf.sample(wo, urand.xy);
+
+            const float lobe_pdf = sheen_p;
+            brdf_sample.value_over_pdf /= lobe_pdf;
+            brdf_sample.value_over_pdf *= coat_transmission;
         } else {
             // Reflection wins!
 
             brdf_sample = specular_brdf.sample(wo, urand.xy);
 
-            const float lobe_pdf = (1.0 - transmission_p);
+            const float lobe_pdf = (1.0 - coat_p - transmission_p - thin_transmission_p - sheen_p);
             brdf_sample.value_over_pdf /= lobe_pdf;
 
             // Apply approximate multi-scatter energy preservation
-            brdf_sample.value_over_pdf *= energy_preservation.preintegrated_reflection_mult;
+            brdf_sample.value_over_pdf *= energy_preservation.preintegrated_reflection_mult * coat_transmission;
         }
 
         return brdf_sample;
@@ -1,10 +1,16 @@
 #ifndef MESH_HLSL
 #define MESH_HLSL
 
+#include "pack_unpack.hlsl"
+
 struct VertexPacked {
 	float4 data0;
 };
 
+// Must match `MAX_MESH_LODS` in `kajiya-asset/src/mesh.rs`
+static const uint MAX_MESH_LODS = 8;
+static const uint RT_INSTANCE_MESH_INDEX_BITS = 20;
+
 struct Mesh {
     uint vertex_core_offset;
     uint vertex_uv_offset;
@@ -13,8 +19,41 @@ struct Mesh {
     uint vertex_tangent_offset;
     uint mat_data_offset;
     uint index_offset;
+    uint vertex_uv1_offset;
+    // Non-zero for skinned meshes, which move between frames
+    uint vertex_prev_core_offset;
+    // `VERTEX_ENCODING_*` flags, and the dequantization of positions
+    uint vertex_encoding;
+    float position_offset[3];
+    float position_scale[3];
+    // Index offsets of the simplified LODs
+    uint lod_index_offsets[MAX_MESH_LODS];
 };
 
+// Ray tracing instances keep the mesh index in the low bits of `InstanceID()`, and the LOD above them.
+// Must match `RT_INSTANCE_MESH_INDEX_BITS` in `world_renderer.rs`.
+uint rt_instance_mesh_index(uint instance_id) {
+    return instance_id & ((1u << RT_INSTANCE_MESH_INDEX_BITS) - 1u);
+}
+
+uint rt_instance_lod(uint instance_id) {
+    return instance_id >> RT_INSTANCE_MESH_INDEX_BITS;
+}
+
+// LOD zero is the full-detail mesh
+uint mesh_lod_index_offset(Mesh mesh, uint lod) {
+    return lod == 0 ? mesh.index_offset : mesh.lod_index_offsets[lod - 1];
+}
+
+// Must match `VertexEncodingFlags` in `kajiya-asset/src/mesh.rs`
+static const uint VERTEX_ENCODING_POSITION_MASK = 3;
+static const uint VERTEX_ENCODING_POSITION_F32 = 0;
+static const uint VERTEX_ENCODING_POSITION_F16 = 1;
+static const uint VERTEX_ENCODING_POSITION_SNORM16 = 2;
+static const uint VERTEX_ENCODING_UV_F16 = 4;
+static const uint VERTEX_ENCODING_TANGENT_OCTAHEDRAL = 8;
+static const uint VERTEX_ENCODING_COLOR_RGBA8 = 16;
+
 struct Vertex {
     float3 position;
     float3 normal;
@@ -28,6 +67,14 @@ float3 unpack_unit_direction_11_10_11(uint pck) {
     );
 }
 
+uint pack_unit_direction_11_10_11(float3 v) {
+    uint x = uint((clamp(v.x, -1.0, 1.0) * 0.5 + 0.5) * float((1u << 11u) - 1u));
+    uint y = uint((clamp(v.y, -1.0, 1.0) * 0.5 + 0.5) * float((1u << 10u) - 1u));
+    uint z = uint((clamp(v.z, -1.0, 1.0) * 0.5 + 0.5) * float((1u << 11u) - 1u));
+
+    return (z << 21u) | (y << 11u) | x;
+}
+
 Vertex unpack_vertex(VertexPacked p) {
     Vertex res;
     res.position = p.data0.xyz;
@@ -35,7 +82,56 @@ Vertex unpack_vertex(VertexPacked p) {
     return res;
 }
 
+VertexPacked pack_vertex(Vertex v) {
+    VertexPacked res;
+    res.data0 = float4(v.position, asfloat(pack_unit_direction_11_10_11(v.normal)));
+    return res;
+}
+
+float unpack_snorm16(uint v) {
+    return max(float(int(v << 16) >> 16) / 32767.0, -1.0);
+}
+
+// `data` is a `QuantizedVertex`: three 16-bit position components and padding, then the packed normal.
+Vertex unpack_quantized_vertex(Mesh mesh, uint3 data) {
+    float3 pos;
+    if ((mesh.vertex_encoding & VERTEX_ENCODING_POSITION_MASK) == VERTEX_ENCODING_POSITION_F16) {
+        pos = float3(f16tof32(data.x), f16tof32(data.x >> 16), f16tof32(data.y));
+    } else {
+        pos = float3(unpack_snorm16(data.x), unpack_snorm16(data.x >> 16), unpack_snorm16(data.y));
+    }
+
+    const float3 scale = float3(mesh.position_scale[0], mesh.position_scale[1], mesh.position_scale[2]);
+    const float3 offset = float3(mesh.position_offset[0], mesh.position_offset[1], mesh.position_offset[2]);
+
+    Vertex res;
+    res.position = pos * scale + offset;
+    res.normal = unpack_unit_direction_11_10_11(data.z);
+    return res;
+}
+
+// 16 bits of X, 15 bits of Y, and the bitangent sign in the top bit.
+// Zero marks vertices without a tangent; those get a zero `w`, like unquantized ones.
+float4 unpack_octahedral_tangent(uint p) {
+    if (p == 0) {
+        return float4(1.0, 0.0, 0.0, 0.0);
+    }
+
+    const float2 f = float2(p & 0xffff, (p >> 16) & 0x7fff) / float2(65535.0, 32767.0);
+    return float4(octa_decode(f), (p >> 31) != 0 ? -1.0 : 1.0);
+}
+
+float4 unpack_color_rgba8(uint p) {
+    return float4(unpack_unorm(p, 8), unpack_unorm(p >> 8, 8), unpack_unorm(p >> 16, 8), unpack_unorm(p >> 24, 8));
+}
+
 static const uint MESH_MATERIAL_FLAG_EMISSIVE_USED_AS_LIGHT = 1;
+static const uint MESH_MATERIAL_FLAG_DOUBLE_SIDED = 2;
+static const uint MESH_MATERIAL_FLAG_SPECULAR_MAP = 4;
+
+static const uint MESH_MATERIAL_ALPHA_MODE_OPAQUE = 0;
+static const uint MESH_MATERIAL_ALPHA_MODE_MASK = 1;
+static const uint MESH_MATERIAL_ALPHA_MODE_BLEND = 2;
 
 struct MeshMaterial {
     float base_color_mult[4];
@@ -43,19 +139,55 @@ struct MeshMaterial {
     uint spec_map;
     uint albedo_map;
     uint emissive_map;
+    // Clearcoat strength in the red channel
+    uint clearcoat_map;
     float roughness_mult;
     float metalness_factor;
     float emissive[3];
     uint flags;
-    float map_transforms[6 * 4];
+    float map_transforms[6 * 5];
+    float transmission;
+    float ior;
+    float specular_factor;
+    float specular_color[3];
+    float clearcoat;
+    float clearcoat_roughness;
+    float sheen_color[3];
+    float sheen_roughness;
+    uint alpha_mode;
+    float alpha_cutoff;
+    uint map_uv_sets[5];
 };
 
-float2 transform_material_uv(MeshMaterial mat, float2 uv, uint map_idx) {
+// `map_idx`: 0 = albedo, 1 = normal, 2 = metalness-roughness, 3 = emissive, 4 = clearcoat.
+// `uv0` and `uv1` are the two UV sets of the mesh; the material picks one per map.
+float2 transform_material_uv(MeshMaterial mat, float2 uv0, float2 uv1, uint map_idx) {
     uint xo = map_idx * 6;
     float2x2 rot_scl = float2x2(mat.map_transforms[xo+0], mat.map_transforms[xo+1], mat.map_transforms[xo+2], mat.map_transforms[xo+3]);
     float2 offset = float2(mat.map_transforms[xo+4], mat.map_transforms[xo+5]);
+    float2 uv = mat.map_uv_sets[map_idx] == 0 ? uv0 : uv1;
     return mul(rot_scl, uv) + offset;
 }
 
+// Reflectance at normal incidence of the dielectric part of the material.
+// The G-buffer only has room for a scalar, so the specular color is reduced to its average.
+// `metalness_roughness` is the texel of the spec map, whose red channel can scale it.
+float material_dielectric_f0(MeshMaterial mat, float4 metalness_roughness) {
+    const float r = (mat.ior - 1.0) / (mat.ior + 1.0);
+    const float specular_color = (mat.specular_color[0] + mat.specular_color[1] + mat.specular_color[2]) / 3.0;
+    const float specular_map = (mat.flags & MESH_MATERIAL_FLAG_SPECULAR_MAP) != 0 ? metalness_roughness.x : 1.0;
+    return min(1.0, r * r * specular_color) * mat.specular_factor * specular_map;
+}
+
+// Nothing gets blended, so both masked and blended materials are alpha-tested.
+bool material_alpha_test_fails(MeshMaterial mat, float alpha) {
+    return mat.alpha_mode != MESH_MATERIAL_ALPHA_MODE_OPAQUE && alpha < mat.alpha_cutoff;
+}
+
+// Normal maps are stored with two channels (BC5); Z is reconstructed.
+float3 decode_tangent_space_normal(float2 xy) {
+    float2 n = xy * 2.0 - 1.0;
+    return float3(n, sqrt(max(0.0, 1.0 - dot(n, n))));
+}
 
 #endif
\ No newline at end of file
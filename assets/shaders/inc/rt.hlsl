@@ -46,6 +46,12 @@ struct ShadowRayPayload {
     }
 };
 
+// Instances of the rasterized LODs are visible to primary rays. Diffuse GI rays may instead see
+// coarser LODs, and should have their origins offset by `gi_ray_lod_bias`.
+// Must match `RT_INSTANCE_MASK_*` in `world_renderer.rs`.
+static const uint RT_INSTANCE_MASK_PRIMARY = 1;
+static const uint RT_INSTANCE_MASK_GI = 2;
+
 RayDesc new_ray(float3 origin, float3 direction, float tmin, float tmax) {
     RayDesc ray;
     ray.Origin = origin;
@@ -57,18 +63,26 @@ RayDesc new_ray(float3 origin, float3 direction, float tmin, float tmax) {
 
 bool rt_is_shadowed(
     RaytracingAccelerationStructure acceleration_structure,
-    RayDesc ray
+    RayDesc ray,
+    uint instance_mask
 ) {
     ShadowRayPayload shadow_payload = ShadowRayPayload::new_hit();
     TraceRay(
         acceleration_structure,
         RAY_FLAG_ACCEPT_FIRST_HIT_AND_END_SEARCH | RAY_FLAG_SKIP_CLOSEST_HIT_SHADER,
-        0xff, 0, 0, 1, ray, shadow_payload
+        instance_mask, 1, 0, 1, ray, shadow_payload
     );
 
     return shadow_payload.is_shadowed;
 }
 
+bool rt_is_shadowed(
+    RaytracingAccelerationStructure acceleration_structure,
+    RayDesc ray
+) {
+    return rt_is_shadowed(acceleration_structure, ray, RT_INSTANCE_MASK_PRIMARY);
+}
+
 struct GbufferPathVertex {
     bool is_hit;
     GbufferDataPacked gbuffer_packed;
@@ -81,6 +95,7 @@ struct GbufferRaytrace {
     RayCone ray_cone;
     uint path_length;
     bool cull_back_faces;
+    uint instance_mask;
 
     static GbufferRaytrace with_ray(RayDesc ray) {
         GbufferRaytrace res;
@@ -88,6 +103,7 @@ struct GbufferRaytrace {
         res.ray_cone = RayCone::from_spread_angle(1.0);
         res.path_length = 0;
         res.cull_back_faces = true;
+        res.instance_mask = RT_INSTANCE_MASK_PRIMARY;
         return res;
     }
 
@@ -109,6 +125,12 @@ struct GbufferRaytrace {
         return res;
     }
 
+    GbufferRaytrace with_instance_mask(uint v) {
+        GbufferRaytrace res = this;
+        res.instance_mask = v;
+        return res;
+    }
+
     GbufferPathVertex trace(RaytracingAccelerationStructure acceleration_structure) {
         GbufferRayPayload payload = GbufferRayPayload::new_miss();
         payload.ray_cone = this.ray_cone;
@@ -119,7 +141,7 @@ struct GbufferRaytrace {
             trace_flags |= RAY_FLAG_CULL_BACK_FACING_TRIANGLES;
         }
 
-        TraceRay(acceleration_structure, trace_flags, 0xff, 0, 0, 0, this.ray, payload);
+        TraceRay(acceleration_structure, trace_flags, this.instance_mask, 0, 0, 0, this.ray, payload);
 
         if (payload.is_hit()) {
             GbufferPathVertex res;
@@ -0,0 +1,81 @@
+#ifndef RT_ALPHA_TEST_HLSL
+#define RT_ALPHA_TEST_HLSL
+
+#include "samplers.hlsl"
+#include "mesh.hlsl"
+#include "bindless.hlsl"
+#include "hash.hlsl"
+
+// The mesh, vertex indices and material of the candidate hit in an any-hit shader.
+void rt_load_candidate_hit(out Mesh mesh, out uint3 ind, out MeshMaterial material) {
+    mesh = meshes[rt_instance_mesh_index(InstanceID())];
+    const uint index_offset = mesh_lod_index_offset(mesh, rt_instance_lod(InstanceID()));
+
+    ind = uint3(
+        vertices.Load((PrimitiveIndex() * 3 + 0) * sizeof(uint) + index_offset),
+        vertices.Load((PrimitiveIndex() * 3 + 1) * sizeof(uint) + index_offset),
+        vertices.Load((PrimitiveIndex() * 3 + 2) * sizeof(uint) + index_offset)
+    );
+
+    uint material_id = vertices.Load(ind.x * sizeof(uint) + mesh.vertex_mat_offset);
+    material = vertices.Load<MeshMaterial>(mesh.mat_data_offset + material_id * sizeof(MeshMaterial));
+}
+
+// Alpha test for any-hit shaders, evaluated at the candidate hit.
+// Matches the test in `raster_simple_ps.hlsl`, so that ray traced effects agree with the rasterized G-buffer.
+bool rt_alpha_test_fails(float2 bary) {
+    float3 barycentrics = float3(1.0 - bary.x - bary.y, bary.x, bary.y);
+
+    Mesh mesh;
+    uint3 ind;
+    MeshMaterial material;
+    rt_load_candidate_hit(mesh, ind, material);
+
+    if (material.alpha_mode == MESH_MATERIAL_ALPHA_MODE_OPAQUE) {
+        return false;
+    }
+
+    float v_alpha = 1.0;
+    if (mesh.vertex_aux_offset != 0) {
+        float vc0 = load_mesh_color(mesh, ind.x).a;
+        float vc1 = load_mesh_color(mesh, ind.y).a;
+        float vc2 = load_mesh_color(mesh, ind.z).a;
+        v_alpha = vc0 * barycentrics.x + vc1 * barycentrics.y + vc2 * barycentrics.z;
+    }
+
+    float2 uv0 = load_mesh_uv(mesh, mesh.vertex_uv_offset, ind.x);
+    float2 uv1 = load_mesh_uv(mesh, mesh.vertex_uv_offset, ind.y);
+    float2 uv2 = load_mesh_uv(mesh, mesh.vertex_uv_offset, ind.z);
+    float2 uv = uv0 * barycentrics.x + uv1 * barycentrics.y + uv2 * barycentrics.z;
+
+    float2 set1_uv0 = load_mesh_uv(mesh, mesh.vertex_uv1_offset, ind.x);
+    float2 set1_uv1 = load_mesh_uv(mesh, mesh.vertex_uv1_offset, ind.y);
+    float2 set1_uv2 = load_mesh_uv(mesh, mesh.vertex_uv1_offset, ind.z);
+    float2 set1_uv = set1_uv0 * barycentrics.x + set1_uv1 * barycentrics.y + set1_uv2 * barycentrics.z;
+
+    // No ray cone here; the top mip keeps thin features such as foliage from eroding.
+    float2 albedo_uv = transform_material_uv(material, uv, set1_uv, 0);
+    Texture2D albedo_tex = bindless_textures[NonUniformResourceIndex(material.albedo_map)];
+    float alpha = albedo_tex.SampleLevel(sampler_llr, albedo_uv, 0).a * material.base_color_mult[3] * v_alpha;
+
+    return material_alpha_test_fails(material, alpha);
+}
+
+// Thin transmissive surfaces let a random fraction of shadow rays through,
+// so that their shadows are attenuated rather than opaque.
+bool rt_shadow_ray_transmitted() {
+    Mesh mesh;
+    uint3 ind;
+    MeshMaterial material;
+    rt_load_candidate_hit(mesh, ind, material);
+
+    const float transmission = material.transmission * max(0.0, 1.0 - material.metalness_factor);
+    if (transmission <= 0.0) {
+        return false;
+    }
+
+    const uint seed = hash4(uint4(DispatchRaysIndex().xy, asuint(RayTCurrent()), PrimitiveIndex()));
+    return uint_to_u01_float(seed) < transmission;
+}
+
+#endif
@@ -121,7 +121,7 @@ void main(in uint2 px : SV_DispatchThreadID) {
     }
 
     LayeredBrdf brdf = LayeredBrdf::from_gbuffer_ndotv(gbuffer, wo.z);
-    const float3 brdf_value = brdf.evaluate_directional_light(wo, wi) * max(0.0, wi.z);
+    const float3 brdf_value = brdf.evaluate_directional_light(wo, wi) * abs(wi.z);
     const float3 light_radiance = shadow_mask * SUN_COLOR;
     float3 total_radiance = brdf_value * light_radiance;
 
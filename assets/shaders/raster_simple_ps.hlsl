@@ -14,6 +14,7 @@ struct PsIn {
     [[vk::location(5)]] float3 bitangent: TEXCOORD5;
     [[vk::location(6)]] float3 vs_pos: TEXCOORD6;
     [[vk::location(7)]] float3 prev_vs_pos: TEXCOORD7;
+    [[vk::location(8)]] float2 uv1: TEXCOORD8;
 };
 
 [[vk::push_constant]]
@@ -35,20 +36,20 @@ struct PsOut {
     float4 velocity: SV_TARGET2;
 };
 
-PsOut main(PsIn ps) {
+PsOut main(PsIn ps, bool is_front_face: SV_IsFrontFace) {
     Mesh mesh = meshes[push_constants.mesh_index];
     MeshMaterial material = vertices.Load<MeshMaterial>(mesh.mat_data_offset + ps.material_id * sizeof(MeshMaterial));
 
-    float2 albedo_uv = transform_material_uv(material, ps.uv, 0);
+    float2 albedo_uv = transform_material_uv(material, ps.uv, ps.uv1, 0);
     Texture2D albedo_tex = bindless_textures[NonUniformResourceIndex(material.albedo_map)];
     float4 albedo_texel = albedo_tex.SampleBias(sampler_llr, albedo_uv, -0.5);
-    if (albedo_texel.a < 0.5) {
+    if (material_alpha_test_fails(material, albedo_texel.a * material.base_color_mult[3] * ps.color.a)) {
         discard;
     }
 
     float3 albedo = albedo_texel.xyz * float4(material.base_color_mult).xyz * ps.color.xyz;
 
-    float2 spec_uv = transform_material_uv(material, ps.uv, 2);
+    float2 spec_uv = transform_material_uv(material, ps.uv, ps.uv1, 2);
     Texture2D spec_tex = bindless_textures[NonUniformResourceIndex(material.spec_map)];
     const float4 metalness_roughness = spec_tex.SampleBias(sampler_llr, spec_uv, -0.5);
     float perceptual_roughness = material.roughness_mult * metalness_roughness.y;
@@ -56,7 +57,8 @@ PsOut main(PsIn ps) {
     float metalness = metalness_roughness.z * material.metalness_factor;
 
     Texture2D normal_tex = bindless_textures[NonUniformResourceIndex(material.normal_map)];
-    const float3 ts_normal = normal_tex.SampleBias(sampler_llr, ps.uv, -0.5).xyz * 2.0 - 1.0;
+    float2 normal_uv = transform_material_uv(material, ps.uv, ps.uv1, 1);
+    const float3 ts_normal = decode_tangent_space_normal(normal_tex.SampleBias(sampler_llr, normal_uv, -0.5).xy);
 
     float3 normal_ws; {
         float3 normal_os = ps.normal;
@@ -68,6 +70,11 @@ PsOut main(PsIn ps) {
             }
         }
 
+        // Back faces of double-sided materials are shaded with reversed normals
+        if (!is_front_face && (material.flags & MESH_MATERIAL_FLAG_DOUBLE_SIDED) != 0) {
+            normal_os = -normal_os;
+        }
+
         // Transform to world space
         normal_ws = normalize(mul(instance_transforms_dyn[push_constants.draw_index].current, float4(normal_os, 0.0)));
     }
@@ -87,7 +94,7 @@ PsOut main(PsIn ps) {
     }
     //normal_ws = geometric_normal_ws;
 
-    float2 emissive_uv = transform_material_uv(material, ps.uv, 3);
+    float2 emissive_uv = transform_material_uv(material, ps.uv, ps.uv1, 3);
     Texture2D emissive_tex = bindless_textures[NonUniformResourceIndex(material.emissive_map)];
     float3 emissive = 1.0.xxx
         * emissive_tex.SampleBias(sampler_llr, emissive_uv, -0.5).rgb
@@ -98,10 +105,19 @@ PsOut main(PsIn ps) {
 
     GbufferData gbuffer = GbufferData::create_zero();
     gbuffer.albedo = albedo;
+    // Refraction is not supported; transmissive surfaces are treated as thin.
+    gbuffer.transmission = material.transmission;
     gbuffer.normal = normal_ws;
     gbuffer.roughness = roughness;
     gbuffer.metalness = metalness;
     gbuffer.emissive = emissive;
+    gbuffer.dielectric_f0 = material_dielectric_f0(material, metalness_roughness);
+    float2 clearcoat_uv = transform_material_uv(material, ps.uv, ps.uv1, 4);
+    Texture2D clearcoat_tex = bindless_textures[NonUniformResourceIndex(material.clearcoat_map)];
+    gbuffer.clearcoat = material.clearcoat * clearcoat_tex.SampleBias(sampler_llr, clearcoat_uv, -0.5).r;
+    gbuffer.clearcoat_roughness = clamp(perceptual_roughness_to_roughness(material.clearcoat_roughness), 1e-4, 1.0);
+    gbuffer.sheen = max(material.sheen_color[0], max(material.sheen_color[1], material.sheen_color[2]));
+    gbuffer.sheen_roughness = clamp(perceptual_roughness_to_roughness(material.sheen_roughness), 1e-4, 1.0);
 
     PsOut ps_out;
     ps_out.geometric_normal = geometric_normal_vs * 0.5 + 0.5;
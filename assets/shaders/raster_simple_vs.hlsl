@@ -25,6 +25,7 @@ struct VsOut {
     [[vk::location(5)]] float3 bitangent: TEXCOORD5;
     [[vk::location(6)]] float3 vs_pos: TEXCOORD6;
     [[vk::location(7)]] float3 prev_vs_pos: TEXCOORD7;
+    [[vk::location(8)]] float2 uv1: TEXCOORD8;
 };
 
 VsOut main(uint vid: SV_VertexID, uint instance_index: SV_InstanceID) {
@@ -32,22 +33,12 @@ VsOut main(uint vid: SV_VertexID, uint instance_index: SV_InstanceID) {
 
     const Mesh mesh = meshes[push_constants.mesh_index];
 
-    // TODO: replace with Load<float4> once there's a fast path for NV
-    // https://github.com/microsoft/DirectXShaderCompiler/issues/2193
-    VertexPacked vp = VertexPacked(asfloat(vertices.Load4(vid * sizeof(float4) + mesh.vertex_core_offset)));
-    Vertex v = unpack_vertex(vp);
+    Vertex v = load_mesh_vertex(mesh, vid);
+    float4 v_color = load_mesh_color(mesh, vid);
+    float4 v_tangent_packed = load_mesh_tangent(mesh, vid);
 
-    float4 v_color =
-        mesh.vertex_aux_offset != 0
-            ? asfloat(vertices.Load4(vid * sizeof(float4) + mesh.vertex_aux_offset))
-            : 1.0.xxxx;
-
-    float4 v_tangent_packed =
-        mesh.vertex_tangent_offset != 0
-            ? asfloat(vertices.Load4(vid * sizeof(float4) + mesh.vertex_tangent_offset))
-            : float4(1, 0, 0, 1);            
-
-    float2 uv = asfloat(vertices.Load2(vid * sizeof(float2) + mesh.vertex_uv_offset));
+    float2 uv = load_mesh_uv(mesh, mesh.vertex_uv_offset, vid);
+    float2 uv1 = load_mesh_uv(mesh, mesh.vertex_uv1_offset, vid);
     uint material_id = vertices.Load(vid * sizeof(uint) + mesh.vertex_mat_offset);
 
     //float3 ws_pos = v.position + float3(push_constants.instance_position);
@@ -56,13 +47,20 @@ VsOut main(uint vid: SV_VertexID, uint instance_index: SV_InstanceID) {
     float4 vs_pos = mul(frame_constants.view_constants.world_to_view, float4(ws_pos, 1.0));
     float4 cs_pos = mul(frame_constants.view_constants.view_to_sample, vs_pos);
 
-    float3 prev_ws_pos = mul(instance_transforms_dyn[push_constants.draw_index].previous, float4(v.position, 1.0));
+    // Skinned meshes also keep the previous positions of their vertices. They are never quantized.
+    float3 prev_pos = v.position;
+    if (mesh.vertex_prev_core_offset != 0) {
+        prev_pos = asfloat(vertices.Load3(vid * sizeof(float4) + mesh.vertex_prev_core_offset));
+    }
+
+    float3 prev_ws_pos = mul(instance_transforms_dyn[push_constants.draw_index].previous, float4(prev_pos, 1.0));
     float4 prev_vs_pos = mul(frame_constants.view_constants.world_to_view, float4(prev_ws_pos, 1.0));
     //float4 prev_cs_pos = mul(frame_constants.view_constants.view_to_sample, prev_vs_pos);
 
     vsout.position = cs_pos;
     vsout.color = v_color;
     vsout.uv = uv;
+    vsout.uv1 = uv1;
     vsout.normal = v.normal;
     vsout.material_id = material_id;
     vsout.tangent = v_tangent_packed.xyz;
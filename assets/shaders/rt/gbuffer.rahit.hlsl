@@ -0,0 +1,13 @@
+#include "../inc/rt.hlsl"
+#include "../inc/rt_alpha_test.hlsl"
+
+struct RayHitAttrib {
+    float2 bary;
+};
+
+[shader("anyhit")]
+void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib: SV_IntersectionAttributes) {
+    if (rt_alpha_test_fails(attrib.bary)) {
+        IgnoreHit();
+    }
+}
@@ -41,58 +41,77 @@ void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib
 
     float3 barycentrics = float3(1.0 - attrib.bary.x - attrib.bary.y, attrib.bary.x, attrib.bary.y);
 
-    //Mesh mesh = meshes[InstanceIndex() / 2];
-    Mesh mesh = meshes[InstanceID()];
+    Mesh mesh = meshes[rt_instance_mesh_index(InstanceID())];
+    const uint index_offset = mesh_lod_index_offset(mesh, rt_instance_lod(InstanceID()));
 
     // Indices of the triangle
     uint3 ind = uint3(
-        vertices.Load((PrimitiveIndex() * 3 + 0) * sizeof(uint) + mesh.index_offset),
-        vertices.Load((PrimitiveIndex() * 3 + 1) * sizeof(uint) + mesh.index_offset),
-        vertices.Load((PrimitiveIndex() * 3 + 2) * sizeof(uint) + mesh.index_offset)
+        vertices.Load((PrimitiveIndex() * 3 + 0) * sizeof(uint) + index_offset),
+        vertices.Load((PrimitiveIndex() * 3 + 1) * sizeof(uint) + index_offset),
+        vertices.Load((PrimitiveIndex() * 3 + 2) * sizeof(uint) + index_offset)
     );
 
-    Vertex v0 = unpack_vertex(VertexPacked(asfloat(vertices.Load4(ind.x * sizeof(float4) + mesh.vertex_core_offset))));
-    Vertex v1 = unpack_vertex(VertexPacked(asfloat(vertices.Load4(ind.y * sizeof(float4) + mesh.vertex_core_offset))));
-    Vertex v2 = unpack_vertex(VertexPacked(asfloat(vertices.Load4(ind.z * sizeof(float4) + mesh.vertex_core_offset))));
+    Vertex v0 = load_mesh_vertex(mesh, ind.x);
+    Vertex v1 = load_mesh_vertex(mesh, ind.y);
+    Vertex v2 = load_mesh_vertex(mesh, ind.z);
     float3 normal = v0.normal * barycentrics.x + v1.normal * barycentrics.y + v2.normal * barycentrics.z;
 
-    const float3 surf_normal = normalize(cross(v1.position - v0.position, v2.position - v0.position));
-    normal = surf_normal;
+    float3 surf_normal = normalize(cross(v1.position - v0.position, v2.position - v0.position));
+
+    // Mirrored instances use meshes with reversed winding
+    if (determinant((float3x3)ObjectToWorld3x4()) < 0.0) {
+        surf_normal = -surf_normal;
+    }
 
     float4 v_color = 1.0.xxxx;
     if (mesh.vertex_aux_offset != 0) {
-        float4 vc0 = asfloat(vertices.Load4(ind.x * sizeof(float4) + mesh.vertex_aux_offset));
-        float4 vc1 = asfloat(vertices.Load4(ind.y * sizeof(float4) + mesh.vertex_aux_offset));
-        float4 vc2 = asfloat(vertices.Load4(ind.z * sizeof(float4) + mesh.vertex_aux_offset));
+        float4 vc0 = load_mesh_color(mesh, ind.x);
+        float4 vc1 = load_mesh_color(mesh, ind.y);
+        float4 vc2 = load_mesh_color(mesh, ind.z);
         v_color = vc0 * barycentrics.x + vc1 * barycentrics.y + vc2 * barycentrics.z;
     }
 
-    float2 uv0 = asfloat(vertices.Load2(ind.x * sizeof(float2) + mesh.vertex_uv_offset));
-    float2 uv1 = asfloat(vertices.Load2(ind.y * sizeof(float2) + mesh.vertex_uv_offset));
-    float2 uv2 = asfloat(vertices.Load2(ind.z * sizeof(float2) + mesh.vertex_uv_offset));
+    float2 uv0 = load_mesh_uv(mesh, mesh.vertex_uv_offset, ind.x);
+    float2 uv1 = load_mesh_uv(mesh, mesh.vertex_uv_offset, ind.y);
+    float2 uv2 = load_mesh_uv(mesh, mesh.vertex_uv_offset, ind.z);
     float2 uv = uv0 * barycentrics.x + uv1 * barycentrics.y + uv2 * barycentrics.z;
 
+    float2 set1_uv0 = load_mesh_uv(mesh, mesh.vertex_uv1_offset, ind.x);
+    float2 set1_uv1 = load_mesh_uv(mesh, mesh.vertex_uv1_offset, ind.y);
+    float2 set1_uv2 = load_mesh_uv(mesh, mesh.vertex_uv1_offset, ind.z);
+    float2 set1_uv = set1_uv0 * barycentrics.x + set1_uv1 * barycentrics.y + set1_uv2 * barycentrics.z;
+
     const float cone_width = payload.ray_cone.width_at_t(hit_dist);
     const float3 v0_pos_ws = mul(ObjectToWorld3x4(), float4(v0.position, 1.0));
     const float3 v1_pos_ws = mul(ObjectToWorld3x4(), float4(v1.position, 1.0));
     const float3 v2_pos_ws = mul(ObjectToWorld3x4(), float4(v2.position, 1.0));
-    const float lod_triangle_constant = 0.5 * log2(twice_uv_area(uv0, uv1, uv2) / twice_triangle_area(v0_pos_ws, v1_pos_ws, v2_pos_ws));
+    const float triangle_area = twice_triangle_area(v0_pos_ws, v1_pos_ws, v2_pos_ws);
+    const float lod_triangle_constants[2] = {
+        0.5 * log2(twice_uv_area(uv0, uv1, uv2) / triangle_area),
+        0.5 * log2(twice_uv_area(set1_uv0, set1_uv1, set1_uv2) / triangle_area)
+    };
 
     uint material_id = vertices.Load(ind.x * sizeof(uint) + mesh.vertex_mat_offset);
     MeshMaterial material = vertices.Load<MeshMaterial>(mesh.mat_data_offset + material_id * sizeof(MeshMaterial));
 
-    float2 albedo_uv = transform_material_uv(material, uv, 0);
+    // Back faces of double-sided materials are shaded with reversed normals
+    if (HitKind() == HIT_KIND_TRIANGLE_BACK_FACE && (material.flags & MESH_MATERIAL_FLAG_DOUBLE_SIDED) != 0) {
+        surf_normal = -surf_normal;
+    }
+    normal = surf_normal;
+
+    float2 albedo_uv = transform_material_uv(material, uv, set1_uv, 0);
     Texture2D albedo_tex = bindless_textures[NonUniformResourceIndex(material.albedo_map)];
-    float albedo_lod = compute_texture_lod(albedo_tex, lod_triangle_constant, WorldRayDirection(), surf_normal, cone_width);
+    float albedo_lod = compute_texture_lod(albedo_tex, lod_triangle_constants[material.map_uv_sets[0]], WorldRayDirection(), surf_normal, cone_width);
 
     float3 albedo =
         albedo_tex.SampleLevel(sampler_llr, albedo_uv, albedo_lod).xyz
         * float4(material.base_color_mult).xyz
         * v_color.rgb;
 
-    float2 spec_uv = transform_material_uv(material, uv, 2);
+    float2 spec_uv = transform_material_uv(material, uv, set1_uv, 2);
     Texture2D spec_tex = bindless_textures[NonUniformResourceIndex(material.spec_map)];
-    float spec_lod = compute_texture_lod(spec_tex, lod_triangle_constant, WorldRayDirection(), surf_normal, cone_width);
+    float spec_lod = compute_texture_lod(spec_tex, lod_triangle_constants[material.map_uv_sets[2]], WorldRayDirection(), surf_normal, cone_width);
     float4 metalness_roughness = spec_tex.SampleLevel(sampler_llr, spec_uv, spec_lod);
     float perceptual_roughness = material.roughness_mult * metalness_roughness.y;
     float roughness = clamp(perceptual_roughness_to_roughness(perceptual_roughness), 1e-4, 1.0);
@@ -101,18 +120,9 @@ void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib
     //albedo *= lerp(0.75, 1.0, metalness);
 
 #if 0
-    float4 v_tangent_packed0 =
-        mesh.vertex_tangent_offset != 0
-            ? asfloat(vertices.Load4(ind.x * sizeof(float4) + mesh.vertex_tangent_offset))
-            : float4(1, 0, 0, 1);            
-    float4 v_tangent_packed1 =
-        mesh.vertex_tangent_offset != 0
-            ? asfloat(vertices.Load4(ind.y * sizeof(float4) + mesh.vertex_tangent_offset))
-            : float4(1, 0, 0, 1);            
-    float4 v_tangent_packed2 =
-        mesh.vertex_tangent_offset != 0
-            ? asfloat(vertices.Load4(ind.z * sizeof(float4) + mesh.vertex_tangent_offset))
-            : float4(1, 0, 0, 1);            
+    float4 v_tangent_packed0 = load_mesh_tangent(mesh, ind.x);
+    float4 v_tangent_packed1 = load_mesh_tangent(mesh, ind.y);
+    float4 v_tangent_packed2 = load_mesh_tangent(mesh, ind.z);
 
     float3 tangent0 = v_tangent_packed0.xyz;
     float3 bitangent0 = normalize(cross(v0.normal, tangent0) * v_tangent_packed0.w);
@@ -126,10 +136,10 @@ void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib
     float3 tangent = tangent0 * barycentrics.x + tangent1 * barycentrics.y + tangent2 * barycentrics.z;
     float3 bitangent = bitangent0 * barycentrics.x + bitangent1 * barycentrics.y + bitangent2 * barycentrics.z;
 
-    float2 normal_uv = transform_material_uv(material, uv, 0);
+    float2 normal_uv = transform_material_uv(material, uv, set1_uv, 1);
     Texture2D normal_tex = bindless_textures[NonUniformResourceIndex(material.normal_map)];
-    float normal_lod = compute_texture_lod(normal_tex, lod_triangle_constant, WorldRayDirection(), surf_normal, cone_width);
-    float3 ts_normal = normal_tex.SampleLevel(sampler_llr, normal_uv, normal_lod).xyz * 2.0 - 1.0;
+    float normal_lod = compute_texture_lod(normal_tex, lod_triangle_constants[material.map_uv_sets[1]], WorldRayDirection(), surf_normal, cone_width);
+    float3 ts_normal = decode_tangent_space_normal(normal_tex.SampleLevel(sampler_llr, normal_uv, normal_lod).xy);
 
     if (dot(bitangent, bitangent) > 0.0) {
         float3x3 tbn = float3x3(tangent, bitangent, normal);
@@ -138,9 +148,9 @@ void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib
     normal = normalize(normal);
 #endif
 
-    float2 emissive_uv = transform_material_uv(material, uv, 3);
+    float2 emissive_uv = transform_material_uv(material, uv, set1_uv, 3);
     Texture2D emissive_tex = bindless_textures[NonUniformResourceIndex(material.emissive_map)];
-    float emissive_lod = compute_texture_lod(emissive_tex, lod_triangle_constant, WorldRayDirection(), surf_normal, cone_width);
+    float emissive_lod = compute_texture_lod(emissive_tex, lod_triangle_constants[material.map_uv_sets[3]], WorldRayDirection(), surf_normal, cone_width);
 
     float3 emissive = 0;
 
@@ -159,11 +169,21 @@ void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib
 
     GbufferData gbuffer = GbufferData::create_zero();
     gbuffer.albedo = albedo;
+    // Refraction is not supported; transmissive surfaces are treated as thin.
+    gbuffer.transmission = material.transmission;
     gbuffer.normal = normalize(mul(ObjectToWorld3x4(), float4(normal, 0.0)));
     gbuffer.roughness = roughness;
     //gbuffer.metalness = lerp(metalness_roughness.z, 1.0, material.metalness_factor);
     gbuffer.metalness = metalness;
     gbuffer.emissive = emissive;
+    gbuffer.dielectric_f0 = material_dielectric_f0(material, metalness_roughness);
+    float2 clearcoat_uv = transform_material_uv(material, uv, set1_uv, 4);
+    Texture2D clearcoat_tex = bindless_textures[NonUniformResourceIndex(material.clearcoat_map)];
+    float clearcoat_lod = compute_texture_lod(clearcoat_tex, lod_triangle_constants[material.map_uv_sets[4]], WorldRayDirection(), surf_normal, cone_width);
+    gbuffer.clearcoat = material.clearcoat * clearcoat_tex.SampleLevel(sampler_llr, clearcoat_uv, clearcoat_lod).r;
+    gbuffer.clearcoat_roughness = clamp(perceptual_roughness_to_roughness(material.clearcoat_roughness), 1e-4, 1.0);
+    gbuffer.sheen = max(material.sheen_color[0], max(material.sheen_color[1], material.sheen_color[2]));
+    gbuffer.sheen_roughness = clamp(perceptual_roughness_to_roughness(material.sheen_roughness), 1e-4, 1.0);
 
     //gbuffer.albedo = float3(0.966653, 0.802156, 0.323968); // Au from Mitsuba
     //gbuffer.metalness = 0;
@@ -156,8 +156,9 @@ void main() {
                 GbufferData gbuffer = primary_hit.gbuffer_packed.unpack();
 
                 if (dot(gbuffer.normal, outgoing_ray.Direction) >= 0.0) {
-                    if (0 == path_length) {
-                        // Flip the normal for primary hits so we don't see blackness
+                    if (0 == path_length || gbuffer.transmission > 0.0) {
+                        // Flip the normal for primary hits so we don't see blackness,
+                        // and for thin transmissive surfaces, which can be hit from either side.
                         gbuffer.normal = -gbuffer.normal;
                     } else {
                         break;
@@ -225,7 +226,7 @@ void main() {
                 if (!FURNACE_TEST && !(ONLY_SPECULAR_FIRST_BOUNCE && path_length == 0)) {
                     const float3 brdf_value = brdf.evaluate_directional_light(wo, wi);
                     const float3 light_radiance = is_shadowed ? 0.0 : SUN_COLOR;
-                    total_radiance += throughput * brdf_value * light_radiance * max(0.0, wi.z);
+                    total_radiance += throughput * brdf_value * light_radiance * abs(wi.z);
 
                     if (USE_EMISSIVE) {
                         total_radiance += gbuffer.emissive * throughput;
@@ -249,8 +250,9 @@ void main() {
                             const float dist_to_light2 = dot(to_light_ws, to_light_ws);
                             const float3 to_light_norm_ws = to_light_ws * rsqrt(dist_to_light2);
 
+                            // Lights behind the surface can still shine through it, via transmission.
                             const float to_psa_metric =
-                                max(0.0, dot(to_light_norm_ws, gbuffer.normal))
+                                abs(dot(to_light_norm_ws, gbuffer.normal))
                                 * max(0.0, dot(to_light_norm_ws, -light_sample.normal))
                                 / dist_to_light2;
 
@@ -40,7 +40,13 @@ void main() {
     const float3 normal_vs = geometric_normal_tex[px] * 2.0 - 1.0;
     const float3 normal_ws = mul(frame_constants.view_constants.view_to_world, float4(normal_vs, 0.0)).xyz;
 
-    const float3 bias_dir = normal_ws;
+    const float3 to_light_norm = sample_sun_direction(
+        blue_noise_for_pixel(px, frame_constants.frame_index).xy,
+        USE_SOFT_SHADOWS
+    );
+
+    // Bias towards the light, so that back-lit transmissive surfaces don't shadow themselves.
+    const float3 bias_dir = dot(normal_ws, to_light_norm) < 0.0 ? -normal_ws : normal_ws;
     const float bias_amount = (-pt_vs.z + length(pt_ws.xyz)) * 1e-5;
     const float3 ray_origin = pt_ws.xyz + bias_dir * bias_amount;
 
@@ -48,10 +54,7 @@ void main() {
         acceleration_structure,
         new_ray(
             ray_origin,
-            sample_sun_direction(
-                blue_noise_for_pixel(px, frame_constants.frame_index).xy,
-                USE_SOFT_SHADOWS
-            ),
+            to_light_norm,
             0,
             FLT_MAX
         ));
@@ -165,7 +165,9 @@ void main() {
     if (brdf_sample.is_valid()) {
         RayDesc outgoing_ray;
         outgoing_ray.Direction = mul(tangent_to_world, brdf_sample.wi);
-        outgoing_ray.Origin = refl_ray_origin;
+        // Clear the surface of the GI LOD, which can differ from the rasterized one
+        outgoing_ray.Origin = refl_ray_origin
+            + primary_hit_normal * frame_constants.gi_ray_lod_bias * length(refl_ray_origin - get_eye_position());
         outgoing_ray.TMin = 0;
 
         #if USE_SHORT_RAYS_ONLY
@@ -215,6 +217,7 @@ void main() {
             .with_cone(ray_cone)
             .with_cull_back_faces(true)
             .with_path_length(1)
+            .with_instance_mask(RT_INSTANCE_MASK_GI)
             .trace(acceleration_structure);
 
         if (primary_hit.is_hit) {
@@ -248,6 +251,9 @@ void main() {
             const float3 wo = mul(-outgoing_ray.Direction, tangent_to_world);
             const LayeredBrdf brdf = LayeredBrdf::from_gbuffer_ndotv(gbuffer, wo.z);
 
+            // The remaining uses of the albedo approximate diffuse-only bounces, which transmission takes light away from.
+            gbuffer.albedo *= 1.0 - gbuffer.transmission;
+
             // Sun
             float3 sun_radiance = SUN_COLOR;
             if (any(sun_radiance) > 0) {
@@ -264,10 +270,10 @@ void main() {
                             to_light_norm,
                             1e-4,
                             SKY_DIST
-                    ));
+                    ), RT_INSTANCE_MASK_GI);
 
                 const float3 wi = mul(to_light_norm, tangent_to_world);
-                const float3 brdf_value = brdf.evaluate(wo, wi) * max(0.0, wi.z);
+                const float3 brdf_value = brdf.evaluate(wo, wi) * abs(wi.z);
                 const float3 light_radiance = is_shadowed ? 0.0 : sun_radiance;
                 total_radiance += brdf_value * light_radiance;
             }
@@ -307,7 +313,7 @@ void main() {
                                         to_light_norm_ws,
                                         1e-3,
                                         sqrt(dist_to_light2) - 2e-3
-                                ));
+                                ), RT_INSTANCE_MASK_GI);
 
                             #if 1
                                 const float3 bounce_albedo = lerp(gbuffer.albedo, 1.0.xxx, 0.04);
@@ -112,7 +112,7 @@ void main() {
     }
 
     SpecularBrdf specular_brdf;
-    specular_brdf.albedo = lerp(0.04, gbuffer.albedo, gbuffer.metalness);
+    specular_brdf.albedo = lerp(gbuffer.dielectric_f0, gbuffer.albedo, gbuffer.metalness);
     specular_brdf.roughness = gbuffer.roughness;
 
 #if USE_AGGRESSIVE_ROUGHNESS_BIAS
@@ -186,6 +186,9 @@ void main() {
             const float3 wo = mul(-outgoing_ray.Direction, tangent_to_world);
             const LayeredBrdf brdf = LayeredBrdf::from_gbuffer_ndotv(gbuffer, wo.z);
 
+            // The remaining uses of the albedo approximate diffuse-only bounces, which transmission takes light away from.
+            gbuffer.albedo *= 1.0 - gbuffer.transmission;
+
             // Project the sample into clip space, and check if it's on-screen
             const float3 primary_hit_cs = position_world_to_clip(primary_hit.position);
             const float2 primary_hit_uv = cs_to_uv(primary_hit_cs.xy);
@@ -220,7 +223,7 @@ void main() {
 
                     const float3 wi = mul(to_light_norm, tangent_to_world);
 
-                    const float3 brdf_value = brdf.evaluate(wo, wi) * max(0.0, wi.z);
+                    const float3 brdf_value = brdf.evaluate(wo, wi) * abs(wi.z);
                     const float3 light_radiance = is_shadowed ? 0.0 : SUN_COLOR;
                     total_radiance += brdf_value * light_radiance;
                 }
@@ -0,0 +1,97 @@
+#include "inc/mesh.hlsl"
+
+struct JointMatrix {
+    row_major float3x4 xform;
+};
+
+[[vk::binding(0)]] RWByteAddressBuffer vertices;
+[[vk::binding(1)]] StructuredBuffer<JointMatrix> joint_matrices_dyn;
+[[vk::binding(2)]] StructuredBuffer<float> morph_weights_dyn;
+[[vk::binding(3)]] cbuffer _ {
+    uint vertex_count;
+    uint source_vertex_core_offset;
+    uint source_vertex_tangent_offset;
+    uint vertex_joints_offset;
+    uint vertex_weights_offset;
+    uint morph_target_count;
+    uint morph_deltas_offset;
+    uint vertex_core_offset;
+    uint vertex_prev_core_offset;
+    uint vertex_tangent_offset;
+    uint joint_count;
+    uint first_frame;
+};
+
+float3x4 load_joint_matrix(uint joint) {
+    return joint_matrices_dyn[min(joint, joint_count - 1)].xform;
+}
+
+[numthreads(64, 1, 1)]
+void main(uint vid: SV_DispatchThreadID) {
+    if (vid >= vertex_count) {
+        return;
+    }
+
+    Vertex v = unpack_vertex(VertexPacked(asfloat(vertices.Load4(vid * sizeof(float4) + source_vertex_core_offset))));
+    float4 tangent = asfloat(vertices.Load4(vid * sizeof(float4) + source_vertex_tangent_offset));
+
+    // Morph targets are applied in the bind pose, before skinning.
+    // Each vertex of each target stores position, normal, and tangent deltas.
+    for (uint target = 0; target < morph_target_count; ++target) {
+        const float weight = morph_weights_dyn[target];
+        if (weight != 0.0) {
+            const uint delta_offset = morph_deltas_offset + (target * vertex_count + vid) * 3 * sizeof(float4);
+            v.position += weight * asfloat(vertices.Load3(delta_offset));
+            v.normal += weight * asfloat(vertices.Load3(delta_offset + sizeof(float4)));
+            tangent.xyz += weight * asfloat(vertices.Load3(delta_offset + 2 * sizeof(float4)));
+        }
+    }
+
+    float3x4 skin_xform = float3x4(
+        1, 0, 0, 0,
+        0, 1, 0, 0,
+        0, 0, 1, 0
+    );
+
+    // Meshes without joints only get morphed
+    if (joint_count > 0) {
+        // Four u16 joint indices per vertex
+        uint2 joints_packed = vertices.Load2(vid * sizeof(uint2) + vertex_joints_offset);
+        uint4 joints = uint4(
+            joints_packed.x & 0xffff, joints_packed.x >> 16,
+            joints_packed.y & 0xffff, joints_packed.y >> 16
+        );
+        float4 weights = asfloat(vertices.Load4(vid * sizeof(float4) + vertex_weights_offset));
+
+        // Vertices without weights stay in the bind pose
+        if (dot(weights, 1.0.xxxx) != 0.0) {
+            skin_xform =
+                weights.x * load_joint_matrix(joints.x)
+                + weights.y * load_joint_matrix(joints.y)
+                + weights.z * load_joint_matrix(joints.z)
+                + weights.w * load_joint_matrix(joints.w);
+        }
+    }
+
+    // Keep the positions from the previous frame for motion vectors
+    if (first_frame == 0) {
+        vertices.Store4(
+            vid * sizeof(float4) + vertex_prev_core_offset,
+            vertices.Load4(vid * sizeof(float4) + vertex_core_offset)
+        );
+    }
+
+    Vertex skinned;
+    skinned.position = mul(skin_xform, float4(v.position, 1.0));
+    skinned.normal = normalize(mul(skin_xform, float4(v.normal, 0.0)));
+
+    VertexPacked skinned_packed = pack_vertex(skinned);
+    vertices.Store4(vid * sizeof(float4) + vertex_core_offset, asuint(skinned_packed.data0));
+
+    if (first_frame != 0) {
+        vertices.Store4(vid * sizeof(float4) + vertex_prev_core_offset, asuint(skinned_packed.data0));
+    }
+
+    tangent.xyz = normalize(mul(skin_xform, float4(tangent.xyz, 0.0)));
+    vertices.Store4(vid * sizeof(float4) + vertex_tangent_offset, asuint(tangent));
+}
@@ -0,0 +1,186 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use twox_hash::XxHash64;
+
+use crate::manifest::SceneBakeDesc;
+
+/// Bump whenever the format of baked assets changes, so that stale outputs get rebuilt.
+const BAKE_CACHE_VERSION: u32 = 17;
+const BAKE_CACHE_PATH: &str = "baked/bake_cache.ron";
+
+/// Persistent record of what previous `bake` runs produced, and from which inputs.
+/// Used to skip scenes and images whose sources haven't changed.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct BakeCache {
+    version: u32,
+
+    /// Keyed by the scene's output name
+    scenes: HashMap<String, SceneCacheEntry>,
+
+    /// Keyed by the identity of the baked image
+    images: HashMap<u64, ImageCacheEntry>,
+
+    #[serde(skip)]
+    file_hashes: HashMap<PathBuf, u64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SceneCacheEntry {
+    key: u64,
+    images: Vec<u64>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ImageCacheEntry {
+    /// Files the image is made from; none if it's embedded in the scene or generated
+    pub sources: Vec<PathBuf>,
+    /// Of the source files if there are any; otherwise of the embedded data
+    pub content_hash: u64,
+}
+
+pub fn scene_output_path(output_name: &str) -> PathBuf {
+    PathBuf::from(format!("baked/{}.scene", output_name))
+}
+
+pub fn image_output_path(identity: u64) -> PathBuf {
+    PathBuf::from(format!("baked/{:8.8x}.image", identity))
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+impl BakeCache {
+    pub fn empty() -> Self {
+        Self {
+            version: BAKE_CACHE_VERSION,
+            ..Default::default()
+        }
+    }
+
+    /// Loads the cache from a previous run. Starts from scratch if it's missing,
+    /// unreadable, or was written by an incompatible version of `bake`.
+    pub fn load() -> Self {
+        let cache: Option<Self> = File::open(BAKE_CACHE_PATH)
+            .ok()
+            .and_then(|f| ron::de::from_reader(f).ok());
+
+        match cache {
+            Some(cache) if cache.version == BAKE_CACHE_VERSION => cache,
+            _ => Self::empty(),
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        ron::ser::to_writer_pretty(
+            File::create(BAKE_CACHE_PATH)
+                .with_context(|| format!("Creating {:?}", BAKE_CACHE_PATH))?,
+            self,
+            Default::default(),
+        )
+        .with_context(|| format!("Writing {:?}", BAKE_CACHE_PATH))
+    }
+
+    pub fn hash_file(&mut self, path: &Path) -> anyhow::Result<u64> {
+        if let Some(hash) = self.file_hashes.get(path) {
+            return Ok(*hash);
+        }
+
+        let bytes = std::fs::read(path).with_context(|| format!("Hashing {:?}", path))?;
+        let hash = hash_bytes(&bytes);
+        self.file_hashes.insert(path.to_owned(), hash);
+
+        Ok(hash)
+    }
+
+    /// Combined hash of the contents of several files
+    pub fn hash_files(&mut self, paths: &[PathBuf]) -> anyhow::Result<u64> {
+        let mut hasher = XxHash64::with_seed(0);
+        for path in paths {
+            self.hash_file(path)?.hash(&mut hasher);
+        }
+        Ok(hasher.finish())
+    }
+
+    /// Derives a key from the contents of the files a scene is built from, and the options it's baked with.
+    pub fn scene_key(
+        &mut self,
+        desc: &SceneBakeDesc,
+        source_files: &[PathBuf],
+    ) -> anyhow::Result<u64> {
+        let mut hasher = XxHash64::with_seed(0);
+        BAKE_CACHE_VERSION.hash(&mut hasher);
+
+        for path in source_files {
+            self.hash_file(path)?.hash(&mut hasher);
+        }
+
+        desc.scale.to_bits().hash(&mut hasher);
+        for angle in desc.rotation {
+            angle.to_bits().hash(&mut hasher);
+        }
+        desc.recenter.hash(&mut hasher);
+        desc.optimize.hash(&mut hasher);
+        desc.lod_count.hash(&mut hasher);
+        desc.lod_error.to_bits().hash(&mut hasher);
+        desc.quantize.vertex_quantization().hash(&mut hasher);
+        desc.normals.hash(&mut hasher);
+
+        Ok(hasher.finish())
+    }
+
+    /// A scene can be skipped if its key hasn't changed, and its outputs are still valid --
+    /// including all the images it references.
+    pub fn is_scene_up_to_date(&mut self, output_name: &str, key: u64) -> bool {
+        let images = match self.scenes.get(output_name) {
+            Some(entry) if entry.key == key => entry.images.clone(),
+            _ => return false,
+        };
+
+        scene_output_path(output_name).exists()
+            && images
+                .into_iter()
+                .all(|identity| self.is_baked_image_valid(identity))
+    }
+
+    fn is_baked_image_valid(&mut self, identity: u64) -> bool {
+        let entry = if let Some(entry) = self.images.get(&identity) {
+            entry.clone()
+        } else {
+            return false;
+        };
+
+        if !image_output_path(identity).exists() {
+            return false;
+        }
+
+        entry.sources.is_empty()
+            || self
+                .hash_files(&entry.sources)
+                .map_or(false, |hash| hash == entry.content_hash)
+    }
+
+    pub fn is_image_up_to_date(&self, identity: u64, content_hash: u64) -> bool {
+        self.images
+            .get(&identity)
+            .map_or(false, |entry| entry.content_hash == content_hash)
+            && image_output_path(identity).exists()
+    }
+
+    pub fn insert_scene(&mut self, output_name: &str, key: u64, images: Vec<u64>) {
+        self.scenes
+            .insert(output_name.to_owned(), SceneCacheEntry { key, images });
+    }
+
+    pub fn insert_image(&mut self, identity: u64, entry: ImageCacheEntry) {
+        self.images.insert(identity, entry);
+    }
+}
@@ -0,0 +1,268 @@
+use std::{fs::File, path::PathBuf};
+
+use anyhow::Context as _;
+use kajiya_asset::{
+    image::decode_gpu_image_mip,
+    mesh::{
+        read_flat_asset, AssetHeader, FlatAsset, GpuImage, MeshMaterialAlphaMode,
+        MeshMaterialFlags, PackedScene, PackedTriMesh, ValidateContents, VertexEncodingFlags,
+    },
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct InspectOpt {
+    /// Baked `.scene` or `.image` file
+    #[structopt(parse(from_os_str))]
+    path: PathBuf,
+
+    /// Decode a mip level of an image, and write it to this PNG file
+    #[structopt(long, parse(from_os_str))]
+    dump_png: Option<PathBuf>,
+
+    /// Mip level written by `--dump-png`
+    #[structopt(long, default_value = "0")]
+    mip: usize,
+}
+
+/// Prints the contents of a baked asset, after validating it like the renderer would
+pub fn inspect(opt: &InspectOpt) -> anyhow::Result<()> {
+    let file = File::open(&opt.path).with_context(|| format!("Could not open {:?}", opt.path))?;
+    let mmap = unsafe { memmap2::MmapOptions::new().map(&file) }
+        .with_context(|| format!("Could not mmap {:?}", opt.path))?;
+
+    let header = AssetHeader::read(&mmap).with_context(|| format!("Reading {:?}", opt.path))?;
+
+    println!("{:?}:", opt.path);
+    println!(
+        "  format version {}, {} bytes, checksum {:#018x}",
+        header.version, header.size, header.checksum
+    );
+
+    if header.type_tag == PackedScene::Flat::TYPE_TAG {
+        let scene = read_asset::<PackedScene::Flat>(&mmap, opt)?;
+        if opt.dump_png.is_some() {
+            anyhow::bail!("--dump-png only works with images");
+        }
+        inspect_scene(scene, opt);
+    } else if header.type_tag == PackedTriMesh::Flat::TYPE_TAG {
+        let mesh = read_asset::<PackedTriMesh::Flat>(&mmap, opt)?;
+        if opt.dump_png.is_some() {
+            anyhow::bail!("--dump-png only works with images");
+        }
+        inspect_mesh(mesh, opt);
+    } else if header.type_tag == GpuImage::Flat::TYPE_TAG {
+        let image = read_asset::<GpuImage::Flat>(&mmap, opt)?;
+        inspect_image(image, opt)?;
+    } else {
+        anyhow::bail!("Unknown asset type tag {:#x}", header.type_tag);
+    }
+
+    Ok(())
+}
+
+fn read_asset<'a, T: FlatAsset + ValidateContents>(
+    bytes: &'a [u8],
+    opt: &InspectOpt,
+) -> anyhow::Result<&'a T> {
+    println!("  type {}", T::TYPE_NAME);
+    read_flat_asset::<T>(bytes).with_context(|| format!("Invalid baked asset {:?}", opt.path))
+}
+
+fn inspect_scene(scene: &PackedScene::Flat, opt: &InspectOpt) {
+    println!(
+        "  {} meshes, {} nodes, {} skins, {} animations, {} cameras, {} lights",
+        scene.meshes.len(),
+        scene.nodes.len(),
+        scene.skins.len(),
+        scene.animations.len(),
+        scene.cameras.len(),
+        scene.lights.len(),
+    );
+
+    for (mesh_idx, mesh) in scene.meshes.iter().enumerate() {
+        let instances: Vec<&str> = scene
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.mesh == mesh_idx as u32)
+            .map(|(node_idx, _)| scene.node_name(node_idx))
+            .collect();
+
+        println!();
+        println!("  Mesh {} (instanced by nodes {:?}):", mesh_idx, instances);
+        inspect_mesh(mesh, opt);
+    }
+}
+
+fn inspect_mesh(mesh: &PackedTriMesh::Flat, opt: &InspectOpt) {
+    let encoding = mesh.vertex_encoding;
+    let flags = encoding.flags;
+
+    let position_encoding = match flags & VertexEncodingFlags::POSITION_MASK {
+        VertexEncodingFlags::POSITION_F32 => "f32",
+        VertexEncodingFlags::POSITION_F16 => "f16",
+        VertexEncodingFlags::POSITION_SNORM16 => "snorm16",
+        _ => "unknown",
+    };
+    let quantized = |flag: u32, name: &'static str| if flags & flag != 0 { name } else { "f32" };
+
+    println!(
+        "    encoding: positions {}, uvs {}, tangents {}, colors {}",
+        position_encoding,
+        quantized(VertexEncodingFlags::UV_F16, "f16"),
+        quantized(VertexEncodingFlags::TANGENT_OCTAHEDRAL, "octahedral"),
+        quantized(VertexEncodingFlags::COLOR_RGBA8, "rgba8"),
+    );
+
+    let vertex_count = mesh.vertex_count();
+    println!(
+        "    {} vertices, {} indices ({} triangles)",
+        vertex_count,
+        mesh.indices.len(),
+        mesh.indices.len() / 3
+    );
+
+    if !mesh.joints.is_empty() {
+        println!("    skinned");
+    }
+    if !mesh.morph_targets.is_empty() {
+        println!("    {} morph targets", mesh.morph_targets.len());
+    }
+
+    for (lod_idx, lod) in mesh.lods.iter().enumerate() {
+        let error = lod.error;
+        println!(
+            "    LOD {}: {} triangles, error {:.3e}",
+            lod_idx + 1,
+            lod.indices.len() / 3,
+            error
+        );
+    }
+
+    if vertex_count > 0 {
+        let (min, max) = (0..vertex_count).map(|idx| mesh.vertex_position(idx)).fold(
+            ([f32::MAX; 3], [f32::MIN; 3]),
+            |(mut min, mut max), p| {
+                for i in 0..3 {
+                    min[i] = min[i].min(p[i]);
+                    max[i] = max[i].max(p[i]);
+                }
+                (min, max)
+            },
+        );
+        println!("    bounds: {:?} .. {:?}", min, max);
+    }
+
+    // Material IDs are per vertex, and uniform across each triangle
+    let mut material_triangles = vec![0usize; mesh.materials.len()];
+    for tri in mesh.indices.as_slice().chunks_exact(3) {
+        let material_id = mesh.material_ids.as_slice().get(tri[0] as usize);
+        if let Some(count) = material_id.and_then(|&id| material_triangles.get_mut(id as usize)) {
+            *count += 1;
+        }
+    }
+
+    for (material_idx, material) in mesh.materials.iter().enumerate() {
+        let alpha_mode = match material.alpha_mode {
+            MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_OPAQUE => "opaque",
+            MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_MASK => "mask",
+            MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_BLEND => "blend",
+            _ => "unknown",
+        };
+
+        println!(
+            "    Material {} ({} triangles):",
+            material_idx, material_triangles[material_idx]
+        );
+        println!("      base color {:?}", material.base_color_mult);
+        println!(
+            "      roughness {}, metalness {}, emissive {:?}",
+            material.roughness_mult, material.metalness_factor, material.emissive
+        );
+        println!(
+            "      specular {} {:?}, ior {}, transmission {}, clearcoat {} (roughness {})",
+            material.specular_factor,
+            material.specular_color,
+            material.ior,
+            material.transmission,
+            material.clearcoat,
+            material.clearcoat_roughness
+        );
+        println!(
+            "      sheen {:?} (roughness {})",
+            material.sheen_color, material.sheen_roughness
+        );
+        println!(
+            "      alpha {} (cutoff {}), double-sided {}, emissive used as light {}",
+            alpha_mode,
+            material.alpha_cutoff,
+            material.flags & MeshMaterialFlags::MESH_MATERIAL_FLAG_DOUBLE_SIDED != 0,
+            material.flags & MeshMaterialFlags::MESH_MATERIAL_FLAG_EMISSIVE_USED_AS_LIGHT != 0,
+        );
+
+        for (map_name, &map_idx) in ["normal", "spec", "albedo", "emissive", "clearcoat"]
+            .iter()
+            .zip(&material.maps)
+        {
+            let identity = match mesh.maps.as_slice().get(map_idx as usize) {
+                Some(map) => map.identity(),
+                None => {
+                    println!("      {} map: invalid index {}", map_name, map_idx);
+                    continue;
+                }
+            };
+
+            let file_name = format!("{:8.8x}.image", identity);
+            let exists = opt
+                .path
+                .parent()
+                .map_or(false, |dir| dir.join(&file_name).exists());
+
+            println!(
+                "      {} map: {}{}",
+                map_name,
+                file_name,
+                if exists { "" } else { " (missing)" }
+            );
+        }
+    }
+}
+
+fn inspect_image(image: &GpuImage::Flat, opt: &InspectOpt) -> anyhow::Result<()> {
+    let format = image.format;
+    let extent = image.extent;
+
+    println!("  {:?}, {}x{}x{}", format, extent[0], extent[1], extent[2]);
+
+    for (level, mip) in image.mips.iter().enumerate() {
+        println!(
+            "    mip {}: {}x{}, {} bytes",
+            level,
+            (extent[0] >> level).max(1),
+            (extent[1] >> level).max(1),
+            mip.len()
+        );
+    }
+
+    if let Some(png_path) = &opt.dump_png {
+        let mip = image.mips.as_slice().get(opt.mip).with_context(|| {
+            format!(
+                "Mip {} requested, but the image has {}",
+                opt.mip,
+                image.mips.len()
+            )
+        })?;
+
+        let width = (extent[0] >> opt.mip).max(1);
+        let height = (extent[1] >> opt.mip).max(1);
+        let rgba = decode_gpu_image_mip(format, mip.as_slice(), width, height)?;
+
+        image::save_buffer(png_path, &rgba, width, height, image::ColorType::Rgba8)
+            .with_context(|| format!("Writing {:?}", png_path))?;
+
+        println!("Wrote mip {} to {:?}", opt.mip, png_path);
+    }
+
+    Ok(())
+}
@@ -1,106 +1,479 @@
+mod cache;
+mod inspect;
+mod manifest;
+mod pack;
+
 use async_channel::unbounded;
 use async_executor::Executor;
+use cache::{hash_bytes, image_output_path, scene_output_path, BakeCache, ImageCacheEntry};
 use easy_parallel::Parallel;
-use glam::Quat;
-use kajiya_asset::mesh::{pack_triangle_mesh, GpuImage, LoadGltfScene, PackedTriMesh};
+use glam::{Vec3, Vec3A};
+use kajiya_asset::{
+    image::ImageSource,
+    mesh::{
+        pack_triangle_scene, GpuImage, LoadGltfScene, LoadObjScene, MeshMaterialMap,
+        NormalGeneration, PackedScene, TriangleScene,
+    },
+    optimize::{optimize_mesh, MeshStats},
+    simplify::generate_lods,
+};
+use manifest::{BakeManifest, QuantizeDesc, SceneBakeDesc};
 use smol::future;
-use std::{collections::HashSet, fs::File, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use turbosloth::*;
 
-use anyhow::Result;
-use structopt::StructOpt;
+use anyhow::{Context as _, Result};
+use structopt::{clap::AppSettings, StructOpt};
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "bake", about = "Kanelbullar")]
+#[structopt(
+    name = "bake",
+    about = "Kanelbullar",
+    setting = AppSettings::SubcommandsNegateReqs
+)]
 struct Opt {
-    #[structopt(long, parse(from_os_str))]
-    scene: PathBuf,
+    /// glTF (.gltf, .glb) or Wavefront OBJ (.obj) file to bake
+    #[structopt(long, parse(from_os_str), required_unless = "manifest")]
+    scene: Option<PathBuf>,
 
     #[structopt(long, default_value = "1.0")]
     scale: f32,
 
-    #[structopt(short = "o")]
-    output_name: String,
+    #[structopt(short = "o", required_unless = "manifest")]
+    output_name: Option<String>,
+
+    /// RON or TOML file listing multiple scenes to bake in one run
+    #[structopt(long, parse(from_os_str), conflicts_with = "scene")]
+    manifest: Option<PathBuf>,
+
+    /// Rebuild everything, even if the sources haven't changed since the last run
+    #[structopt(long)]
+    force: bool,
+
+    /// Keep vertex and index data as imported, instead of welding and reordering it.
+    /// Applies to all scenes, overriding the manifest.
+    #[structopt(long)]
+    no_optimize: bool,
+
+    /// Maximum number of simplified LODs generated per mesh, when baking without a manifest
+    #[structopt(long, default_value = "4")]
+    lod_count: usize,
+
+    /// Error budget of the coarsest LOD relative to the mesh's size, when baking without a manifest
+    #[structopt(long, default_value = "0.01")]
+    lod_error: f32,
+
+    /// Store positions as normalized integers, UVs as half-floats, tangents as octahedral
+    /// directions, and colors as RGBA8, when baking without a manifest
+    #[structopt(long)]
+    quantize: bool,
+
+    /// Generate smooth rather than flat normals for glTF primitives without any,
+    /// when baking without a manifest
+    #[structopt(long)]
+    smooth_normals: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() -> Result<()> {
-    env_logger::init();
-    let lazy_cache = LazyCache::create();
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Print the contents of a baked scene or image, and optionally dump an image mip to PNG
+    Inspect(inspect::InspectOpt),
+    /// Pack directories of baked assets and shaders into a single file, mountable through the VFS
+    Pack(pack::PackOpt),
+}
 
-    let opt = Opt::from_args();
-    std::fs::create_dir_all("baked")?;
+struct SceneImage {
+    image: Lazy<GpuImage::Proto>,
+    cache_entry: ImageCacheEntry,
+}
 
-    {
-        println!("Loading {:?}...", opt.scene);
+/// A packed scene, written once the images it uses are
+struct BakedScene {
+    output_path: PathBuf,
+    packed: PackedScene::Proto,
+    images: Vec<SceneImage>,
+}
 
-        let mesh = LoadGltfScene {
-            path: opt.scene,
-            scale: opt.scale,
-            //rotation: Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
-            rotation: Quat::IDENTITY,
+fn recenter_scene(scene: &mut TriangleScene) {
+    let (min, max) = scene
+        .nodes
+        .iter()
+        .zip(scene.node_world_transforms())
+        .filter_map(|(node, xform)| Some((&scene.meshes[node.mesh?], xform)))
+        .flat_map(|(mesh, xform)| {
+            mesh.positions
+                .iter()
+                .map(move |&p| xform.transform_point3(p.into()))
+        })
+        .fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), p| (min.min(p), max.max(p)),
+        );
+
+    if min.cmpgt(max).any() {
+        return;
+    }
+
+    let center = (min + max) * 0.5;
+
+    // On the root transform, so that animated root nodes stay centered too
+    scene.root_transform.translation -= Vec3A::from(center);
+}
+
+/// Optimizes all meshes of a scene, and prints their statistics before and after
+fn optimize_scene(scene: &mut TriangleScene) {
+    let mut total_before = MeshStats::default();
+    let mut total_after = MeshStats::default();
+
+    for (mesh_idx, mesh) in scene.meshes.iter_mut().enumerate() {
+        let (before, after) = optimize_mesh(mesh);
+        println!("  Mesh {}:", mesh_idx);
+        println!("    before: {}", before);
+        println!("    after:  {}", after);
+
+        for (total, stats) in [(&mut total_before, before), (&mut total_after, after)] {
+            total.vertex_count += stats.vertex_count;
+            total.triangle_count += stats.triangle_count;
         }
-        .into_lazy();
+    }
 
-        let mesh = &*smol::block_on(mesh.eval(&lazy_cache))?;
+    println!(
+        "Optimized {} meshes: {} -> {} verts, {} -> {} tris",
+        scene.meshes.len(),
+        total_before.vertex_count,
+        total_after.vertex_count,
+        total_before.triangle_count,
+        total_after.triangle_count,
+    );
+}
 
-        println!("Packing the mesh...");
-        let mesh: PackedTriMesh::Proto = pack_triangle_mesh(mesh);
+/// Generates simplified LODs for all meshes of a scene
+fn generate_scene_lods(scene: &mut TriangleScene, desc: &SceneBakeDesc) {
+    for (mesh_idx, mesh) in scene.meshes.iter_mut().enumerate() {
+        mesh.lods = generate_lods(mesh, desc.lod_count, desc.lod_error);
 
-        mesh.flatten_into(&mut File::create(format!(
-            "baked/{}.mesh",
-            opt.output_name
-        ))?);
-        let unique_images: Vec<Lazy<GpuImage::Proto>> = mesh
-            .maps
-            .into_iter()
-            .collect::<HashSet<_>>()
-            .into_iter()
-            .collect::<Vec<_>>();
+        let lod_summary: Vec<String> = mesh
+            .lods
+            .iter()
+            .map(|lod| format!("{} (error {:.3e})", lod.indices.len() / 3, lod.error))
+            .collect();
 
-        let ex = &Executor::new();
-        let (signal, shutdown) = unbounded::<()>();
+        println!(
+            "  Mesh {}: {} tris -> [{}]",
+            mesh_idx,
+            mesh.indices.len() / 3,
+            lod_summary.join(", ")
+        );
+    }
+}
 
-        // Prepare tasks for processing all images
-        let lazy_cache = &lazy_cache;
-        let images = unique_images.iter().cloned().map(|img| async move {
-            let loaded = img.eval(lazy_cache).await?;
+/// The importer for a scene, chosen by the extension of its file
+enum LoadScene {
+    Gltf(LoadGltfScene),
+    Obj(LoadObjScene),
+}
 
-            loaded.flatten_into(&mut File::create(format!(
-                "baked/{:8.8x}.image",
-                img.identity()
-            ))?);
+impl LoadScene {
+    fn new(desc: &SceneBakeDesc) -> Result<Self> {
+        let extension = desc
+            .scene
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
 
-            //println!("Wrote baked/{:8.8x}.image", img.identity());
+        match extension.as_str() {
+            "gltf" | "glb" => Ok(Self::Gltf(LoadGltfScene {
+                path: desc.scene.clone(),
+                scale: desc.scale,
+                rotation: desc.rotation_quat(),
+                normals: desc.normals,
+            })),
+            "obj" => Ok(Self::Obj(LoadObjScene {
+                path: desc.scene.clone(),
+                scale: desc.scale,
+                rotation: desc.rotation_quat(),
+            })),
+            _ => anyhow::bail!(
+                "Unsupported scene format {:?}; expected .gltf, .glb, or .obj",
+                desc.scene
+            ),
+        }
+    }
 
-            anyhow::Result::<()>::Ok(())
-        });
+    fn source_files(&self) -> Result<Vec<PathBuf>> {
+        match self {
+            Self::Gltf(load) => load.source_files(),
+            Self::Obj(load) => load.source_files(),
+        }
+    }
+
+    fn eval(self, lazy_cache: &Arc<LazyCache>) -> Result<Arc<TriangleScene>> {
+        match self {
+            Self::Gltf(load) => smol::block_on(load.into_lazy().eval(lazy_cache)),
+            Self::Obj(load) => smol::block_on(load.into_lazy().eval(lazy_cache)),
+        }
+    }
+}
+
+/// Bakes the meshes of a single scene, and returns the images it references.
+/// Those are processed separately, so that they can be shared between scenes.
+///
+/// If the scene hasn't changed since it was last baked, nothing is returned.
+fn bake_scene(
+    desc: &SceneBakeDesc,
+    lazy_cache: &Arc<LazyCache>,
+    cache: &mut BakeCache,
+) -> Result<Option<BakedScene>> {
+    let load_scene = LoadScene::new(desc)?;
+
+    let key = cache.scene_key(desc, &load_scene.source_files()?)?;
+    if cache.is_scene_up_to_date(&desc.output, key) {
+        println!("{:?} is up to date.", desc.scene);
+        return Ok(None);
+    }
+
+    println!("Loading {:?}...", desc.scene);
+
+    let mut scene = load_scene.eval(lazy_cache)?;
+
+    let report = &scene.import_report;
+    if !report.is_empty() {
+        println!("Import report for {:?}:", desc.scene);
+        for entry in &report.fixed_up {
+            println!("  fixed up: {}", entry);
+        }
+        for entry in &report.dropped {
+            println!("  dropped:  {}", entry);
+        }
+    }
 
-        // Now spawn them onto the executor
-        let images = images.map(|task| ex.spawn(task));
-        let image_count = images.len();
+    if desc.recenter {
+        let mut recentered = TriangleScene::clone(&scene);
+        recenter_scene(&mut recentered);
+        scene = Arc::new(recentered);
+    }
+
+    if desc.optimize {
+        println!("Optimizing {} meshes...", scene.meshes.len());
+        let mut optimized = TriangleScene::clone(&scene);
+        optimize_scene(&mut optimized);
+        scene = Arc::new(optimized);
+    }
 
-        if image_count > 0 {
-            // A task to join them all
-            let all_images = futures::future::try_join_all(images);
+    // After optimization, which reorders vertices
+    if desc.lod_count > 0 {
+        println!("Generating LODs...");
+        let mut with_lods = TriangleScene::clone(&scene);
+        generate_scene_lods(&mut with_lods, desc);
+        scene = Arc::new(with_lods);
+    }
 
-            println!("Processing {} images...", image_count);
+    println!("Packing {} meshes...", scene.meshes.len());
+    let packed: PackedScene::Proto =
+        pack_triangle_scene(&scene, desc.quantize.vertex_quantization());
 
-            // Now spawn threads for the executor and run it to completion
-            Parallel::new()
-                .each(0..num_cpus::get(), |_| {
-                    future::block_on(ex.run(shutdown.recv()))
+    // `pack_triangle_mesh` creates one image per material map, in the same order
+    let mut images = Vec::new();
+    for (mesh, packed_mesh) in scene.meshes.iter().zip(&packed.meshes) {
+        for (map, image) in mesh.maps.iter().zip(&packed_mesh.maps) {
+            let cache_entry = match map {
+                MeshMaterialMap::Image {
+                    source: ImageSource::Memory(bytes),
+                    ..
+                } => ImageCacheEntry {
+                    sources: Vec::new(),
+                    content_hash: hash_bytes(bytes),
+                },
+                // Files, and images converted from them
+                MeshMaterialMap::Image { source, .. } => {
+                    let sources = source.source_files();
+                    ImageCacheEntry {
+                        content_hash: cache.hash_files(&sources)?,
+                        sources,
+                    }
+                }
+                MeshMaterialMap::Placeholder(values) => ImageCacheEntry {
+                    sources: Vec::new(),
+                    content_hash: hash_bytes(values),
+                },
+            };
+
+            images.push(SceneImage {
+                image: image.clone(),
+                cache_entry,
+            });
+        }
+    }
+
+    cache.insert_scene(
+        &desc.output,
+        key,
+        images.iter().map(|img| img.image.identity()).collect(),
+    );
+
+    Ok(Some(BakedScene {
+        output_path: scene_output_path(&desc.output),
+        packed,
+        images,
+    }))
+}
+
+/// Writes a baked asset to a temporary file, which then replaces the one at `path`.
+/// A running renderer with the previous version mapped keeps reading it intact,
+/// and can reload the new version once it's complete.
+fn write_baked_asset(path: &Path, write: impl FnOnce(&mut File)) -> Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    write(&mut File::create(&temp_path)?);
+    std::fs::rename(&temp_path, path)
+        .with_context(|| format!("Replacing {:?} with {:?}", path, temp_path))
+}
+
+fn bake_images(unique_images: Vec<Lazy<GpuImage::Proto>>, lazy_cache: &Arc<LazyCache>) {
+    let ex = &Executor::new();
+    let (signal, shutdown) = unbounded::<()>();
+
+    // Prepare tasks for processing all images
+    let images = unique_images.iter().cloned().map(|img| async move {
+        let loaded = img.eval(lazy_cache).await?;
+
+        write_baked_asset(&image_output_path(img.identity()), |file| {
+            loaded.flatten_into(file)
+        })?;
+
+        //println!("Wrote baked/{:8.8x}.image", img.identity());
+
+        anyhow::Result::<()>::Ok(())
+    });
+
+    // Now spawn them onto the executor
+    let images = images.map(|task| ex.spawn(task));
+    let image_count = images.len();
+
+    if image_count > 0 {
+        // A task to join them all
+        let all_images = futures::future::try_join_all(images);
+
+        println!("Processing {} images...", image_count);
+
+        // Now spawn threads for the executor and run it to completion
+        Parallel::new()
+            .each(0..num_cpus::get(), |_| {
+                future::block_on(ex.run(shutdown.recv()))
+            })
+            .finish(|| {
+                future::block_on(async {
+                    all_images.await.expect("Failed to load mesh images");
+                    drop(signal);
                 })
-                .finish(|| {
-                    future::block_on(async {
-                        all_images.await.expect("Failed to load mesh images");
-                        drop(signal);
-                    })
-                });
+            });
+    }
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let opt = Opt::from_args();
+
+    match &opt.command {
+        Some(Command::Inspect(inspect_opt)) => return inspect::inspect(inspect_opt),
+        Some(Command::Pack(pack_opt)) => return pack::pack(pack_opt),
+        None => {}
+    }
+
+    let lazy_cache = LazyCache::create();
+
+    let scenes: Vec<SceneBakeDesc> = if let Some(manifest) = &opt.manifest {
+        BakeManifest::load(manifest)?.scenes
+    } else {
+        vec![SceneBakeDesc {
+            scene: opt.scene.unwrap(),
+            output: opt.output_name.unwrap(),
+            scale: opt.scale,
+            rotation: [0.0; 3],
+            recenter: false,
+            optimize: true,
+            lod_count: opt.lod_count,
+            lod_error: opt.lod_error,
+            quantize: if opt.quantize {
+                QuantizeDesc::all()
+            } else {
+                QuantizeDesc::default()
+            },
+            normals: if opt.smooth_normals {
+                NormalGeneration::Smooth
+            } else {
+                NormalGeneration::Flat
+            },
+        }]
+    };
+
+    let scenes: Vec<SceneBakeDesc> = scenes
+        .into_iter()
+        .map(|desc| SceneBakeDesc {
+            optimize: desc.optimize && !opt.no_optimize,
+            ..desc
+        })
+        .collect();
+
+    std::fs::create_dir_all("baked")?;
+
+    let mut cache = if opt.force {
+        BakeCache::empty()
+    } else {
+        BakeCache::load()
+    };
+
+    // Images are de-duplicated across all scenes, so that ones shared
+    // between them are only processed once.
+    let mut unique_images: HashMap<u64, SceneImage> = HashMap::new();
+    let mut baked_scenes = Vec::new();
+    for desc in &scenes {
+        if let Some(mut baked) = bake_scene(desc, &lazy_cache, &mut cache)? {
+            for img in std::mem::take(&mut baked.images) {
+                unique_images.entry(img.image.identity()).or_insert(img);
+            }
+            baked_scenes.push(baked);
         }
+    }
+
+    let (up_to_date_images, dirty_images): (Vec<SceneImage>, Vec<SceneImage>) =
+        unique_images.into_values().partition(|img| {
+            cache.is_image_up_to_date(img.image.identity(), img.cache_entry.content_hash)
+        });
+
+    if !up_to_date_images.is_empty() {
+        println!("{} images are up to date.", up_to_date_images.len());
+    }
+
+    bake_images(
+        dirty_images.iter().map(|img| img.image.clone()).collect(),
+        &lazy_cache,
+    );
+
+    for img in dirty_images {
+        cache.insert_image(img.image.identity(), img.cache_entry);
+    }
 
-        println!("Done.");
+    // After the images, so that a running renderer reloading a scene finds them up to date
+    for baked in baked_scenes {
+        write_baked_asset(&baked.output_path, |file| baked.packed.flatten_into(file))?;
     }
 
+    cache.save()?;
+
+    println!("Done.");
+
     Ok(())
 }
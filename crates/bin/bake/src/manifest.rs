@@ -0,0 +1,147 @@
+use glam::{EulerRot, Quat};
+use kajiya_asset::mesh::{NormalGeneration, PositionQuantization, VertexQuantization};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+
+/// A list of scenes to bake in one go. Can be stored as RON or TOML;
+/// the format is picked based on the file extension.
+#[derive(serde::Deserialize)]
+pub struct BakeManifest {
+    pub scenes: Vec<SceneBakeDesc>,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct SceneBakeDesc {
+    /// Path to the source scene file: glTF (`.gltf`, `.glb`) or Wavefront OBJ (`.obj`)
+    pub scene: PathBuf,
+
+    /// Name of the output file; written to `baked/{output}.scene`
+    pub output: String,
+
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+
+    /// Euler angles in degrees, applied in the Y-X-Z order
+    #[serde(default)]
+    pub rotation: [f32; 3],
+
+    /// Translate the baked mesh so that the center of its bounding box is at the origin
+    #[serde(default)]
+    pub recenter: bool,
+
+    /// Weld vertices, and reorder triangles and vertices for the GPU's caches
+    #[serde(default = "default_optimize")]
+    pub optimize: bool,
+
+    /// Maximum number of simplified LODs generated per mesh, up to 8; 0 disables LOD generation
+    #[serde(default = "default_lod_count")]
+    pub lod_count: usize,
+
+    /// Error budget of the coarsest LOD, relative to the radius of the mesh's bounds
+    #[serde(default = "default_lod_error")]
+    pub lod_error: f32,
+
+    /// Vertex streams to store in compact formats; none by default
+    #[serde(default)]
+    pub quantize: QuantizeDesc,
+
+    /// Normals generated for glTF primitives which don't have any; `flat` or `smooth`
+    #[serde(default)]
+    pub normals: NormalGeneration,
+}
+
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct QuantizeDesc {
+    pub positions: QuantizePositions,
+    /// Half-float UVs
+    pub uvs: bool,
+    /// Octahedral tangents
+    pub tangents: bool,
+    /// RGBA8 colors
+    pub colors: bool,
+}
+
+#[derive(Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuantizePositions {
+    F32,
+    /// Half-floats relative to the center of each mesh's bounds
+    F16,
+    /// 16-bit normalized integers spanning each mesh's bounds
+    Snorm16,
+}
+
+impl Default for QuantizePositions {
+    fn default() -> Self {
+        Self::F32
+    }
+}
+
+impl QuantizeDesc {
+    pub fn all() -> Self {
+        Self {
+            positions: QuantizePositions::Snorm16,
+            uvs: true,
+            tangents: true,
+            colors: true,
+        }
+    }
+
+    pub fn vertex_quantization(&self) -> VertexQuantization {
+        VertexQuantization {
+            positions: match self.positions {
+                QuantizePositions::F32 => PositionQuantization::F32,
+                QuantizePositions::F16 => PositionQuantization::F16,
+                QuantizePositions::Snorm16 => PositionQuantization::Snorm16,
+            },
+            uvs: self.uvs,
+            tangents: self.tangents,
+            colors: self.colors,
+        }
+    }
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_optimize() -> bool {
+    true
+}
+
+fn default_lod_count() -> usize {
+    4
+}
+
+fn default_lod_error() -> f32 {
+    0.01
+}
+
+impl SceneBakeDesc {
+    pub fn rotation_quat(&self) -> Quat {
+        Quat::from_euler(
+            EulerRot::YXZ,
+            self.rotation[1].to_radians(),
+            self.rotation[0].to_radians(),
+            self.rotation[2].to_radians(),
+        )
+    }
+}
+
+impl BakeManifest {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading bake manifest {:?}", path))?;
+
+        let manifest: Self = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents)
+                .with_context(|| format!("Parsing TOML bake manifest {:?}", path))?,
+            _ => ron::de::from_str(&contents)
+                .with_context(|| format!("Parsing RON bake manifest {:?}", path))?,
+        };
+
+        Ok(manifest)
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use kajiya_backend::pack::write_asset_pack;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct PackOpt {
+    /// Asset pack to write
+    #[structopt(short = "o", parse(from_os_str))]
+    output: PathBuf,
+
+    /// Directories to include, as `path` or `name=path`. Their files are stored
+    /// under `name`, which defaults to the last component of `path`.
+    #[structopt(required = true)]
+    dirs: Vec<String>,
+}
+
+fn collect_files(
+    dir: &Path,
+    entry_prefix: &str,
+    files: &mut Vec<(String, PathBuf)>,
+) -> anyhow::Result<()> {
+    for dir_entry in std::fs::read_dir(dir).with_context(|| format!("Reading {:?}", dir))? {
+        let path = dir_entry?.path();
+        let name = path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .with_context(|| format!("{:?} is not UTF-8", path))?;
+        let entry = if entry_prefix.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}/{}", entry_prefix, name)
+        };
+
+        if path.is_dir() {
+            collect_files(&path, &entry, files)?;
+        } else {
+            files.push((entry, path));
+        }
+    }
+
+    Ok(())
+}
+
+/// Packs whole directories, such as `baked` and `assets/shaders`, into a single file
+pub fn pack(opt: &PackOpt) -> anyhow::Result<()> {
+    let mut files: Vec<(String, PathBuf)> = Vec::new();
+
+    for dir in &opt.dirs {
+        let (name, path) = match dir.split_once('=') {
+            Some((name, path)) => (name.trim_matches('/').to_owned(), PathBuf::from(path)),
+            None => {
+                let path = PathBuf::from(dir);
+                let name = path
+                    .file_name()
+                    .with_context(|| format!("Can't name the directory {:?}; use name=path", dir))?
+                    .to_string_lossy()
+                    .into_owned();
+                (name, path)
+            }
+        };
+
+        collect_files(&path, &name, &mut files)?;
+    }
+
+    // Deterministic output for identical inputs
+    files.sort();
+
+    write_asset_pack(&opt.output, &files)?;
+
+    println!("Wrote {} files to {:?}", files.len(), opt.output);
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use kajiya::world_renderer::AddMeshOptions;
+use kajiya::{animation::AnimationPlayer, world_renderer::AddMeshOptions};
 use kajiya_simple::*;
 
 fn main() -> anyhow::Result<()> {
@@ -18,23 +18,26 @@ fn main() -> anyhow::Result<()> {
         ..Default::default()
     };
 
-    let car_mesh = kajiya
-        .world_renderer
-        .add_baked_mesh("/baked/336_lrm.mesh", AddMeshOptions::new())?;
+    let car = kajiya.world_renderer.add_baked_scene(
+        "/baked/336_lrm.scene",
+        AddMeshOptions::new(),
+        Affine3A::IDENTITY,
+    )?;
 
-    let car_inst = kajiya.world_renderer.add_instance(
-        car_mesh,
-        Affine3A::from_rotation_translation(Quat::IDENTITY, Vec3::ZERO),
-    );
+    // Poses the car, and plays its animation if it has one
+    let mut car_anim = AnimationPlayer::new(&car, Affine3A::IDENTITY);
+    if car_anim.clip_count() > 0 {
+        car_anim.play(0);
+    }
 
     let mut car_rot = 0.0f32;
 
     kajiya.run(move |ctx| {
         car_rot += 0.5 * ctx.dt_filtered;
-        ctx.world_renderer.set_instance_transform(
-            car_inst,
-            Affine3A::from_rotation_translation(Quat::from_rotation_y(car_rot), Vec3::ZERO),
-        );
+        car_anim.root_transform =
+            Affine3A::from_rotation_translation(Quat::from_rotation_y(car_rot), Vec3::ZERO);
+        car_anim.advance(ctx.dt_filtered);
+        car_anim.apply(ctx.world_renderer);
 
         WorldFrameDesc {
             camera_matrices: camera.through(&lens),
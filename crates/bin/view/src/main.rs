@@ -2,7 +2,12 @@ use anyhow::Context;
 
 use dolly::prelude::*;
 use imgui::im_str;
-use kajiya::{rg::GraphDebugHook, world_renderer::AddMeshOptions};
+use kajiya::{
+    animation::AnimationPlayer,
+    asset::mesh::SceneLightKind,
+    rg::GraphDebugHook,
+    world_renderer::{AddMeshOptions, PunctualLight, PunctualLightKind},
+};
 use kajiya_simple::*;
 
 use std::fs::File;
@@ -89,6 +94,18 @@ struct PersistedAppState {
     ev_shift: f32,
 }
 
+/// Viewpoint imported from a scene
+struct SceneCamera {
+    name: String,
+    position: Vec3,
+    rotation: Quat,
+    /// In degrees
+    vertical_fov: f32,
+}
+
+/// Size of the emissive shapes imported punctual lights are rendered as
+const PUNCTUAL_LIGHT_RADIUS: f32 = 0.05;
+
 #[derive(PartialEq, Eq)]
 enum LeftClickEditMode {
     MoveSun,
@@ -169,17 +186,84 @@ fn main() -> anyhow::Result<()> {
     let mut light_instances = Vec::new();*/
 
     let mut render_instances = vec![];
+    let mut animation_players = vec![];
+    let mut scene_cameras = vec![];
+    let mut scene_lights = vec![];
     for instance in scene_desc.instances {
-        let mesh = kajiya.world_renderer.add_baked_mesh(
-            format!("/baked/{}.mesh", instance.mesh),
+        let root_transform =
+            Affine3A::from_rotation_translation(Quat::IDENTITY, instance.position.into());
+        let scene = kajiya.world_renderer.add_baked_scene(
+            format!("/baked/{}.scene", instance.mesh),
             AddMeshOptions::new(),
+            root_transform,
         )?;
-        render_instances.push(kajiya.world_renderer.add_instance(
-            mesh,
-            Affine3A::from_rotation_translation(Quat::IDENTITY, instance.position.into()),
-        ));
+
+        // Play the first clip of animated scenes
+        if !scene.scene.animations.is_empty() {
+            let mut player = AnimationPlayer::new(&scene, root_transform);
+            player.play(0);
+            animation_players.push(player);
+        }
+
+        let node_transforms = scene.scene.node_world_transforms();
+
+        for (camera_idx, camera) in scene.scene.cameras.iter().enumerate() {
+            let (_, rotation, position) = (root_transform * node_transforms[camera.node as usize])
+                .to_scale_rotation_translation();
+
+            scene_cameras.push(SceneCamera {
+                name: format!("{}: {}", instance.mesh, scene.scene.camera_name(camera_idx)),
+                position,
+                rotation,
+                vertical_fov: camera.vertical_fov.to_degrees(),
+            });
+        }
+
+        for (light_idx, light) in scene.scene.lights.iter().enumerate() {
+            let kind = match light.kind {
+                SceneLightKind::POINT => PunctualLightKind::Point,
+                SceneLightKind::SPOT => PunctualLightKind::Spot,
+                _ => {
+                    log::info!(
+                        "Ignoring directional light {:?}; the sun is controlled by the viewer",
+                        scene.scene.light_name(light_idx)
+                    );
+                    continue;
+                }
+            };
+
+            // Triangle lights have neither, so the light reaches further than authored
+            if kind == PunctualLightKind::Spot {
+                log::warn!(
+                    "Spot light {:?} emits in a cosine lobe; its cone angles are not supported",
+                    scene.scene.light_name(light_idx)
+                );
+            }
+            if light.range > 0.0 {
+                log::warn!(
+                    "Light {:?} is not limited to its range of {}",
+                    scene.scene.light_name(light_idx),
+                    light.range
+                );
+            }
+
+            let xform = root_transform * node_transforms[light.node as usize];
+            scene_lights.push(PunctualLight {
+                kind,
+                position: xform.translation.into(),
+                direction: xform.transform_vector3(-Vec3::Z).normalize(),
+                intensity: Vec3::from(light.color) * light.intensity,
+                radius: PUNCTUAL_LIGHT_RADIUS,
+            });
+        }
+
+        render_instances.extend(scene.instances.into_iter().map(|inst| inst.instance));
     }
 
+    // Index into `scene_cameras`, plus one; zero for the free camera
+    let mut selected_camera = 0usize;
+    let mut animation_speed = 1.0f32;
+
     /*let car_mesh = kajiya
         .world_renderer
         .add_baked_mesh("/baked/336_lrm.mesh")?;
@@ -267,6 +351,19 @@ fn main() -> anyhow::Result<()> {
                 Quat::from_rotation_y(car_rot),
             );*/
 
+            for player in &mut animation_players {
+                player.advance(ctx.dt_filtered * animation_speed);
+                player.apply(ctx.world_renderer);
+            }
+
+            ctx.world_renderer.punctual_lights = scene_lights
+                .iter()
+                .map(|light| PunctualLight {
+                    intensity: light.intensity * state.lights.multiplier,
+                    ..*light
+                })
+                .collect();
+
             for inst in &render_instances {
                 ctx.world_renderer
                     .get_instance_dynamic_parameters_mut(*inst)
@@ -421,6 +518,52 @@ fn main() -> anyhow::Result<()> {
                             .speed(0.02)
                             .build(ui, &mut ctx.world_renderer.sun_size_multiplier);
 
+                        imgui::Drag::<f32>::new(im_str!("LOD error (pixels)"))
+                            .range(0.0..=16.0)
+                            .speed(0.05)
+                            .build(ui, &mut ctx.world_renderer.lod_max_error_pixels);
+
+                        imgui::Drag::<f32>::new(im_str!("GI LOD error (pixels)"))
+                            .range(0.0..=64.0)
+                            .speed(0.1)
+                            .build(ui, &mut ctx.world_renderer.gi_lod_max_error_pixels);
+
+                        if !scene_cameras.is_empty() {
+                            let camera_names: Vec<imgui::ImString> =
+                                std::iter::once(imgui::ImString::new("Free"))
+                                    .chain(
+                                        scene_cameras
+                                            .iter()
+                                            .map(|camera| imgui::ImString::new(&camera.name)),
+                                    )
+                                    .collect();
+                            let camera_names: Vec<&imgui::ImStr> = camera_names
+                                .iter()
+                                .map(|name| -> &imgui::ImStr { name })
+                                .collect();
+
+                            if imgui::ComboBox::new(im_str!("Camera")).build_simple_string(
+                                ui,
+                                &mut selected_camera,
+                                &camera_names,
+                            ) && selected_camera > 0
+                            {
+                                let scene_camera = &scene_cameras[selected_camera - 1];
+                                camera.driver_mut::<Position>().position = scene_camera.position;
+                                camera
+                                    .driver_mut::<YawPitch>()
+                                    .set_rotation_quat(scene_camera.rotation);
+                                state.vertical_fov = scene_camera.vertical_fov;
+                            }
+                        }
+
+                        if !animation_players.is_empty() {
+                            imgui::Drag::<f32>::new(im_str!("Animation speed"))
+                                .range(-4.0..=4.0)
+                                .speed(0.01)
+                                .build(ui, &mut animation_speed);
+                        }
+
                         /*if ui.radio_button_bool(
                             im_str!("Move sun"),
                             left_click_edit_mode == LeftClickEditMode::MoveSun,
@@ -0,0 +1,611 @@
+// Block compression encoders for the formats emitted by the asset pipeline.
+//
+// These favor simplicity over quality: endpoints are fit along the principal axis
+// of each block, and every texel picks the nearest palette entry. BC7 only uses mode 6
+// (a single subset with RGBA endpoints), which handles most content reasonably well.
+//
+// The decoders are for inspecting baked images, and cover the same subset of the formats.
+
+type Block = [[u8; 4]; 16];
+
+/// Gathers 4x4 blocks of an RGBA8 image, replicating edge texels for partial blocks,
+/// and concatenates the results of `encode` for each.
+fn compress_blocks<const BLOCK_BYTES: usize>(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    encode: impl Fn(&Block) -> [u8; BLOCK_BYTES],
+) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    assert_eq!(rgba.len(), width * height * 4);
+
+    let blocks_x = (width + 3) / 4;
+    let blocks_y = (height + 3) / 4;
+    let mut result = Vec::with_capacity(blocks_x * blocks_y * BLOCK_BYTES);
+
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let mut block: Block = [[0; 4]; 16];
+            for (i, texel) in block.iter_mut().enumerate() {
+                let x = (bx * 4 + i % 4).min(width - 1);
+                let y = (by * 4 + i / 4).min(height - 1);
+                let offset = (y * width + x) * 4;
+                texel.copy_from_slice(&rgba[offset..offset + 4]);
+            }
+
+            result.extend_from_slice(&encode(&block));
+        }
+    }
+
+    result
+}
+
+pub fn compress_bc1(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    compress_blocks(rgba, width, height, encode_bc1_block)
+}
+
+pub fn compress_bc3(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    compress_blocks(rgba, width, height, |block| {
+        let mut res = [0u8; 16];
+        res[0..8].copy_from_slice(&encode_bc4_block(block, 3));
+        res[8..16].copy_from_slice(&encode_bc1_block(block));
+        res
+    })
+}
+
+/// Compresses the red channel
+pub fn compress_bc4(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    compress_blocks(rgba, width, height, |block| encode_bc4_block(block, 0))
+}
+
+/// Compresses the red and green channels
+pub fn compress_bc5(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    compress_blocks(rgba, width, height, |block| {
+        let mut res = [0u8; 16];
+        res[0..8].copy_from_slice(&encode_bc4_block(block, 0));
+        res[8..16].copy_from_slice(&encode_bc4_block(block, 1));
+        res
+    })
+}
+
+pub fn compress_bc7(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    compress_blocks(rgba, width, height, encode_bc7_mode6_block)
+}
+
+/// Inverse of `compress_blocks`: decodes every block of `data` with `decode`, and scatters
+/// the texels into an RGBA8 image, dropping the ones past the edges of partial blocks.
+fn decompress_blocks<const BLOCK_BYTES: usize>(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    decode: impl Fn(&[u8; BLOCK_BYTES]) -> Option<Block>,
+) -> Option<Vec<u8>> {
+    let (width, height) = (width as usize, height as usize);
+
+    let blocks_x = (width + 3) / 4;
+    let blocks_y = (height + 3) / 4;
+    if data.len() != blocks_x * blocks_y * BLOCK_BYTES {
+        return None;
+    }
+
+    let mut result = vec![0u8; width * height * 4];
+
+    for (block_idx, block) in data.chunks_exact(BLOCK_BYTES).enumerate() {
+        let (bx, by) = (block_idx % blocks_x, block_idx / blocks_x);
+        let block = decode(block.try_into().unwrap())?;
+
+        for (i, texel) in block.iter().enumerate() {
+            let x = bx * 4 + i % 4;
+            let y = by * 4 + i / 4;
+            if x < width && y < height {
+                let offset = (y * width + x) * 4;
+                result[offset..offset + 4].copy_from_slice(texel);
+            }
+        }
+    }
+
+    Some(result)
+}
+
+pub fn decompress_bc1(data: &[u8], width: u32, height: u32) -> Option<Vec<u8>> {
+    decompress_blocks(data, width, height, |block: &[u8; 8]| {
+        Some(decode_bc1_block(block))
+    })
+}
+
+pub fn decompress_bc3(data: &[u8], width: u32, height: u32) -> Option<Vec<u8>> {
+    decompress_blocks(data, width, height, |block: &[u8; 16]| {
+        let mut res = decode_bc1_block(block[8..16].try_into().unwrap());
+        decode_bc4_block(block[0..8].try_into().unwrap(), 3, &mut res);
+        Some(res)
+    })
+}
+
+/// Decodes into the red channel, with green and blue zero
+pub fn decompress_bc4(data: &[u8], width: u32, height: u32) -> Option<Vec<u8>> {
+    decompress_blocks(data, width, height, |block: &[u8; 8]| {
+        let mut res: Block = [[0, 0, 0, 255]; 16];
+        decode_bc4_block(block, 0, &mut res);
+        Some(res)
+    })
+}
+
+/// Decodes into the red and green channels, with blue zero
+pub fn decompress_bc5(data: &[u8], width: u32, height: u32) -> Option<Vec<u8>> {
+    decompress_blocks(data, width, height, |block: &[u8; 16]| {
+        let mut res: Block = [[0, 0, 0, 255]; 16];
+        decode_bc4_block(block[0..8].try_into().unwrap(), 0, &mut res);
+        decode_bc4_block(block[8..16].try_into().unwrap(), 1, &mut res);
+        Some(res)
+    })
+}
+
+/// Returns `None` if any block uses a mode other than 6
+pub fn decompress_bc7(data: &[u8], width: u32, height: u32) -> Option<Vec<u8>> {
+    decompress_blocks(data, width, height, decode_bc7_mode6_block)
+}
+
+/// Returns the mean of the block, and its principal axis (not normalized) over the first `N` channels.
+fn principal_axis<const N: usize>(block: &Block) -> ([f32; N], [f32; N]) {
+    let mut mean = [0.0f32; N];
+    for texel in block {
+        for c in 0..N {
+            mean[c] += texel[c] as f32 / 16.0;
+        }
+    }
+
+    let mut cov = [[0.0f32; N]; N];
+    for texel in block {
+        for i in 0..N {
+            for j in 0..N {
+                cov[i][j] += (texel[i] as f32 - mean[i]) * (texel[j] as f32 - mean[j]);
+            }
+        }
+    }
+
+    // Power iteration, starting from the diagonal
+    let mut axis = [1.0f32; N];
+    for _ in 0..8 {
+        let mut next = [0.0f32; N];
+        for i in 0..N {
+            for j in 0..N {
+                next[i] += cov[i][j] * axis[j];
+            }
+        }
+
+        let max = next.iter().fold(0.0f32, |a, &b| a.max(b.abs()));
+        if max < 1e-8 {
+            break;
+        }
+
+        for (a, n) in axis.iter_mut().zip(next) {
+            *a = n / max;
+        }
+    }
+
+    (mean, axis)
+}
+
+/// Finds endpoints at the extremes of the block's projection onto its principal axis
+fn fit_endpoints<const N: usize>(block: &Block) -> ([f32; N], [f32; N]) {
+    let (mean, axis) = principal_axis::<N>(block);
+    let axis_len_sq: f32 = axis.iter().map(|a| a * a).sum();
+
+    if axis_len_sq < 1e-8 {
+        return (mean, mean);
+    }
+
+    let (mut t_min, mut t_max) = (f32::MAX, f32::MIN);
+    for texel in block {
+        let t: f32 = (0..N)
+            .map(|c| (texel[c] as f32 - mean[c]) * axis[c])
+            .sum::<f32>()
+            / axis_len_sq;
+        t_min = t_min.min(t);
+        t_max = t_max.max(t);
+    }
+
+    let mut e0 = [0.0f32; N];
+    let mut e1 = [0.0f32; N];
+    for c in 0..N {
+        e0[c] = (mean[c] + axis[c] * t_max).clamp(0.0, 255.0);
+        e1[c] = (mean[c] + axis[c] * t_min).clamp(0.0, 255.0);
+    }
+
+    (e0, e1)
+}
+
+fn nearest_palette_index<const N: usize>(texel: &[u8; 4], palette: &[[i32; N]]) -> usize {
+    let mut best = 0;
+    let mut best_dist = i32::MAX;
+
+    for (i, entry) in palette.iter().enumerate() {
+        let dist: i32 = (0..N)
+            .map(|c| {
+                let d = texel[c] as i32 - entry[c];
+                d * d
+            })
+            .sum();
+
+        if dist < best_dist {
+            best_dist = dist;
+            best = i;
+        }
+    }
+
+    best
+}
+
+fn pack_565(c: [f32; 3]) -> u16 {
+    let r = (c[0] * 31.0 / 255.0).round() as u16;
+    let g = (c[1] * 63.0 / 255.0).round() as u16;
+    let b = (c[2] * 31.0 / 255.0).round() as u16;
+    (r << 11) | (g << 5) | b
+}
+
+fn unpack_565(c: u16) -> [i32; 3] {
+    let r = ((c >> 11) & 31) as i32;
+    let g = ((c >> 5) & 63) as i32;
+    let b = (c & 31) as i32;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+fn encode_bc1_block(block: &Block) -> [u8; 8] {
+    let (e0, e1) = fit_endpoints::<3>(block);
+    let mut c0 = pack_565(e0);
+    let mut c1 = pack_565(e1);
+
+    // Four-color mode requires c0 > c1
+    if c0 < c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+
+    let mut indices = 0u32;
+
+    if c0 != c1 {
+        let p0 = unpack_565(c0);
+        let p1 = unpack_565(c1);
+        let mut palette = [p0, p1, [0; 3], [0; 3]];
+        for c in 0..3 {
+            palette[2][c] = (2 * p0[c] + p1[c]) / 3;
+            palette[3][c] = (p0[c] + 2 * p1[c]) / 3;
+        }
+
+        for (i, texel) in block.iter().enumerate() {
+            indices |= (nearest_palette_index(texel, &palette) as u32) << (i * 2);
+        }
+    }
+
+    let mut res = [0u8; 8];
+    res[0..2].copy_from_slice(&c0.to_le_bytes());
+    res[2..4].copy_from_slice(&c1.to_le_bytes());
+    res[4..8].copy_from_slice(&indices.to_le_bytes());
+    res
+}
+
+fn decode_bc1_block(block: &[u8; 8]) -> Block {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+
+    let p0 = unpack_565(c0);
+    let p1 = unpack_565(c1);
+    let mut palette = [p0, p1, [0; 3], [0; 3]];
+    for c in 0..3 {
+        if c0 > c1 {
+            palette[2][c] = (2 * p0[c] + p1[c]) / 3;
+            palette[3][c] = (p0[c] + 2 * p1[c]) / 3;
+        } else {
+            palette[2][c] = (p0[c] + p1[c]) / 2;
+        }
+    }
+
+    let mut res: Block = [[0; 4]; 16];
+    for (i, texel) in res.iter_mut().enumerate() {
+        let index = ((indices >> (i * 2)) & 3) as usize;
+        let [r, g, b] = palette[index];
+        // The three-color mode uses the last entry for transparent black
+        let a = if c0 <= c1 && index == 3 { 0 } else { 255 };
+        *texel = [r as u8, g as u8, b as u8, a];
+    }
+    res
+}
+
+/// Decodes one channel of the block, leaving the others as they are
+fn decode_bc4_block(block: &[u8; 8], channel: usize, res: &mut Block) {
+    let (e0, e1) = (block[0] as i32, block[1] as i32);
+    let mut index_bytes = [0u8; 8];
+    index_bytes[0..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(index_bytes);
+
+    let mut palette = [e0, e1, 0, 0, 0, 0, 0, 0];
+    if e0 > e1 {
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as i32) * e0 + i as i32 * e1 + 3) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((5 - i as i32) * e0 + i as i32 * e1 + 2) / 5;
+        }
+        palette[7] = 255;
+    }
+
+    for (i, texel) in res.iter_mut().enumerate() {
+        texel[channel] = palette[((indices >> (i * 3)) & 7) as usize] as u8;
+    }
+}
+
+/// Encodes one channel of the block using the eight-value BC4 mode
+fn encode_bc4_block(block: &Block, channel: usize) -> [u8; 8] {
+    let e0 = block.iter().map(|t| t[channel]).max().unwrap();
+    let e1 = block.iter().map(|t| t[channel]).min().unwrap();
+
+    let mut indices = 0u64;
+
+    if e0 != e1 {
+        let (e0, e1) = (e0 as i32, e1 as i32);
+        let mut palette = [[0i32; 1]; 8];
+        palette[0][0] = e0;
+        palette[1][0] = e1;
+        for i in 1..7 {
+            palette[i + 1][0] = ((7 - i as i32) * e0 + i as i32 * e1 + 3) / 7;
+        }
+
+        for (i, texel) in block.iter().enumerate() {
+            let value = [texel[channel], 0, 0, 0];
+            indices |= (nearest_palette_index(&value, &palette) as u64) << (i * 3);
+        }
+    }
+
+    let mut res = [0u8; 8];
+    res[0] = e0;
+    res[1] = e1;
+    res[2..8].copy_from_slice(&indices.to_le_bytes()[0..6]);
+    res
+}
+
+struct BitWriter {
+    bits: u128,
+    offset: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bit_count: u32) {
+        self.bits |= ((value & ((1 << bit_count) - 1)) as u128) << self.offset;
+        self.offset += bit_count;
+    }
+}
+
+struct BitReader {
+    bits: u128,
+    offset: u32,
+}
+
+impl BitReader {
+    fn read(&mut self, bit_count: u32) -> u32 {
+        let value = (self.bits >> self.offset) as u32 & ((1 << bit_count) - 1);
+        self.offset += bit_count;
+        value
+    }
+}
+
+fn decode_bc7_mode6_block(block: &[u8; 16]) -> Option<Block> {
+    const WEIGHTS: [i32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+    let mut reader = BitReader {
+        bits: u128::from_le_bytes(*block),
+        offset: 0,
+    };
+
+    if reader.read(7) != 1 << 6 {
+        return None;
+    }
+
+    let mut q0 = [0u32; 4];
+    let mut q1 = [0u32; 4];
+    for c in 0..4 {
+        q0[c] = reader.read(7);
+        q1[c] = reader.read(7);
+    }
+
+    let p0 = reader.read(1);
+    let p1 = reader.read(1);
+
+    let mut res: Block = [[0; 4]; 16];
+    for (i, texel) in res.iter_mut().enumerate() {
+        let w = WEIGHTS[reader.read(if i == 0 { 3 } else { 4 }) as usize];
+        for c in 0..4 {
+            let a = ((q0[c] << 1) | p0) as i32;
+            let b = ((q1[c] << 1) | p1) as i32;
+            texel[c] = (((64 - w) * a + w * b + 32) >> 6) as u8;
+        }
+    }
+
+    Some(res)
+}
+
+/// Quantizes an endpoint to 7 bits per channel plus a shared p-bit, picking the p-bit with lower error
+fn quantize_bc7_mode6_endpoint(e: [f32; 4]) -> ([u8; 4], u8) {
+    let mut best = ([0u8; 4], 0u8);
+    let mut best_err = f32::MAX;
+
+    for p in 0..2u8 {
+        let mut q = [0u8; 4];
+        let mut err = 0.0;
+        for c in 0..4 {
+            q[c] = ((e[c] - p as f32) / 2.0).round().clamp(0.0, 127.0) as u8;
+            let d = ((q[c] << 1) | p) as f32 - e[c];
+            err += d * d;
+        }
+
+        if err < best_err {
+            best_err = err;
+            best = (q, p);
+        }
+    }
+
+    best
+}
+
+fn encode_bc7_mode6_block(block: &Block) -> [u8; 16] {
+    const WEIGHTS: [i32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+    let (e0, e1) = fit_endpoints::<4>(block);
+    let (mut q0, mut p0) = quantize_bc7_mode6_endpoint(e0);
+    let (mut q1, mut p1) = quantize_bc7_mode6_endpoint(e1);
+
+    let palette = |q0: [u8; 4], p0: u8, q1: [u8; 4], p1: u8| {
+        let mut palette = [[0i32; 4]; 16];
+        for (entry, w) in palette.iter_mut().zip(WEIGHTS) {
+            for c in 0..4 {
+                let a = ((q0[c] << 1) | p0) as i32;
+                let b = ((q1[c] << 1) | p1) as i32;
+                entry[c] = ((64 - w) * a + w * b + 32) >> 6;
+            }
+        }
+        palette
+    };
+
+    let mut indices = [0u32; 16];
+    {
+        let palette = palette(q0, p0, q1, p1);
+        for (index, texel) in indices.iter_mut().zip(block) {
+            *index = nearest_palette_index(texel, &palette) as u32;
+        }
+    }
+
+    // The anchor index is stored with its most significant bit implicitly zero
+    if indices[0] >= 8 {
+        std::mem::swap(&mut q0, &mut q1);
+        std::mem::swap(&mut p0, &mut p1);
+        for index in &mut indices {
+            *index = 15 - *index;
+        }
+    }
+
+    let mut writer = BitWriter { bits: 0, offset: 0 };
+
+    // Mode 6: six zero bits followed by a one
+    writer.write(1 << 6, 7);
+
+    for c in 0..4 {
+        writer.write(q0[c] as u32, 7);
+        writer.write(q1[c] as u32, 7);
+    }
+
+    writer.write(p0 as u32, 1);
+    writer.write(p1 as u32, 1);
+
+    writer.write(indices[0], 3);
+    for &index in &indices[1..] {
+        writer.write(index, 4);
+    }
+
+    debug_assert_eq!(writer.offset, 128);
+    writer.bits.to_le_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Codec = (
+        fn(&[u8], u32, u32) -> Vec<u8>,
+        fn(&[u8], u32, u32) -> Option<Vec<u8>>,
+    );
+
+    // Smooth, in all four channels, so that every format can get close. The slope doesn't
+    // depend on the size, so that small images aren't harder than large ones.
+    fn gradient_image(width: u32, height: u32) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| {
+                (0..width).flat_map(move |x| {
+                    let (u, v) = (x * 8, y * 6);
+                    [u, v, (u + v) / 2, 255 - u / 2].map(|c| c as u8)
+                })
+            })
+            .collect()
+    }
+
+    // Largest difference in each channel; `channels` limits the comparison to the first few
+    fn max_error(a: &[u8], b: &[u8], channels: usize) -> [u8; 4] {
+        let mut res = [0u8; 4];
+        for (a, b) in a.chunks_exact(4).zip(b.chunks_exact(4)) {
+            for c in 0..channels {
+                res[c] = res[c].max(a[c].abs_diff(b[c]));
+            }
+        }
+        res
+    }
+
+    fn round_trip((compress, decompress): Codec, rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let compressed = compress(rgba, width, height);
+        let decoded = decompress(&compressed, width, height).expect("valid blocks");
+        assert_eq!(decoded.len(), rgba.len());
+        decoded
+    }
+
+    #[test]
+    fn round_trips_stay_within_error_bounds() {
+        // Channels compared, and the largest error allowed in them
+        let formats: [(&str, Codec, usize, u8); 5] = [
+            ("BC1", (compress_bc1, decompress_bc1), 3, 16),
+            ("BC3", (compress_bc3, decompress_bc3), 4, 16),
+            ("BC4", (compress_bc4, decompress_bc4), 1, 2),
+            ("BC5", (compress_bc5, decompress_bc5), 2, 2),
+            ("BC7", (compress_bc7, decompress_bc7), 4, 12),
+        ];
+
+        // Whole blocks, partial ones at the edges, and images smaller than a block
+        for [width, height] in [[16, 8], [13, 7], [5, 3], [1, 1]] {
+            let image = gradient_image(width, height);
+
+            for (name, codec, channels, bound) in formats {
+                let decoded = round_trip(codec, &image, width, height);
+                let error = max_error(&image, &decoded, channels);
+                assert!(
+                    error.iter().all(|&e| e <= bound),
+                    "{} {}x{}: error {:?}",
+                    name,
+                    width,
+                    height,
+                    error
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn uniform_blocks_are_exact() {
+        let image: Vec<u8> = [200, 100, 50, 255].repeat(6 * 5);
+
+        // The single-channel formats store 8 bits, and BC1 colors are representable in 565
+        let bc4 = round_trip((compress_bc4, decompress_bc4), &image, 6, 5);
+        assert!(bc4.chunks_exact(4).all(|t| t == [200, 0, 0, 255]));
+
+        let bc5 = round_trip((compress_bc5, decompress_bc5), &image, 6, 5);
+        assert!(bc5.chunks_exact(4).all(|t| t == [200, 100, 0, 255]));
+
+        let opaque_565: Vec<u8> = [255, 0, 255, 255].repeat(6 * 5);
+        let bc1 = round_trip((compress_bc1, decompress_bc1), &opaque_565, 6, 5);
+        assert_eq!(bc1, opaque_565);
+    }
+
+    #[test]
+    fn truncated_data_is_rejected() {
+        let image = gradient_image(5, 3);
+
+        // Two blocks across, one down
+        let bc1 = compress_bc1(&image, 5, 3);
+        assert_eq!(bc1.len(), 2 * 8);
+        assert!(decompress_bc1(&bc1[..8], 5, 3).is_none());
+
+        let bc7 = compress_bc7(&image, 5, 3);
+        assert_eq!(bc7.len(), 2 * 16);
+        assert!(decompress_bc7(&bc7[..16], 5, 3).is_none());
+    }
+}
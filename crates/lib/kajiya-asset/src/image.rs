@@ -1,17 +1,241 @@
-use std::path::PathBuf;
+use std::{
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    sync::Arc,
+};
 
+use anyhow::Context as _;
 use bytes::Bytes;
-use image::{imageops::FilterType, DynamicImage, GenericImageView as _};
-use kajiya_backend::{ash::vk, file::LoadFile, ImageDesc};
+use image::{imageops::FilterType, ColorType, GenericImageView as _, ImageFormat, Rgba};
+use kajiya_backend::{ash::vk, file::LoadFile};
 use turbosloth::*;
 
+use crate::{
+    import_obj::phong_exponent_to_roughness,
+    mesh::{TexChannels, TexCompressionMode, TexGamma, TexParams},
+    mips::{to_linear_image, LinearImage},
+};
+
 #[derive(Clone, Hash, PartialEq, Eq)]
 pub enum ImageSource {
     File(PathBuf),
     Memory(Bytes),
+    Converted(Box<ImageConversion>),
+}
+
+impl ImageSource {
+    /// Files the image is loaded from; none for images in memory
+    pub fn source_files(&self) -> Vec<PathBuf> {
+        match self {
+            ImageSource::File(path) => vec![path.clone()],
+            ImageSource::Memory(_) => Vec::new(),
+            ImageSource::Converted(conversion) => conversion
+                .sources()
+                .into_iter()
+                .flat_map(ImageSource::source_files)
+                .collect(),
+        }
+    }
+}
+
+/// `f32` parameter of an `ImageConversion`, hashed and compared by its bits
+#[derive(Debug, Clone, Copy)]
+pub struct ConversionParam(pub f32);
+
+impl Hash for ConversionParam {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl PartialEq for ConversionParam {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for ConversionParam {}
+
+/// Images computed from other images, for maps which formats such as OBJ store differently
+/// from what materials expect. The sources are read as linear values.
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub enum ImageConversion {
+    /// Color from `color`, or white without one, and alpha from the red channel of `alpha`
+    ReplaceAlpha {
+        color: Option<ImageSource>,
+        alpha: ImageSource,
+    },
+    /// Tangent-space normals from the slopes of a height map in its red channel.
+    /// Heights are measured in texels, after multiplying them by `scale`.
+    HeightToNormal {
+        height: ImageSource,
+        scale: ConversionParam,
+    },
+    /// The layout of metalness-roughness maps, from Phong specular maps: the largest channel
+    /// of `specular` in red, roughness from the exponents in the red channel of `exponent`
+    /// multiplied by `max_exponent` in green, and full metalness in blue. Missing maps leave
+    /// their channel at one.
+    PhongToMetalnessRoughness {
+        specular: Option<ImageSource>,
+        exponent: Option<ImageSource>,
+        max_exponent: ConversionParam,
+    },
+}
+
+impl ImageConversion {
+    fn sources(&self) -> Vec<&ImageSource> {
+        match self {
+            ImageConversion::ReplaceAlpha { color, alpha } => {
+                color.iter().chain(std::iter::once(alpha)).collect()
+            }
+            ImageConversion::HeightToNormal { height, .. } => vec![height],
+            ImageConversion::PhongToMetalnessRoughness {
+                specular, exponent, ..
+            } => specular.iter().chain(exponent).collect(),
+        }
+    }
+
+    async fn run(self, ctx: &RunContext) -> anyhow::Result<RawImage> {
+        let load = |source: &ImageSource| -> anyhow::Result<Lazy<RawImage>> {
+            Ok(LoadImage::new(source)?.into_lazy())
+        };
+        let linear = |image: Arc<RawImage>| to_linear_image(&image, TexGamma::Linear);
+
+        let image = match self {
+            ImageConversion::ReplaceAlpha { color, alpha } => {
+                let alpha = linear(load(&alpha)?.eval(ctx).await?);
+                let mut image = match color {
+                    Some(color) => linear(load(&color)?.eval(ctx).await?),
+                    None => LinearImage::from_pixel(alpha.width(), alpha.height(), Rgba([1.0; 4])),
+                };
+
+                let alpha = resize_to_match(alpha, &image);
+                for (px, alpha) in image.pixels_mut().zip(alpha.pixels()) {
+                    px[3] = alpha[0];
+                }
+
+                image
+            }
+            ImageConversion::HeightToNormal { height, scale } => {
+                let height = linear(load(&height)?.eval(ctx).await?);
+                height_to_normal(&height, scale.0)
+            }
+            ImageConversion::PhongToMetalnessRoughness {
+                specular,
+                exponent,
+                max_exponent,
+            } => {
+                let specular = match specular {
+                    Some(specular) => Some(linear(load(&specular)?.eval(ctx).await?)),
+                    None => None,
+                };
+                let exponent = match exponent {
+                    Some(exponent) => Some(linear(load(&exponent)?.eval(ctx).await?)),
+                    None => None,
+                };
+
+                let (width, height) = exponent
+                    .as_ref()
+                    .or(specular.as_ref())
+                    .context("Phong conversion without any maps")?
+                    .dimensions();
+                let mut image = LinearImage::from_pixel(width, height, Rgba([1.0; 4]));
+
+                if let Some(specular) = specular {
+                    let specular = resize_to_match(specular, &image);
+                    for (px, spec) in image.pixels_mut().zip(specular.pixels()) {
+                        px[0] = spec[0].max(spec[1]).max(spec[2]);
+                    }
+                }
+
+                if let Some(exponent) = exponent {
+                    for (px, exp) in image.pixels_mut().zip(exponent.pixels()) {
+                        px[1] = phong_exponent_to_roughness(exp[0] * max_exponent.0);
+                    }
+                }
+
+                image
+            }
+        };
+
+        Ok(RawImage {
+            format: RawImageFormat::Rgba8,
+            data: image
+                .as_raw()
+                .iter()
+                .map(|&v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+                .collect::<Vec<u8>>()
+                .into(),
+            dimensions: [image.width(), image.height()],
+        })
+    }
+}
+
+fn resize_to_match(image: LinearImage, reference: &LinearImage) -> LinearImage {
+    if image.dimensions() == reference.dimensions() {
+        image
+    } else {
+        image::imageops::resize(
+            &image,
+            reference.width(),
+            reference.height(),
+            FilterType::Triangle,
+        )
+    }
+}
+
+/// Normals with `+Y` pointing up the image, as in glTF. Height maps tile, so slopes wrap around the edges.
+fn height_to_normal(height: &LinearImage, scale: f32) -> LinearImage {
+    let (width, height_px) = height.dimensions();
+    let at = |x: i64, y: i64| {
+        height.get_pixel(
+            x.rem_euclid(width as i64) as u32,
+            y.rem_euclid(height_px as i64) as u32,
+        )[0] * scale
+    };
+
+    LinearImage::from_fn(width, height_px, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        let dx = (at(x + 1, y) - at(x - 1, y)) * 0.5;
+        // Rows go down the image
+        let dy = (at(x, y + 1) - at(x, y - 1)) * 0.5;
+
+        let n = [-dx, dy, 1.0];
+        let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        Rgba([
+            n[0] / len * 0.5 + 0.5,
+            n[1] / len * 0.5 + 0.5,
+            n[2] / len * 0.5 + 0.5,
+            1.0,
+        ])
+    })
 }
 
-pub struct RawRgba8Image {
+/// Layout of the pixel data in a `RawImage`. Components are stored in native endianness.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum RawImageFormat {
+    Rgba8,
+    /// 16-bit unsigned normalized
+    Rgba16,
+    /// Linear floating point, e.g. from HDR files
+    Rgba32F,
+    /// 16-bit unsigned normalized, single channel
+    R16,
+}
+
+impl RawImageFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            RawImageFormat::Rgba8 => 4,
+            RawImageFormat::Rgba16 => 8,
+            RawImageFormat::Rgba32F => 16,
+            RawImageFormat::R16 => 2,
+        }
+    }
+}
+
+pub struct RawImage {
+    pub format: RawImageFormat,
     pub data: Bytes,
     pub dimensions: [u32; 2],
 }
@@ -20,6 +244,7 @@ pub struct RawRgba8Image {
 pub enum LoadImage {
     Lazy(Lazy<Bytes>),
     Immediate(Bytes),
+    Converted(ImageConversion),
 }
 
 impl LoadImage {
@@ -31,34 +256,81 @@ impl LoadImage {
         match source {
             ImageSource::File(path) => Ok(Self::Lazy(LoadFile::new(path)?.into_lazy())),
             ImageSource::Memory(bytes) => Ok(Self::Immediate(bytes.clone())),
+            ImageSource::Converted(conversion) => Ok(Self::Converted((**conversion).clone())),
         }
     }
 }
 
 #[async_trait]
 impl LazyWorker for LoadImage {
-    type Output = anyhow::Result<RawRgba8Image>;
+    type Output = anyhow::Result<RawImage>;
 
     async fn run(self, ctx: RunContext) -> Self::Output {
         let bytes: Bytes = match self {
             // Note: `Bytes` does internal reference counting, so this clone is cheap
             LoadImage::Lazy(bytes) => Bytes::clone(bytes.eval(&ctx).await?.as_ref()),
             LoadImage::Immediate(bytes) => bytes,
+            LoadImage::Converted(conversion) => return conversion.run(&ctx).await,
         };
 
+        // `load_from_memory` would tonemap HDR images down to 8 bits per channel
+        if image::guess_format(&bytes)? == ImageFormat::Hdr {
+            return load_hdr_image(&bytes);
+        }
+
         let image = image::load_from_memory(&bytes)?;
         let image_dimensions = image.dimensions();
         log::info!("Loaded image: {:?} {:?}", image_dimensions, image.color());
 
-        let image = image.to_rgba8();
+        let (format, data): (_, Vec<u8>) = match image.color() {
+            ColorType::L16 => (
+                RawImageFormat::R16,
+                image
+                    .to_luma16()
+                    .into_raw()
+                    .into_iter()
+                    .flat_map(u16::to_ne_bytes)
+                    .collect(),
+            ),
+            ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => (
+                RawImageFormat::Rgba16,
+                image
+                    .to_rgba16()
+                    .into_raw()
+                    .into_iter()
+                    .flat_map(u16::to_ne_bytes)
+                    .collect(),
+            ),
+            _ => (RawImageFormat::Rgba8, image.to_rgba8().into_raw()),
+        };
 
-        Ok(RawRgba8Image {
-            data: image.into_raw().into(),
+        Ok(RawImage {
+            format,
+            data: data.into(),
             dimensions: [image_dimensions.0, image_dimensions.1],
         })
     }
 }
 
+fn load_hdr_image(bytes: &[u8]) -> anyhow::Result<RawImage> {
+    let decoder = image::codecs::hdr::HdrDecoder::new(std::io::Cursor::new(bytes))?;
+    let metadata = decoder.metadata();
+    log::info!("Loaded image: {:?} HDR", (metadata.width, metadata.height));
+
+    let data: Vec<u8> = decoder
+        .read_image_hdr()?
+        .into_iter()
+        .flat_map(|px| [px[0], px[1], px[2], 1.0])
+        .flat_map(f32::to_ne_bytes)
+        .collect();
+
+    Ok(RawImage {
+        format: RawImageFormat::Rgba32F,
+        data: data.into(),
+        dimensions: [metadata.width, metadata.height],
+    })
+}
+
 #[derive(Clone, Hash)]
 pub struct CreatePlaceholderImage {
     values: [u8; 4],
@@ -72,20 +344,183 @@ impl CreatePlaceholderImage {
 
 #[async_trait]
 impl LazyWorker for CreatePlaceholderImage {
-    type Output = anyhow::Result<RawRgba8Image>;
+    type Output = anyhow::Result<RawImage>;
 
     async fn run(self, _ctx: RunContext) -> Self::Output {
-        Ok(RawRgba8Image {
+        Ok(RawImage {
+            format: RawImageFormat::Rgba8,
             data: Bytes::from(self.values.to_vec()),
             dimensions: [1, 1],
         })
     }
 }
 
+/// Format of GPU images created from raw images in `src_format`. 8-bit images get
+/// block-compressed according to `params`; higher precision data is stored uncompressed
+/// and linear, so that it keeps its precision.
+pub fn gpu_image_format(params: TexParams, src_format: RawImageFormat) -> vk::Format {
+    match src_format {
+        RawImageFormat::Rgba8 => {}
+        // Single-channel maps keep only red, whatever the source has
+        RawImageFormat::R16 | RawImageFormat::Rgba16 if params.channels == TexChannels::R => {
+            return vk::Format::R16_UNORM
+        }
+        RawImageFormat::R16 | RawImageFormat::Rgba16 => return vk::Format::R16G16B16A16_SFLOAT,
+        RawImageFormat::Rgba32F => return vk::Format::R32G32B32A32_SFLOAT,
+    }
+
+    match (params.compression, params.gamma) {
+        (TexCompressionMode::None, TexGamma::Linear) => vk::Format::R8G8B8A8_UNORM,
+        (TexCompressionMode::None, TexGamma::Srgb) => vk::Format::R8G8B8A8_SRGB,
+        (TexCompressionMode::Bc1, TexGamma::Linear) => vk::Format::BC1_RGB_UNORM_BLOCK,
+        (TexCompressionMode::Bc1, TexGamma::Srgb) => vk::Format::BC1_RGB_SRGB_BLOCK,
+        (TexCompressionMode::Bc3, TexGamma::Linear) => vk::Format::BC3_UNORM_BLOCK,
+        (TexCompressionMode::Bc3, TexGamma::Srgb) => vk::Format::BC3_SRGB_BLOCK,
+        (TexCompressionMode::Bc4, _) => vk::Format::BC4_UNORM_BLOCK,
+        (TexCompressionMode::Bc5, _) => vk::Format::BC5_UNORM_BLOCK,
+        (TexCompressionMode::Bc7, TexGamma::Linear) => vk::Format::BC7_UNORM_BLOCK,
+        (TexCompressionMode::Bc7, TexGamma::Srgb) => vk::Format::BC7_SRGB_BLOCK,
+    }
+}
+
+/// Block-compresses an RGBA8 mip level if `format` calls for it; other formats
+/// are already laid out by the mip chain builder.
+fn compress_mip(format: vk::Format, mip: Vec<u8>, width: u32, height: u32) -> Vec<u8> {
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK | vk::Format::BC1_RGB_SRGB_BLOCK => {
+            crate::bc::compress_bc1(&mip, width, height)
+        }
+        vk::Format::BC3_UNORM_BLOCK | vk::Format::BC3_SRGB_BLOCK => {
+            crate::bc::compress_bc3(&mip, width, height)
+        }
+        vk::Format::BC4_UNORM_BLOCK => crate::bc::compress_bc4(&mip, width, height),
+        vk::Format::BC5_UNORM_BLOCK => crate::bc::compress_bc5(&mip, width, height),
+        vk::Format::BC7_UNORM_BLOCK | vk::Format::BC7_SRGB_BLOCK => {
+            crate::bc::compress_bc7(&mip, width, height)
+        }
+        _ => mip,
+    }
+}
+
+/// Size in bytes of one row of a GPU image in the given format: a row of texels
+/// for uncompressed formats, and a row of 4x4 blocks for block-compressed ones.
+pub fn gpu_image_row_pitch(format: vk::Format, width: u32) -> usize {
+    let width = width as usize;
+    let blocks = (width + 3) / 4;
+
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK => blocks * 8,
+        vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => blocks * 16,
+        vk::Format::R16_UNORM => width * 2,
+        vk::Format::R16G16B16A16_SFLOAT => width * 8,
+        vk::Format::R32G32B32A32_SFLOAT => width * 16,
+        _ => width * 4,
+    }
+}
+
+/// Size in bytes of a mip level of a GPU image, or `None` for formats which `bake` doesn't write
+pub fn gpu_image_mip_size(format: vk::Format, width: u32, height: u32) -> Option<usize> {
+    let rows = match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => (height as usize + 3) / 4,
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::R16_UNORM
+        | vk::Format::R16G16B16A16_SFLOAT
+        | vk::Format::R32G32B32A32_SFLOAT => height as usize,
+        _ => return None,
+    };
+
+    Some(gpu_image_row_pitch(format, width) * rows)
+}
+
+/// Decodes a mip level of a GPU image to RGBA8, for inspection. High precision
+/// formats get clamped to `[0, 1]`; single and two-channel data lands in red and green.
+pub fn decode_gpu_image_mip(
+    format: vk::Format,
+    data: &[u8],
+    width: u32,
+    height: u32,
+) -> anyhow::Result<Vec<u8>> {
+    let texel_count = width as usize * height as usize;
+    let expected_len = gpu_image_mip_size(format, width, height)
+        .with_context(|| format!("Decoding {:?} images is not supported", format))?;
+
+    if data.len() != expected_len {
+        anyhow::bail!(
+            "Expected {} bytes for a {}x{} {:?} image, but got {}",
+            expected_len,
+            width,
+            height,
+            format,
+            data.len()
+        );
+    }
+
+    let unorm8 = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+    let decoded = match format {
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => Some(data.to_vec()),
+        vk::Format::BC1_RGB_UNORM_BLOCK | vk::Format::BC1_RGB_SRGB_BLOCK => {
+            crate::bc::decompress_bc1(data, width, height)
+        }
+        vk::Format::BC3_UNORM_BLOCK | vk::Format::BC3_SRGB_BLOCK => {
+            crate::bc::decompress_bc3(data, width, height)
+        }
+        vk::Format::BC4_UNORM_BLOCK => crate::bc::decompress_bc4(data, width, height),
+        vk::Format::BC5_UNORM_BLOCK => crate::bc::decompress_bc5(data, width, height),
+        vk::Format::BC7_UNORM_BLOCK | vk::Format::BC7_SRGB_BLOCK => Some(
+            crate::bc::decompress_bc7(data, width, height)
+                .context("Only BC7 blocks in mode 6, as written by `bake`, can be decoded")?,
+        ),
+        vk::Format::R16_UNORM => Some(
+            data.chunks_exact(2)
+                .flat_map(|v| [(u16::from_ne_bytes([v[0], v[1]]) >> 8) as u8, 0, 0, 255])
+                .collect(),
+        ),
+        vk::Format::R16G16B16A16_SFLOAT => Some(
+            data.chunks_exact(2)
+                .map(|v| {
+                    unorm8(crate::mips::f16_bits_to_f32(u16::from_ne_bytes([
+                        v[0], v[1],
+                    ])))
+                })
+                .collect(),
+        ),
+        vk::Format::R32G32B32A32_SFLOAT => Some(
+            data.chunks_exact(4)
+                .map(|v| unorm8(f32::from_ne_bytes(v.try_into().unwrap())))
+                .collect(),
+        ),
+        _ => anyhow::bail!("Decoding {:?} images is not supported", format),
+    };
+
+    let decoded = decoded.ok_or_else(|| anyhow::anyhow!("Malformed {:?} image data", format))?;
+    assert_eq!(decoded.len(), texel_count * 4);
+
+    Ok(decoded)
+}
+
 #[derive(Clone, Hash)]
 pub struct CreateGpuImage {
-    pub image: Lazy<RawRgba8Image>,
-    pub params: super::mesh::TexParams,
+    pub image: Lazy<RawImage>,
+    pub params: TexParams,
+
+    /// For metallic-roughness maps: the normal map used alongside, whose variance
+    /// gets folded into roughness as the mips get smaller.
+    pub roughness_normal_map: Option<Lazy<RawImage>>,
 }
 
 #[async_trait]
@@ -94,70 +529,110 @@ impl LazyWorker for CreateGpuImage {
 
     async fn run(self, ctx: RunContext) -> Self::Output {
         let src = self.image.eval(&ctx).await?;
-
-        let format = match self.params.gamma {
-            crate::mesh::TexGamma::Linear => vk::Format::R8G8B8A8_UNORM,
-            crate::mesh::TexGamma::Srgb => vk::Format::R8G8B8A8_SRGB,
+        let roughness_normal_map = match &self.roughness_normal_map {
+            Some(normal_map) => Some(normal_map.eval(&ctx).await?),
+            None => None,
         };
 
-        let mut image = image::DynamicImage::ImageRgba8(
-            image::ImageBuffer::<image::Rgba<u8>, _>::from_raw(
-                src.dimensions[0],
-                src.dimensions[1],
-                src.data.to_vec(),
-            )
-            .unwrap(),
-        );
+        let format = gpu_image_format(self.params, src.format);
 
         const MAX_SIZE: u32 = 2048;
 
-        if image.dimensions().0 > MAX_SIZE || image.dimensions().1 > MAX_SIZE {
-            image = image.resize_exact(
-                image.dimensions().0.min(MAX_SIZE),
-                image.dimensions().1.min(MAX_SIZE),
-                FilterType::Lanczos3,
-            );
-        }
-
-        let mut desc = ImageDesc::new_2d(format, [image.dimensions().0, image.dimensions().1])
-            .usage(vk::ImageUsageFlags::SAMPLED);
+        let mip_chain = crate::mips::build_mip_chain(
+            &src,
+            self.params,
+            format,
+            MAX_SIZE,
+            roughness_normal_map.as_deref(),
+        );
 
-        let mips: Vec<Vec<u8>> = if self.params.use_mips {
-            desc = desc.all_mip_levels();
+        let extent = [mip_chain.extent[0], mip_chain.extent[1], 1];
 
-            let downsample = |image: &DynamicImage| {
-                // TODO: gamma-correct resize
-                image.resize_exact(
-                    (image.dimensions().0 / 2).max(1),
-                    (image.dimensions().1 / 2).max(1),
-                    FilterType::Lanczos3,
+        let mips = mip_chain
+            .levels
+            .into_iter()
+            .enumerate()
+            .map(|(level, mip)| {
+                compress_mip(
+                    format,
+                    mip,
+                    (extent[0] >> level).max(1),
+                    (extent[1] >> level).max(1),
                 )
-            };
-
-            let mut mips;
-            image = {
-                let next = downsample(&image);
-                mips = vec![image.into_rgba8().into_raw()];
-                next
-            };
-
-            for _ in 1..desc.mip_levels {
-                let next = downsample(&image);
-                let mip = std::mem::replace(&mut image, next);
-                mips.push(mip.into_rgba8().into_raw());
-            }
-
-            mips
-        } else {
-            vec![image.into_rgba8().into_raw()]
-        };
+            })
+            .collect();
 
         Ok(super::mesh::GpuImage::Proto {
             format,
-            extent: desc.extent,
+            extent,
             mips,
         })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::TexMipFilter;
+
+    #[test]
+    fn formats_follow_the_channels_of_the_map() {
+        let params = |compression, channels| TexParams {
+            gamma: TexGamma::Linear,
+            use_mips: true,
+            compression,
+            mip_filter: TexMipFilter::Default,
+            channels,
+        };
+        let rgba = params(TexCompressionMode::Bc7, TexChannels::Rgba);
+        let r = params(TexCompressionMode::Bc4, TexChannels::R);
+
+        assert_eq!(
+            gpu_image_format(r, RawImageFormat::Rgba8),
+            vk::Format::BC4_UNORM_BLOCK
+        );
+        assert_eq!(
+            gpu_image_format(r, RawImageFormat::R16),
+            vk::Format::R16_UNORM
+        );
+        assert_eq!(
+            gpu_image_format(r, RawImageFormat::Rgba16),
+            vk::Format::R16_UNORM
+        );
+
+        // Uncompressed single-channel maps are too
+        let r_uncompressed = params(TexCompressionMode::None, TexChannels::R);
+        assert_eq!(
+            gpu_image_format(r_uncompressed, RawImageFormat::R16),
+            vk::Format::R16_UNORM
+        );
+
+        // Grayscale sources of multi-channel maps are replicated to RGB
+        assert_eq!(
+            gpu_image_format(rgba, RawImageFormat::R16),
+            vk::Format::R16G16B16A16_SFLOAT
+        );
+    }
+
+    #[test]
+    fn height_maps_slope_away_from_higher_texels() {
+        // Rising to the right, then down the image; away from the wrapped-around edges
+        let ramp_x = LinearImage::from_fn(4, 4, |x, _| Rgba([x as f32 * 0.25, 0.0, 0.0, 1.0]));
+        let ramp_y = LinearImage::from_fn(4, 4, |_, y| Rgba([y as f32 * 0.25, 0.0, 0.0, 1.0]));
+
+        let n = height_to_normal(&ramp_x, 4.0).get_pixel(1, 1).0;
+        // A slope of one texel per texel, tilted 45 degrees
+        let tilt = std::f32::consts::FRAC_1_SQRT_2 * 0.5;
+        assert!((n[0] - (0.5 - tilt)).abs() < 1e-6);
+        assert!((n[1] - 0.5).abs() < 1e-6);
+        assert!((n[2] - (0.5 + tilt)).abs() < 1e-6);
+
+        // Up the image is +Y, and the surface faces away from the rise
+        let n = height_to_normal(&ramp_y, 4.0).get_pixel(1, 1).0;
+        assert!(n[1] > 0.5 && (n[0] - 0.5).abs() < 1e-6);
 
-        //self.device.create_image(desc, initial_data)
+        // Zero scale is flat
+        let n = height_to_normal(&ramp_x, 0.0).get_pixel(1, 1).0;
+        assert_eq!(n, [0.5, 0.5, 1.0, 1.0]);
     }
 }
@@ -1,15 +1,182 @@
 // Based on `import.rs` in the `gltf` crate, but modified not to load images (we do that separately).
 
 use bytes::Bytes;
-use gltf::{buffer, image, Document, Error, Gltf, Result};
-use std::{fs, io, path::Path};
+use gltf::{binary::Glb, buffer, image, json, Document, Error, Gltf, Result};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use crate::image::ImageSource;
 
 type BufferBytes = Bytes;
 
 /// Return type of `import`.
-type Import = (Document, Vec<BufferBytes>, Vec<ImageSource>);
+type Import = (
+    Document,
+    Vec<BufferBytes>,
+    Vec<ImageSource>,
+    Vec<MaterialExtensions>,
+);
+
+/// Parameters of material extensions which the `gltf` crate doesn't parse.
+/// Of the textures of the extensions, only the clearcoat one is imported.
+#[derive(Clone, Copy, Debug)]
+pub struct MaterialExtensions {
+    /// `KHR_materials_emissive_strength`
+    pub emissive_strength: f32,
+    /// `KHR_materials_transmission`
+    pub transmission: f32,
+    /// `KHR_materials_ior`
+    pub ior: f32,
+    /// `KHR_materials_specular`
+    pub specular_factor: f32,
+    /// `KHR_materials_specular`
+    pub specular_color: [f32; 3],
+    /// `KHR_materials_clearcoat`
+    pub clearcoat: f32,
+    /// `KHR_materials_clearcoat`
+    pub clearcoat_roughness: f32,
+    /// `KHR_materials_clearcoat`; strength in the red channel
+    pub clearcoat_texture: Option<ExtensionTexture>,
+    /// `KHR_materials_sheen`
+    pub sheen_color: [f32; 3],
+    /// `KHR_materials_sheen`
+    pub sheen_roughness: f32,
+    /// `KHR_texture_transform` of the normal texture, which the `gltf` crate doesn't expose
+    pub normal_texture_transform: Option<TextureTransformParams>,
+}
+
+/// Texture info of a material extension
+#[derive(Clone, Copy, Debug)]
+pub struct ExtensionTexture {
+    /// Index into the images of the document
+    pub image: usize,
+    pub tex_coord: u32,
+    pub transform: Option<TextureTransformParams>,
+}
+
+impl ExtensionTexture {
+    /// `textures` are the textures of the document, which map `info` to an image
+    fn from_json(info: &json::Value, textures: &[json::Value]) -> Option<Self> {
+        let texture = textures.get(info.get("index")?.as_u64()? as usize)?;
+
+        Some(Self {
+            image: texture.get("source")?.as_u64()? as usize,
+            tex_coord: info.get("texCoord").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
+            transform: info
+                .get("extensions")
+                .and_then(|ext| ext.get("KHR_texture_transform"))
+                .map(TextureTransformParams::from_json),
+        })
+    }
+}
+
+/// `KHR_texture_transform` parameters
+#[derive(Clone, Copy, Debug)]
+pub struct TextureTransformParams {
+    pub offset: [f32; 2],
+    pub rotation: f32,
+    pub scale: [f32; 2],
+    /// Overrides the `texCoord` of the texture info if specified
+    pub tex_coord: Option<u32>,
+}
+
+impl TextureTransformParams {
+    fn from_json(transform: &json::Value) -> Self {
+        let vec2 = |field: &str, default: [f32; 2]| {
+            transform
+                .get(field)
+                .and_then(|v| v.as_array())
+                .filter(|v| v.len() == 2)
+                .map_or(default, |v| {
+                    let c = |i: usize| v[i].as_f64().unwrap_or(default[i] as f64) as f32;
+                    [c(0), c(1)]
+                })
+        };
+
+        Self {
+            offset: vec2("offset", [0.0, 0.0]),
+            rotation: transform
+                .get("rotation")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(0.0) as f32,
+            scale: vec2("scale", [1.0, 1.0]),
+            tex_coord: transform
+                .get("texCoord")
+                .and_then(|v| v.as_u64())
+                .map(|v| v as u32),
+        }
+    }
+}
+
+impl Default for MaterialExtensions {
+    fn default() -> Self {
+        Self {
+            emissive_strength: 1.0,
+            transmission: 0.0,
+            ior: 1.5,
+            specular_factor: 1.0,
+            specular_color: [1.0, 1.0, 1.0],
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            clearcoat_texture: None,
+            sheen_color: [0.0, 0.0, 0.0],
+            sheen_roughness: 0.0,
+            normal_texture_transform: None,
+        }
+    }
+}
+
+impl MaterialExtensions {
+    fn from_json(extensions: &json::Value) -> Self {
+        let factor = |ext: &str, field: &str| -> Option<f32> {
+            extensions.get(ext)?.get(field)?.as_f64().map(|v| v as f32)
+        };
+
+        let defaults = Self::default();
+
+        let color = |ext: &str, field: &str, default: [f32; 3]| -> [f32; 3] {
+            extensions
+                .get(ext)
+                .and_then(|ext| ext.get(field))
+                .and_then(|v| v.as_array())
+                .filter(|v| v.len() == 3)
+                .map_or(default, |v| {
+                    let c = |i: usize| v[i].as_f64().map_or(default[i], |c| c as f32);
+                    [c(0), c(1), c(2)]
+                })
+        };
+
+        Self {
+            emissive_strength: factor("KHR_materials_emissive_strength", "emissiveStrength")
+                .unwrap_or(defaults.emissive_strength),
+            transmission: factor("KHR_materials_transmission", "transmissionFactor")
+                .unwrap_or(defaults.transmission),
+            ior: factor("KHR_materials_ior", "ior").unwrap_or(defaults.ior),
+            specular_factor: factor("KHR_materials_specular", "specularFactor")
+                .unwrap_or(defaults.specular_factor),
+            specular_color: color(
+                "KHR_materials_specular",
+                "specularColorFactor",
+                defaults.specular_color,
+            ),
+            clearcoat: factor("KHR_materials_clearcoat", "clearcoatFactor")
+                .unwrap_or(defaults.clearcoat),
+            clearcoat_roughness: factor("KHR_materials_clearcoat", "clearcoatRoughnessFactor")
+                .unwrap_or(defaults.clearcoat_roughness),
+            clearcoat_texture: None,
+            sheen_color: color(
+                "KHR_materials_sheen",
+                "sheenColorFactor",
+                defaults.sheen_color,
+            ),
+            sheen_roughness: factor("KHR_materials_sheen", "sheenRoughnessFactor")
+                .unwrap_or(defaults.sheen_roughness),
+            normal_texture_transform: None,
+        }
+    }
+}
 
 /// Represents the set of URI schemes the importer supports.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -154,18 +321,68 @@ pub fn import_image_data(
     Ok(images)
 }
 
-fn import_impl(Gltf { document, blob }: Gltf, base: Option<&Path>) -> Result<Import> {
+/// Parse the material extensions out of the raw JSON of the document, since `Document`
+/// discards the ones the `gltf` crate doesn't know about, as well as the transform of normal
+/// textures. Indexed like `Document::materials`.
+pub fn import_material_extensions(slice: &[u8]) -> Result<Vec<MaterialExtensions>> {
+    let root: json::Value = if slice.starts_with(b"glTF") {
+        json::deserialize::from_slice(&Glb::from_slice(slice)?.json)?
+    } else {
+        json::deserialize::from_slice(slice)?
+    };
+
+    let materials = root
+        .get("materials")
+        .and_then(|materials| materials.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    let textures = root
+        .get("textures")
+        .and_then(|textures| textures.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    Ok(materials
+        .iter()
+        .map(|material| {
+            let mut extensions = material
+                .get("extensions")
+                .map_or_else(MaterialExtensions::default, MaterialExtensions::from_json);
+
+            extensions.normal_texture_transform = material
+                .get("normalTexture")
+                .and_then(|tex| tex.get("extensions"))
+                .and_then(|ext| ext.get("KHR_texture_transform"))
+                .map(TextureTransformParams::from_json);
+
+            extensions.clearcoat_texture = material
+                .get("extensions")
+                .and_then(|ext| ext.get("KHR_materials_clearcoat"))
+                .and_then(|ext| ext.get("clearcoatTexture"))
+                .and_then(|info| ExtensionTexture::from_json(info, textures));
+
+            extensions
+        })
+        .collect())
+}
+
+fn import_impl(
+    Gltf { document, blob }: Gltf,
+    material_extensions: Vec<MaterialExtensions>,
+    base: Option<&Path>,
+) -> Result<Import> {
     let buffer_data = import_buffer_data(&document, base, blob)?;
     let image_data = import_image_data(&document, base, &buffer_data)?;
-    let import = (document, buffer_data, image_data);
+    let import = (document, buffer_data, image_data, material_extensions);
     Ok(import)
 }
 
 fn import_path(path: &Path) -> Result<Import> {
     let base = path.parent().unwrap_or_else(|| Path::new("./"));
-    let file = fs::File::open(path).map_err(Error::Io)?;
-    let reader = io::BufReader::new(file);
-    import_impl(Gltf::from_reader(reader)?, Some(base))
+    let slice = read_to_end(path)?;
+    let material_extensions = import_material_extensions(&slice)?;
+    import_impl(Gltf::from_slice(&slice)?, material_extensions, Some(base))
 }
 
 /// Import some glTF 2.0 from the file system.
@@ -175,3 +392,97 @@ where
 {
     import_path(path.as_ref())
 }
+
+/// Return the paths of the files a glTF document is stored in: the document itself,
+/// and any external buffers it references. Images are not included.
+pub fn buffer_source_files<P>(path: P) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let base = path.parent().unwrap_or_else(|| Path::new("./"));
+    let file = fs::File::open(path).map_err(Error::Io)?;
+    let reader = io::BufReader::new(file);
+    let Gltf { document, .. } = Gltf::from_reader(reader)?;
+
+    let mut files = vec![path.to_owned()];
+    for buffer in document.buffers() {
+        if let buffer::Source::Uri(uri) = buffer.source() {
+            match Scheme::parse(uri) {
+                Scheme::File(path) => files.push(PathBuf::from(path)),
+                Scheme::Relative => files.push(base.join(uri)),
+                _ => (),
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clearcoat_textures_resolve_to_images() {
+        let document = br#"{
+            "textures": [{ "source": 2 }, { "source": 0 }],
+            "materials": [
+                {
+                    "extensions": {
+                        "KHR_materials_clearcoat": {
+                            "clearcoatFactor": 0.5,
+                            "clearcoatTexture": {
+                                "index": 1,
+                                "texCoord": 1,
+                                "extensions": { "KHR_texture_transform": { "scale": [2, 2] } }
+                            }
+                        }
+                    }
+                },
+                { "extensions": { "KHR_materials_clearcoat": { "clearcoatTexture": { "index": 5 } } } },
+                {}
+            ]
+        }"#;
+
+        let extensions = import_material_extensions(document).unwrap();
+        assert_eq!(extensions.len(), 3);
+
+        assert_eq!(extensions[0].clearcoat, 0.5);
+        let tex = extensions[0].clearcoat_texture.unwrap();
+        assert_eq!((tex.image, tex.tex_coord), (0, 1));
+        assert_eq!(tex.transform.unwrap().scale, [2.0, 2.0]);
+
+        // Dangling texture indices are dropped
+        assert!(extensions[1].clearcoat_texture.is_none());
+        assert!(extensions[2].clearcoat_texture.is_none());
+    }
+
+    #[test]
+    fn sheen_and_specular_colors_fall_back_to_defaults() {
+        let document = br#"{
+            "materials": [
+                {
+                    "extensions": {
+                        "KHR_materials_sheen": {
+                            "sheenColorFactor": [0.5, 0.25, 1.0],
+                            "sheenRoughnessFactor": 0.75
+                        },
+                        "KHR_materials_specular": { "specularColorFactor": [0.5, 0.5] }
+                    }
+                },
+                {}
+            ]
+        }"#;
+
+        let extensions = import_material_extensions(document).unwrap();
+
+        assert_eq!(extensions[0].sheen_color, [0.5, 0.25, 1.0]);
+        assert_eq!(extensions[0].sheen_roughness, 0.75);
+        // Malformed colors are ignored
+        assert_eq!(extensions[0].specular_color, [1.0, 1.0, 1.0]);
+
+        assert_eq!(extensions[1].sheen_color, [0.0, 0.0, 0.0]);
+        assert_eq!(extensions[1].sheen_roughness, 0.0);
+    }
+}
@@ -0,0 +1,449 @@
+// A minimal Wavefront OBJ and MTL parser. Only the subset used by typical asset exporters
+// is supported: polygonal faces, vertex colors, materials, and their textures.
+
+use anyhow::Context as _;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// One corner of a face; zero-based indices into the attributes of `ObjData`
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct ObjVertex {
+    pub position: u32,
+    pub uv: Option<u32>,
+    pub normal: Option<u32>,
+}
+
+pub struct ObjFace {
+    /// Index into `ObjData::materials`
+    pub material: Option<usize>,
+    /// Three or more corners; polygons are convex, and get triangulated as fans
+    pub verts: Vec<ObjVertex>,
+}
+
+#[derive(Default)]
+pub struct ObjData {
+    pub positions: Vec<[f32; 3]>,
+    /// Non-standard, but widely supported `v x y z r g b` extension. Empty if unused.
+    pub colors: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// Flipped to have V pointing down, like in glTF
+    pub uvs: Vec<[f32; 2]>,
+    pub faces: Vec<ObjFace>,
+    pub materials: Vec<MtlMaterial>,
+}
+
+/// A `map_*` statement of an MTL material
+#[derive(Clone)]
+pub struct MtlTexture {
+    pub path: PathBuf,
+    /// `-o`
+    pub offset: [f32; 2],
+    /// `-s`
+    pub scale: [f32; 2],
+    /// `-bm`; multiplies the heights of bump maps
+    pub bump_multiplier: f32,
+}
+
+#[derive(Clone, Default)]
+pub struct MtlMaterial {
+    pub name: String,
+    /// Diffuse color
+    pub kd: Option<[f32; 3]>,
+    /// Specular color
+    pub ks: Option<[f32; 3]>,
+    /// Specular exponent
+    pub ns: Option<f32>,
+    /// Emissive color
+    pub ke: Option<[f32; 3]>,
+    /// Index of refraction
+    pub ni: Option<f32>,
+    /// Opacity; `Tr` is stored as `1 - Tr`
+    pub d: Option<f32>,
+    /// Roughness, from the PBR extension of MTL
+    pub pr: Option<f32>,
+    /// Metalness, from the PBR extension of MTL
+    pub pm: Option<f32>,
+    pub map_kd: Option<MtlTexture>,
+    /// Specular color
+    pub map_ks: Option<MtlTexture>,
+    /// Scales the specular exponent
+    pub map_ns: Option<MtlTexture>,
+    /// Opacity
+    pub map_d: Option<MtlTexture>,
+    /// `norm`; a tangent-space normal map
+    pub map_normal: Option<MtlTexture>,
+    /// `bump` or `map_Bump`; a height map
+    pub map_bump: Option<MtlTexture>,
+    pub map_ke: Option<MtlTexture>,
+    /// Names of `map_*` statements which aren't imported
+    pub unsupported_maps: Vec<String>,
+}
+
+fn parse_floats<const N: usize>(args: &[&str]) -> Option<[f32; N]> {
+    if args.len() < N {
+        return None;
+    }
+
+    let mut res = [0.0f32; N];
+    for (dst, arg) in res.iter_mut().zip(args) {
+        *dst = arg.parse().ok()?;
+    }
+    Some(res)
+}
+
+/// Perceptual roughness of GGX matching a Blinn-Phong exponent:
+/// `alpha = sqrt(2 / (Ns + 2))`, and `roughness = sqrt(alpha)`
+pub fn phong_exponent_to_roughness(ns: f32) -> f32 {
+    (2.0 / (ns.max(0.0) + 2.0)).sqrt().sqrt()
+}
+
+/// Resolves a one-based, possibly negative (relative to the end) OBJ index
+fn parse_index(index: &str, count: usize) -> Option<u32> {
+    let index: i64 = index.parse().ok()?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+
+    (0..count as i64)
+        .contains(&resolved)
+        .then(|| resolved as u32)
+}
+
+/// Files referenced by `mtllib` statements, relative to the directory of the OBJ file
+fn material_library_paths(obj_source: &str, base: &Path) -> Vec<PathBuf> {
+    obj_source
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("mtllib "))
+        .map(|file| base.join(file.trim()))
+        .collect()
+}
+
+/// Return the paths of the files an OBJ scene is stored in: the OBJ itself,
+/// and its material libraries. Textures are not included.
+pub fn source_files(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let source = std::fs::read_to_string(path).with_context(|| format!("Reading {:?}", path))?;
+    let base = path.parent().unwrap_or_else(|| Path::new("./"));
+
+    let mut files = vec![path.to_owned()];
+    files.extend(material_library_paths(&source, base));
+    Ok(files)
+}
+
+pub fn import(path: &Path) -> anyhow::Result<ObjData> {
+    let source = std::fs::read_to_string(path).with_context(|| format!("Reading {:?}", path))?;
+    let base = path.parent().unwrap_or_else(|| Path::new("./"));
+
+    let mut res = ObjData::default();
+    let mut material_indices: HashMap<String, usize> = HashMap::new();
+
+    for mtl_path in material_library_paths(&source, base) {
+        match std::fs::read_to_string(&mtl_path) {
+            Ok(mtl_source) => {
+                let mtl_base = mtl_path.parent().unwrap_or(base);
+                for material in parse_mtl(&mtl_source, mtl_base) {
+                    material_indices.insert(material.name.clone(), res.materials.len());
+                    res.materials.push(material);
+                }
+            }
+            Err(err) => log::warn!("Could not read material library {:?}: {}", mtl_path, err),
+        }
+    }
+
+    let mut current_material: Option<usize> = None;
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        let invalid = || anyhow::anyhow!("{:?}:{}: invalid `{}`", path, line_idx + 1, keyword);
+
+        match keyword {
+            "v" => {
+                res.positions.push(parse_floats(&args).ok_or_else(invalid)?);
+                if let Some(color) = args.get(3..).and_then(parse_floats::<3>) {
+                    // Vertices before the first colored one are white
+                    res.colors.resize(res.positions.len() - 1, [1.0, 1.0, 1.0]);
+                    res.colors.push(color);
+                } else if !res.colors.is_empty() {
+                    res.colors.push([1.0, 1.0, 1.0]);
+                }
+            }
+            "vn" => res.normals.push(parse_floats(&args).ok_or_else(invalid)?),
+            "vt" => {
+                // The V coordinate is optional
+                let u = args
+                    .first()
+                    .and_then(|u| u.parse().ok())
+                    .ok_or_else(invalid)?;
+                let v: f32 = args.get(1).and_then(|v| v.parse().ok()).unwrap_or(0.0);
+                res.uvs.push([u, 1.0 - v]);
+            }
+            "f" => {
+                let verts = args
+                    .iter()
+                    .map(|vert| {
+                        let mut indices = vert.split('/');
+                        let position = parse_index(indices.next()?, res.positions.len())?;
+                        let uv = match indices.next() {
+                            Some(uv) if !uv.is_empty() => Some(parse_index(uv, res.uvs.len())?),
+                            _ => None,
+                        };
+                        let normal = match indices.next() {
+                            Some(normal) if !normal.is_empty() => {
+                                Some(parse_index(normal, res.normals.len())?)
+                            }
+                            _ => None,
+                        };
+
+                        Some(ObjVertex {
+                            position,
+                            uv,
+                            normal,
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(invalid)?;
+
+                if verts.len() < 3 {
+                    log::warn!(
+                        "{:?}:{}: skipping a face with fewer than three vertices",
+                        path,
+                        line_idx + 1
+                    );
+                    continue;
+                }
+
+                res.faces.push(ObjFace {
+                    material: current_material,
+                    verts,
+                });
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                current_material = material_indices.get(&name).copied();
+                if current_material.is_none() {
+                    log::warn!("{:?}: unknown material {:?}", path, name);
+                }
+            }
+            // Objects, groups, smoothing groups, lines, points, and curves
+            _ => (),
+        }
+    }
+
+    Ok(res)
+}
+
+/// Parses the arguments of a `map_*` statement: options, followed by the file name
+fn parse_mtl_texture(args: &[&str], base: &Path) -> Option<MtlTexture> {
+    let mut offset = [0.0, 0.0];
+    let mut scale = [1.0, 1.0];
+    let mut bump_multiplier = 1.0;
+
+    let mut i = 0;
+    while i < args.len() && args[i].starts_with('-') {
+        let option = args[i];
+        i += 1;
+
+        // The `-o` and `-s` options take up to three values; the third one is ignored
+        let take_values = |i: &mut usize, max: usize| {
+            let start = *i;
+            while *i < args.len() && *i - start < max && args[*i].parse::<f32>().is_ok() {
+                *i += 1;
+            }
+            args[start..*i]
+                .iter()
+                .map(|v| v.parse::<f32>().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        match option {
+            "-o" => {
+                let values = take_values(&mut i, 3);
+                offset = [
+                    values.first().copied().unwrap_or(0.0),
+                    values.get(1).copied().unwrap_or(0.0),
+                ];
+            }
+            "-s" => {
+                let values = take_values(&mut i, 3);
+                scale = [
+                    values.first().copied().unwrap_or(1.0),
+                    values.get(1).copied().unwrap_or(1.0),
+                ];
+            }
+            "-t" => {
+                take_values(&mut i, 3);
+            }
+            "-bm" => {
+                bump_multiplier = take_values(&mut i, 1).first().copied().unwrap_or(1.0);
+            }
+            "-mm" => i += 2,
+            // Options with a single argument: `-blendu`, `-blendv`, `-boost`, `-cc`,
+            // `-clamp`, `-imfchan`, `-texres`, `-type`
+            _ => i += 1,
+        }
+    }
+
+    let file = args.get(i..)?.join(" ");
+    if file.is_empty() {
+        return None;
+    }
+
+    Some(MtlTexture {
+        // Exporters on Windows tend to write backslashes
+        path: base.join(file.replace('\\', "/")),
+        offset,
+        scale,
+        bump_multiplier,
+    })
+}
+
+fn parse_mtl(source: &str, base: &Path) -> Vec<MtlMaterial> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for line in source.lines() {
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            materials.push(MtlMaterial {
+                name: args.join(" "),
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+
+        let scalar = || args.first().and_then(|v| v.parse::<f32>().ok());
+
+        match keyword {
+            "Kd" => material.kd = parse_floats(&args),
+            "Ks" => material.ks = parse_floats(&args),
+            "Ke" => material.ke = parse_floats(&args),
+            "Ns" => material.ns = scalar(),
+            "Ni" => material.ni = scalar(),
+            "d" => material.d = scalar(),
+            "Tr" => material.d = scalar().map(|tr| 1.0 - tr),
+            "Pr" => material.pr = scalar(),
+            "Pm" => material.pm = scalar(),
+            "map_Kd" => material.map_kd = parse_mtl_texture(&args, base),
+            "map_Ks" => material.map_ks = parse_mtl_texture(&args, base),
+            "map_Ns" => material.map_ns = parse_mtl_texture(&args, base),
+            "map_d" => material.map_d = parse_mtl_texture(&args, base),
+            "norm" => material.map_normal = parse_mtl_texture(&args, base),
+            "map_Bump" | "map_bump" | "bump" => material.map_bump = parse_mtl_texture(&args, base),
+            "map_Ke" => material.map_ke = parse_mtl_texture(&args, base),
+            _ if keyword.starts_with("map_") || keyword == "disp" || keyword == "decal" => {
+                material.unsupported_maps.push(keyword.to_owned())
+            }
+            _ => (),
+        }
+    }
+
+    materials
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_MTL: &str = r"
+# Exported by hand
+newmtl painted metal
+Kd 0.8 0.1 0.1
+Ks 0.5 0.5 0.5
+Ns 250
+Tr 0.25
+map_Kd -o 0.5 0.25 -s 2 4 1 textures\paint.png
+map_Ks spec.png
+map_Ns -clamp on gloss.png
+map_d -imfchan m leaves alpha.tga
+bump -bm 0.2 height.png
+map_refl sky.hdr
+
+newmtl glowing
+Ke 1 2 3
+Pr 0.3
+Pm 1
+d 0.5
+norm normal.png
+map_Ke glow.png
+";
+
+    #[test]
+    fn mtl_statements_are_parsed() {
+        let base = Path::new("assets");
+        let materials = parse_mtl(SAMPLE_MTL, base);
+        assert_eq!(materials.len(), 2);
+
+        let painted = &materials[0];
+        assert_eq!(painted.name, "painted metal");
+        assert_eq!(painted.kd, Some([0.8, 0.1, 0.1]));
+        assert_eq!(painted.ks, Some([0.5, 0.5, 0.5]));
+        assert_eq!(painted.ns, Some(250.0));
+        assert_eq!(painted.d, Some(0.75));
+
+        let map_kd = painted.map_kd.as_ref().unwrap();
+        assert_eq!(map_kd.path, base.join("textures/paint.png"));
+        assert_eq!(map_kd.offset, [0.5, 0.25]);
+        assert_eq!(map_kd.scale, [2.0, 4.0]);
+
+        assert_eq!(painted.map_ks.as_ref().unwrap().path, base.join("spec.png"));
+        assert_eq!(
+            painted.map_ns.as_ref().unwrap().path,
+            base.join("gloss.png")
+        );
+        // File names may contain spaces
+        assert_eq!(
+            painted.map_d.as_ref().unwrap().path,
+            base.join("leaves alpha.tga")
+        );
+
+        // `bump` is a height map, not a normal map
+        assert!(painted.map_normal.is_none());
+        let map_bump = painted.map_bump.as_ref().unwrap();
+        assert_eq!(map_bump.path, base.join("height.png"));
+        assert_eq!(map_bump.bump_multiplier, 0.2);
+
+        assert_eq!(painted.unsupported_maps, vec!["map_refl".to_owned()]);
+
+        let glowing = &materials[1];
+        assert_eq!(glowing.name, "glowing");
+        assert_eq!(glowing.ke, Some([1.0, 2.0, 3.0]));
+        assert_eq!(glowing.pr, Some(0.3));
+        assert_eq!(glowing.pm, Some(1.0));
+        assert_eq!(glowing.d, Some(0.5));
+        assert_eq!(
+            glowing.map_normal.as_ref().unwrap().path,
+            base.join("normal.png")
+        );
+        assert!(glowing.map_bump.is_none());
+        assert_eq!(glowing.map_ke.as_ref().unwrap().path, base.join("glow.png"));
+        assert!(glowing.unsupported_maps.is_empty());
+    }
+
+    #[test]
+    fn statements_before_newmtl_are_ignored() {
+        let materials = parse_mtl("Kd 1 0 0\nmap_Kd a.png\nnewmtl a\nKd 0 1", Path::new(""));
+        assert_eq!(materials.len(), 1);
+        // Too few components
+        assert_eq!(materials[0].kd, None);
+        assert!(materials[0].map_kd.is_none());
+    }
+}
@@ -1,4 +1,10 @@
 pub mod image;
 pub mod mesh;
+pub mod mips;
+pub mod optimize;
+pub mod simplify;
+pub mod tangents;
 
+mod bc;
 mod import_gltf;
+mod import_obj;
@@ -2,8 +2,7 @@
 #![allow(unused_imports)]
 
 use byteorder::{ByteOrder, NativeEndian, WriteBytesExt};
-use glam::{Mat4, Quat, Vec3, Vec4};
-use gltf::texture::TextureTransform;
+use glam::{Affine3A, Mat4, Quat, Vec3, Vec4};
 use kajiya_backend::bytes::into_byte_vec;
 /*use render_core::{
     constants::MAX_VERTEX_STREAMS,
@@ -17,13 +16,21 @@ use kajiya_backend::bytes::into_byte_vec;
 };*/
 use anyhow::Context as _;
 use std::{
+    collections::HashMap,
     hash::Hash,
     mem::size_of,
     path::{Path, PathBuf},
 };
 use turbosloth::*;
 
-use crate::image::ImageSource;
+use crate::{
+    image::{ConversionParam, ImageConversion, ImageSource},
+    import_gltf::{MaterialExtensions, TextureTransformParams},
+    import_obj::{phong_exponent_to_roughness, MtlMaterial, MtlTexture, ObjData, ObjVertex},
+    mips::{f16_bits_to_f32, f32_to_f16_bits},
+    optimize::reorder_vertices,
+    tangents::generate_tangents,
+};
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum TexGamma {
@@ -31,10 +38,45 @@ pub enum TexGamma {
     Srgb,
 }
 
+/// Block compression applied to baked textures
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum TexCompressionMode {
+    None,
+    /// RGB; no alpha
+    Bc1,
+    /// RGBA, with alpha stored separately from color
+    Bc3,
+    /// Single channel; red
+    Bc4,
+    /// Two channels; red and green. Used for tangent-space normal maps.
+    Bc5,
+    /// RGBA
+    Bc7,
+}
+
+/// How the content of a texture is treated when generating its mip chain
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum TexMipFilter {
+    Default,
+    /// Tangent-space normals in RGB; renormalized after filtering
+    NormalMap,
+}
+
+/// Channels of a texture which materials sample
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum TexChannels {
+    Rgba,
+    /// Red only, e.g. strength masks. 16-bit sources stay single-channel at full precision.
+    R,
+}
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct TexParams {
     pub gamma: TexGamma,
     pub use_mips: bool,
+    pub compression: TexCompressionMode,
+    pub mip_filter: TexMipFilter,
+    pub channels: TexChannels,
 }
 
 #[derive(Clone, Hash, PartialEq, Eq)]
@@ -49,45 +91,276 @@ pub enum MeshMaterialMap {
 pub struct MeshMaterialFlags;
 impl MeshMaterialFlags {
     pub const MESH_MATERIAL_FLAG_EMISSIVE_USED_AS_LIGHT: u32 = 1;
+    /// Back faces are rendered, with flipped normals
+    pub const MESH_MATERIAL_FLAG_DOUBLE_SIDED: u32 = 2;
+    /// The red channel of the metalness-roughness map scales the specular reflectance of dielectrics
+    pub const MESH_MATERIAL_FLAG_SPECULAR_MAP: u32 = 4;
+}
+
+pub struct MeshMaterialAlphaMode;
+impl MeshMaterialAlphaMode {
+    pub const MESH_MATERIAL_ALPHA_MODE_OPAQUE: u32 = 0;
+    /// Surfaces with alpha below the cutoff are discarded
+    pub const MESH_MATERIAL_ALPHA_MODE_MASK: u32 = 1;
+    /// There is no blending in the deferred renderer; rendered like `MASK`
+    pub const MESH_MATERIAL_ALPHA_MODE_BLEND: u32 = 2;
 }
 
 #[derive(Clone, Copy)]
 #[repr(C)]
 pub struct MeshMaterial {
     pub base_color_mult: [f32; 4],
-    pub maps: [u32; 4],
+    /// `[normal, spec, albedo, emissive, clearcoat]`
+    pub maps: [u32; 5],
     pub roughness_mult: f32,
     pub metalness_factor: f32,
     pub emissive: [f32; 3],
     pub flags: u32,
-    pub map_transforms: [[f32; 6]; 4],
+    pub map_transforms: [[f32; 6]; 5],
+    pub transmission: f32,
+    pub ior: f32,
+    pub specular_factor: f32,
+    pub specular_color: [f32; 3],
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    /// Tint of the retro-reflective fuzz of cloth-like materials
+    pub sheen_color: [f32; 3],
+    pub sheen_roughness: f32,
+    pub alpha_mode: u32,
+    pub alpha_cutoff: f32,
+    /// Index of the UV set each map samples, ordered like `map_transforms`
+    pub map_uv_sets: [u32; 5],
 }
 
+/// Number of UV sets stored in meshes
+pub const MAX_UV_SETS: usize = 2;
+
 #[derive(Clone, Default)]
 pub struct TriangleMesh {
     pub positions: Vec<[f32; 3]>,
     pub normals: Vec<[f32; 3]>,
     pub colors: Vec<[f32; 4]>,
     pub uvs: Vec<[f32; 2]>,
+    /// Second UV set; a copy of `uvs` if the source doesn't have one
+    pub uvs1: Vec<[f32; 2]>,
     pub tangents: Vec<[f32; 4]>,
+    /// Indices into the joints of the skin the mesh is used with. Empty if the mesh isn't skinned.
+    pub joints: Vec<[u16; 4]>,
+    /// Indexed like `joints`
+    pub weights: Vec<[f32; 4]>,
+    pub morph_targets: Vec<MorphTarget>,
+    /// Default weights of `morph_targets`
+    pub morph_weights: Vec<f32>,
     pub material_ids: Vec<u32>, // per index, but can be flat shaded
     pub indices: Vec<u32>,
+    /// Progressively simplified versions of `indices`, sharing the vertices of the mesh
+    pub lods: Vec<MeshLod>,
     pub materials: Vec<MeshMaterial>, // global
     pub maps: Vec<MeshMaterialMap>,   // global
     pub images: Vec<ImageSource>,
 }
 
-fn iter_gltf_node_tree<F: FnMut(&gltf::scene::Node, Mat4)>(
-    node: &gltf::scene::Node,
-    xform: Mat4,
-    f: &mut F,
-) {
-    let node_xform = Mat4::from_cols_array_2d(&node.transform().matrix());
-    let xform = xform * node_xform;
+/// Displacements of the vertices of a mesh, indexed like `TriangleMesh::positions`
+#[derive(Clone, Default)]
+pub struct MorphTarget {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tangents: Vec<[f32; 3]>,
+}
+
+/// Limit on the simplified LODs of a mesh, not counting the full-detail one.
+/// Must match `MAX_MESH_LODS` in `mesh.hlsl`.
+pub const MAX_MESH_LODS: usize = 8;
+
+#[derive(Clone)]
+pub struct MeshLod {
+    pub indices: Vec<u32>,
+    /// How far the surface may be from the full-detail one, in object space
+    pub error: f32,
+}
+
+#[derive(Clone)]
+pub struct SceneNode {
+    pub name: String,
+    /// Parents precede their children in `TriangleScene::nodes`
+    pub parent: Option<usize>,
+    /// Index into `TriangleScene::meshes`
+    pub mesh: Option<usize>,
+    /// Index into `TriangleScene::skins`; deforms the mesh of the node
+    pub skin: Option<usize>,
+    /// Transform relative to the parent
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+#[derive(Clone)]
+pub struct Skin {
+    /// Indices into `TriangleScene::nodes`
+    pub joints: Vec<usize>,
+    /// Per joint; transforms from the space of the mesh to that of the joint in its bind pose
+    pub inverse_bind_matrices: Vec<Affine3A>,
+}
+
+pub struct AnimationProperty;
+impl AnimationProperty {
+    /// `[x, y, z]` per keyframe
+    pub const TRANSLATION: u32 = 0;
+    /// `[x, y, z, w]` quaternion per keyframe
+    pub const ROTATION: u32 = 1;
+    /// `[x, y, z]` per keyframe
+    pub const SCALE: u32 = 2;
+    /// One weight per morph target of the node's mesh, per keyframe
+    pub const MORPH_WEIGHTS: u32 = 3;
+}
+
+pub struct AnimationInterpolation;
+impl AnimationInterpolation {
+    pub const LINEAR: u32 = 0;
+    pub const STEP: u32 = 1;
+    /// Each keyframe stores an in-tangent, a value, and an out-tangent
+    pub const CUBIC_SPLINE: u32 = 2;
+}
+
+#[derive(Clone)]
+pub struct AnimationChannel {
+    /// Index into `TriangleScene::nodes`
+    pub node: usize,
+    /// One of `AnimationProperty`
+    pub property: u32,
+    /// One of `AnimationInterpolation`
+    pub interpolation: u32,
+    /// Keyframe times, in seconds
+    pub times: Vec<f32>,
+    /// Keyframe values, flattened
+    pub values: Vec<f32>,
+}
+
+#[derive(Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    pub channels: Vec<AnimationChannel>,
+}
+
+/// Perspective camera; looks along -Z of its node, with +Y up
+#[derive(Clone)]
+pub struct SceneCamera {
+    pub name: String,
+    /// Index into `TriangleScene::nodes`
+    pub node: usize,
+    /// In radians
+    pub vertical_fov: f32,
+    /// Width over height; that of the viewport if `None`
+    pub aspect_ratio: Option<f32>,
+    pub near: f32,
+    /// Infinite if `None`
+    pub far: Option<f32>,
+}
+
+pub struct SceneLightKind;
+impl SceneLightKind {
+    pub const DIRECTIONAL: u32 = 0;
+    pub const POINT: u32 = 1;
+    pub const SPOT: u32 = 2;
+}
+
+/// `KHR_lights_punctual` light. Directional and spot lights shine along -Z of their node.
+#[derive(Clone)]
+pub struct SceneLight {
+    pub name: String,
+    /// Index into `TriangleScene::nodes`
+    pub node: usize,
+    /// One of `SceneLightKind`
+    pub kind: u32,
+    pub color: [f32; 3],
+    /// Candela for point and spot lights, lux for directional ones
+    pub intensity: f32,
+    /// Infinite if `None`
+    pub range: Option<f32>,
+    /// In radians; only used by spot lights
+    pub inner_cone_angle: f32,
+    pub outer_cone_angle: f32,
+}
+
+/// Unique meshes of a scene, and the node hierarchy instancing them
+#[derive(Clone, Default)]
+pub struct TriangleScene {
+    pub meshes: Vec<TriangleMesh>,
+    pub nodes: Vec<SceneNode>,
+    /// Applied on top of the root nodes, such as the scale and rotation of the scene manifest.
+    /// Kept apart from the nodes, so that animating a root doesn't replace it.
+    pub root_transform: Affine3A,
+    pub skins: Vec<Skin>,
+    pub animations: Vec<AnimationClip>,
+    pub cameras: Vec<SceneCamera>,
+    pub lights: Vec<SceneLight>,
+    pub import_report: ImportReport,
+}
+
+/// What the importer had to fix up, or leave out, to load a scene
+#[derive(Clone, Default)]
+pub struct ImportReport {
+    /// Data which was generated or converted, such as missing normals, or triangle strips
+    pub fixed_up: Vec<String>,
+    /// Data which couldn't be imported, such as line and point primitives
+    pub dropped: Vec<String>,
+}
+
+impl ImportReport {
+    pub fn is_empty(&self) -> bool {
+        self.fixed_up.is_empty() && self.dropped.is_empty()
+    }
+
+    // Mirrored instances load meshes a second time, which reports the same things again
+    fn add_fixed_up(&mut self, entry: String) {
+        if !self.fixed_up.contains(&entry) {
+            self.fixed_up.push(entry);
+        }
+    }
+
+    fn add_dropped(&mut self, entry: String) {
+        if !self.dropped.contains(&entry) {
+            log::warn!("Skipping {}", entry);
+            self.dropped.push(entry);
+        }
+    }
+}
 
-    f(node, xform);
-    for child in node.children() {
-        iter_gltf_node_tree(&child, xform, f);
+/// How normals are generated for primitives which don't have any
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalGeneration {
+    /// Each triangle gets the normal of its plane, as the glTF specification requires
+    Flat,
+    /// Normals of the triangles around each position are averaged, weighted by their area
+    Smooth,
+}
+
+impl Default for NormalGeneration {
+    fn default() -> Self {
+        Self::Flat
+    }
+}
+
+impl TriangleScene {
+    /// Transforms of all nodes in the space of the scene, including `root_transform`, indexed like `nodes`
+    pub fn node_world_transforms(&self) -> Vec<Affine3A> {
+        let mut res: Vec<Affine3A> = Vec::with_capacity(self.nodes.len());
+
+        for node in &self.nodes {
+            let local = Affine3A::from_scale_rotation_translation(
+                node.scale,
+                node.rotation,
+                node.translation,
+            );
+
+            res.push(match node.parent {
+                Some(parent) => res[parent] * local,
+                None => self.root_transform * local,
+            });
+        }
+
+        res
     }
 }
 
@@ -100,16 +373,16 @@ fn get_gltf_texture_source(tex: gltf::texture::Texture) -> Option<String> {
 
 fn load_gltf_material(
     mat: &gltf::material::Material,
+    extensions: &MaterialExtensions,
     document_images: &[ImageSource],
 ) -> (Vec<MeshMaterialMap>, MeshMaterial) {
     const DEFAULT_MAP_TRANSFORM: [f32; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
-    let mut map_transforms: [[f32; 6]; 4] = [DEFAULT_MAP_TRANSFORM; 4];
 
-    fn texture_transform_to_matrix(xform: Option<TextureTransform>) -> [f32; 6] {
+    fn texture_transform_to_matrix(xform: Option<TextureTransformParams>) -> [f32; 6] {
         if let Some(xform) = xform {
-            let r = xform.rotation();
-            let s = xform.scale();
-            let o = xform.offset();
+            let r = xform.rotation;
+            let s = xform.scale;
+            let o = xform.offset;
 
             [
                 r.cos() * s[0],
@@ -124,44 +397,86 @@ fn load_gltf_material(
         }
     }
 
-    let (albedo_map, albedo_map_transform) =
+    // Returns the UV transform of a texture, and the index of the UV set it samples
+    fn texture_uv_params(tex_coord: u32, xform: Option<TextureTransformParams>) -> ([f32; 6], u32) {
+        let tex_coord = xform.and_then(|xform| xform.tex_coord).unwrap_or(tex_coord);
+        let uv_set = if (tex_coord as usize) < MAX_UV_SETS {
+            tex_coord
+        } else {
+            log::warn!("Unsupported UV set {}; using the first one", tex_coord);
+            0
+        };
+
+        (texture_transform_to_matrix(xform), uv_set)
+    }
+
+    fn gltf_texture_uv_params(tex: &gltf::texture::Info) -> ([f32; 6], u32) {
+        let xform = tex.texture_transform().map(|xform| TextureTransformParams {
+            offset: xform.offset(),
+            rotation: xform.rotation(),
+            scale: xform.scale(),
+            tex_coord: xform.tex_coord(),
+        });
+
+        texture_uv_params(tex.tex_coord(), xform)
+    }
+
+    const DEFAULT_MAP_UV_PARAMS: ([f32; 6], u32) = (DEFAULT_MAP_TRANSFORM, 0);
+
+    // Indexed by the `map_idx` of `transform_material_uv` in `mesh.hlsl`: `[albedo, normal, spec, emissive, clearcoat]`
+    let mut map_uv_params: [([f32; 6], u32); 5] = [DEFAULT_MAP_UV_PARAMS; 5];
+
+    let (albedo_map, albedo_map_uv_params) =
         mat.pbr_metallic_roughness().base_color_texture().map_or(
             (
                 MeshMaterialMap::Placeholder([255, 255, 255, 255]),
-                DEFAULT_MAP_TRANSFORM,
+                DEFAULT_MAP_UV_PARAMS,
             ),
             |tex| {
-                let transform = texture_transform_to_matrix(tex.texture_transform());
-
                 (
                     MeshMaterialMap::Image {
                         source: document_images[tex.texture().source().index()].clone(),
                         params: TexParams {
                             gamma: TexGamma::Srgb,
                             use_mips: true,
+                            compression: TexCompressionMode::Bc7,
+                            mip_filter: TexMipFilter::Default,
+                            channels: TexChannels::Rgba,
                         },
                     },
-                    transform,
+                    gltf_texture_uv_params(&tex),
                 )
             },
         );
 
-    map_transforms[0] = albedo_map_transform;
+    map_uv_params[0] = albedo_map_uv_params;
 
-    // TODO: add texture transform to the normal map in the `gltf` crate
-    let normal_map =
-        mat.normal_texture()
-            .map_or(MeshMaterialMap::Placeholder([127, 127, 255, 255]), |tex| {
+    // The `gltf` crate doesn't expose the transform of normal textures; it's parsed from raw JSON instead
+    let (normal_map, normal_map_uv_params) = mat.normal_texture().map_or(
+        (
+            MeshMaterialMap::Placeholder([127, 127, 255, 255]),
+            DEFAULT_MAP_UV_PARAMS,
+        ),
+        |tex| {
+            (
                 MeshMaterialMap::Image {
                     source: document_images[tex.texture().source().index()].clone(),
                     params: TexParams {
                         gamma: TexGamma::Linear,
                         use_mips: true,
+                        compression: TexCompressionMode::Bc5,
+                        mip_filter: TexMipFilter::NormalMap,
+                        channels: TexChannels::Rgba,
                     },
-                }
-            });
+                },
+                texture_uv_params(tex.tex_coord(), extensions.normal_texture_transform),
+            )
+        },
+    );
 
-    let (spec_map, spec_map_transform) = mat
+    map_uv_params[1] = normal_map_uv_params;
+
+    let (spec_map, spec_map_uv_params) = mat
         .pbr_metallic_roughness()
         .metallic_roughness_texture()
         .map_or_else(
@@ -170,7 +485,7 @@ fn load_gltf_material(
                 let metalness = 255;
                 (
                     MeshMaterialMap::Placeholder([127, roughness, metalness, 255]),
-                    DEFAULT_MAP_TRANSFORM,
+                    DEFAULT_MAP_UV_PARAMS,
                 )
             },
             |tex| {
@@ -180,28 +495,57 @@ fn load_gltf_material(
                         params: TexParams {
                             gamma: TexGamma::Linear,
                             use_mips: true,
+                            compression: TexCompressionMode::Bc7,
+                            mip_filter: TexMipFilter::Default,
+                            channels: TexChannels::Rgba,
                         },
                     },
-                    texture_transform_to_matrix(tex.texture_transform()),
+                    gltf_texture_uv_params(&tex),
                 )
             },
         );
 
-    map_transforms[2] = spec_map_transform;
+    map_uv_params[2] = spec_map_uv_params;
 
     let mut emissive_map = MeshMaterialMap::Placeholder([255, 255, 255, 255]);
     if let Some(tex) = mat.emissive_texture() {
-        map_transforms[3] = texture_transform_to_matrix(tex.texture_transform());
+        map_uv_params[3] = gltf_texture_uv_params(&tex);
         emissive_map = MeshMaterialMap::Image {
             source: document_images[tex.texture().source().index()].clone(),
             params: TexParams {
                 gamma: TexGamma::Linear,
                 use_mips: true,
+                compression: TexCompressionMode::Bc1,
+                mip_filter: TexMipFilter::Default,
+                channels: TexChannels::Rgba,
             },
         }
     }
 
-    let emissive = mat.emissive_factor();
+    // Only the red channel is used; the clearcoat factor scales it
+    let mut clearcoat_map = MeshMaterialMap::Placeholder([255, 255, 255, 255]);
+    if let Some(tex) = extensions.clearcoat_texture {
+        if let Some(source) = document_images.get(tex.image) {
+            map_uv_params[4] = texture_uv_params(tex.tex_coord, tex.transform);
+            clearcoat_map = MeshMaterialMap::Image {
+                source: source.clone(),
+                params: TexParams {
+                    gamma: TexGamma::Linear,
+                    use_mips: true,
+                    compression: TexCompressionMode::Bc4,
+                    mip_filter: TexMipFilter::Default,
+                    channels: TexChannels::R,
+                },
+            }
+        } else {
+            log::warn!("Clearcoat texture refers to a missing image {}", tex.image);
+        }
+    }
+
+    // `KHR_materials_emissive_strength` only exists to go past the [0, 1] range of the factor
+    let emissive = mat
+        .emissive_factor()
+        .map(|v| v * extensions.emissive_strength);
 
     let base_color_mult = mat.pbr_metallic_roughness().base_color_factor();
     let roughness_mult = mat.pbr_metallic_roughness().roughness_factor();
@@ -209,16 +553,52 @@ fn load_gltf_material(
 
     //mata.normal_texture().and_then(|tex| tex.transform())
 
+    let mut flags = 0;
+    if mat.double_sided() {
+        flags |= MeshMaterialFlags::MESH_MATERIAL_FLAG_DOUBLE_SIDED;
+    }
+
+    // Blended materials get the same cutoff as masked ones, so that mostly transparent parts disappear.
+    let (alpha_mode, alpha_cutoff) = match mat.alpha_mode() {
+        gltf::material::AlphaMode::Opaque => {
+            (MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_OPAQUE, 0.0)
+        }
+        gltf::material::AlphaMode::Mask => (
+            MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_MASK,
+            mat.alpha_cutoff().unwrap_or(0.5),
+        ),
+        gltf::material::AlphaMode::Blend => {
+            (MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_BLEND, 0.5)
+        }
+    };
+
     (
-        vec![normal_map, spec_map, albedo_map, emissive_map],
+        vec![
+            normal_map,
+            spec_map,
+            albedo_map,
+            emissive_map,
+            clearcoat_map,
+        ],
         MeshMaterial {
             base_color_mult,
-            maps: [0, 1, 2, 3],
+            maps: [0, 1, 2, 3, 4],
             roughness_mult,
             metalness_factor,
             emissive,
-            flags: 0,
-            map_transforms,
+            flags,
+            map_transforms: map_uv_params.map(|(transform, _)| transform),
+            transmission: extensions.transmission,
+            ior: extensions.ior,
+            specular_factor: extensions.specular_factor,
+            specular_color: extensions.specular_color,
+            clearcoat: extensions.clearcoat,
+            clearcoat_roughness: extensions.clearcoat_roughness,
+            sheen_color: extensions.sheen_color,
+            sheen_roughness: extensions.sheen_roughness,
+            alpha_mode,
+            alpha_cutoff,
+            map_uv_sets: map_uv_params.map(|(_, uv_set)| uv_set),
         },
     )
 }
@@ -228,6 +608,8 @@ pub struct LoadGltfScene {
     pub path: PathBuf,
     pub scale: f32,
     pub rotation: Quat,
+    /// Used for primitives without normals
+    pub normals: NormalGeneration,
 }
 
 impl Hash for LoadGltfScene {
@@ -238,239 +620,1234 @@ impl Hash for LoadGltfScene {
         self.rotation.y.to_ne_bytes().hash(state);
         self.rotation.z.to_ne_bytes().hash(state);
         self.rotation.w.to_ne_bytes().hash(state);
+        self.normals.hash(state);
     }
 }
 
-#[async_trait]
-impl LazyWorker for LoadGltfScene {
-    type Output = anyhow::Result<TriangleMesh>;
+impl LoadGltfScene {
+    /// Files the scene geometry is loaded from. Used for change detection.
+    pub fn source_files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        crate::import_gltf::buffer_source_files(&self.path)
+            .with_context(|| format!("Reading GLTF scene dependencies of {:?}", self.path))
+    }
+}
 
-    async fn run(self, _ctx: RunContext) -> Self::Output {
-        let (gltf, buffers, imgs) = crate::import_gltf::import(&self.path)
-            .with_context(|| format!("Loading GLTF scene from {:?}", self.path))?;
+/// Triangle list with the triangles of a strip, as ordered by the glTF specification
+fn triangle_strip_to_list(indices: &[u32]) -> Vec<u32> {
+    (0..indices.len().saturating_sub(2))
+        .flat_map(|i| {
+            let odd = i % 2;
+            [indices[i], indices[i + 1 + odd], indices[i + 2 - odd]]
+        })
+        .collect()
+}
 
-        if let Some(scene) = gltf.default_scene().or_else(|| gltf.scenes().next()) {
-            let mut res: TriangleMesh = TriangleMesh::default();
+/// Triangle list with the triangles of a fan, as ordered by the glTF specification
+fn triangle_fan_to_list(indices: &[u32]) -> Vec<u32> {
+    (0..indices.len().saturating_sub(2))
+        .flat_map(|i| [indices[i + 1], indices[i + 2], indices[0]])
+        .collect()
+}
 
-            let mut process_node = |node: &gltf::scene::Node, xform: Mat4| {
-                if let Some(mesh) = node.mesh() {
-                    let flip_winding_order = xform.determinant() < 0.0;
+/// Generates normals for the vertices and triangles of a mesh starting at `first_vertex`
+/// and `first_index`. Flat normals need vertices of their own for each triangle.
+fn generate_normals(
+    mesh: &mut TriangleMesh,
+    first_vertex: usize,
+    first_index: usize,
+    generation: NormalGeneration,
+) {
+    let triangle_normal = |mesh: &TriangleMesh, tri: &[u32]| {
+        let [p0, p1, p2] = [0, 1, 2].map(|i| Vec3::from(mesh.positions[tri[i] as usize]));
+        // Not normalized, so that larger triangles weigh more when summed
+        (p1 - p0).cross(p2 - p0)
+    };
+    let normalize = |n: Vec3| {
+        if n.length_squared() > 0.0 {
+            n.normalize()
+        } else {
+            Vec3::Y
+        }
+    };
 
-                    for prim in mesh.primitives() {
-                        let reader = prim.reader(|buffer| Some(&buffers[buffer.index()]));
+    match generation {
+        NormalGeneration::Flat => {
+            let order: Vec<u32> = (0..first_vertex as u32)
+                .chain(mesh.indices[first_index..].iter().copied())
+                .collect();
+            reorder_vertices(mesh, &order);
 
-                        let res_material_index = res.materials.len() as u32;
+            for (i, idx) in mesh.indices[first_index..].iter_mut().enumerate() {
+                *idx = (first_vertex + i) as u32;
+            }
 
-                        {
-                            let (mut maps, mut material) =
-                                load_gltf_material(&prim.material(), imgs.as_slice());
+            for tri_start in (first_index..mesh.indices.len()).step_by(3) {
+                let tri = &mesh.indices[tri_start..tri_start + 3];
+                let normal = normalize(triangle_normal(mesh, tri)).to_array();
 
-                            let map_base = res.maps.len() as u32;
-                            for id in material.maps.iter_mut() {
-                                *id += map_base;
-                            }
+                for i in 0..3 {
+                    let idx = mesh.indices[tri_start + i] as usize;
+                    mesh.normals[idx] = normal;
+                }
+            }
+        }
+        NormalGeneration::Smooth => {
+            // Vertices split along UV seams get the same normal
+            let position_key = |p: [f32; 3]| p.map(f32::to_bits);
+            let mut position_normals: HashMap<[u32; 3], Vec3> = HashMap::new();
+
+            for tri in mesh.indices[first_index..].chunks_exact(3) {
+                let normal = triangle_normal(mesh, tri);
+                for &idx in tri {
+                    *position_normals
+                        .entry(position_key(mesh.positions[idx as usize]))
+                        .or_default() += normal;
+                }
+            }
 
-                            res.materials.push(material);
-                            res.maps.append(&mut maps);
-                        }
+            for idx in first_vertex..mesh.positions.len() {
+                let normal = position_normals
+                    .get(&position_key(mesh.positions[idx]))
+                    .copied()
+                    .unwrap_or_default();
+                mesh.normals[idx] = normalize(normal).to_array();
+            }
+        }
+    }
+}
 
-                        // Collect positions (required)
-                        let positions = if let Some(iter) = reader.read_positions() {
-                            iter.collect::<Vec<_>>()
-                        } else {
-                            return;
-                        };
-
-                        // Collect normals (required)
-                        let normals = if let Some(iter) = reader.read_normals() {
-                            iter.collect::<Vec<_>>()
-                        } else {
-                            return;
-                        };
-
-                        // Collect tangents (optional)
-                        let tangents = if let Some(iter) = reader.read_tangents() {
-                            iter.collect::<Vec<_>>()
-                        } else {
-                            vec![[1.0, 0.0, 0.0, 0.0]; positions.len()]
-                        };
-
-                        // Collect uvs (optional)
-                        let mut uvs = if let Some(iter) = reader.read_tex_coords(0) {
-                            iter.into_f32().collect::<Vec<_>>()
-                        } else {
-                            vec![[0.0, 0.0]; positions.len()]
-                        };
-
-                        // Collect colors (optional)
-                        let mut colors = if let Some(iter) = reader.read_colors(0) {
-                            iter.into_rgba_f32().collect::<Vec<_>>()
-                        } else {
-                            vec![[1.0, 1.0, 1.0, 1.0]; positions.len()]
-                        };
-
-                        // Collect material ids
-                        let mut material_ids = vec![res_material_index; positions.len()];
-
-                        // --------------------------------------------------------
-                        // Write it all to the output
-
-                        {
-                            let mut indices: Vec<u32>;
-                            let base_index = res.positions.len() as u32;
-
-                            if let Some(indices_reader) = reader.read_indices() {
-                                indices =
-                                    indices_reader.into_u32().map(|i| i + base_index).collect();
-                            } else {
-                                indices =
-                                    (base_index..(base_index + positions.len() as u32)).collect();
-                            }
-
-                            if flip_winding_order {
-                                for tri in indices.chunks_exact_mut(3) {
-                                    tri.swap(0, 2);
-                                }
-                            }
-
-                            // log::info!("Loading a mesh with {} indices", indices.len());
-
-                            res.indices.append(&mut indices);
-                            res.colors.append(&mut colors);
-                            res.material_ids.append(&mut material_ids);
-                        }
+/// Loads the primitives of a glTF mesh in its own space. Mirroring instances need
+/// `flip_winding_order`, so that triangles keep facing outwards after the transform.
+///
+/// Strips and fans are converted to triangle lists, and missing normals and tangents are generated.
+/// Primitives which can't be loaded are skipped. Both are noted in the `report`.
+fn load_gltf_mesh(
+    mesh: &gltf::Mesh,
+    flip_winding_order: bool,
+    normal_generation: NormalGeneration,
+    buffers: &[bytes::Bytes],
+    imgs: &[ImageSource],
+    material_extensions: &[MaterialExtensions],
+    report: &mut ImportReport,
+) -> TriangleMesh {
+    let mut res = TriangleMesh::default();
+
+    let mesh_name = match mesh.name() {
+        Some(name) => format!("mesh {:?}", name),
+        None => format!("mesh {}", mesh.index()),
+    };
 
-                        for v in positions {
-                            let pos = (xform * Vec3::from(v).extend(1.0)).truncate();
-                            res.positions.push(pos.into());
-                        }
+    // Indexed like the vertices of `res`
+    let mut missing_tangents: Vec<bool> = Vec::new();
+
+    // All primitives of a mesh have the same number of morph targets
+    let morph_target_count = mesh
+        .primitives()
+        .map(|prim| prim.morph_targets().count())
+        .max()
+        .unwrap_or(0);
+    res.morph_targets = vec![MorphTarget::default(); morph_target_count];
+    res.morph_weights = mesh
+        .weights()
+        .map(|weights| weights.to_vec())
+        .unwrap_or_default();
+    res.morph_weights.resize(morph_target_count, 0.0);
+
+    for (prim_idx, prim) in mesh.primitives().enumerate() {
+        let prim_name = format!("primitive {} of {}", prim_idx, mesh_name);
+        let reader = prim.reader(|buffer| Some(&buffers[buffer.index()]));
+
+        // Points and lines can't be rendered
+        let mode = prim.mode();
+        if !matches!(
+            mode,
+            gltf::mesh::Mode::Triangles
+                | gltf::mesh::Mode::TriangleStrip
+                | gltf::mesh::Mode::TriangleFan
+        ) {
+            report.add_dropped(format!("{}: {:?} are not supported", prim_name, mode));
+            continue;
+        }
 
-                        for v in normals {
-                            let norm = (xform * Vec3::from(v).extend(0.0)).truncate().normalize();
-                            res.normals.push(norm.into());
-                        }
+        // Collect positions (required)
+        let positions = if let Some(iter) = reader.read_positions() {
+            iter.collect::<Vec<_>>()
+        } else {
+            report.add_dropped(format!("{}: it has no positions", prim_name));
+            continue;
+        };
 
-                        for v in tangents {
-                            let v = Vec4::from(v);
-                            let t = (xform * v.truncate().extend(0.0)).truncate().normalize();
-                            res.tangents.push(
-                                t.extend(v.w * if flip_winding_order { -1.0 } else { 1.0 })
-                                    .into(),
-                            );
-                        }
+        // Collect indices, as a triangle list
+        let indices: Vec<u32> = if let Some(indices_reader) = reader.read_indices() {
+            indices_reader.into_u32().collect()
+        } else {
+            (0..positions.len() as u32).collect()
+        };
 
-                        res.uvs.append(&mut uvs);
-                    }
+        if indices.iter().any(|&idx| idx as usize >= positions.len()) {
+            report.add_dropped(format!("{}: indices are out of bounds", prim_name));
+            continue;
+        }
+
+        let indices = match mode {
+            gltf::mesh::Mode::TriangleStrip => {
+                report.add_fixed_up(format!(
+                    "{}: converted a triangle strip to a list",
+                    prim_name
+                ));
+                triangle_strip_to_list(&indices)
+            }
+            gltf::mesh::Mode::TriangleFan => {
+                report.add_fixed_up(format!("{}: converted a triangle fan to a list", prim_name));
+                triangle_fan_to_list(&indices)
+            }
+            _ => {
+                let mut indices = indices;
+                if indices.len() % 3 != 0 {
+                    report.add_fixed_up(format!(
+                        "{}: ignored {} indices past the last whole triangle",
+                        prim_name,
+                        indices.len() % 3
+                    ));
+                    indices.truncate(indices.len() / 3 * 3);
                 }
-            };
+                indices
+            }
+        };
 
-            let xform = Mat4::from_scale_rotation_translation(
-                Vec3::splat(self.scale),
-                self.rotation,
-                Vec3::ZERO,
-            );
-            for node in scene.nodes() {
-                iter_gltf_node_tree(&node, xform, &mut process_node);
+        if indices.is_empty() {
+            report.add_dropped(format!("{}: it has no triangles", prim_name));
+            continue;
+        }
+
+        let res_material_index = res.materials.len() as u32;
+
+        {
+            let material = prim.material();
+            let extensions = material
+                .index()
+                .and_then(|idx| material_extensions.get(idx))
+                .copied()
+                .unwrap_or_default();
+
+            let (mut maps, mut material) = load_gltf_material(&material, &extensions, imgs);
+
+            let map_base = res.maps.len() as u32;
+            for id in material.maps.iter_mut() {
+                *id += map_base;
             }
 
-            Ok(res)
-        } else {
-            Err(anyhow::anyhow!("No default scene found in gltf"))
+            res.materials.push(material);
+            res.maps.append(&mut maps);
         }
-    }
-}
 
-#[derive(Clone, Copy)]
-#[repr(C)]
-pub struct PackedVertex {
-    pub pos: [f32; 3],
-    normal: u32,
-}
+        // Collect normals (optional); generated once the primitive is written
+        let normals_missing = reader.read_normals().is_none();
+        let normals = if let Some(iter) = reader.read_normals() {
+            iter.collect::<Vec<_>>()
+        } else {
+            vec![[0.0, 0.0, 0.0]; positions.len()]
+        };
 
-fn pack_unit_direction_11_10_11(x: f32, y: f32, z: f32) -> u32 {
-    let x = ((x.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 11u32) - 1u32) as f32) as u32;
-    let y = ((y.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 10u32) - 1u32) as f32) as u32;
-    let z = ((z.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 11u32) - 1u32) as f32) as u32;
+        // Collect tangents (optional); generated once the whole mesh is loaded
+        let tangents_missing = reader.read_tangents().is_none();
+        let tangents = if let Some(iter) = reader.read_tangents() {
+            iter.collect::<Vec<_>>()
+        } else {
+            vec![[1.0, 0.0, 0.0, 0.0]; positions.len()]
+        };
 
-    (z << 21) | (y << 11) | x
-}
+        // Collect uvs (optional)
+        let mut uvs = if let Some(iter) = reader.read_tex_coords(0) {
+            iter.into_f32().collect::<Vec<_>>()
+        } else {
+            vec![[0.0, 0.0]; positions.len()]
+        };
 
-#[repr(packed)]
-pub struct FlatVec<T> {
-    len: u64,
-    offset: u64,
-    marker: std::marker::PhantomData<T>,
-}
+        let mut uvs1 = if let Some(iter) = reader.read_tex_coords(1) {
+            iter.into_f32().collect::<Vec<_>>()
+        } else {
+            uvs.clone()
+        };
 
-impl<T> std::borrow::Borrow<[T]> for FlatVec<T> {
-    fn borrow(&self) -> &[T] {
-        unsafe {
-            let data = (self as *const Self as *const u8).add(self.offset as usize);
-            std::slice::from_raw_parts(data as *const T, self.len as usize)
+        // Collect colors (optional)
+        let mut colors = if let Some(iter) = reader.read_colors(0) {
+            iter.into_rgba_f32().collect::<Vec<_>>()
+        } else {
+            vec![[1.0, 1.0, 1.0, 1.0]; positions.len()]
+        };
+
+        // Collect skinning data (optional)
+        let mut joints = if let Some(iter) = reader.read_joints(0) {
+            iter.into_u16().collect::<Vec<_>>()
+        } else {
+            vec![[0, 0, 0, 0]; positions.len()]
+        };
+
+        let mut weights = if let Some(iter) = reader.read_weights(0) {
+            iter.into_f32().collect::<Vec<_>>()
+        } else {
+            vec![[0.0, 0.0, 0.0, 0.0]; positions.len()]
+        };
+
+        // Collect morph targets (optional); missing attributes don't displace anything
+        {
+            let mut targets = reader.read_morph_targets();
+            for target in res.morph_targets.iter_mut() {
+                let (target_positions, target_normals, target_tangents) =
+                    targets.next().unwrap_or((None, None, None));
+
+                let zeros = || vec![[0.0, 0.0, 0.0]; positions.len()];
+                target
+                    .positions
+                    .extend(target_positions.map_or_else(zeros, |iter| iter.collect()));
+                target
+                    .normals
+                    .extend(target_normals.map_or_else(zeros, |iter| iter.collect()));
+                target
+                    .tangents
+                    .extend(target_tangents.map_or_else(zeros, |iter| iter.collect()));
+            }
         }
-    }
-}
 
-impl<T> std::ops::Index<usize> for FlatVec<T> {
-    type Output = T;
+        // Collect material ids
+        let mut material_ids = vec![res_material_index; positions.len()];
 
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.as_slice()[index]
-    }
-}
+        // --------------------------------------------------------
+        // Write it all to the output
 
-impl<T> FlatVec<T> {
-    #[inline(always)]
-    pub fn len(&self) -> usize {
-        self.len as usize
+        let base_vertex = res.positions.len();
+        let base_index = res.indices.len();
+
+        {
+            let base_vertex = base_vertex as u32;
+            res.indices
+                .extend(indices.iter().map(|&idx| idx + base_vertex));
+            res.colors.append(&mut colors);
+            res.material_ids.append(&mut material_ids);
+        }
+
+        res.positions.extend(positions);
+        res.normals.extend(normals);
+        res.tangents.extend(tangents);
+        res.joints.append(&mut joints);
+        res.weights.append(&mut weights);
+
+        res.uvs.append(&mut uvs);
+        res.uvs1.append(&mut uvs1);
+
+        if normals_missing {
+            generate_normals(&mut res, base_vertex, base_index, normal_generation);
+            report.add_fixed_up(format!(
+                "{}: generated {} normals",
+                prim_name,
+                match normal_generation {
+                    NormalGeneration::Flat => "flat",
+                    NormalGeneration::Smooth => "smooth",
+                }
+            ));
+        }
+
+        if tangents_missing {
+            report.add_fixed_up(format!("{}: generated tangents", prim_name));
+        }
+        missing_tangents.resize(res.positions.len(), tangents_missing);
     }
 
-    #[inline(always)]
-    pub fn is_empty(&self) -> bool {
-        self.len == 0
+    if res.weights.iter().all(|w| *w == [0.0, 0.0, 0.0, 0.0]) {
+        res.joints.clear();
+        res.weights.clear();
     }
 
-    #[inline(always)]
-    pub fn as_slice(&self) -> &[T] {
-        unsafe {
-            let data = (std::ptr::addr_of!(self.offset) as *const u64 as *const u8)
-                .add(self.offset as usize);
-            std::slice::from_raw_parts(data as *const T, self.len as usize)
-        }
+    // Tangents follow the winding of the source, which is what `w` is relative to
+    if missing_tangents.contains(&true) {
+        generate_tangents(&mut res, &missing_tangents);
     }
 
-    #[inline(always)]
-    pub fn as_ptr(&self) -> *const T {
-        unsafe {
-            let data = (std::ptr::addr_of!(self.offset) as *const u64 as *const u8)
-                .add(self.offset as usize);
-            data as *const T
+    if flip_winding_order {
+        for tri in res.indices.chunks_exact_mut(3) {
+            tri.swap(0, 2);
         }
     }
 
-    #[inline(always)]
-    pub fn iter(&self) -> impl Iterator<Item = &T> {
-        self.as_slice().iter()
-    }
+    res
 }
 
-pub fn flatten_vec_header(writer: &mut Vec<u8>, len: usize) -> usize {
-    writer
-        .write_u64::<byteorder::NativeEndian>(len as u64)
-        .unwrap();
-    writer.write_u64::<byteorder::NativeEndian>(0u64).unwrap();
-    writer.len() - 8
-}
+fn load_gltf_skin(
+    skin: &gltf::Skin,
+    buffers: &[bytes::Bytes],
+    node_indices: &HashMap<usize, usize>,
+) -> Option<Skin> {
+    let joints = skin
+        .joints()
+        .map(|joint| node_indices.get(&joint.index()).copied())
+        .collect::<Option<Vec<_>>>()?;
+
+    let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    // Identity matrices are implied if the skin doesn't have any
+    let inverse_bind_matrices = if let Some(iter) = reader.read_inverse_bind_matrices() {
+        iter.map(|m| Affine3A::from_mat4(Mat4::from_cols_array_2d(&m)))
+            .collect()
+    } else {
+        vec![Affine3A::IDENTITY; joints.len()]
+    };
 
-pub fn flatten_plain_field<T: Copy + Sized>(writer: &mut impl std::io::Write, data: &T) {
-    writer
-        .write_all(unsafe {
-            std::slice::from_raw_parts(data as *const T as *const u8, std::mem::size_of::<T>())
-        })
-        .unwrap();
+    Some(Skin {
+        joints,
+        inverse_bind_matrices,
+    })
 }
 
-pub fn flatten_bytes(writer: &mut impl std::io::Write, data: &[u8]) {
+fn load_gltf_animation(
+    animation: &gltf::Animation,
+    buffers: &[bytes::Bytes],
+    node_indices: &HashMap<usize, usize>,
+) -> AnimationClip {
+    use gltf::animation::{util::ReadOutputs, Interpolation};
+
+    let channels = animation
+        .channels()
+        .filter_map(|channel| {
+            // Nodes outside of the scene can't be animated
+            let node = *node_indices.get(&channel.target().node().index())?;
+
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times = reader.read_inputs()?.collect::<Vec<f32>>();
+
+            let (property, values) = match reader.read_outputs()? {
+                ReadOutputs::Translations(iter) => {
+                    (AnimationProperty::TRANSLATION, iter.flatten().collect())
+                }
+                ReadOutputs::Rotations(iter) => (
+                    AnimationProperty::ROTATION,
+                    iter.into_f32().flatten().collect(),
+                ),
+                ReadOutputs::Scales(iter) => (AnimationProperty::SCALE, iter.flatten().collect()),
+                ReadOutputs::MorphTargetWeights(iter) => {
+                    (AnimationProperty::MORPH_WEIGHTS, iter.into_f32().collect())
+                }
+            };
+
+            let interpolation = match channel.sampler().interpolation() {
+                Interpolation::Linear => AnimationInterpolation::LINEAR,
+                Interpolation::Step => AnimationInterpolation::STEP,
+                Interpolation::CubicSpline => AnimationInterpolation::CUBIC_SPLINE,
+            };
+
+            Some(AnimationChannel {
+                node,
+                property,
+                interpolation,
+                times,
+                values,
+            })
+        })
+        .collect();
+
+    AnimationClip {
+        name: animation.name().unwrap_or_default().to_owned(),
+        channels,
+    }
+}
+
+fn load_gltf_camera(camera: &gltf::Camera, node: usize) -> Option<SceneCamera> {
+    match camera.projection() {
+        gltf::camera::Projection::Perspective(perspective) => Some(SceneCamera {
+            name: camera.name().unwrap_or_default().to_owned(),
+            node,
+            vertical_fov: perspective.yfov(),
+            aspect_ratio: perspective.aspect_ratio(),
+            near: perspective.znear(),
+            far: perspective.zfar(),
+        }),
+        gltf::camera::Projection::Orthographic(_) => None,
+    }
+}
+
+fn load_gltf_light(light: &gltf::khr_lights_punctual::Light, node: usize) -> SceneLight {
+    use gltf::khr_lights_punctual::Kind;
+
+    let (kind, inner_cone_angle, outer_cone_angle) = match light.kind() {
+        Kind::Directional => (SceneLightKind::DIRECTIONAL, 0.0, 0.0),
+        Kind::Point => (SceneLightKind::POINT, 0.0, 0.0),
+        Kind::Spot {
+            inner_cone_angle,
+            outer_cone_angle,
+        } => (SceneLightKind::SPOT, inner_cone_angle, outer_cone_angle),
+    };
+
+    SceneLight {
+        name: light.name().unwrap_or_default().to_owned(),
+        node,
+        kind,
+        color: light.color(),
+        intensity: light.intensity(),
+        range: light.range(),
+        inner_cone_angle,
+        outer_cone_angle,
+    }
+}
+
+#[async_trait]
+impl LazyWorker for LoadGltfScene {
+    type Output = anyhow::Result<TriangleScene>;
+
+    async fn run(self, _ctx: RunContext) -> Self::Output {
+        let (gltf, buffers, imgs, material_extensions) = crate::import_gltf::import(&self.path)
+            .with_context(|| format!("Loading GLTF scene from {:?}", self.path))?;
+
+        let scene = gltf
+            .default_scene()
+            .or_else(|| gltf.scenes().next())
+            .ok_or_else(|| anyhow::anyhow!("No default scene found in gltf"))?;
+
+        let mut res = TriangleScene::default();
+
+        // Each glTF mesh is loaded once, plus once more if any instance of it is mirrored
+        let mut mesh_variants: HashMap<(usize, bool), usize> = HashMap::new();
+
+        // glTF node index to index into `res.nodes`
+        let mut node_indices: HashMap<usize, usize> = HashMap::new();
+
+        // Skins reference joint nodes, so they're resolved once all nodes are known
+        let mut skinned_nodes: Vec<(usize, gltf::Skin)> = Vec::new();
+
+        res.root_transform = Affine3A::from_scale_rotation_translation(
+            Vec3::splat(self.scale),
+            self.rotation,
+            Vec3::ZERO,
+        );
+
+        // Depth-first, so that parents precede their children
+        let root_xform = Mat4::from(res.root_transform);
+        let mut stack: Vec<(gltf::scene::Node, Option<usize>, Mat4)> =
+            scene.nodes().map(|node| (node, None, root_xform)).collect();
+        stack.reverse();
+
+        while let Some((node, parent, parent_xform)) = stack.pop() {
+            let (translation, rotation, scale) = node.transform().decomposed();
+            let local_xform = Mat4::from_scale_rotation_translation(
+                scale.into(),
+                Quat::from_xyzw(rotation[0], rotation[1], rotation[2], rotation[3]),
+                translation.into(),
+            );
+
+            let xform = parent_xform * local_xform;
+
+            let mesh = if let Some(mesh) = node.mesh() {
+                let flip_winding_order = xform.determinant() < 0.0;
+
+                Some(
+                    *mesh_variants
+                        .entry((mesh.index(), flip_winding_order))
+                        .or_insert_with(|| {
+                            res.meshes.push(load_gltf_mesh(
+                                &mesh,
+                                flip_winding_order,
+                                self.normals,
+                                &buffers,
+                                &imgs,
+                                &material_extensions,
+                                &mut res.import_report,
+                            ));
+                            res.meshes.len() - 1
+                        }),
+                )
+            } else {
+                None
+            };
+
+            let (scale, rotation, translation) = local_xform.to_scale_rotation_translation();
+
+            let node_idx = res.nodes.len();
+            res.nodes.push(SceneNode {
+                name: node.name().unwrap_or_default().to_owned(),
+                parent,
+                mesh,
+                skin: None,
+                translation,
+                rotation,
+                scale,
+            });
+            node_indices.insert(node.index(), node_idx);
+
+            // Cameras and lights without names of their own go by that of their node
+            let node_name = node.name().unwrap_or_default();
+
+            if let Some(camera) = node.camera() {
+                if let Some(mut camera) = load_gltf_camera(&camera, node_idx) {
+                    if camera.name.is_empty() {
+                        camera.name = node_name.to_owned();
+                    }
+                    res.cameras.push(camera);
+                } else {
+                    res.import_report.add_dropped(format!(
+                        "camera {}: orthographic cameras are not supported",
+                        camera.index()
+                    ));
+                }
+            }
+
+            if let Some(light) = node.light() {
+                let mut light = load_gltf_light(&light, node_idx);
+                if light.name.is_empty() {
+                    light.name = node_name.to_owned();
+                }
+                res.lights.push(light);
+            }
+
+            if let (Some(skin), Some(_)) = (node.skin(), mesh) {
+                skinned_nodes.push((node_idx, skin));
+            }
+
+            let first_child = stack.len();
+            stack.extend(node.children().map(|child| (child, Some(node_idx), xform)));
+            stack[first_child..].reverse();
+        }
+
+        let mut skin_indices: HashMap<usize, Option<usize>> = HashMap::new();
+        for (node_idx, skin) in skinned_nodes {
+            let skins = &mut res.skins;
+            let report = &mut res.import_report;
+            res.nodes[node_idx].skin = *skin_indices.entry(skin.index()).or_insert_with(|| {
+                if let Some(loaded) = load_gltf_skin(&skin, &buffers, &node_indices) {
+                    skins.push(loaded);
+                    Some(skins.len() - 1)
+                } else {
+                    report.add_dropped(format!(
+                        "skin {}: it references nodes outside of the scene",
+                        skin.index()
+                    ));
+                    None
+                }
+            });
+        }
+
+        res.animations = gltf
+            .animations()
+            .map(|animation| load_gltf_animation(&animation, &buffers, &node_indices))
+            .collect();
+
+        Ok(res)
+    }
+}
+
+/// Maps an MTL material to the metallic-roughness model. `Ns` is converted to roughness,
+/// and `Ks` scales and tints the specular reflectance of dielectrics. The `Pr` and `Pm`
+/// parameters of the PBR extension take precedence where present.
+fn load_obj_material(mtl: &MtlMaterial) -> (Vec<MeshMaterialMap>, MeshMaterial) {
+    const DEFAULT_MAP_TRANSFORM: [f32; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+    // `-o` and `-s` apply to the UVs of the OBJ file, whose V was flipped on import:
+    // `1 - (s * (1 - v) + o) = s * v + (1 - s - o)`
+    let map_transform = |tex: &MtlTexture| {
+        [
+            tex.scale[0],
+            0.0,
+            0.0,
+            tex.scale[1],
+            tex.offset[0],
+            1.0 - tex.scale[1] - tex.offset[1],
+        ]
+    };
+
+    // Maps combined into one image have to share their UV transform
+    let combined_map_transform = |a: Option<&MtlTexture>, b: Option<&MtlTexture>| {
+        match (a, b) {
+            (Some(a), Some(b)) if map_transform(a) != map_transform(b) => log::warn!(
+                "Material {:?}: {:?} and {:?} are combined, but have different -o or -s options; using those of the former",
+                mtl.name,
+                a.path,
+                b.path
+            ),
+            _ => (),
+        }
+        a.or(b).map_or(DEFAULT_MAP_TRANSFORM, map_transform)
+    };
+
+    let file = |tex: &MtlTexture| ImageSource::File(tex.path.clone());
+    let converted = |conversion: ImageConversion| ImageSource::Converted(Box::new(conversion));
+
+    // Indexed by the `map_idx` of `transform_material_uv` in `mesh.hlsl`: `[albedo, normal, spec, emissive, clearcoat]`
+    let mut map_transforms = [DEFAULT_MAP_TRANSFORM; 5];
+
+    // Opacity maps become the alpha of the albedo map, which the alpha test reads
+    let albedo_source = match (&mtl.map_kd, &mtl.map_d) {
+        (kd, Some(d)) => Some(converted(ImageConversion::ReplaceAlpha {
+            color: kd.as_ref().map(file),
+            alpha: file(d),
+        })),
+        (Some(kd), None) => Some(file(kd)),
+        (None, None) => None,
+    };
+    map_transforms[0] = combined_map_transform(mtl.map_kd.as_ref(), mtl.map_d.as_ref());
+
+    let albedo_map = if let Some(source) = albedo_source {
+        MeshMaterialMap::Image {
+            source,
+            params: TexParams {
+                gamma: TexGamma::Srgb,
+                use_mips: true,
+                compression: TexCompressionMode::Bc7,
+                mip_filter: TexMipFilter::Default,
+                channels: TexChannels::Rgba,
+            },
+        }
+    } else {
+        MeshMaterialMap::Placeholder([255, 255, 255, 255])
+    };
+
+    // `norm` is a normal map; `bump` is a height map, which gets converted to one
+    let normal_source = if let Some(tex) = &mtl.map_normal {
+        Some((file(tex), tex))
+    } else {
+        mtl.map_bump.as_ref().map(|tex| {
+            (
+                converted(ImageConversion::HeightToNormal {
+                    height: file(tex),
+                    scale: ConversionParam(tex.bump_multiplier),
+                }),
+                tex,
+            )
+        })
+    };
+
+    let normal_map = if let Some((source, tex)) = normal_source {
+        map_transforms[1] = map_transform(tex);
+        MeshMaterialMap::Image {
+            source,
+            params: TexParams {
+                gamma: TexGamma::Linear,
+                use_mips: true,
+                compression: TexCompressionMode::Bc5,
+                mip_filter: TexMipFilter::NormalMap,
+                channels: TexChannels::Rgba,
+            },
+        }
+    } else {
+        MeshMaterialMap::Placeholder([127, 127, 255, 255])
+    };
+
+    // `map_Ns` scales `Ns`, which is at most 1000
+    let max_exponent = mtl.ns.unwrap_or(1000.0);
+
+    let mut flags = 0;
+    let spec_map = if mtl.map_ks.is_some() || mtl.map_ns.is_some() {
+        if mtl.map_ks.is_some() {
+            flags |= MeshMaterialFlags::MESH_MATERIAL_FLAG_SPECULAR_MAP;
+        }
+
+        map_transforms[2] = combined_map_transform(mtl.map_ns.as_ref(), mtl.map_ks.as_ref());
+        MeshMaterialMap::Image {
+            source: converted(ImageConversion::PhongToMetalnessRoughness {
+                specular: mtl.map_ks.as_ref().map(file),
+                exponent: mtl.map_ns.as_ref().map(file),
+                max_exponent: ConversionParam(max_exponent),
+            }),
+            params: TexParams {
+                gamma: TexGamma::Linear,
+                use_mips: true,
+                compression: TexCompressionMode::Bc7,
+                mip_filter: TexMipFilter::Default,
+                channels: TexChannels::Rgba,
+            },
+        }
+    } else {
+        MeshMaterialMap::Placeholder([127, 255, 255, 255])
+    };
+
+    let emissive_map = if let Some(tex) = &mtl.map_ke {
+        map_transforms[3] = map_transform(tex);
+        MeshMaterialMap::Image {
+            source: file(tex),
+            params: TexParams {
+                gamma: TexGamma::Linear,
+                use_mips: true,
+                compression: TexCompressionMode::Bc1,
+                mip_filter: TexMipFilter::Default,
+                channels: TexChannels::Rgba,
+            },
+        }
+    } else {
+        MeshMaterialMap::Placeholder([255, 255, 255, 255])
+    };
+
+    if !mtl.unsupported_maps.is_empty() {
+        log::warn!(
+            "Material {:?}: ignoring unsupported maps {:?}",
+            mtl.name,
+            mtl.unsupported_maps
+        );
+    }
+
+    // With a `map_Ns`, the exponent is in the map
+    let roughness_mult = if mtl.map_ns.is_some() {
+        1.0
+    } else {
+        mtl.pr
+            .unwrap_or_else(|| mtl.ns.map_or(0.5, phong_exponent_to_roughness))
+    };
+
+    let (specular_factor, specular_color) = mtl.ks.map_or((1.0, [1.0; 3]), |ks| {
+        let max = ks[0].max(ks[1]).max(ks[2]);
+        if max > 0.0 {
+            (max.min(1.0), ks.map(|c| c / max))
+        } else {
+            (0.0, [1.0; 3])
+        }
+    });
+
+    let kd = mtl.kd.unwrap_or([1.0; 3]);
+
+    // Opacity maps cut out surfaces, like masked glTF materials. Nothing gets blended,
+    // so uniformly translucent materials become transmissive instead of disappearing.
+    let (alpha_mode, alpha_cutoff) = if mtl.map_d.is_some() {
+        (MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_MASK, 0.5)
+    } else {
+        (MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_OPAQUE, 0.0)
+    };
+    let transmission = if mtl.map_d.is_none() {
+        1.0 - mtl.d.unwrap_or(1.0).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    (
+        vec![
+            normal_map,
+            spec_map,
+            albedo_map,
+            emissive_map,
+            // MTL has no clearcoat
+            MeshMaterialMap::Placeholder([255, 255, 255, 255]),
+        ],
+        MeshMaterial {
+            base_color_mult: [kd[0], kd[1], kd[2], 1.0],
+            maps: [0, 1, 2, 3, 4],
+            roughness_mult,
+            metalness_factor: mtl.pm.unwrap_or(0.0),
+            emissive: mtl.ke.unwrap_or([0.0; 3]),
+            flags,
+            map_transforms,
+            transmission,
+            ior: mtl.ni.filter(|&ni| ni >= 1.0).unwrap_or(1.5),
+            specular_factor,
+            specular_color,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            sheen_color: [0.0; 3],
+            sheen_roughness: 0.0,
+            alpha_mode,
+            alpha_cutoff,
+            map_uv_sets: [0; 5],
+        },
+    )
+}
+
+/// Loads a Wavefront OBJ file and its MTL materials as a single mesh
+#[derive(Clone)]
+pub struct LoadObjScene {
+    pub path: PathBuf,
+    pub scale: f32,
+    pub rotation: Quat,
+}
+
+impl Hash for LoadObjScene {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+        self.scale.to_ne_bytes().hash(state);
+        self.rotation.x.to_ne_bytes().hash(state);
+        self.rotation.y.to_ne_bytes().hash(state);
+        self.rotation.z.to_ne_bytes().hash(state);
+        self.rotation.w.to_ne_bytes().hash(state);
+    }
+}
+
+impl LoadObjScene {
+    /// Files the scene geometry is loaded from. Used for change detection.
+    pub fn source_files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        crate::import_obj::source_files(&self.path)
+            .with_context(|| format!("Reading OBJ scene dependencies of {:?}", self.path))
+    }
+}
+
+/// Area-weighted normals of the positions of an OBJ file, for faces which don't specify any
+fn obj_position_normals(obj: &ObjData) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; obj.positions.len()];
+
+    for face in &obj.faces {
+        let p0 = Vec3::from(obj.positions[face.verts[0].position as usize]);
+        for tri in face.verts[1..].windows(2) {
+            let p1 = Vec3::from(obj.positions[tri[0].position as usize]);
+            let p2 = Vec3::from(obj.positions[tri[1].position as usize]);
+            let normal = (p1 - p0).cross(p2 - p0);
+
+            for vert in [face.verts[0], tri[0], tri[1]] {
+                normals[vert.position as usize] += normal;
+            }
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|n| {
+            if n.length_squared() > 0.0 {
+                n.normalize()
+            } else {
+                Vec3::Y
+            }
+        })
+        .collect()
+}
+
+#[async_trait]
+impl LazyWorker for LoadObjScene {
+    type Output = anyhow::Result<TriangleScene>;
+
+    async fn run(self, _ctx: RunContext) -> Self::Output {
+        let obj = crate::import_obj::import(&self.path)
+            .with_context(|| format!("Loading OBJ scene from {:?}", self.path))?;
+
+        let mut mesh = TriangleMesh::default();
+
+        // Faces without a material get the last one, which is the default
+        let default_material = MtlMaterial::default();
+        for mtl in obj
+            .materials
+            .iter()
+            .chain(std::iter::once(&default_material))
+        {
+            let (mut maps, mut material) = load_obj_material(mtl);
+
+            let map_base = mesh.maps.len() as u32;
+            for id in material.maps.iter_mut() {
+                *id += map_base;
+            }
+
+            mesh.materials.push(material);
+            mesh.maps.append(&mut maps);
+        }
+
+        let default_material_id = obj.materials.len() as u32;
+        let position_normals = obj_position_normals(&obj);
+
+        // Corners of faces sharing all attributes and the material share vertices
+        let mut vertex_indices: HashMap<(ObjVertex, u32), u32> = HashMap::new();
+        let mut vertices_without_normals = 0usize;
+
+        for face in &obj.faces {
+            let material_id = face.material.map_or(default_material_id, |idx| idx as u32);
+
+            let mut corner_indices = Vec::with_capacity(face.verts.len());
+            for &vert in &face.verts {
+                let index = *vertex_indices
+                    .entry((vert, material_id))
+                    .or_insert_with(|| {
+                        let position = vert.position as usize;
+
+                        mesh.positions.push(obj.positions[position]);
+                        mesh.normals.push(vert.normal.map_or_else(
+                            || {
+                                vertices_without_normals += 1;
+                                position_normals[position].into()
+                            },
+                            |normal| obj.normals[normal as usize],
+                        ));
+
+                        let uv = vert.uv.map_or([0.0, 0.0], |uv| obj.uvs[uv as usize]);
+                        mesh.uvs.push(uv);
+                        mesh.uvs1.push(uv);
+
+                        let color = obj.colors.get(position).copied().unwrap_or([1.0; 3]);
+                        mesh.colors.push([color[0], color[1], color[2], 1.0]);
+
+                        mesh.tangents.push([1.0, 0.0, 0.0, 0.0]);
+                        mesh.material_ids.push(material_id);
+
+                        mesh.positions.len() as u32 - 1
+                    });
+
+                corner_indices.push(index);
+            }
+
+            // Triangulate as a fan
+            for tri in corner_indices[1..].windows(2) {
+                mesh.indices
+                    .extend_from_slice(&[corner_indices[0], tri[0], tri[1]]);
+            }
+        }
+
+        if mesh.indices.is_empty() {
+            anyhow::bail!("{:?} contains no faces", self.path);
+        }
+
+        let mut import_report = ImportReport::default();
+        if vertices_without_normals > 0 {
+            import_report.add_fixed_up(format!(
+                "generated smooth normals for {} vertices",
+                vertices_without_normals
+            ));
+        }
+
+        // OBJ has no tangents
+        let missing_tangents = vec![true; mesh.positions.len()];
+        generate_tangents(&mut mesh, &missing_tangents);
+        import_report.add_fixed_up("generated tangents".to_owned());
+
+        Ok(TriangleScene {
+            meshes: vec![mesh],
+            nodes: vec![SceneNode {
+                name: self
+                    .path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                parent: None,
+                mesh: Some(0),
+                skin: None,
+                translation: Vec3::ZERO,
+                rotation: Quat::IDENTITY,
+                scale: Vec3::ONE,
+            }],
+            root_transform: Affine3A::from_scale_rotation_translation(
+                Vec3::splat(self.scale),
+                self.rotation,
+                Vec3::ZERO,
+            ),
+            import_report,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct PackedVertex {
+    pub pos: [f32; 3],
+    normal: u32,
+}
+
+fn pack_unit_direction_11_10_11(x: f32, y: f32, z: f32) -> u32 {
+    let x = ((x.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 11u32) - 1u32) as f32) as u32;
+    let y = ((y.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 10u32) - 1u32) as f32) as u32;
+    let z = ((z.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 11u32) - 1u32) as f32) as u32;
+
+    (z << 21) | (y << 11) | x
+}
+
+/// Position and normal of a vertex, with the position quantized as described by `VertexEncoding`
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct QuantizedVertex {
+    /// Half-floats or normalized integers; the last one is padding, so that the positions
+    /// can be read as a four-component format when building acceleration structures.
+    pub pos: [u16; 4],
+    normal: u32,
+}
+
+pub struct VertexEncodingFlags;
+impl VertexEncodingFlags {
+    pub const POSITION_MASK: u32 = 3;
+    /// `PackedVertex`
+    pub const POSITION_F32: u32 = 0;
+    /// `QuantizedVertex` with half-float positions
+    pub const POSITION_F16: u32 = 1;
+    /// `QuantizedVertex` with signed normalized positions
+    pub const POSITION_SNORM16: u32 = 2;
+    /// Both UV sets as pairs of half-floats
+    pub const UV_F16: u32 = 4;
+    /// Tangents as octahedral directions: 16 bits of X, 15 bits of Y, and the bitangent sign in the top bit.
+    /// `OCTAHEDRAL_NO_TANGENT` for vertices without a tangent.
+    pub const TANGENT_OCTAHEDRAL: u32 = 8;
+    /// Colors as RGBA8 unorm
+    pub const COLOR_RGBA8: u32 = 16;
+}
+
+/// How the vertex streams of a mesh are stored
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct VertexEncoding {
+    /// Combination of `VertexEncodingFlags`
+    pub flags: u32,
+    /// Quantized positions are multiplied by `position_scale`, then offset by `position_offset`
+    pub position_offset: [f32; 3],
+    pub position_scale: [f32; 3],
+}
+
+impl Default for VertexEncoding {
+    fn default() -> Self {
+        Self {
+            flags: 0,
+            position_offset: [0.0; 3],
+            position_scale: [1.0; 3],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum PositionQuantization {
+    F32,
+    /// Half-floats, relative to the center of the mesh's bounds
+    F16,
+    /// 16-bit normalized integers spanning the mesh's bounds
+    Snorm16,
+}
+
+/// Vertex streams to quantize when packing meshes. Meshes with joints or morph targets
+/// are always stored unquantized, since the skinning pass writes their streams as floats.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct VertexQuantization {
+    pub positions: PositionQuantization,
+    /// Half-float UVs
+    pub uvs: bool,
+    /// Octahedral tangents
+    pub tangents: bool,
+    /// RGBA8 colors
+    pub colors: bool,
+}
+
+impl Default for VertexQuantization {
+    fn default() -> Self {
+        Self {
+            positions: PositionQuantization::F32,
+            uvs: false,
+            tangents: false,
+            colors: false,
+        }
+    }
+}
+
+impl VertexQuantization {
+    /// Quantizes all the streams, with positions as normalized integers
+    pub fn all() -> Self {
+        Self {
+            positions: PositionQuantization::Snorm16,
+            uvs: true,
+            tangents: true,
+            colors: true,
+        }
+    }
+}
+
+fn pack_uv_f16(uv: [f32; 2]) -> u32 {
+    u32::from(f32_to_f16_bits(uv[0])) | (u32::from(f32_to_f16_bits(uv[1])) << 16)
+}
+
+// Matches `octa_decode` in `pack_unpack.hlsl`. Tangents with a zero `w`, which make
+// shaders skip normal mapping, pack to `OCTAHEDRAL_NO_TANGENT`.
+fn pack_tangent_octahedral(t: [f32; 4]) -> u32 {
+    if t[3] == 0.0 {
+        return OCTAHEDRAL_NO_TANGENT;
+    }
+
+    let v = Vec3::new(t[0], t[1], t[2]);
+    let l1 = v.x.abs() + v.y.abs() + v.z.abs();
+    let v = if l1 > 0.0 { v / l1 } else { Vec3::X };
+
+    let sign_not_zero = |x: f32| if x >= 0.0 { 1.0 } else { -1.0 };
+    let (x, y) = if v.z < 0.0 {
+        (
+            (1.0 - v.y.abs()) * sign_not_zero(v.x),
+            (1.0 - v.x.abs()) * sign_not_zero(v.y),
+        )
+    } else {
+        (v.x, v.y)
+    };
+
+    let x = ((x * 0.5 + 0.5).clamp(0.0, 1.0) * 65535.0).round() as u32;
+    let y = ((y * 0.5 + 0.5).clamp(0.0, 1.0) * 32767.0).round() as u32;
+    let sign = u32::from(t[3] < 0.0);
+
+    // Only directions right next to -Z round to the reserved value; nudge them by a step
+    (x | (y << 16) | (sign << 31)).max(1)
+}
+
+/// Packed octahedral tangent of vertices without a tangent frame. Valid tangents never pack to it.
+pub const OCTAHEDRAL_NO_TANGENT: u32 = 0;
+
+fn pack_color_rgba8(c: [f32; 4]) -> u32 {
+    c.iter().enumerate().fold(0, |packed, (i, &c)| {
+        packed | (((c.clamp(0.0, 1.0) * 255.0).round() as u32) << (i * 8))
+    })
+}
+
+#[repr(packed)]
+pub struct FlatVec<T> {
+    len: u64,
+    offset: u64,
+    marker: std::marker::PhantomData<T>,
+}
+
+impl<T> std::borrow::Borrow<[T]> for FlatVec<T> {
+    fn borrow(&self) -> &[T] {
+        unsafe {
+            let data = (self as *const Self as *const u8).add(self.offset as usize);
+            std::slice::from_raw_parts(data as *const T, self.len as usize)
+        }
+    }
+}
+
+impl<T> std::ops::Index<usize> for FlatVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.as_slice()[index]
+    }
+}
+
+impl<T> FlatVec<T> {
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline(always)]
+    pub fn as_slice(&self) -> &[T] {
+        unsafe {
+            let data = (std::ptr::addr_of!(self.offset) as *const u64 as *const u8)
+                .add(self.offset as usize);
+            std::slice::from_raw_parts(data as *const T, self.len as usize)
+        }
+    }
+
+    #[inline(always)]
+    pub fn as_ptr(&self) -> *const T {
+        unsafe {
+            let data = (std::ptr::addr_of!(self.offset) as *const u64 as *const u8)
+                .add(self.offset as usize);
+            data as *const T
+        }
+    }
+
+    #[inline(always)]
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.as_slice().iter()
+    }
+}
+
+pub fn flatten_vec_header(writer: &mut Vec<u8>, len: usize) -> usize {
+    writer
+        .write_u64::<byteorder::NativeEndian>(len as u64)
+        .unwrap();
+    writer.write_u64::<byteorder::NativeEndian>(0u64).unwrap();
+    writer.len() - 8
+}
+
+pub fn flatten_plain_field<T: Copy + Sized>(writer: &mut impl std::io::Write, data: &T) {
+    writer
+        .write_all(unsafe {
+            std::slice::from_raw_parts(data as *const T as *const u8, std::mem::size_of::<T>())
+        })
+        .unwrap();
+}
+
+pub fn flatten_bytes(writer: &mut impl std::io::Write, data: &[u8]) {
     writer.write_all(data).unwrap();
 }
 
@@ -486,22 +1863,31 @@ pub struct FlattenCtx {
     pub deferred: Vec<DeferredBlob>,
 }
 
+const SECTION_ALIGNMENT: usize = 8;
+
 impl FlattenCtx {
+    // Breadth-first, matching the order in which `finish` lays out the sections
     fn allocate_section_indices(&mut self) {
         let mut counter = 0;
-        self.allocate_section_indices_impl(&mut counter);
-    }
+        let mut ctx_list: Vec<&mut Self> = vec![self];
+
+        while !ctx_list.is_empty() {
+            let mut next_ctx_list: Vec<&mut Self> = vec![];
+
+            for ctx in ctx_list {
+                ctx.section_idx = Some(counter);
+                counter += 1;
 
-    fn allocate_section_indices_impl(&mut self, counter: &mut usize) {
-        self.section_idx = Some(*counter);
-        *counter += 1;
+                for child in &mut ctx.deferred {
+                    next_ctx_list.push(&mut child.nested);
+                }
+            }
 
-        for child in &mut self.deferred {
-            child.nested.allocate_section_indices_impl(counter);
+            ctx_list = next_ctx_list;
         }
     }
 
-    fn finish(mut self, writer: &mut impl std::io::Write) {
+    fn finish(mut self) -> Vec<u8> {
         self.allocate_section_indices();
 
         type FixupAddr = usize;
@@ -537,6 +1923,13 @@ impl FlattenCtx {
             ctx_list = next_ctx_list;
         }
 
+        // Pad the sections, so that each one starts suitably aligned for its contents
+        for section in &mut sections {
+            let padded_len =
+                (section.bytes.len() + SECTION_ALIGNMENT - 1) & !(SECTION_ALIGNMENT - 1);
+            section.bytes.resize(padded_len, 0);
+        }
+
         // Lay out the sections
         let mut total_bytes = 0usize;
         let section_base_addr: Vec<usize> = sections
@@ -548,24 +1941,269 @@ impl FlattenCtx {
             })
             .collect();
 
-        // Apply fixups
-        for (section, &section_addr) in sections.iter_mut().zip(&section_base_addr) {
-            for &(fixup_addr, target_section) in &section.fixups {
-                // Apply the fixup of the structure which was pointing at this deferred blob
-                let fixup_target: u64 = section_base_addr[target_section] as u64;
+        // Apply fixups
+        for (section, &section_addr) in sections.iter_mut().zip(&section_base_addr) {
+            for &(fixup_addr, target_section) in &section.fixups {
+                // Apply the fixup of the structure which was pointing at this deferred blob
+                let fixup_target: u64 = section_base_addr[target_section] as u64;
+
+                // The fixup is relative to the `offset` field itself
+                let fixup_relative = fixup_target - (fixup_addr + section_addr) as u64;
+
+                section.bytes[fixup_addr..fixup_addr + 8]
+                    .copy_from_slice(&fixup_relative.to_ne_bytes());
+            }
+        }
+
+        sections
+            .into_iter()
+            .flat_map(|section| section.bytes)
+            .collect()
+    }
+}
+
+/// Starts every baked asset file, ahead of the flattened asset
+#[derive(Clone, Copy, Debug)]
+pub struct AssetHeader {
+    /// `ASSET_MAGIC`
+    pub magic: [u8; 8],
+    /// `FlatAsset::TYPE_TAG` of the asset
+    pub type_tag: u64,
+    /// `ASSET_FORMAT_VERSION` of the `bake` which wrote the file
+    pub version: u32,
+    /// `ASSET_ENDIANNESS_MARKER`, in the byte order of the machine which wrote the file
+    pub endianness: u32,
+    /// Size of the flattened asset following the header, in bytes
+    pub size: u64,
+    /// `asset_checksum` of the flattened asset
+    pub checksum: u64,
+}
+
+pub const ASSET_MAGIC: [u8; 8] = *b"KJYASSET";
+
+/// Bump whenever the layout of any flattened asset changes
+pub const ASSET_FORMAT_VERSION: u32 = 2;
+
+pub const ASSET_ENDIANNESS_MARKER: u32 = 0x0102_0304;
+
+impl AssetHeader {
+    pub const SIZE: usize = 40;
+
+    fn new(type_tag: u64, data: &[u8]) -> Self {
+        Self {
+            magic: ASSET_MAGIC,
+            type_tag,
+            version: ASSET_FORMAT_VERSION,
+            endianness: ASSET_ENDIANNESS_MARKER,
+            size: data.len() as u64,
+            checksum: asset_checksum(data),
+        }
+    }
+
+    fn write(&self, writer: &mut impl std::io::Write) -> std::io::Result<()> {
+        writer.write_all(&self.magic)?;
+        writer.write_u64::<NativeEndian>(self.type_tag)?;
+        writer.write_u32::<NativeEndian>(self.version)?;
+        writer.write_u32::<NativeEndian>(self.endianness)?;
+        writer.write_u64::<NativeEndian>(self.size)?;
+        writer.write_u64::<NativeEndian>(self.checksum)
+    }
+
+    /// Reads the header at the start of a baked asset file. Only checks the magic number.
+    pub fn read(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() < Self::SIZE {
+            anyhow::bail!(
+                "Too small to be a baked asset: {} bytes, but the header alone is {}",
+                bytes.len(),
+                Self::SIZE
+            );
+        }
+
+        let magic: [u8; 8] = bytes[0..8].try_into().unwrap();
+        if magic != ASSET_MAGIC {
+            anyhow::bail!(
+                "Not a baked asset, or baked by a version of `bake` without asset headers"
+            );
+        }
+
+        Ok(Self {
+            magic,
+            type_tag: NativeEndian::read_u64(&bytes[8..16]),
+            version: NativeEndian::read_u32(&bytes[16..20]),
+            endianness: NativeEndian::read_u32(&bytes[20..24]),
+            size: NativeEndian::read_u64(&bytes[24..32]),
+            checksum: NativeEndian::read_u64(&bytes[32..40]),
+        })
+    }
+
+    /// Checks that the header describes a compatible asset file of `T`, and that the file is complete
+    pub fn validate<T: FlatAsset>(&self, bytes: &[u8]) -> anyhow::Result<()> {
+        if self.endianness == ASSET_ENDIANNESS_MARKER.swap_bytes() {
+            anyhow::bail!("Baked on a machine with a different byte order");
+        } else if self.endianness != ASSET_ENDIANNESS_MARKER {
+            anyhow::bail!(
+                "Corrupt header: bad endianness marker {:#x}",
+                self.endianness
+            );
+        }
+
+        if self.version != ASSET_FORMAT_VERSION {
+            anyhow::bail!(
+                "Baked with asset format version {}, but version {} is expected. Re-run `bake`.",
+                self.version,
+                ASSET_FORMAT_VERSION
+            );
+        }
+
+        if self.type_tag != T::TYPE_TAG {
+            anyhow::bail!(
+                "Expected a {} asset, but the file contains a different type of asset (type tag {:#x})",
+                T::TYPE_NAME,
+                self.type_tag
+            );
+        }
+
+        let expected_len = (Self::SIZE as u64).checked_add(self.size);
+        if expected_len != Some(bytes.len() as u64) {
+            anyhow::bail!(
+                "Expected {} bytes of asset data after the header, but the file has {}",
+                self.size,
+                bytes.len() - Self::SIZE
+            );
+        }
+
+        let checksum = asset_checksum(&bytes[Self::SIZE..]);
+        if checksum != self.checksum {
+            anyhow::bail!(
+                "Checksum mismatch: expected {:#x}, got {:#x}",
+                self.checksum,
+                checksum
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Catches corruption, not tampering. Runs over 64-bit words, so that validating
+/// large assets doesn't take much longer than reading them in.
+///
+/// Validation hashes the whole asset every time it's loaded, so mapping an asset
+/// touches all of its pages up front, rather than as they get used. That's at
+/// memory bandwidth for files in the page cache, and at disk speed for cold ones.
+pub fn asset_checksum(bytes: &[u8]) -> u64 {
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut mix = |word: u64| hash = (hash ^ word).wrapping_mul(PRIME).rotate_left(29);
+
+    let mut words = bytes.chunks_exact(8);
+    for word in &mut words {
+        mix(NativeEndian::read_u64(word));
+    }
+    for &byte in words.remainder() {
+        mix(u64::from(byte));
+    }
+
+    hash
+}
+
+/// Top-level types of baked asset files; implemented by `def_asset!` for the `Flat` structs
+pub trait FlatAsset: Sized {
+    const TYPE_NAME: &'static str;
+    const TYPE_TAG: u64;
+
+    /// Checks that all vectors of the flattened asset at the start of `data` are within it
+    fn validate(data: &[u8]) -> anyhow::Result<()>;
+}
+
+/// FNV-1a of the name of an asset type
+pub const fn asset_type_tag(type_name: &str) -> u64 {
+    let bytes = type_name.as_bytes();
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash = (hash ^ bytes[i] as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Checks the values stored in a flattened asset which `FlatAsset::validate` found to be
+/// within bounds: that indices point into the vectors they index, and that vectors indexed
+/// alike have the same length. Users of baked assets index with these values without checking.
+pub trait ValidateContents {
+    fn validate_contents(&self) -> anyhow::Result<()>;
+}
+
+/// Validates the header and contents of a baked asset file, and returns the asset in it.
+/// See `asset_checksum` for what that costs.
+///
+/// Other assets referenced with `AssetRef`s are separate files, and get validated when they're read.
+pub fn read_flat_asset<T: FlatAsset + ValidateContents>(bytes: &[u8]) -> anyhow::Result<&T> {
+    AssetHeader::read(bytes)?.validate::<T>(bytes)?;
+
+    let data = &bytes[AssetHeader::SIZE..];
+    T::validate(data)?;
+
+    let asset = unsafe { &*(data.as_ptr() as *const T) };
+    asset.validate_contents()?;
+
+    Ok(asset)
+}
 
-                // The fixup is relative to the `offset` field itself
-                let fixup_relative = fixup_target - (fixup_addr + section_addr) as u64;
+fn validate_index(what: &str, idx: u32, len: usize) -> anyhow::Result<()> {
+    if idx as usize >= len {
+        anyhow::bail!("{} {} is out of range; there are only {}", what, idx, len);
+    }
+    Ok(())
+}
 
-                section.bytes[fixup_addr..fixup_addr + 8]
-                    .copy_from_slice(&fixup_relative.to_ne_bytes());
+fn validate_len(what: &str, len: usize, expected: usize) -> anyhow::Result<()> {
+    if len != expected {
+        anyhow::bail!("Expected {} {}, but there are {}", expected, what, len);
+    }
+    Ok(())
+}
+
+/// Checks the header of the `FlatVec` at `offset` within `data`, and that its elements are within `data`.
+/// Returns the offset of the first element, and the number of elements.
+pub fn validate_flat_vec<T>(data: &[u8], offset: usize) -> anyhow::Result<(usize, usize)> {
+    let header = offset
+        .checked_add(16)
+        .and_then(|end| data.get(offset..end))
+        .with_context(|| format!("Vector header at {} is out of bounds", offset))?;
+
+    let len = NativeEndian::read_u64(&header[0..8]);
+    // Relative to the `offset` field itself
+    let relative_offset = NativeEndian::read_u64(&header[8..16]);
+
+    let start = (offset as u64 + 8).checked_add(relative_offset);
+    let end = len
+        .checked_mul(size_of::<T>() as u64)
+        .zip(start)
+        .and_then(|(byte_len, start)| start.checked_add(byte_len));
+
+    match (start, end) {
+        (Some(start), Some(end)) if end <= data.len() as u64 => {
+            let start = start as usize;
+            if (data.as_ptr() as usize + start) % std::mem::align_of::<T>() != 0 {
+                anyhow::bail!(
+                    "Vector data at {} is misaligned for {}",
+                    start,
+                    std::any::type_name::<T>()
+                );
             }
-        }
 
-        // Write sections out
-        for section in sections {
-            writer.write_all(section.bytes.as_slice()).unwrap();
+            Ok((start, len as usize))
         }
+        _ => anyhow::bail!(
+            "Vector at {} with {} elements at relative offset {} exceeds the asset size of {} bytes",
+            offset,
+            len,
+            relative_offset,
+            data.len()
+        ),
     }
 }
 
@@ -588,6 +2226,20 @@ macro_rules! def_asset {
             nested,
         });
     };
+    (@has_vectors Vec($($type:tt)+)) => {
+        true
+    };
+    (@validate $data:expr; $offset:expr; Vec($($type:tt)+)) => {
+        let (items_offset, item_count) =
+            validate_flat_vec::<def_asset!(@flat_ty $($type)+ )>($data, $offset)?;
+        if def_asset!(@has_vectors $($type)+ ) {
+            for item_idx in 0..item_count {
+                let item_offset =
+                    items_offset + item_idx * size_of::<def_asset!(@flat_ty $($type)+ )>();
+                def_asset!(@validate $data; item_offset; $($type)+ );
+            }
+        }
+    };
 
     // Bytes
     (@proto_ty Bytes) => {
@@ -605,6 +2257,12 @@ macro_rules! def_asset {
             nested,
         });
     };
+    (@has_vectors Bytes) => {
+        true
+    };
+    (@validate $data:expr; $offset:expr; Bytes) => {
+        validate_flat_vec::<u8>($data, $offset)?;
+    };
 
     // Asset
     (@proto_ty Asset($($type:tt)+)) => {
@@ -620,8 +2278,31 @@ macro_rules! def_asset {
         };
         flatten_plain_field(&mut $output.bytes, &asset_ref)
     };
+    (@has_vectors Asset($($type:tt)+)) => {
+        false
+    };
+    (@validate $data:expr; $offset:expr; Asset($($type:tt)+)) => {
+        let _ = $offset;
+    };
 
 
+    // Another asset struct, stored inline
+    (@proto_ty Nested($($type:tt)+)) => {
+        $($type)+ ::Proto
+    };
+    (@flat_ty Nested($($type:tt)+)) => {
+        $($type)+ ::Flat
+    };
+    (@flatten $output:expr; $field:expr; Nested($($type:tt)+)) => {
+        $field.flatten_fields($output)
+    };
+    (@has_vectors Nested($($type:tt)+)) => {
+        true
+    };
+    (@validate $data:expr; $offset:expr; Nested($($type:tt)+)) => {
+        $($type)+ ::Flat::validate_at($data, $offset)?;
+    };
+
     // Plain type
     (@proto_ty $($type:tt)+) => {
         $($type)+
@@ -632,6 +2313,12 @@ macro_rules! def_asset {
     (@flatten $output:expr; $field:expr; $($type:tt)+) => {
         flatten_plain_field(&mut $output.bytes, $field)
     };
+    (@has_vectors $($type:tt)+) => {
+        false
+    };
+    (@validate $data:expr; $offset:expr; $($type:tt)+) => {
+        let _ = $offset;
+    };
 
     (
         $(
@@ -661,120 +2348,853 @@ macro_rules! def_asset {
                 )*
             }
 
-            impl Proto {
-                pub fn flatten_into(&self, writer: &mut impl std::io::Write) {
-                    let mut output = FlattenCtx {
-                        bytes: Vec::new(),
-                        deferred: Default::default(),
-                        section_idx: None,
-                    };
+            impl Proto {
+                /// Writes the asset, preceded by an `AssetHeader`
+                pub fn flatten_into(&self, writer: &mut impl std::io::Write) {
+                    let mut output = FlattenCtx {
+                        bytes: Vec::new(),
+                        deferred: Default::default(),
+                        section_idx: None,
+                    };
+
+                    self.flatten_fields(&mut output);
+                    let data = output.finish();
+
+                    AssetHeader::new(<Flat as FlatAsset>::TYPE_TAG, &data)
+                        .write(writer)
+                        .unwrap();
+                    writer.write_all(&data).unwrap();
+                }
+
+                pub fn flatten_fields(&self, output: &mut FlattenCtx) {
+                    $(
+                        def_asset!(@flatten output; &self.$name; $($type)+ );
+                    )*
+                }
+            }
+
+            impl Flat {
+                /// Checks that the struct at `offset` within `data`, and all vectors
+                /// reachable from it, are within `data`
+                #[allow(unused_assignments)]
+                pub fn validate_at(data: &[u8], offset: usize) -> anyhow::Result<()> {
+                    if offset
+                        .checked_add(size_of::<Self>())
+                        .map_or(true, |end| end > data.len())
+                    {
+                        anyhow::bail!(
+                            "{} at {} exceeds the asset size of {} bytes",
+                            stringify!($struct_name),
+                            offset,
+                            data.len()
+                        );
+                    }
+
+                    let mut field_offset = offset;
+                    $(
+                        def_asset!(@validate data; field_offset; $($type)+ );
+                        field_offset += size_of::<def_asset!(@flat_ty $($type)+ )>();
+                    )*
+
+                    Ok(())
+                }
+            }
+
+            impl FlatAsset for Flat {
+                const TYPE_NAME: &'static str = stringify!($struct_name);
+                const TYPE_TAG: u64 = asset_type_tag(stringify!($struct_name));
+
+                fn validate(data: &[u8]) -> anyhow::Result<()> {
+                    Self::validate_at(data, 0)
+                }
+            }
+        }
+    };
+}
+
+#[repr(C)]
+pub struct AssetRef<T> {
+    identity: u64,
+    marker: std::marker::PhantomData<fn(&T)>,
+}
+impl<T> AssetRef<T> {
+    pub fn identity(&self) -> u64 {
+        self.identity
+    }
+}
+impl<T> Clone for AssetRef<T> {
+    fn clone(&self) -> Self {
+        Self {
+            identity: self.identity,
+            marker: std::marker::PhantomData,
+        }
+    }
+}
+impl<T> Copy for AssetRef<T> {}
+impl<T> PartialEq for AssetRef<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.identity == other.identity
+    }
+}
+impl<T> Eq for AssetRef<T> {}
+impl<T> Hash for AssetRef<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_u64(self.identity)
+    }
+}
+impl<T> PartialOrd for AssetRef<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.identity.partial_cmp(&other.identity)
+    }
+}
+impl<T> Ord for AssetRef<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.identity.cmp(&other.identity)
+    }
+}
+
+// TODO: use `rkyv` instead
+def_asset! {
+    GpuImage {
+        format { kajiya_backend::ash::vk::Format }
+        extent { [u32; 3] }
+        mips { Vec(Vec(u8)) }
+    }
+}
+
+// TODO: use `rkyv` instead
+def_asset! {
+    #[derive(Clone)]
+    PackedTriMesh {
+        vertex_encoding { VertexEncoding }
+        // Each stream is stored either as floats, or quantized, as indicated by `vertex_encoding`.
+        // The other one is empty.
+        verts { Vec(PackedVertex) }
+        quantized_verts { Vec(QuantizedVertex) }
+        uvs { Vec([f32; 2]) }
+        uvs1 { Vec([f32; 2]) }
+        quantized_uvs { Vec(u32) }
+        quantized_uvs1 { Vec(u32) }
+        tangents { Vec([f32; 4]) }
+        quantized_tangents { Vec(u32) }
+        colors { Vec([f32; 4]) }
+        quantized_colors { Vec(u32) }
+        // Empty if the mesh isn't skinned
+        joints { Vec([u16; 4]) }
+        weights { Vec([f32; 4]) }
+        morph_targets { Vec(Nested(PackedMorphTarget)) }
+        // Default weights of `morph_targets`
+        morph_weights { Vec(f32) }
+        indices { Vec(u32) }
+        // Coarser versions of `indices`, from the most detailed one
+        lods { Vec(Nested(PackedMeshLod)) }
+        material_ids { Vec(u32) }
+        materials { Vec(MeshMaterial) }
+        maps { Vec(Asset(GpuImage)) }
+    }
+}
+
+def_asset! {
+    #[derive(Clone)]
+    PackedMeshLod {
+        // Single-sided triangles come first, like in the full-detail index buffer
+        indices { Vec(u32) }
+        // Object-space distance of the simplified surface from the full-detail one
+        error { f32 }
+    }
+}
+
+def_asset! {
+    #[derive(Clone)]
+    PackedMorphTarget {
+        // Indexed like the vertices of the mesh
+        positions { Vec([f32; 3]) }
+        normals { Vec([f32; 3]) }
+        tangents { Vec([f32; 3]) }
+    }
+}
+
+/*#[derive(Clone)]
+pub struct PackedTriangleMesh {
+    pub verts: Vec<PackedVertex>,
+    pub uvs: Vec<[f32; 2]>,
+    pub uvs1: Vec<[f32; 2]>,
+    pub tangents: Vec<[f32; 4]>,
+    pub colors: Vec<[f32; 4]>,
+    pub indices: Vec<u32>,
+    pub material_ids: Vec<u32>,
+    pub materials: Vec<MeshMaterial>,
+    pub maps: Vec<MeshMaterialMap>,
+}*/
+
+pub type PackedTriangleMesh = PackedTriMesh::Proto;
+
+impl PackedTriMesh::Flat {
+    pub fn vertex_count(&self) -> usize {
+        self.verts.len().max(self.quantized_verts.len())
+    }
+
+    /// Object-space position of a vertex, decoded according to `vertex_encoding`
+    pub fn vertex_position(&self, idx: usize) -> [f32; 3] {
+        let encoding = self.vertex_encoding;
+        let position = encoding.flags & VertexEncodingFlags::POSITION_MASK;
+
+        if position == VertexEncodingFlags::POSITION_F32 {
+            return self.verts[idx].pos;
+        }
+
+        let pos = self.quantized_verts[idx].pos;
+        let decode = |i: usize| {
+            let v = if position == VertexEncodingFlags::POSITION_F16 {
+                f16_bits_to_f32(pos[i])
+            } else {
+                (pos[i] as i16 as f32 / 32767.0).max(-1.0)
+            };
+            v * encoding.position_scale[i] + encoding.position_offset[i]
+        };
+
+        [decode(0), decode(1), decode(2)]
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct PackedSceneNode {
+    /// Index of the parent node, or `PackedSceneNode::NONE` for roots.
+    /// Parents precede their children.
+    pub parent: u32,
+    /// Index into the meshes of the scene, or `PackedSceneNode::NONE`
+    pub mesh: u32,
+    /// Index into the skins of the scene, or `PackedSceneNode::NONE`
+    pub skin: u32,
+    /// Transform relative to the parent
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl PackedSceneNode {
+    pub const NONE: u32 = u32::MAX;
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct PackedSceneCamera {
+    /// Index into the nodes of the scene; the camera looks along its -Z, with +Y up
+    pub node: u32,
+    /// In radians
+    pub vertical_fov: f32,
+    /// Width over height, or zero to use that of the viewport
+    pub aspect_ratio: f32,
+    pub near: f32,
+    /// Zero if infinite
+    pub far: f32,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct PackedSceneLight {
+    /// Index into the nodes of the scene; directional and spot lights shine along its -Z
+    pub node: u32,
+    /// One of `SceneLightKind`
+    pub kind: u32,
+    pub color: [f32; 3],
+    /// Candela for point and spot lights, lux for directional ones
+    pub intensity: f32,
+    /// Zero if infinite
+    pub range: f32,
+    /// In radians; only used by spot lights
+    pub inner_cone_angle: f32,
+    pub outer_cone_angle: f32,
+}
+
+def_asset! {
+    PackedSkin {
+        // Indices into the nodes of the scene
+        joints { Vec(u32) }
+        // `Affine3A` columns
+        inverse_bind_matrices { Vec([f32; 12]) }
+    }
+}
+
+def_asset! {
+    PackedAnimationChannel {
+        node { u32 }
+        property { u32 }
+        interpolation { u32 }
+        times { Vec(f32) }
+        values { Vec(f32) }
+    }
+}
+
+def_asset! {
+    PackedAnimation {
+        // UTF-8
+        name { Vec(u8) }
+        channels { Vec(Nested(PackedAnimationChannel)) }
+    }
+}
+
+impl PackedAnimation::Flat {
+    pub fn name(&self) -> &str {
+        std::str::from_utf8(self.name.as_slice()).unwrap_or_default()
+    }
+}
+
+// TODO: use `rkyv` instead
+def_asset! {
+    PackedScene {
+        meshes { Vec(Nested(PackedTriMesh)) }
+        nodes { Vec(PackedSceneNode) }
+        // `Affine3A` columns; applied on top of the root nodes
+        root_transform { [f32; 12] }
+        // UTF-8, indexed like `nodes`
+        node_names { Vec(Vec(u8)) }
+        skins { Vec(Nested(PackedSkin)) }
+        animations { Vec(Nested(PackedAnimation)) }
+        cameras { Vec(PackedSceneCamera) }
+        // UTF-8, indexed like `cameras`
+        camera_names { Vec(Vec(u8)) }
+        lights { Vec(PackedSceneLight) }
+        // UTF-8, indexed like `lights`
+        light_names { Vec(Vec(u8)) }
+    }
+}
+
+impl PackedScene::Flat {
+    pub fn node_name(&self, node_idx: usize) -> &str {
+        std::str::from_utf8(self.node_names[node_idx].as_slice()).unwrap_or_default()
+    }
+
+    pub fn camera_name(&self, camera_idx: usize) -> &str {
+        std::str::from_utf8(self.camera_names[camera_idx].as_slice()).unwrap_or_default()
+    }
+
+    pub fn light_name(&self, light_idx: usize) -> &str {
+        std::str::from_utf8(self.light_names[light_idx].as_slice()).unwrap_or_default()
+    }
+
+    /// Applied on top of the root nodes, such as the scale and rotation of the scene manifest
+    pub fn root_transform(&self) -> Affine3A {
+        let cols = self.root_transform;
+        Affine3A::from_cols_array(&cols)
+    }
+
+    /// Transforms of all nodes in the space of the scene, including `root_transform`, indexed like `nodes`
+    pub fn node_world_transforms(&self) -> Vec<Affine3A> {
+        self.compose_node_transforms(self.nodes.iter().map(|node| {
+            Affine3A::from_scale_rotation_translation(
+                node.scale.into(),
+                Quat::from_vec4(node.rotation.into()),
+                node.translation.into(),
+            )
+        }))
+    }
+
+    /// Like `node_world_transforms`, but with the transforms of nodes relative to their parents
+    /// given in `local_transforms`, such as those of an animated pose
+    pub fn compose_node_transforms(
+        &self,
+        local_transforms: impl IntoIterator<Item = Affine3A>,
+    ) -> Vec<Affine3A> {
+        let root_transform = self.root_transform();
+        let mut res: Vec<Affine3A> = Vec::with_capacity(self.nodes.len());
+
+        for (node, local) in self.nodes.iter().zip(local_transforms) {
+            res.push(if node.parent == PackedSceneNode::NONE {
+                root_transform * local
+            } else {
+                res[node.parent as usize] * local
+            });
+        }
+
+        res
+    }
+
+    /// Joint matrices deforming the mesh of a skinned node, given transforms of all nodes
+    /// in the space of the scene. Empty if the node isn't skinned.
+    ///
+    /// The matrices transform vertices into the space of the node, so that the instance
+    /// of its mesh keeps using the node's transform.
+    pub fn skin_joint_matrices(
+        &self,
+        node_idx: usize,
+        node_world_transforms: &[Affine3A],
+    ) -> Vec<Affine3A> {
+        let node = &self.nodes[node_idx];
+        if node.skin == PackedSceneNode::NONE {
+            return Vec::new();
+        }
+
+        let skin = &self.skins[node.skin as usize];
+        let world_to_node = node_world_transforms[node_idx].inverse();
+
+        skin.joints
+            .iter()
+            .zip(skin.inverse_bind_matrices.iter())
+            .map(|(&joint, inverse_bind_matrix)| {
+                world_to_node
+                    * node_world_transforms[joint as usize]
+                    * Affine3A::from_cols_array(inverse_bind_matrix)
+            })
+            .collect()
+    }
+}
+
+impl ValidateContents for GpuImage::Flat {
+    fn validate_contents(&self) -> anyhow::Result<()> {
+        let format = self.format;
+        let extent = self.extent;
+
+        if extent[0] == 0 || extent[1] == 0 || extent[2] != 1 {
+            anyhow::bail!("Invalid image extent {:?}", extent);
+        }
+
+        let max_mip_count = 32 - extent[0].max(extent[1]).leading_zeros() as usize;
+        if self.mips.is_empty() || self.mips.len() > max_mip_count {
+            anyhow::bail!(
+                "A {}x{} image can't have {} mips",
+                extent[0],
+                extent[1],
+                self.mips.len()
+            );
+        }
+
+        for (level, mip) in self.mips.iter().enumerate() {
+            let (width, height) = ((extent[0] >> level).max(1), (extent[1] >> level).max(1));
+            let size = crate::image::gpu_image_mip_size(format, width, height)
+                .with_context(|| format!("Unsupported image format {:?}", format))?;
+            validate_len(&format!("bytes in mip {}", level), mip.len(), size)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ValidateContents for PackedTriMesh::Flat {
+    fn validate_contents(&self) -> anyhow::Result<()> {
+        let flags = self.vertex_encoding.flags;
+        let vertex_count = self.vertex_count();
+
+        // Streams not selected by the encoding are empty
+        let stream_len = |is_used: bool| if is_used { vertex_count } else { 0 };
+        let position = flags & VertexEncodingFlags::POSITION_MASK;
+        if position > VertexEncodingFlags::POSITION_SNORM16 {
+            anyhow::bail!("Unknown position encoding {}", position);
+        }
+        let f32_positions = position == VertexEncodingFlags::POSITION_F32;
+        let f16_uvs = flags & VertexEncodingFlags::UV_F16 != 0;
+        let octahedral_tangents = flags & VertexEncodingFlags::TANGENT_OCTAHEDRAL != 0;
+        let rgba8_colors = flags & VertexEncodingFlags::COLOR_RGBA8 != 0;
+
+        validate_len("vertices", self.verts.len(), stream_len(f32_positions))?;
+        validate_len(
+            "quantized vertices",
+            self.quantized_verts.len(),
+            stream_len(!f32_positions),
+        )?;
+        validate_len("UVs", self.uvs.len(), stream_len(!f16_uvs))?;
+        validate_len("second UVs", self.uvs1.len(), stream_len(!f16_uvs))?;
+        validate_len(
+            "quantized UVs",
+            self.quantized_uvs.len(),
+            stream_len(f16_uvs),
+        )?;
+        validate_len(
+            "quantized second UVs",
+            self.quantized_uvs1.len(),
+            stream_len(f16_uvs),
+        )?;
+        validate_len(
+            "tangents",
+            self.tangents.len(),
+            stream_len(!octahedral_tangents),
+        )?;
+        validate_len(
+            "quantized tangents",
+            self.quantized_tangents.len(),
+            stream_len(octahedral_tangents),
+        )?;
+        validate_len("colors", self.colors.len(), stream_len(!rgba8_colors))?;
+        validate_len(
+            "quantized colors",
+            self.quantized_colors.len(),
+            stream_len(rgba8_colors),
+        )?;
+
+        let is_skinned = !self.joints.is_empty();
+        validate_len("joint indices", self.joints.len(), stream_len(is_skinned))?;
+        validate_len("joint weights", self.weights.len(), stream_len(is_skinned))?;
+
+        // Morph targets may leave out normals and tangents
+        for target in self.morph_targets.iter() {
+            validate_len(
+                "morph target positions",
+                target.positions.len(),
+                vertex_count,
+            )?;
+            for (what, len) in [
+                ("morph target normals", target.normals.len()),
+                ("morph target tangents", target.tangents.len()),
+            ] {
+                if len != 0 {
+                    validate_len(what, len, vertex_count)?;
+                }
+            }
+        }
+        validate_len(
+            "morph target weights",
+            self.morph_weights.len(),
+            self.morph_targets.len(),
+        )?;
+
+        let validate_indices = |indices: &FlatVec<u32>| -> anyhow::Result<()> {
+            if indices.len() % 3 != 0 {
+                anyhow::bail!("{} indices don't make whole triangles", indices.len());
+            }
+            for &idx in indices.iter() {
+                validate_index("Vertex index", idx, vertex_count)?;
+            }
+            Ok(())
+        };
+        validate_indices(&self.indices)?;
+        if self.lods.len() > MAX_MESH_LODS {
+            anyhow::bail!(
+                "{} LODs exceed the limit of {}",
+                self.lods.len(),
+                MAX_MESH_LODS
+            );
+        }
+        for lod in self.lods.iter() {
+            validate_indices(&lod.indices)?;
+        }
+
+        validate_len("material ids", self.material_ids.len(), vertex_count)?;
+        for &material_id in self.material_ids.iter() {
+            validate_index("Material", material_id, self.materials.len())?;
+        }
+
+        for material in self.materials.iter() {
+            for map in material.maps {
+                validate_index("Material map", map, self.maps.len())?;
+            }
+            for uv_set in material.map_uv_sets {
+                validate_index("UV set", uv_set, MAX_UV_SETS)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ValidateContents for PackedScene::Flat {
+    fn validate_contents(&self) -> anyhow::Result<()> {
+        let node_count = self.nodes.len();
+        let optional_index = |what: &str, idx: u32, len: usize| {
+            if idx == PackedSceneNode::NONE {
+                Ok(())
+            } else {
+                validate_index(what, idx, len)
+            }
+        };
+
+        for mesh in self.meshes.iter() {
+            mesh.validate_contents()?;
+        }
 
-                    $(
-                        def_asset!(@flatten &mut output; &self.$name; $($type)+ );
-                    )*
+        for skin in self.skins.iter() {
+            for &joint in skin.joints.iter() {
+                validate_index("Joint node", joint, node_count)?;
+            }
+            validate_len(
+                "inverse bind matrices",
+                skin.inverse_bind_matrices.len(),
+                skin.joints.len(),
+            )?;
+        }
 
-                    output.finish(writer)
+        for (node_idx, node) in self.nodes.iter().enumerate() {
+            optional_index("Parent node", node.parent, node_idx)
+                .with_context(|| format!("Parents must precede node {}", node_idx))?;
+            optional_index("Mesh", node.mesh, self.meshes.len())?;
+            optional_index("Skin", node.skin, self.skins.len())?;
+
+            // Skinned vertices index into the joints of the skin of their node
+            if node.mesh != PackedSceneNode::NONE && node.skin != PackedSceneNode::NONE {
+                let joint_count = self.skins[node.skin as usize].joints.len();
+                for joints in self.meshes[node.mesh as usize].joints.iter() {
+                    for &joint in joints {
+                        validate_index("Joint", u32::from(joint), joint_count)?;
+                    }
                 }
             }
         }
-    };
-}
+        validate_len("node names", self.node_names.len(), node_count)?;
+
+        for animation in self.animations.iter() {
+            for channel in animation.channels.iter() {
+                validate_index("Animated node", channel.node, node_count)?;
+
+                let components = match channel.property {
+                    AnimationProperty::TRANSLATION | AnimationProperty::SCALE => 3,
+                    AnimationProperty::ROTATION => 4,
+                    // Sampled with the morph target count of whichever mesh the node has
+                    AnimationProperty::MORPH_WEIGHTS => continue,
+                    property => anyhow::bail!("Unknown animation property {}", property),
+                };
+                let stride = match channel.interpolation {
+                    AnimationInterpolation::LINEAR | AnimationInterpolation::STEP => 1,
+                    AnimationInterpolation::CUBIC_SPLINE => 3,
+                    interpolation => {
+                        anyhow::bail!("Unknown animation interpolation {}", interpolation)
+                    }
+                };
+                validate_len(
+                    "animation values",
+                    channel.values.len(),
+                    channel.times.len() * stride * components,
+                )?;
+            }
+        }
 
-#[repr(C)]
-pub struct AssetRef<T> {
-    identity: u64,
-    marker: std::marker::PhantomData<fn(&T)>,
-}
-impl<T> AssetRef<T> {
-    pub fn identity(&self) -> u64 {
-        self.identity
-    }
-}
-impl<T> Clone for AssetRef<T> {
-    fn clone(&self) -> Self {
-        Self {
-            identity: self.identity,
-            marker: std::marker::PhantomData,
+        for camera in self.cameras.iter() {
+            validate_index("Camera node", camera.node, node_count)?;
         }
-    }
-}
-impl<T> Copy for AssetRef<T> {}
-impl<T> PartialEq for AssetRef<T> {
-    fn eq(&self, other: &Self) -> bool {
-        self.identity == other.identity
-    }
-}
-impl<T> Eq for AssetRef<T> {}
-impl<T> Hash for AssetRef<T> {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        state.write_u64(self.identity)
-    }
-}
-impl<T> PartialOrd for AssetRef<T> {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.identity.partial_cmp(&other.identity)
-    }
-}
-impl<T> Ord for AssetRef<T> {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.identity.cmp(&other.identity)
-    }
-}
+        validate_len("camera names", self.camera_names.len(), self.cameras.len())?;
 
-// TODO: use `rkyv` instead
-def_asset! {
-    GpuImage {
-        format { kajiya_backend::ash::vk::Format }
-        extent { [u32; 3] }
-        mips { Vec(Vec(u8)) }
+        for light in self.lights.iter() {
+            validate_index("Light node", light.node, node_count)?;
+            if light.kind > SceneLightKind::SPOT {
+                anyhow::bail!("Unknown light kind {}", light.kind);
+            }
+        }
+        validate_len("light names", self.light_names.len(), self.lights.len())?;
+
+        Ok(())
     }
 }
 
-// TODO: use `rkyv` instead
-def_asset! {
-    #[derive(Clone)]
-    PackedTriMesh {
-        verts { Vec(PackedVertex) }
-        uvs { Vec([f32; 2]) }
-        tangents { Vec([f32; 4]) }
-        colors { Vec([f32; 4]) }
-        indices { Vec(u32) }
-        material_ids { Vec(u32) }
-        materials { Vec(MeshMaterial) }
-        maps { Vec(Asset(GpuImage)) }
+pub fn pack_triangle_scene(
+    scene: &TriangleScene,
+    quantization: VertexQuantization,
+) -> PackedScene::Proto {
+    let to_index = |idx: Option<usize>| idx.map_or(PackedSceneNode::NONE, |idx| idx as u32);
+
+    PackedScene::Proto {
+        meshes: scene
+            .meshes
+            .iter()
+            .map(|mesh| pack_triangle_mesh(mesh, quantization))
+            .collect(),
+        nodes: scene
+            .nodes
+            .iter()
+            .map(|node| PackedSceneNode {
+                parent: to_index(node.parent),
+                mesh: to_index(node.mesh),
+                skin: to_index(node.skin),
+                translation: node.translation.into(),
+                rotation: Vec4::from(node.rotation).into(),
+                scale: node.scale.into(),
+            })
+            .collect(),
+        root_transform: scene.root_transform.to_cols_array(),
+        node_names: scene
+            .nodes
+            .iter()
+            .map(|node| node.name.as_bytes().to_vec())
+            .collect(),
+        skins: scene
+            .skins
+            .iter()
+            .map(|skin| PackedSkin::Proto {
+                joints: skin.joints.iter().map(|&joint| joint as u32).collect(),
+                inverse_bind_matrices: skin
+                    .inverse_bind_matrices
+                    .iter()
+                    .map(|m| m.to_cols_array())
+                    .collect(),
+            })
+            .collect(),
+        animations: scene
+            .animations
+            .iter()
+            .map(|animation| PackedAnimation::Proto {
+                name: animation.name.as_bytes().to_vec(),
+                channels: animation
+                    .channels
+                    .iter()
+                    .map(|channel| PackedAnimationChannel::Proto {
+                        node: channel.node as u32,
+                        property: channel.property,
+                        interpolation: channel.interpolation,
+                        times: channel.times.clone(),
+                        values: channel.values.clone(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        cameras: scene
+            .cameras
+            .iter()
+            .map(|camera| PackedSceneCamera {
+                node: camera.node as u32,
+                vertical_fov: camera.vertical_fov,
+                aspect_ratio: camera.aspect_ratio.unwrap_or(0.0),
+                near: camera.near,
+                far: camera.far.unwrap_or(0.0),
+            })
+            .collect(),
+        camera_names: scene
+            .cameras
+            .iter()
+            .map(|camera| camera.name.as_bytes().to_vec())
+            .collect(),
+        lights: scene
+            .lights
+            .iter()
+            .map(|light| PackedSceneLight {
+                node: light.node as u32,
+                kind: light.kind,
+                color: light.color,
+                intensity: light.intensity,
+                range: light.range.unwrap_or(0.0),
+                inner_cone_angle: light.inner_cone_angle,
+                outer_cone_angle: light.outer_cone_angle,
+            })
+            .collect(),
+        light_names: scene
+            .lights
+            .iter()
+            .map(|light| light.name.as_bytes().to_vec())
+            .collect(),
     }
 }
 
-/*#[derive(Clone)]
-pub struct PackedTriangleMesh {
-    pub verts: Vec<PackedVertex>,
-    pub uvs: Vec<[f32; 2]>,
-    pub tangents: Vec<[f32; 4]>,
-    pub colors: Vec<[f32; 4]>,
-    pub indices: Vec<u32>,
-    pub material_ids: Vec<u32>,
-    pub materials: Vec<MeshMaterial>,
-    pub maps: Vec<MeshMaterialMap>,
-}*/
-
-pub type PackedTriangleMesh = PackedTriMesh::Proto;
+pub fn pack_triangle_mesh(
+    mesh: &TriangleMesh,
+    quantization: VertexQuantization,
+) -> PackedTriangleMesh {
+    let quantization = if mesh.joints.is_empty() && mesh.morph_targets.is_empty() {
+        quantization
+    } else {
+        VertexQuantization::default()
+    };
 
-pub fn pack_triangle_mesh(mesh: &TriangleMesh) -> PackedTriangleMesh {
-    let mut verts: Vec<PackedVertex> = Vec::with_capacity(mesh.positions.len());
+    let mut vertex_encoding = VertexEncoding::default();
+    let mut verts: Vec<PackedVertex> = Vec::new();
+    let mut quantized_verts: Vec<QuantizedVertex> = Vec::new();
 
-    for (i, pos) in mesh.positions.iter().enumerate() {
+    let packed_normal = |i: usize| {
         let n = mesh.normals[i];
+        pack_unit_direction_11_10_11(n[0], n[1], n[2])
+    };
 
-        verts.push(PackedVertex {
-            pos: *pos,
-            normal: pack_unit_direction_11_10_11(n[0], n[1], n[2]),
-        });
+    if quantization.positions == PositionQuantization::F32 {
+        verts = mesh
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| PackedVertex {
+                pos: *pos,
+                normal: packed_normal(i),
+            })
+            .collect();
+    } else {
+        let (min, max) = mesh.positions.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), &p| (min.min(p.into()), max.max(p.into())),
+        );
+        let center = (min + max) * 0.5;
+
+        let (flags, scale) = if quantization.positions == PositionQuantization::F16 {
+            (VertexEncodingFlags::POSITION_F16, Vec3::ONE)
+        } else {
+            // Flat meshes get a unit scale along the axis they have no extent in
+            let half_extent = (max - min) * 0.5;
+            let scale = Vec3::from(
+                half_extent
+                    .to_array()
+                    .map(|e| if e > 0.0 { e } else { 1.0 }),
+            );
+            (VertexEncodingFlags::POSITION_SNORM16, scale)
+        };
+
+        let quantize = |v: f32| {
+            if flags == VertexEncodingFlags::POSITION_F16 {
+                f32_to_f16_bits(v)
+            } else {
+                (v.clamp(-1.0, 1.0) * 32767.0).round() as i16 as u16
+            }
+        };
+
+        quantized_verts = mesh
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| {
+                let p = (Vec3::from(*pos) - center) / scale;
+                QuantizedVertex {
+                    pos: [quantize(p.x), quantize(p.y), quantize(p.z), 0],
+                    normal: packed_normal(i),
+                }
+            })
+            .collect();
+
+        vertex_encoding.flags |= flags;
+        vertex_encoding.position_offset = center.into();
+        vertex_encoding.position_scale = scale.into();
     }
 
+    let (uvs, uvs1, quantized_uvs, quantized_uvs1) = if quantization.uvs {
+        vertex_encoding.flags |= VertexEncodingFlags::UV_F16;
+        (
+            Vec::new(),
+            Vec::new(),
+            mesh.uvs.iter().copied().map(pack_uv_f16).collect(),
+            mesh.uvs1.iter().copied().map(pack_uv_f16).collect(),
+        )
+    } else {
+        (mesh.uvs.clone(), mesh.uvs1.clone(), Vec::new(), Vec::new())
+    };
+
+    let (tangents, quantized_tangents) = if quantization.tangents {
+        vertex_encoding.flags |= VertexEncodingFlags::TANGENT_OCTAHEDRAL;
+        (
+            Vec::new(),
+            mesh.tangents
+                .iter()
+                .copied()
+                .map(pack_tangent_octahedral)
+                .collect(),
+        )
+    } else {
+        (mesh.tangents.clone(), Vec::new())
+    };
+
+    let (colors, quantized_colors) = if quantization.colors {
+        vertex_encoding.flags |= VertexEncodingFlags::COLOR_RGBA8;
+        (
+            Vec::new(),
+            mesh.colors.iter().copied().map(pack_color_rgba8).collect(),
+        )
+    } else {
+        (mesh.colors.clone(), Vec::new())
+    };
+
+    // Metallic-roughness maps get their roughness widened by the variance of the normal map
+    // used alongside them. Map slots per material are `[normal, spec, albedo, emissive, clearcoat]`.
+    let spec_normal_maps: HashMap<usize, usize> = mesh
+        .materials
+        .iter()
+        .map(|material| (material.maps[1] as usize, material.maps[0] as usize))
+        .collect();
+
     let maps = mesh
         .maps
         .iter()
-        .map(|map| {
+        .enumerate()
+        .map(|(map_idx, map)| {
             let (image, params) = match map {
                 MeshMaterialMap::Image { source, params } => (
                     super::image::LoadImage::new(source).unwrap().into_lazy(),
@@ -785,26 +3205,93 @@ pub fn pack_triangle_mesh(mesh: &TriangleMesh) -> PackedTriangleMesh {
                     TexParams {
                         gamma: crate::mesh::TexGamma::Linear,
                         use_mips: false,
+                        compression: TexCompressionMode::None,
+                        mip_filter: TexMipFilter::Default,
+                        channels: TexChannels::Rgba,
                     },
                 ),
             };
 
-            crate::image::CreateGpuImage { image, params }.into_lazy()
+            let roughness_normal_map = match (map, spec_normal_maps.get(&map_idx)) {
+                (MeshMaterialMap::Image { .. }, Some(&normal_map_idx)) => {
+                    match &mesh.maps[normal_map_idx] {
+                        MeshMaterialMap::Image { source, .. } => {
+                            Some(super::image::LoadImage::new(source).unwrap().into_lazy())
+                        }
+                        MeshMaterialMap::Placeholder(_) => None,
+                    }
+                }
+                _ => None,
+            };
+
+            crate::image::CreateGpuImage {
+                image,
+                params,
+                roughness_normal_map,
+            }
+            .into_lazy()
         })
         .collect();
 
     PackedTriangleMesh {
+        vertex_encoding,
         verts,
-        uvs: mesh.uvs.clone(),
-        tangents: mesh.tangents.clone(),
-        colors: mesh.colors.clone(),
-        indices: mesh.indices.clone(),
+        quantized_verts,
+        uvs,
+        uvs1,
+        quantized_uvs,
+        quantized_uvs1,
+        tangents,
+        quantized_tangents,
+        colors,
+        quantized_colors,
+        joints: mesh.joints.clone(),
+        weights: mesh.weights.clone(),
+        morph_targets: mesh
+            .morph_targets
+            .iter()
+            .map(|target| PackedMorphTarget::Proto {
+                positions: target.positions.clone(),
+                normals: target.normals.clone(),
+                tangents: target.tangents.clone(),
+            })
+            .collect(),
+        morph_weights: mesh.morph_weights.clone(),
+        indices: partition_double_sided_triangles(mesh, &mesh.indices),
+        lods: mesh
+            .lods
+            .iter()
+            .map(|lod| PackedMeshLod::Proto {
+                indices: partition_double_sided_triangles(mesh, &lod.indices),
+                error: lod.error,
+            })
+            .collect(),
         material_ids: mesh.material_ids.clone(),
         materials: mesh.materials.clone(),
         maps,
     }
 }
 
+/// Moves triangles with double-sided materials after all the single-sided ones,
+/// so that each group can be drawn with a single call, with and without back-face culling.
+fn partition_double_sided_triangles(mesh: &TriangleMesh, indices: &[u32]) -> Vec<u32> {
+    let is_double_sided = |tri: &[u32]| {
+        let material = &mesh.materials[mesh.material_ids[tri[0] as usize] as usize];
+        material.flags & MeshMaterialFlags::MESH_MATERIAL_FLAG_DOUBLE_SIDED != 0
+    };
+
+    let (double_sided, single_sided): (Vec<&[u32]>, Vec<&[u32]>) = indices
+        .chunks_exact(3)
+        .partition(|tri| is_double_sided(tri));
+
+    single_sided
+        .into_iter()
+        .chain(double_sided)
+        .flatten()
+        .copied()
+        .collect()
+}
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 struct GpuMaterial {
@@ -820,3 +3307,523 @@ impl Default for GpuMaterial {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn face_normal(positions: &[[f32; 3]], tri: &[u32]) -> Vec3 {
+        let [p0, p1, p2] = [0, 1, 2].map(|i| Vec3::from(positions[tri[i] as usize]));
+        (p1 - p0).cross(p2 - p0).normalize()
+    }
+
+    fn assert_close(a: [f32; 3], b: Vec3) {
+        assert!(
+            Vec3::from(a).abs_diff_eq(b, 1e-5),
+            "{:?} != {:?}",
+            a,
+            b.to_array()
+        );
+    }
+
+    // Two triangles folded along the X axis, facing +Y and +Z
+    fn folded_mesh() -> TriangleMesh {
+        TriangleMesh {
+            positions: vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [0.0, 0.0, -1.0],
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+            ],
+            normals: vec![[0.0; 3]; 6],
+            uvs: vec![[0.0; 2]; 6],
+            indices: vec![0, 1, 2, 3, 4, 5],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn triangle_strip_keeps_winding() {
+        // A zigzag in the XY plane, with all triangles facing +Z
+        let positions = [
+            [0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [2.0, 0.0, 0.0],
+            [2.0, 1.0, 0.0],
+        ];
+        let list = triangle_strip_to_list(&[0, 1, 2, 3, 4, 5]);
+
+        assert_eq!(list, [0, 1, 2, 1, 3, 2, 2, 3, 4, 3, 5, 4]);
+        for tri in list.chunks_exact(3) {
+            assert_close(face_normal(&positions, tri).to_array(), -Vec3::Z);
+        }
+
+        assert!(triangle_strip_to_list(&[0, 1]).is_empty());
+    }
+
+    #[test]
+    fn triangle_fan_keeps_winding() {
+        let positions = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [-1.0, 1.0, 0.0],
+        ];
+        let list = triangle_fan_to_list(&[0, 1, 2, 3, 4]);
+
+        assert_eq!(list, [1, 2, 0, 2, 3, 0, 3, 4, 0]);
+        for tri in list.chunks_exact(3) {
+            assert_close(face_normal(&positions, tri).to_array(), Vec3::Z);
+        }
+
+        assert!(triangle_fan_to_list(&[0]).is_empty());
+    }
+
+    #[test]
+    fn flat_normals_split_shared_vertices() {
+        let mut mesh = folded_mesh();
+        // Both triangles share their first two vertices
+        mesh.indices = vec![0, 1, 2, 0, 1, 5];
+        generate_normals(&mut mesh, 0, 0, NormalGeneration::Flat);
+
+        assert_eq!(mesh.positions.len(), 6);
+        assert_eq!(mesh.uvs.len(), 6);
+        assert_eq!(mesh.indices, [0, 1, 2, 3, 4, 5]);
+        for idx in 0..3 {
+            assert_close(mesh.normals[idx], Vec3::Y);
+            assert_close(mesh.normals[idx + 3], Vec3::Z);
+        }
+    }
+
+    #[test]
+    fn flat_normals_keep_earlier_primitives() {
+        let mut mesh = folded_mesh();
+        mesh.normals[0] = [1.0, 0.0, 0.0];
+        mesh.positions.push([0.0, 0.0, 0.0]);
+        mesh.normals.push([0.0; 3]);
+        mesh.uvs.push([0.0; 2]);
+        // The second primitive reuses a vertex of the first one by index
+        mesh.indices = vec![0, 1, 2, 6, 4, 5];
+        generate_normals(&mut mesh, 6, 3, NormalGeneration::Flat);
+
+        assert_eq!(mesh.positions.len(), 9);
+        assert_eq!(&mesh.indices[..3], [0, 1, 2]);
+        assert_eq!(&mesh.indices[3..], [6, 7, 8]);
+        assert_eq!(mesh.normals[0], [1.0, 0.0, 0.0]);
+        assert_eq!(mesh.positions[7], [1.0, 0.0, 0.0]);
+        for idx in 6..9 {
+            assert_close(mesh.normals[idx], Vec3::Z);
+        }
+    }
+
+    #[test]
+    fn smooth_normals_average_across_positions() {
+        let mut mesh = folded_mesh();
+        // Same position as vertex 1, but split from it, as along a UV seam
+        mesh.uvs[4] = [1.0, 0.0];
+        generate_normals(&mut mesh, 0, 0, NormalGeneration::Smooth);
+
+        let shared = (Vec3::Y + Vec3::Z).normalize();
+        assert_eq!(mesh.positions.len(), 6);
+        assert_close(mesh.normals[0], shared);
+        assert_close(mesh.normals[1], shared);
+        assert_close(mesh.normals[2], Vec3::Y);
+        assert_close(mesh.normals[3], shared);
+        assert_close(mesh.normals[4], shared);
+        assert_close(mesh.normals[5], Vec3::Z);
+    }
+
+    #[test]
+    fn degenerate_triangles_get_a_valid_normal() {
+        let mut mesh = folded_mesh();
+        mesh.positions[5] = [2.0, 0.0, 0.0];
+        mesh.indices = vec![3, 4, 5];
+        generate_normals(&mut mesh, 0, 0, NormalGeneration::Smooth);
+
+        assert_close(mesh.normals[3], Vec3::Y);
+    }
+
+    #[test]
+    fn octahedral_tangents_keep_missing_ones_apart() {
+        assert_eq!(
+            pack_tangent_octahedral([1.0, 0.0, 0.0, 0.0]),
+            OCTAHEDRAL_NO_TANGENT
+        );
+
+        for w in [1.0, -1.0] {
+            for t in [
+                [1.0, 0.0, 0.0],
+                [0.0, 0.0, -1.0],
+                [-1e-6, -1e-6, -1.0],
+                [-0.0, -0.0, -1.0],
+            ] {
+                let packed = pack_tangent_octahedral([t[0], t[1], t[2], w]);
+                assert_ne!(packed, OCTAHEDRAL_NO_TANGENT, "{:?}", t);
+                assert_eq!(packed >> 31, u32::from(w < 0.0));
+            }
+        }
+    }
+
+    fn test_material() -> MeshMaterial {
+        MeshMaterial {
+            base_color_mult: [1.0; 4],
+            maps: [0, 1, 2, 3, 4],
+            roughness_mult: 1.0,
+            metalness_factor: 0.0,
+            emissive: [0.0; 3],
+            flags: 0,
+            map_transforms: [[1.0, 0.0, 0.0, 1.0, 0.0, 0.0]; 5],
+            transmission: 0.0,
+            ior: 1.5,
+            specular_factor: 1.0,
+            specular_color: [1.0; 3],
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            sheen_color: [0.0; 3],
+            sheen_roughness: 0.0,
+            alpha_mode: MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_OPAQUE,
+            alpha_cutoff: 0.0,
+            map_uv_sets: [0; 5],
+        }
+    }
+
+    // A skinned quad under an animated root node
+    fn test_scene() -> PackedScene::Proto {
+        let mesh = TriangleMesh {
+            positions: vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+            ],
+            normals: vec![[0.0, 0.0, 1.0]; 4],
+            colors: vec![[1.0; 4]; 4],
+            uvs: vec![[0.0; 2]; 4],
+            uvs1: vec![[0.0; 2]; 4],
+            tangents: vec![[1.0, 0.0, 0.0, 1.0]; 4],
+            joints: vec![[0; 4]; 4],
+            weights: vec![[1.0, 0.0, 0.0, 0.0]; 4],
+            material_ids: vec![0; 4],
+            indices: vec![0, 1, 2, 0, 2, 3],
+            materials: vec![test_material()],
+            maps: vec![MeshMaterialMap::Placeholder([255; 4]); 5],
+            ..Default::default()
+        };
+
+        let node = |parent, mesh, skin| PackedSceneNode {
+            parent,
+            mesh,
+            skin,
+            translation: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0; 3],
+        };
+
+        PackedScene::Proto {
+            meshes: vec![pack_triangle_mesh(&mesh, VertexQuantization::default())],
+            nodes: vec![
+                node(
+                    PackedSceneNode::NONE,
+                    PackedSceneNode::NONE,
+                    PackedSceneNode::NONE,
+                ),
+                node(0, 0, 0),
+            ],
+            root_transform: Affine3A::IDENTITY.to_cols_array(),
+            node_names: vec![b"root".to_vec(), b"quad".to_vec()],
+            skins: vec![PackedSkin::Proto {
+                joints: vec![0],
+                inverse_bind_matrices: vec![Affine3A::IDENTITY.to_cols_array()],
+            }],
+            animations: vec![PackedAnimation::Proto {
+                name: b"spin".to_vec(),
+                channels: vec![PackedAnimationChannel::Proto {
+                    node: 0,
+                    property: AnimationProperty::ROTATION,
+                    interpolation: AnimationInterpolation::LINEAR,
+                    times: vec![0.0, 1.0],
+                    values: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+                }],
+            }],
+            cameras: Vec::new(),
+            camera_names: Vec::new(),
+            lights: Vec::new(),
+            light_names: Vec::new(),
+        }
+    }
+
+    fn flatten_scene(scene: &PackedScene::Proto) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        scene.flatten_into(&mut bytes);
+        bytes
+    }
+
+    // Assets are read from mappings, which start at page boundaries
+    fn aligned_copy(bytes: &[u8]) -> Vec<u64> {
+        let mut words = vec![0u64; (bytes.len() + 7) / 8];
+        let aligned =
+            unsafe { std::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, bytes.len()) };
+        aligned.copy_from_slice(bytes);
+        words
+    }
+
+    fn read<T: FlatAsset + ValidateContents>(bytes: &[u8]) -> anyhow::Result<()> {
+        let words = aligned_copy(bytes);
+        let aligned =
+            unsafe { std::slice::from_raw_parts(words.as_ptr() as *const u8, bytes.len()) };
+
+        read_flat_asset::<T>(aligned).map(|_| ())
+    }
+
+    fn assert_invalid<T: FlatAsset + ValidateContents>(bytes: &[u8], expected_error: &str) {
+        let err = format!("{:#}", read::<T>(bytes).unwrap_err());
+        assert!(
+            err.contains(expected_error),
+            "expected {:?}, got {:?}",
+            expected_error,
+            err
+        );
+    }
+
+    #[test]
+    fn damaged_asset_files_are_rejected() {
+        let bytes = flatten_scene(&test_scene());
+        read::<PackedScene::Flat>(&bytes).unwrap();
+
+        assert_invalid::<PackedScene::Flat>(&bytes[..AssetHeader::SIZE - 1], "Too small");
+        assert_invalid::<PackedScene::Flat>(&bytes[..bytes.len() - 8], "bytes of asset data");
+        assert_invalid::<GpuImage::Flat>(&bytes, "Expected a GpuImage asset");
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[16..20].copy_from_slice(&(ASSET_FORMAT_VERSION + 1).to_ne_bytes());
+        assert_invalid::<PackedScene::Flat>(&wrong_version, "Re-run `bake`");
+
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert_invalid::<PackedScene::Flat>(&corrupt, "Checksum mismatch");
+
+        // Vectors pointing past the end get caught even when the checksum matches
+        let mut bad_offset = bytes[AssetHeader::SIZE..].to_vec();
+        let past_the_end = bad_offset.len() as u64;
+        bad_offset[8..16].copy_from_slice(&past_the_end.to_ne_bytes());
+        let mut header_and_data = Vec::new();
+        AssetHeader::new(<PackedScene::Flat as FlatAsset>::TYPE_TAG, &bad_offset)
+            .write(&mut header_and_data)
+            .unwrap();
+        header_and_data.extend(bad_offset);
+        assert_invalid::<PackedScene::Flat>(&header_and_data, "exceeds the asset size");
+    }
+
+    #[test]
+    fn animated_roots_keep_the_scene_root_transform() {
+        let mut scene = test_scene();
+        scene.root_transform = Affine3A::from_scale_rotation_translation(
+            Vec3::splat(2.0),
+            Quat::IDENTITY,
+            Vec3::new(1.0, 0.0, 0.0),
+        )
+        .to_cols_array();
+
+        let bytes = flatten_scene(&scene);
+        let words = aligned_copy(&bytes);
+        let aligned =
+            unsafe { std::slice::from_raw_parts(words.as_ptr() as *const u8, bytes.len()) };
+        let scene = read_flat_asset::<PackedScene::Flat>(aligned).unwrap();
+
+        let rest = scene.node_world_transforms();
+        let corner = rest[1].transform_point3(Vec3::X);
+        assert!(
+            corner.abs_diff_eq(Vec3::new(3.0, 0.0, 0.0), 1e-5),
+            "{}",
+            corner
+        );
+
+        // Halfway through the "spin" clip, the animated root replaces its own transform only
+        let spun_root = Affine3A::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        let posed = scene.compose_node_transforms([spun_root, Affine3A::IDENTITY]);
+        let corner = posed[1].transform_point3(Vec3::X);
+        assert!(
+            corner.abs_diff_eq(Vec3::new(1.0, 2.0, 0.0), 1e-5),
+            "{}",
+            corner
+        );
+
+        // Skinning happens in the space of the node, where the root transform cancels out
+        let joint_matrices = scene.skin_joint_matrices(1, &posed);
+        let skinned = joint_matrices[0].transform_point3(Vec3::X);
+        assert!(skinned.abs_diff_eq(Vec3::X, 1e-5), "{}", skinned);
+    }
+
+    #[test]
+    fn out_of_range_indices_are_rejected() {
+        type Corruption = fn(&mut PackedScene::Proto);
+        let cases: [(Corruption, &str); 10] = [
+            (|s| s.nodes[1].parent = 1, "Parents must precede node 1"),
+            (|s| s.nodes[1].mesh = 1, "Mesh 1 is out of range"),
+            (|s| s.nodes[1].skin = 1, "Skin 1 is out of range"),
+            (|s| s.node_names.truncate(1), "node names"),
+            (|s| s.meshes[0].indices[5] = 4, "Vertex index 4"),
+            (|s| s.meshes[0].material_ids[2] = 1, "Material 1"),
+            (|s| s.meshes[0].materials[0].maps[4] = 5, "Material map 5"),
+            (|s| s.meshes[0].joints[0] = [1, 0, 0, 0], "Joint 1"),
+            (|s| s.animations[0].channels[0].node = 2, "Animated node 2"),
+            (
+                |s| s.animations[0].channels[0].values.truncate(6),
+                "animation values",
+            ),
+        ];
+
+        for (corrupt, expected_error) in cases {
+            let mut scene = test_scene();
+            corrupt(&mut scene);
+            assert_invalid::<PackedScene::Flat>(&flatten_scene(&scene), expected_error);
+        }
+    }
+
+    #[test]
+    fn image_mips_must_match_the_format() {
+        let flatten_image = |format, mips: Vec<Vec<u8>>| {
+            let mut bytes = Vec::new();
+            GpuImage::Proto {
+                format,
+                extent: [8, 2, 1],
+                mips,
+            }
+            .flatten_into(&mut bytes);
+            bytes
+        };
+        use kajiya_backend::ash::vk::Format;
+
+        let rgba8_mips = vec![vec![0; 64], vec![0; 16], vec![0; 8], vec![0; 4]];
+        read::<GpuImage::Flat>(&flatten_image(Format::R8G8B8A8_UNORM, rgba8_mips.clone())).unwrap();
+
+        // Block-compressed mips are padded to whole blocks
+        let bc1_mips = vec![vec![0; 16], vec![0; 8], vec![0; 8], vec![0; 8]];
+        read::<GpuImage::Flat>(&flatten_image(Format::BC1_RGB_UNORM_BLOCK, bc1_mips)).unwrap();
+
+        let mut short_mip = rgba8_mips.clone();
+        short_mip[1].pop();
+        assert_invalid::<GpuImage::Flat>(
+            &flatten_image(Format::R8G8B8A8_UNORM, short_mip),
+            "bytes in mip 1",
+        );
+
+        let mut extra_mip = rgba8_mips.clone();
+        extra_mip.push(vec![0; 4]);
+        assert_invalid::<GpuImage::Flat>(
+            &flatten_image(Format::R8G8B8A8_UNORM, extra_mip),
+            "can't have 5 mips",
+        );
+
+        assert_invalid::<GpuImage::Flat>(
+            &flatten_image(Format::D32_SFLOAT, rgba8_mips),
+            "Unsupported image format",
+        );
+    }
+
+    fn mtl_texture(path: &str) -> MtlTexture {
+        MtlTexture {
+            path: PathBuf::from(path),
+            offset: [0.0, 0.0],
+            scale: [1.0, 1.0],
+            bump_multiplier: 1.0,
+        }
+    }
+
+    #[test]
+    fn obj_materials_convert_phong_maps() {
+        let mtl = MtlMaterial {
+            map_kd: Some(MtlTexture {
+                offset: [0.25, 0.125],
+                scale: [2.0, 0.5],
+                ..mtl_texture("color.png")
+            }),
+            map_d: Some(mtl_texture("alpha.png")),
+            map_ks: Some(mtl_texture("spec.png")),
+            map_ns: Some(mtl_texture("gloss.png")),
+            map_bump: Some(MtlTexture {
+                bump_multiplier: 0.5,
+                ..mtl_texture("height.png")
+            }),
+            ns: Some(200.0),
+            ..Default::default()
+        };
+        let (maps, material) = load_obj_material(&mtl);
+
+        // V was flipped on import, so the offset is too
+        assert_eq!(
+            material.map_transforms[0],
+            [2.0, 0.0, 0.0, 0.5, 0.25, 1.0 - 0.5 - 0.125]
+        );
+
+        let source = |map: &MeshMaterialMap| match map {
+            MeshMaterialMap::Image { source, .. } => source.clone(),
+            MeshMaterialMap::Placeholder(_) => panic!("expected an image"),
+        };
+        let file = |path: &str| ImageSource::File(PathBuf::from(path));
+        let converted = |conversion| ImageSource::Converted(Box::new(conversion));
+
+        // `[normal, spec, albedo, emissive]`
+        assert!(
+            source(&maps[0])
+                == converted(ImageConversion::HeightToNormal {
+                    height: file("height.png"),
+                    scale: ConversionParam(0.5),
+                })
+        );
+        assert!(
+            source(&maps[1])
+                == converted(ImageConversion::PhongToMetalnessRoughness {
+                    specular: Some(file("spec.png")),
+                    exponent: Some(file("gloss.png")),
+                    max_exponent: ConversionParam(200.0),
+                })
+        );
+        assert!(
+            source(&maps[2])
+                == converted(ImageConversion::ReplaceAlpha {
+                    color: Some(file("color.png")),
+                    alpha: file("alpha.png"),
+                })
+        );
+        assert!(matches!(maps[3], MeshMaterialMap::Placeholder(_)));
+
+        assert_eq!(material.roughness_mult, 1.0);
+        assert_ne!(
+            material.flags & MeshMaterialFlags::MESH_MATERIAL_FLAG_SPECULAR_MAP,
+            0
+        );
+        assert_eq!(
+            material.alpha_mode,
+            MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_MASK
+        );
+        assert_eq!(material.transmission, 0.0);
+    }
+
+    #[test]
+    fn translucent_obj_materials_are_transmissive() {
+        let mtl = MtlMaterial {
+            d: Some(0.25),
+            ns: Some(0.0),
+            ..Default::default()
+        };
+        let (maps, material) = load_obj_material(&mtl);
+
+        assert!(maps
+            .iter()
+            .all(|map| matches!(map, MeshMaterialMap::Placeholder(_))));
+        assert_eq!(
+            material.alpha_mode,
+            MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_OPAQUE
+        );
+        assert_eq!(material.base_color_mult[3], 1.0);
+        assert_eq!(material.transmission, 0.75);
+        assert_eq!(material.roughness_mult, 1.0);
+        assert_eq!(material.flags, 0);
+    }
+}
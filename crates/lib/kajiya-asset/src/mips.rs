@@ -0,0 +1,290 @@
+use image::{imageops::FilterType, ImageBuffer, Rgba};
+use kajiya_backend::ash::vk;
+
+use crate::{
+    image::{RawImage, RawImageFormat},
+    mesh::{TexGamma, TexMipFilter, TexParams},
+};
+
+/// Floating point RGBA image used for filtering. Color data is stored in linear space.
+pub(crate) type LinearImage = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
+pub struct MipChain {
+    pub extent: [u32; 2],
+
+    /// Texel data of each mip level, starting with the largest one. Laid out as RGBA8
+    /// for 8-bit and block-compressed formats, and as the target format otherwise.
+    pub levels: Vec<Vec<u8>>,
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Round to nearest even; out of range values become infinities.
+pub(crate) fn f32_to_f16_bits(v: f32) -> u16 {
+    let bits = v.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan_bit = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan_bit;
+    }
+
+    let exponent = exponent - 127 + 15;
+
+    if exponent >= 0x1f {
+        sign | 0x7c00
+    } else if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+
+        // Subnormal; shift in the implicit leading bit
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        sign | round_shifted(mantissa, shift) as u16
+    } else {
+        // A carry out of the mantissa correctly bumps the exponent
+        sign | round_shifted(((exponent as u32) << 23) | mantissa, 13) as u16
+    }
+}
+
+pub(crate) fn f16_bits_to_f32(bits: u16) -> f32 {
+    let sign = u32::from(bits & 0x8000) << 16;
+    let exponent = u32::from((bits >> 10) & 0x1f);
+    let mantissa = u32::from(bits & 0x3ff);
+
+    if exponent == 0x1f {
+        f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13))
+    } else if exponent == 0 {
+        // Zero or subnormal
+        let magnitude = mantissa as f32 * (1.0 / (1 << 24) as f32);
+        if sign != 0 {
+            -magnitude
+        } else {
+            magnitude
+        }
+    } else {
+        f32::from_bits(sign | ((exponent + 127 - 15) << 23) | (mantissa << 13))
+    }
+}
+
+fn round_shifted(value: u32, shift: u32) -> u32 {
+    let truncated = value >> shift;
+    let remainder = value & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+
+    if remainder > halfway || (remainder == halfway && truncated & 1 != 0) {
+        truncated + 1
+    } else {
+        truncated
+    }
+}
+
+pub(crate) fn to_linear_image(src: &RawImage, gamma: TexGamma) -> LinearImage {
+    let decode = |i: usize, v: f32| {
+        // Alpha is always linear
+        if gamma == TexGamma::Srgb && i % 4 != 3 {
+            srgb_to_linear(v)
+        } else {
+            v
+        }
+    };
+
+    let data: Vec<f32> = match src.format {
+        RawImageFormat::Rgba8 => src
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| decode(i, v as f32 / 255.0))
+            .collect(),
+        RawImageFormat::Rgba16 => src
+            .data
+            .chunks_exact(2)
+            .enumerate()
+            .map(|(i, v)| decode(i, u16::from_ne_bytes([v[0], v[1]]) as f32 / 65535.0))
+            .collect(),
+        // Float images are linear regardless of `gamma`
+        RawImageFormat::Rgba32F => src
+            .data
+            .chunks_exact(4)
+            .map(|v| f32::from_ne_bytes([v[0], v[1], v[2], v[3]]))
+            .collect(),
+        // Replicate to RGB, like conversion to RGBA would
+        RawImageFormat::R16 => src
+            .data
+            .chunks_exact(2)
+            .flat_map(|v| {
+                let v = decode(0, u16::from_ne_bytes([v[0], v[1]]) as f32 / 65535.0);
+                [v, v, v, 1.0]
+            })
+            .collect(),
+    };
+
+    LinearImage::from_raw(src.dimensions[0], src.dimensions[1], data).unwrap()
+}
+
+fn encode_linear_image(image: &LinearImage, gamma: TexGamma, format: vk::Format) -> Vec<u8> {
+    let texels = image.as_raw();
+
+    match format {
+        vk::Format::R16G16B16A16_SFLOAT => texels
+            .iter()
+            .flat_map(|&v| f32_to_f16_bits(v).to_ne_bytes())
+            .collect(),
+        vk::Format::R32G32B32A32_SFLOAT => texels.iter().flat_map(|&v| v.to_ne_bytes()).collect(),
+        vk::Format::R16_UNORM => texels
+            .chunks_exact(4)
+            .flat_map(|px| ((px[0].clamp(0.0, 1.0) * 65535.0).round() as u16).to_ne_bytes())
+            .collect(),
+        _ => texels
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                let v = v.clamp(0.0, 1.0);
+                let v = if gamma == TexGamma::Srgb && i % 4 != 3 {
+                    linear_to_srgb(v)
+                } else {
+                    v
+                };
+                (v * 255.0).round() as u8
+            })
+            .collect(),
+    }
+}
+
+fn decode_normal(p: &Rgba<f32>) -> [f32; 3] {
+    [p[0] * 2.0 - 1.0, p[1] * 2.0 - 1.0, p[2] * 2.0 - 1.0]
+}
+
+fn length(v: [f32; 3]) -> f32 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+/// Filtering shortens normals wherever they disagree; restore unit length.
+fn renormalize_normals(image: &mut LinearImage) {
+    for p in image.pixels_mut() {
+        let n = decode_normal(p);
+        let len = length(n);
+
+        if len > 1e-5 {
+            for c in 0..3 {
+                p[c] = n[c] / len * 0.5 + 0.5;
+            }
+        } else {
+            p[0] = 0.5;
+            p[1] = 0.5;
+            p[2] = 1.0;
+        }
+    }
+}
+
+/// Widens the roughness stored in the green channel of a glTF metallic-roughness map
+/// to account for the variation of normals within each texel, which filtering otherwise
+/// loses. Without it, bumpy surfaces turn mirror-like and sparkly in the distance.
+///
+/// The average normal length is interpreted as a von Mises-Fisher lobe, whose sharpness
+/// is then converted into additional GGX roughness (Toksvig; Neubelt and Pettineo).
+fn widen_roughness_by_normal_variance(image: &mut LinearImage, normal_map: &LinearImage) {
+    let (width, height) = image.dimensions();
+
+    // Area-filter the unnormalized normals to the footprint of each roughness texel
+    let avg_normals = image::imageops::resize(normal_map, width, height, FilterType::Triangle);
+
+    for (p, n) in image.pixels_mut().zip(avg_normals.pixels()) {
+        let r = length(decode_normal(n)).min(1.0);
+        if r >= 0.9999 {
+            continue;
+        }
+
+        let kappa = (3.0 * r - r * r * r) / (1.0 - r * r);
+        let perceptual_roughness = p[1];
+        let roughness_sq = perceptual_roughness.powi(4);
+        let roughness_sq = (roughness_sq + 2.0 / kappa.max(1e-5)).min(1.0);
+
+        p[1] = roughness_sq.powf(0.25);
+    }
+}
+
+/// Builds the mip chain of an image destined for a GPU image in `format`. Filtering happens
+/// in linear space, with sRGB data converted before and after. Normal maps get renormalized
+/// after filtering. Float and 16-bit formats have no sRGB variants, so they store linear values.
+///
+/// If `roughness_normal_map` is specified, the image is treated as a glTF metallic-roughness map,
+/// and the variance of the given normal map is folded into its roughness.
+///
+/// Images larger than `max_extent` in either dimension are downsized first.
+pub fn build_mip_chain(
+    src: &RawImage,
+    params: TexParams,
+    format: vk::Format,
+    max_extent: u32,
+    roughness_normal_map: Option<&RawImage>,
+) -> MipChain {
+    let mut image = to_linear_image(src, params.gamma);
+
+    if image.width() > max_extent || image.height() > max_extent {
+        image = image::imageops::resize(
+            &image,
+            image.width().min(max_extent),
+            image.height().min(max_extent),
+            FilterType::Lanczos3,
+        );
+
+        if params.mip_filter == TexMipFilter::NormalMap {
+            renormalize_normals(&mut image);
+        }
+    }
+
+    let extent = [image.width(), image.height()];
+    let roughness_normal_map = roughness_normal_map.map(|n| to_linear_image(n, TexGamma::Linear));
+
+    let mip_count = if params.use_mips {
+        32 - extent[0].max(extent[1]).leading_zeros()
+    } else {
+        1
+    };
+
+    let mut levels = Vec::with_capacity(mip_count as usize);
+
+    for level in 0..mip_count {
+        if level > 0 {
+            image = image::imageops::resize(
+                &image,
+                (image.width() / 2).max(1),
+                (image.height() / 2).max(1),
+                FilterType::Lanczos3,
+            );
+
+            if params.mip_filter == TexMipFilter::NormalMap {
+                renormalize_normals(&mut image);
+            }
+        }
+
+        if let Some(normal_map) = &roughness_normal_map {
+            // Roughness is widened on a copy, so that the adjustment doesn't compound through the chain
+            let mut adjusted = image.clone();
+            widen_roughness_by_normal_variance(&mut adjusted, normal_map);
+            levels.push(encode_linear_image(&adjusted, params.gamma, format));
+        } else {
+            levels.push(encode_linear_image(&image, params.gamma, format));
+        }
+    }
+
+    MipChain { extent, levels }
+}
@@ -0,0 +1,497 @@
+// Bake-time optimization of triangle meshes: welding of identical vertices, and reordering
+// of triangles and vertices for the post-transform vertex cache, overdraw, and vertex fetch.
+
+use crate::mesh::{PackedVertex, TriangleMesh};
+use glam::Vec3;
+use std::{collections::HashMap, fmt};
+
+/// Size of the FIFO cache used to estimate vertex cache efficiency. Matches common hardware.
+const ANALYSIS_CACHE_SIZE: usize = 16;
+
+/// Size of the LRU cache modelled when ordering triangles
+const OPTIMIZATION_CACHE_SIZE: usize = 32;
+
+/// Clusters of triangles may be up to this much less cache-efficient than the ordering
+/// they're carved out of, in exchange for finer-grained sorting against overdraw.
+const OVERDRAW_ACMR_THRESHOLD: f32 = 1.05;
+
+#[derive(Clone, Copy, Default)]
+pub struct MeshStats {
+    pub vertex_count: usize,
+    pub triangle_count: usize,
+    /// Average cache miss ratio: vertices transformed per triangle. 0.5 is ideal; 3.0 is the worst case.
+    pub acmr: f32,
+    /// Average transform to vertex ratio: vertices transformed per unique vertex. 1.0 is ideal.
+    pub atvr: f32,
+    /// Bytes of `PackedVertex` data read through a small cache of 64-byte lines, over the
+    /// size of the vertex buffer. 1.0 is ideal.
+    pub overfetch: f32,
+}
+
+impl fmt::Display for MeshStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} verts, {} tris, ACMR {:.3}, ATVR {:.3}, overfetch {:.3}",
+            self.vertex_count, self.triangle_count, self.acmr, self.atvr, self.overfetch
+        )
+    }
+}
+
+pub fn analyze_mesh(mesh: &TriangleMesh) -> MeshStats {
+    const VERTEX_SIZE: usize = std::mem::size_of::<PackedVertex>();
+    const CACHE_LINE_SIZE: usize = 64;
+    const CACHE_LINE_COUNT: usize = 64;
+
+    let vertex_count = mesh.positions.len();
+    let triangle_count = mesh.indices.len() / 3;
+
+    let mut cache: Vec<u32> = Vec::with_capacity(ANALYSIS_CACHE_SIZE);
+    let mut lines: Vec<usize> = Vec::with_capacity(CACHE_LINE_COUNT);
+    let mut transformed = 0usize;
+    let mut fetched_lines = 0usize;
+
+    for &idx in &mesh.indices {
+        if cache.contains(&idx) {
+            continue;
+        }
+
+        if cache.len() == ANALYSIS_CACHE_SIZE {
+            cache.remove(0);
+        }
+        cache.push(idx);
+        transformed += 1;
+
+        // Vertices missing the transform cache get fetched, possibly straddling two lines
+        let start = idx as usize * VERTEX_SIZE;
+        for line in start / CACHE_LINE_SIZE..=(start + VERTEX_SIZE - 1) / CACHE_LINE_SIZE {
+            if !lines.contains(&line) {
+                if lines.len() == CACHE_LINE_COUNT {
+                    lines.remove(0);
+                }
+                lines.push(line);
+                fetched_lines += 1;
+            }
+        }
+    }
+
+    MeshStats {
+        vertex_count,
+        triangle_count,
+        acmr: transformed as f32 / triangle_count.max(1) as f32,
+        atvr: transformed as f32 / vertex_count.max(1) as f32,
+        overfetch: (fetched_lines * CACHE_LINE_SIZE) as f32
+            / (vertex_count * VERTEX_SIZE).max(1) as f32,
+    }
+}
+
+/// Runs all optimization passes on `mesh`, and returns its statistics before and after.
+/// Vertices get reordered, so LODs must be generated afterwards.
+pub fn optimize_mesh(mesh: &mut TriangleMesh) -> (MeshStats, MeshStats) {
+    assert!(
+        mesh.lods.is_empty(),
+        "meshes must be optimized before generating LODs"
+    );
+
+    let before = analyze_mesh(mesh);
+
+    weld_vertices(mesh);
+    remove_degenerate_triangles(mesh);
+    optimize_vertex_cache(mesh);
+    optimize_overdraw(mesh);
+    optimize_vertex_fetch(mesh);
+
+    (before, analyze_mesh(mesh))
+}
+
+/// Reorders or drops the vertices of `mesh`. Vertex `i` of the result is vertex `order[i]` of the input.
+pub(crate) fn reorder_vertices(mesh: &mut TriangleMesh, order: &[u32]) {
+    fn apply<T: Copy>(data: &mut Vec<T>, order: &[u32]) {
+        // Optional attributes are empty
+        if !data.is_empty() {
+            *data = order.iter().map(|&src| data[src as usize]).collect();
+        }
+    }
+
+    apply(&mut mesh.positions, order);
+    apply(&mut mesh.normals, order);
+    apply(&mut mesh.colors, order);
+    apply(&mut mesh.uvs, order);
+    apply(&mut mesh.uvs1, order);
+    apply(&mut mesh.tangents, order);
+    apply(&mut mesh.joints, order);
+    apply(&mut mesh.weights, order);
+    apply(&mut mesh.material_ids, order);
+
+    for target in &mut mesh.morph_targets {
+        apply(&mut target.positions, order);
+        apply(&mut target.normals, order);
+        apply(&mut target.tangents, order);
+    }
+}
+
+/// Bit patterns of all the attributes of a vertex, including its morph target displacements
+fn vertex_key(mesh: &TriangleMesh, vertex: usize) -> Vec<u32> {
+    fn push<const N: usize>(key: &mut Vec<u32>, data: &[[f32; N]], vertex: usize) {
+        if let Some(value) = data.get(vertex) {
+            key.extend(value.iter().map(|v| v.to_bits()));
+        }
+    }
+
+    let mut key = Vec::with_capacity(32);
+    push(&mut key, &mesh.positions, vertex);
+    push(&mut key, &mesh.normals, vertex);
+    push(&mut key, &mesh.colors, vertex);
+    push(&mut key, &mesh.uvs, vertex);
+    push(&mut key, &mesh.uvs1, vertex);
+    push(&mut key, &mesh.tangents, vertex);
+    push(&mut key, &mesh.weights, vertex);
+
+    if let Some(joints) = mesh.joints.get(vertex) {
+        key.extend(joints.iter().map(|&j| j as u32));
+    }
+    if let Some(&material_id) = mesh.material_ids.get(vertex) {
+        key.push(material_id);
+    }
+
+    for target in &mesh.morph_targets {
+        push(&mut key, &target.positions, vertex);
+        push(&mut key, &target.normals, vertex);
+        push(&mut key, &target.tangents, vertex);
+    }
+
+    key
+}
+
+/// Merges vertices whose attributes are bitwise identical, and drops unreferenced ones
+pub fn weld_vertices(mesh: &mut TriangleMesh) {
+    let mut unique: HashMap<Vec<u32>, u32> = HashMap::new();
+    let mut order: Vec<u32> = Vec::new();
+    let mut remap: Vec<Option<u32>> = vec![None; mesh.positions.len()];
+
+    let mut indices = std::mem::take(&mut mesh.indices);
+    for idx in indices.iter_mut() {
+        let vertex = *idx as usize;
+        *idx = *remap[vertex].get_or_insert_with(|| {
+            *unique.entry(vertex_key(mesh, vertex)).or_insert_with(|| {
+                order.push(vertex as u32);
+                order.len() as u32 - 1
+            })
+        });
+    }
+
+    mesh.indices = indices;
+    reorder_vertices(mesh, &order);
+}
+
+/// Drops triangles which reference the same vertex more than once
+pub fn remove_degenerate_triangles(mesh: &mut TriangleMesh) {
+    mesh.indices = mesh
+        .indices
+        .chunks_exact(3)
+        .filter(|tri| tri[0] != tri[1] && tri[1] != tri[2] && tri[2] != tri[0])
+        .flatten()
+        .copied()
+        .collect();
+}
+
+/// Triangles adjacent to each vertex, in compressed sparse row form
+struct VertexAdjacency {
+    offsets: Vec<u32>,
+    triangles: Vec<u32>,
+}
+
+impl VertexAdjacency {
+    fn new(indices: &[u32], vertex_count: usize) -> Self {
+        let mut offsets = vec![0u32; vertex_count + 1];
+        for &idx in indices {
+            offsets[idx as usize + 1] += 1;
+        }
+        for i in 0..vertex_count {
+            offsets[i + 1] += offsets[i];
+        }
+
+        let mut fill = offsets.clone();
+        let mut triangles = vec![0u32; indices.len()];
+        for (tri_idx, tri) in indices.chunks_exact(3).enumerate() {
+            for &idx in tri {
+                triangles[fill[idx as usize] as usize] = tri_idx as u32;
+                fill[idx as usize] += 1;
+            }
+        }
+
+        Self { offsets, triangles }
+    }
+
+    fn triangles(&self, vertex: u32) -> &[u32] {
+        &self.triangles
+            [self.offsets[vertex as usize] as usize..self.offsets[vertex as usize + 1] as usize]
+    }
+}
+
+/// Score of a vertex in Tom Forsyth's "Linear-Speed Vertex Cache Optimisation"
+fn forsyth_vertex_score(cache_position: Option<usize>, remaining_valence: u32) -> f32 {
+    const CACHE_DECAY_POWER: f32 = 1.5;
+    const LAST_TRI_SCORE: f32 = 0.75;
+    const VALENCE_BOOST_SCALE: f32 = 2.0;
+    const VALENCE_BOOST_POWER: f32 = 0.5;
+
+    if remaining_valence == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        // The vertices of the last triangle are deliberately scored lower,
+        // so that strips aren't preferred over fans
+        Some(pos) if pos < 3 => LAST_TRI_SCORE,
+        Some(pos) => {
+            let scale = 1.0 / (OPTIMIZATION_CACHE_SIZE - 3) as f32;
+            (1.0 - (pos - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+        None => 0.0,
+    };
+
+    // Boost vertices with few triangles left, so that they get finished off
+    cache_score + VALENCE_BOOST_SCALE * (remaining_valence as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Orders triangles to make the most of the post-transform vertex cache
+pub fn optimize_vertex_cache(mesh: &mut TriangleMesh) {
+    mesh.indices = vertex_cache_order(&mesh.indices, mesh.positions.len());
+}
+
+/// Returns `indices` with its triangles reordered for the post-transform vertex cache
+pub fn vertex_cache_order(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return Vec::new();
+    }
+
+    let adjacency = VertexAdjacency::new(indices, vertex_count);
+
+    let mut valence: Vec<u32> = (0..vertex_count as u32)
+        .map(|v| adjacency.triangles(v).len() as u32)
+        .collect();
+    let mut vertex_scores: Vec<f32> = valence
+        .iter()
+        .map(|&valence| forsyth_vertex_score(None, valence))
+        .collect();
+    let mut triangle_scores: Vec<f32> = indices
+        .chunks_exact(3)
+        .map(|tri| tri.iter().map(|&v| vertex_scores[v as usize]).sum())
+        .collect();
+
+    let mut emitted = vec![false; triangle_count];
+    let mut cache: Vec<u32> = Vec::with_capacity(OPTIMIZATION_CACHE_SIZE + 3);
+    let mut new_cache: Vec<u32> = Vec::with_capacity(OPTIMIZATION_CACHE_SIZE + 3);
+    let mut result: Vec<u32> = Vec::with_capacity(indices.len());
+
+    // Where to resume the search for a starting triangle when the cache has nothing to offer
+    let mut input_cursor = 0;
+    let mut best_triangle = Some(
+        (0..triangle_count)
+            .max_by(|&a, &b| triangle_scores[a].total_cmp(&triangle_scores[b]))
+            .unwrap(),
+    );
+
+    while let Some(tri_idx) = best_triangle {
+        let tri = &indices[tri_idx * 3..tri_idx * 3 + 3];
+        emitted[tri_idx] = true;
+        result.extend_from_slice(tri);
+
+        for &v in tri {
+            valence[v as usize] -= 1;
+        }
+
+        // Move the vertices of the triangle to the front of the cache
+        new_cache.clear();
+        new_cache.extend_from_slice(tri);
+        new_cache.extend(cache.iter().copied().filter(|v| !tri.contains(v)));
+
+        // Rescore the vertices which were in the cache or just fell out of it,
+        // and the triangles which use them
+        best_triangle = None;
+        let mut best_score = f32::MIN;
+
+        for (pos, &v) in new_cache.iter().enumerate() {
+            let cache_position = (pos < OPTIMIZATION_CACHE_SIZE).then_some(pos);
+            vertex_scores[v as usize] = forsyth_vertex_score(cache_position, valence[v as usize]);
+        }
+
+        for &v in &new_cache {
+            for &adj_tri in adjacency.triangles(v) {
+                let adj_tri = adj_tri as usize;
+                if emitted[adj_tri] {
+                    continue;
+                }
+
+                let score: f32 = indices[adj_tri * 3..adj_tri * 3 + 3]
+                    .iter()
+                    .map(|&v| vertex_scores[v as usize])
+                    .sum();
+                triangle_scores[adj_tri] = score;
+
+                if score > best_score {
+                    best_score = score;
+                    best_triangle = Some(adj_tri);
+                }
+            }
+        }
+
+        new_cache.truncate(OPTIMIZATION_CACHE_SIZE);
+        std::mem::swap(&mut cache, &mut new_cache);
+
+        if best_triangle.is_none() {
+            while input_cursor < triangle_count && emitted[input_cursor] {
+                input_cursor += 1;
+            }
+            best_triangle = (input_cursor < triangle_count).then_some(input_cursor);
+        }
+    }
+
+    result
+}
+
+/// Splits the triangle order into clusters which can be reordered without hurting
+/// vertex cache efficiency much, and sorts them so that ones facing outwards come first.
+/// Those are likely to occlude the rest, which reduces overdraw.
+///
+/// Based on "Fast Triangle Reordering for Vertex Locality and Reduced Overdraw" by Sander et al.
+pub fn optimize_overdraw(mesh: &mut TriangleMesh) {
+    let triangle_count = mesh.indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    // Misses of each triangle in a FIFO cache
+    let mut cache: Vec<u32> = Vec::with_capacity(ANALYSIS_CACHE_SIZE);
+    let simulate = |tri: &[u32], cache: &mut Vec<u32>| -> u32 {
+        let mut misses = 0;
+        for &v in tri {
+            if !cache.contains(&v) {
+                if cache.len() == ANALYSIS_CACHE_SIZE {
+                    cache.remove(0);
+                }
+                cache.push(v);
+                misses += 1;
+            }
+        }
+        misses
+    };
+
+    // Hard boundaries are where the cache got flushed anyway: all three vertices missed
+    let mut hard_boundaries = vec![0];
+    for (tri_idx, tri) in mesh.indices.chunks_exact(3).enumerate() {
+        if simulate(tri, &mut cache) == 3 && tri_idx > 0 {
+            hard_boundaries.push(tri_idx);
+        }
+    }
+    hard_boundaries.push(triangle_count);
+
+    // Soft boundaries split those further, wherever the cluster so far is about as
+    // cache-efficient as its whole hard cluster
+    let mut clusters: Vec<std::ops::Range<usize>> = Vec::new();
+    for range in hard_boundaries.windows(2) {
+        let (start, end) = (range[0], range[1]);
+        let tris = &mesh.indices[start * 3..end * 3];
+
+        cache.clear();
+        let total_misses: u32 = tris
+            .chunks_exact(3)
+            .map(|tri| simulate(tri, &mut cache))
+            .sum();
+        let threshold = OVERDRAW_ACMR_THRESHOLD * total_misses as f32 / (end - start) as f32;
+
+        cache.clear();
+        let mut cluster_start = start;
+        let mut cluster_misses = 0;
+        for (i, tri) in tris.chunks_exact(3).enumerate() {
+            cluster_misses += simulate(tri, &mut cache);
+
+            let tri_idx = start + i + 1;
+            let acmr = cluster_misses as f32 / (tri_idx - cluster_start) as f32;
+            if acmr <= threshold && tri_idx < end {
+                clusters.push(cluster_start..tri_idx);
+                cluster_start = tri_idx;
+                cluster_misses = 0;
+                cache.clear();
+            }
+        }
+        clusters.push(cluster_start..end);
+    }
+
+    let position = |v: u32| Vec3::from(mesh.positions[v as usize]);
+
+    // Area-weighted centroid of the mesh, and of each cluster
+    let mut mesh_centroid = Vec3::ZERO;
+    let mut mesh_area = 0.0;
+    let cluster_keys: Vec<f32> = {
+        let cluster_geometry: Vec<(Vec3, Vec3, f32)> = clusters
+            .iter()
+            .map(|cluster| {
+                let mut centroid = Vec3::ZERO;
+                let mut normal = Vec3::ZERO;
+                let mut area = 0.0;
+
+                for tri in mesh.indices[cluster.start * 3..cluster.end * 3].chunks_exact(3) {
+                    let (p0, p1, p2) = (position(tri[0]), position(tri[1]), position(tri[2]));
+                    let cross = (p1 - p0).cross(p2 - p0);
+                    let tri_area = cross.length() * 0.5;
+
+                    centroid += (p0 + p1 + p2) * (tri_area / 3.0);
+                    normal += cross;
+                    area += tri_area;
+                }
+
+                mesh_centroid += centroid;
+                mesh_area += area;
+
+                (centroid, normal, area)
+            })
+            .collect();
+
+        if mesh_area > 0.0 {
+            mesh_centroid /= mesh_area;
+        }
+
+        cluster_geometry
+            .into_iter()
+            .map(|(centroid, normal, area)| {
+                if area > 0.0 && normal.length_squared() > 0.0 {
+                    (centroid / area - mesh_centroid).dot(normal.normalize())
+                } else {
+                    f32::MIN
+                }
+            })
+            .collect()
+    };
+
+    let mut cluster_order: Vec<usize> = (0..clusters.len()).collect();
+    cluster_order.sort_by(|&a, &b| cluster_keys[b].total_cmp(&cluster_keys[a]));
+
+    mesh.indices = cluster_order
+        .into_iter()
+        .flat_map(|cluster| {
+            let cluster = &clusters[cluster];
+            mesh.indices[cluster.start * 3..cluster.end * 3]
+                .iter()
+                .copied()
+        })
+        .collect();
+}
+
+/// Orders vertices by their first use in the index buffer, and drops unreferenced ones
+pub fn optimize_vertex_fetch(mesh: &mut TriangleMesh) {
+    let mut remap: Vec<Option<u32>> = vec![None; mesh.positions.len()];
+    let mut order: Vec<u32> = Vec::with_capacity(mesh.positions.len());
+
+    for idx in mesh.indices.iter_mut() {
+        let vertex = *idx;
+        *idx = *remap[vertex as usize].get_or_insert_with(|| {
+            order.push(vertex);
+            order.len() as u32 - 1
+        });
+    }
+
+    reorder_vertices(mesh, &order);
+}
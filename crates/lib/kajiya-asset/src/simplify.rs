@@ -0,0 +1,445 @@
+// Mesh simplification for LOD generation. Edges get collapsed in the order of their quadric
+// error, as in "Surface Simplification Using Quadric Error Metrics" by Garland and Heckbert.
+//
+// Collapses only move vertices onto existing ones, so simplified index buffers can share the
+// vertex data of the full-detail mesh. Vertices on attribute seams and mesh borders are locked.
+
+use crate::mesh::{MeshLod, TriangleMesh, MAX_MESH_LODS};
+use glam::Vec3;
+use std::collections::HashMap;
+
+/// Each LOD aims to have this fraction of the triangles of the previous one
+const LOD_TRIANGLE_RATIO: f32 = 0.5;
+
+/// LODs which don't get below this fraction of the triangles of the previous one are dropped
+const LOD_MIN_REDUCTION: f32 = 0.85;
+
+/// Symmetric 4x4 matrix measuring the sum of squared distances to a set of planes
+#[derive(Clone, Copy, Default)]
+struct Quadric {
+    // Upper triangle, row-major
+    m: [f64; 10],
+}
+
+impl Quadric {
+    fn from_plane(normal: Vec3, point: Vec3) -> Self {
+        let [a, b, c] = normal.to_array().map(f64::from);
+        let d = -f64::from(normal.dot(point));
+
+        Self {
+            m: [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ],
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (dst, src) in self.m.iter_mut().zip(other.m.iter()) {
+            *dst += src;
+        }
+    }
+
+    fn error(&self, p: Vec3) -> f64 {
+        let [x, y, z] = p.to_array().map(f64::from);
+        let m = &self.m;
+
+        let err = m[0] * x * x
+            + 2.0 * m[1] * x * y
+            + 2.0 * m[2] * x * z
+            + 2.0 * m[3] * x
+            + m[4] * y * y
+            + 2.0 * m[5] * y * z
+            + 2.0 * m[6] * y
+            + m[7] * z * z
+            + 2.0 * m[8] * z
+            + m[9];
+
+        err.max(0.0)
+    }
+}
+
+struct Collapse {
+    // Canonical vertices
+    src: u32,
+    dst: u32,
+    cost: f64,
+}
+
+/// Simplifies the triangles in `indices` until there are no more than `target_index_count`
+/// indices, or collapses would move the surface by more than `max_error`.
+///
+/// Returns the new indices, and the error of the result in the units of `positions`.
+pub fn simplify(
+    indices: &[u32],
+    positions: &[[f32; 3]],
+    target_index_count: usize,
+    max_error: f32,
+) -> (Vec<u32>, f32) {
+    let vertex_count = positions.len();
+    let position = |v: u32| Vec3::from(positions[v as usize]);
+
+    // Vertices sharing a position are separate only because their other attributes differ.
+    // Map all of them to the first one.
+    let mut canonical: Vec<u32> = Vec::with_capacity(vertex_count);
+    {
+        let mut first_at_position: HashMap<[u32; 3], u32> = HashMap::new();
+        for (v, p) in positions.iter().enumerate() {
+            let first = *first_at_position
+                .entry(p.map(f32::to_bits))
+                .or_insert(v as u32);
+            canonical.push(first);
+        }
+    }
+
+    let mut indices: Vec<u32> = indices.to_vec();
+
+    // Lock attribute seams, as well as borders and non-manifold edges
+    let mut locked: Vec<bool> = vec![false; vertex_count];
+    {
+        let mut wedges: HashMap<u32, u32> = HashMap::new();
+        for &v in &indices {
+            let entry = wedges.entry(canonical[v as usize]).or_insert(v);
+            if *entry != v {
+                locked[canonical[v as usize] as usize] = true;
+            }
+        }
+
+        let mut edge_use: HashMap<(u32, u32), u32> = HashMap::new();
+        for tri in indices.chunks_exact(3) {
+            for i in 0..3 {
+                let a = canonical[tri[i] as usize];
+                let b = canonical[tri[(i + 1) % 3] as usize];
+                *edge_use.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+
+        for ((a, b), count) in edge_use {
+            if count != 2 {
+                locked[a as usize] = true;
+                locked[b as usize] = true;
+            }
+        }
+    }
+
+    let mut quadrics: Vec<Quadric> = vec![Quadric::default(); vertex_count];
+    for tri in indices.chunks_exact(3) {
+        let (p0, p1, p2) = (position(tri[0]), position(tri[1]), position(tri[2]));
+        let normal = (p1 - p0).cross(p2 - p0);
+        if normal.length_squared() == 0.0 {
+            continue;
+        }
+
+        let q = Quadric::from_plane(normal.normalize(), p0);
+        for &v in tri {
+            quadrics[canonical[v as usize] as usize].add(&q);
+        }
+    }
+
+    let max_cost = f64::from(max_error) * f64::from(max_error);
+    let mut result_cost = 0.0f64;
+
+    while indices.len() > target_index_count {
+        // Triangles around each canonical vertex
+        let mut vertex_triangles: Vec<Vec<u32>> = vec![Vec::new(); vertex_count];
+        for (tri_idx, tri) in indices.chunks_exact(3).enumerate() {
+            for &v in tri {
+                vertex_triangles[canonical[v as usize] as usize].push(tri_idx as u32);
+            }
+        }
+
+        let mut collapses: Vec<Collapse> = Vec::new();
+        for tri in indices.chunks_exact(3) {
+            for i in 0..3 {
+                let a = canonical[tri[i] as usize];
+                let b = canonical[tri[(i + 1) % 3] as usize];
+
+                for (src, dst) in [(a, b), (b, a)] {
+                    if locked[src as usize] {
+                        continue;
+                    }
+
+                    let mut q = quadrics[src as usize];
+                    q.add(&quadrics[dst as usize]);
+
+                    collapses.push(Collapse {
+                        src,
+                        dst,
+                        cost: q.error(position(dst)),
+                    });
+                }
+            }
+        }
+
+        collapses.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+
+        // Collapses performed in this pass mustn't touch each other's triangles
+        let mut touched: Vec<bool> = vec![false; vertex_count];
+        let mut collapse_to: HashMap<u32, u32> = HashMap::new();
+        let mut removed_indices = 0;
+
+        for collapse in collapses {
+            if collapse.cost > max_cost || indices.len() - removed_indices <= target_index_count {
+                break;
+            }
+
+            let (src, dst) = (collapse.src as usize, collapse.dst as usize);
+            if touched[src] || touched[dst] {
+                continue;
+            }
+
+            let src_triangles = &vertex_triangles[src];
+            let tri_vertices = |tri_idx: u32| {
+                let tri = &indices[tri_idx as usize * 3..tri_idx as usize * 3 + 3];
+                [tri[0], tri[1], tri[2]]
+            };
+
+            // The vertex `src` gets replaced with, taken from a triangle on the collapsed edge
+            let dst_wedge = src_triangles.iter().find_map(|&tri_idx| {
+                tri_vertices(tri_idx)
+                    .iter()
+                    .copied()
+                    .find(|&v| canonical[v as usize] as usize == dst)
+            });
+            let dst_wedge = match dst_wedge {
+                Some(dst_wedge) => dst_wedge,
+                None => continue,
+            };
+
+            // Reject collapses which would flip triangles over. Normals may turn by up to 60 degrees,
+            // so that slivers can't flip over gradually across passes either.
+            let flips = src_triangles.iter().any(|&tri_idx| {
+                let tri = tri_vertices(tri_idx);
+                if tri.iter().any(|&v| canonical[v as usize] as usize == dst) {
+                    return false;
+                }
+
+                let before = tri.map(position);
+                let after = tri.map(|v| {
+                    if canonical[v as usize] as usize == src {
+                        position(dst_wedge)
+                    } else {
+                        position(v)
+                    }
+                });
+
+                let normal_before = (before[1] - before[0]).cross(before[2] - before[0]);
+                let normal_after = (after[1] - after[0]).cross(after[2] - after[0]);
+                normal_before.dot(normal_after)
+                    <= 0.5 * normal_before.length() * normal_after.length()
+            });
+
+            if flips {
+                continue;
+            }
+
+            for &tri_idx in src_triangles {
+                for v in tri_vertices(tri_idx) {
+                    touched[canonical[v as usize] as usize] = true;
+                }
+
+                // Triangles on the collapsed edge degenerate
+                if tri_vertices(tri_idx)
+                    .iter()
+                    .any(|&v| canonical[v as usize] as usize == dst)
+                {
+                    removed_indices += 3;
+                }
+            }
+
+            let src_quadric = quadrics[src];
+            quadrics[dst].add(&src_quadric);
+
+            collapse_to.insert(src as u32, dst_wedge);
+            result_cost = result_cost.max(collapse.cost);
+        }
+
+        if collapse_to.is_empty() {
+            break;
+        }
+
+        // Unlocked vertices have a single wedge, so all their uses get replaced
+        for v in indices.iter_mut() {
+            if let Some(&dst) = collapse_to.get(&canonical[*v as usize]) {
+                *v = dst;
+            }
+        }
+
+        indices = indices
+            .chunks_exact(3)
+            .filter(|tri| {
+                let [a, b, c] = [tri[0], tri[1], tri[2]].map(|v| canonical[v as usize]);
+                a != b && b != c && c != a
+            })
+            .flatten()
+            .copied()
+            .collect();
+    }
+
+    (indices, result_cost.sqrt() as f32)
+}
+
+/// Generates up to `max_lod_count`, but no more than `MAX_MESH_LODS`, progressively simplified
+/// index buffers for `mesh`.
+/// Simplification stops once it would move the surface by more than `max_relative_error`
+/// times the radius of the mesh's bounds.
+pub fn generate_lods(
+    mesh: &TriangleMesh,
+    max_lod_count: usize,
+    max_relative_error: f32,
+) -> Vec<MeshLod> {
+    let (min, max) = mesh.positions.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), &p| (min.min(p.into()), max.max(p.into())),
+    );
+    if min.cmpgt(max).any() {
+        return Vec::new();
+    }
+
+    let max_error = (max - min).length() * 0.5 * max_relative_error;
+
+    let mut lods: Vec<MeshLod> = Vec::new();
+    let mut prev_index_count = mesh.indices.len();
+
+    while lods.len() < max_lod_count.min(MAX_MESH_LODS) {
+        let target_index_count = (prev_index_count as f32 * LOD_TRIANGLE_RATIO) as usize / 3 * 3;
+
+        // Simplify the full-detail mesh every time, so that the error is measured against it
+        let (indices, error) = simplify(
+            &mesh.indices,
+            &mesh.positions,
+            target_index_count,
+            max_error,
+        );
+
+        if indices.is_empty() || indices.len() as f32 > prev_index_count as f32 * LOD_MIN_REDUCTION
+        {
+            break;
+        }
+
+        prev_index_count = indices.len();
+        lods.push(MeshLod {
+            indices: crate::optimize::vertex_cache_order(&indices, mesh.positions.len()),
+            error,
+        });
+    }
+
+    lods
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A grid of `n` by `n` quads over [0, 1]^2, displaced along Z by `height`
+    fn grid(n: u32, height: impl Fn(f32, f32) -> f32) -> TriangleMesh {
+        let mut mesh = TriangleMesh::default();
+
+        for y in 0..=n {
+            for x in 0..=n {
+                let (u, v) = (x as f32 / n as f32, y as f32 / n as f32);
+                mesh.positions.push([u, v, height(u, v)]);
+            }
+        }
+
+        let vertex = |x: u32, y: u32| y * (n + 1) + x;
+        for y in 0..n {
+            for x in 0..n {
+                let (a, b) = (vertex(x, y), vertex(x + 1, y));
+                let (c, d) = (vertex(x, y + 1), vertex(x + 1, y + 1));
+                mesh.indices.extend_from_slice(&[a, b, d, a, d, c]);
+            }
+        }
+
+        mesh
+    }
+
+    fn bumpy_grid() -> TriangleMesh {
+        grid(16, |u, v| 0.05 * (u * 9.0).sin() * (v * 7.0).cos())
+    }
+
+    fn assert_valid_triangles(indices: &[u32], vertex_count: usize) {
+        assert_eq!(indices.len() % 3, 0);
+        for tri in indices.chunks_exact(3) {
+            assert!(
+                tri.iter().all(|&v| (v as usize) < vertex_count),
+                "{:?}",
+                tri
+            );
+            assert!(tri[0] != tri[1] && tri[1] != tri[2] && tri[2] != tri[0]);
+        }
+    }
+
+    #[test]
+    fn flat_surfaces_simplify_without_error() {
+        let mesh = grid(8, |_, _| 0.0);
+        let (indices, error) = simplify(&mesh.indices, &mesh.positions, 0, 1e-4);
+
+        assert_valid_triangles(&indices, mesh.positions.len());
+        assert!(indices.len() < mesh.indices.len() / 4);
+        assert!(error < 1e-4);
+
+        // Border vertices are locked, so the simplified grid still covers the square
+        let area: f32 = indices
+            .chunks_exact(3)
+            .map(|tri| {
+                let [p0, p1, p2] = [0, 1, 2].map(|i| Vec3::from(mesh.positions[tri[i] as usize]));
+                (p1 - p0).cross(p2 - p0).length() * 0.5
+            })
+            .sum();
+        assert!((area - 1.0).abs() < 1e-4, "{}", area);
+    }
+
+    #[test]
+    fn simplification_stays_within_the_error_bound() {
+        let mesh = bumpy_grid();
+
+        for max_error in [0.0, 0.005, 0.01, 0.02] {
+            let (indices, error) = simplify(&mesh.indices, &mesh.positions, 0, max_error);
+
+            assert_valid_triangles(&indices, mesh.positions.len());
+            assert!(error <= max_error, "{} > {}", error, max_error);
+            if max_error >= 0.01 {
+                assert!(indices.len() < mesh.indices.len());
+            }
+        }
+
+        // Without a budget, curved regions stay intact
+        let (indices, error) = simplify(&mesh.indices, &mesh.positions, 0, 0.0);
+        assert_eq!(error, 0.0);
+        assert!(indices.len() > mesh.indices.len() / 2);
+    }
+
+    #[test]
+    fn lod_chain_gets_coarser() {
+        let mesh = bumpy_grid();
+        let max_relative_error = 0.05;
+        let radius = Vec3::new(1.0, 1.0, 0.1).length() * 0.5;
+
+        let lods = generate_lods(&mesh, MAX_MESH_LODS + 2, max_relative_error);
+        assert!(lods.len() >= 2);
+        assert!(lods.len() <= MAX_MESH_LODS);
+
+        let mut prev_index_count = mesh.indices.len();
+        let mut prev_error = 0.0;
+        for lod in &lods {
+            assert_valid_triangles(&lod.indices, mesh.positions.len());
+            assert!(lod.indices.len() as f32 <= prev_index_count as f32 * LOD_MIN_REDUCTION);
+            assert!(lod.error >= prev_error);
+            assert!(lod.error <= radius * max_relative_error);
+
+            prev_index_count = lod.indices.len();
+            prev_error = lod.error;
+        }
+
+        assert!(generate_lods(&mesh, 0, max_relative_error).is_empty());
+    }
+}
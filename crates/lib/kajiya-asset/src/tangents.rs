@@ -0,0 +1,408 @@
+// Generation of MikkTSpace tangents, for meshes whose source doesn't provide them.
+//
+// Follows the reference implementation by Morten S. Mikkelsen, which is what tools bake
+// tangent-space normal maps against, and what glTF mandates in the absence of tangents:
+//
+// * Vertices with identical positions, normals, and UVs are treated as one
+// * Each triangle gets a tangent from its UV derivatives, and an orientation from the sign of its UV area
+// * Around each vertex, triangles connected through shared edges and with the same orientation
+//   form a group. Triangles with degenerate UVs join any group, but don't contribute to it.
+// * Each group gets the average of the tangents of its triangles, projected onto the plane
+//   of the vertex normal, and weighted by the angle of the triangle at the vertex
+//
+// The reference implementation splits groups further when their tangents differ by more than
+// an angular threshold. Its default of 180 degrees never does that, so that step is omitted.
+
+use crate::{mesh::TriangleMesh, optimize::reorder_vertices};
+use glam::{Vec2, Vec3};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy)]
+struct TriangleInfo {
+    /// Normalized direction of increasing U
+    tangent: Vec3,
+    /// Whether the UVs wind the same way as the positions
+    orientation_preserving: bool,
+    /// Degenerate UVs; the triangle groups with any other, and its tangent isn't used
+    group_with_any: bool,
+    /// Repeated vertices; the triangle borrows the tangents of other triangles
+    degenerate: bool,
+}
+
+fn not_zero(x: f32) -> bool {
+    x.abs() > f32::MIN_POSITIVE
+}
+
+fn normalize_if_not_zero(v: Vec3) -> Vec3 {
+    let len = v.length();
+    if not_zero(len) {
+        v / len
+    } else {
+        v
+    }
+}
+
+/// Some unit vector perpendicular to `n`, for vertices which get no tangent from their UVs
+fn any_tangent(n: Vec3) -> Vec3 {
+    let other = if n.x.abs() < 0.9 { Vec3::X } else { Vec3::Y };
+    normalize_if_not_zero(other - n * n.dot(other))
+}
+
+fn find_root(parents: &mut [usize], mut idx: usize) -> usize {
+    while parents[idx] != idx {
+        parents[idx] = parents[parents[idx]];
+        idx = parents[idx];
+    }
+    idx
+}
+
+/// Generates MikkTSpace tangents for the triangles whose vertices are flagged in `missing`,
+/// from the positions, normals, and first UV set of the mesh. Other tangents are kept.
+///
+/// Vertices used by triangles which need different tangents, as happens along the seams of
+/// mirrored UVs, are split.
+pub fn generate_tangents(mesh: &mut TriangleMesh, missing: &[bool]) {
+    let positions: Vec<Vec3> = mesh.positions.iter().copied().map(Vec3::from).collect();
+    let normals: Vec<Vec3> = mesh.normals.iter().copied().map(Vec3::from).collect();
+    let uvs: Vec<Vec2> = mesh.uvs.iter().copied().map(Vec2::from).collect();
+
+    // Triangles, and the welded vertices of their corners
+    let triangles: Vec<usize> = (0..mesh.indices.len() / 3)
+        .filter(|&tri| {
+            mesh.indices[tri * 3..tri * 3 + 3]
+                .iter()
+                .any(|&idx| missing[idx as usize])
+        })
+        .collect();
+    if triangles.is_empty() {
+        return;
+    }
+
+    let mut welded: HashMap<[u32; 8], u32> = HashMap::new();
+    let corner_vertices: Vec<[u32; 3]> = triangles
+        .iter()
+        .map(|&tri| {
+            let mut corners = [0u32; 3];
+            for (corner, &idx) in corners.iter_mut().zip(&mesh.indices[tri * 3..tri * 3 + 3]) {
+                let idx = idx as usize;
+                let p = mesh.positions[idx];
+                let n = mesh.normals[idx];
+                let uv = mesh.uvs[idx];
+                let key = [p[0], p[1], p[2], n[0], n[1], n[2], uv[0], uv[1]].map(f32::to_bits);
+
+                let next_id = welded.len() as u32;
+                *corner = *welded.entry(key).or_insert(next_id);
+            }
+            corners
+        })
+        .collect();
+
+    let infos: Vec<TriangleInfo> = triangles
+        .iter()
+        .zip(&corner_vertices)
+        .map(|(&tri, welded)| {
+            let [i0, i1, i2] = [0, 1, 2].map(|k| mesh.indices[tri * 3 + k] as usize);
+
+            let d1 = positions[i1] - positions[i0];
+            let d2 = positions[i2] - positions[i0];
+            let t21 = uvs[i1] - uvs[i0];
+            let t31 = uvs[i2] - uvs[i0];
+
+            let signed_uv_area_x2 = t21.x * t31.y - t21.y * t31.x;
+            let orientation_preserving = signed_uv_area_x2 > 0.0;
+
+            let mut tangent = d1 * t31.y - d2 * t21.y;
+            let bitangent = d2 * t21.x - d1 * t31.x;
+            let mut group_with_any = true;
+
+            if not_zero(signed_uv_area_x2) {
+                let sign = if orientation_preserving { 1.0 } else { -1.0 };
+                let tangent_len = tangent.length();
+                let bitangent_len = bitangent.length();
+
+                if not_zero(tangent_len) {
+                    tangent *= sign / tangent_len;
+                }
+
+                let area = signed_uv_area_x2.abs();
+                if not_zero(tangent_len / area) && not_zero(bitangent_len / area) {
+                    group_with_any = false;
+                }
+            }
+
+            TriangleInfo {
+                tangent,
+                orientation_preserving,
+                group_with_any,
+                degenerate: welded[0] == welded[1]
+                    || welded[1] == welded[2]
+                    || welded[2] == welded[0],
+            }
+        })
+        .collect();
+
+    // Corners around each welded vertex, as (triangle, corner) pairs
+    let mut vertex_corners: Vec<Vec<(usize, usize)>> = vec![Vec::new(); welded.len()];
+    for (tri, corners) in corner_vertices.iter().enumerate() {
+        if !infos[tri].degenerate {
+            for (corner, &vertex) in corners.iter().enumerate() {
+                vertex_corners[vertex as usize].push((tri, corner));
+            }
+        }
+    }
+
+    // Tangent and bitangent sign of each corner
+    let mut corner_tangents: Vec<Option<[f32; 4]>> = vec![None; triangles.len() * 3];
+
+    for (vertex, corners) in vertex_corners.iter().enumerate() {
+        // Group the triangles around the vertex. Two of them connect if they share an edge
+        // ending at the vertex, and either have the same orientation, or one groups with any.
+        let mut parents: Vec<usize> = (0..corners.len()).collect();
+        for a in 0..corners.len() {
+            for b in a + 1..corners.len() {
+                let (tri_a, tri_b) = (corners[a].0, corners[b].0);
+                let shares_edge = corner_vertices[tri_a]
+                    .iter()
+                    .any(|&v| v != vertex as u32 && corner_vertices[tri_b].contains(&v));
+                let (info_a, info_b) = (&infos[tri_a], &infos[tri_b]);
+                let compatible = info_a.group_with_any
+                    || info_b.group_with_any
+                    || info_a.orientation_preserving == info_b.orientation_preserving;
+
+                if shares_edge && compatible {
+                    let (root_a, root_b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+                    parents[root_a] = root_b;
+                }
+            }
+        }
+
+        let mut group_tangents: HashMap<usize, (Vec3, Option<bool>)> = HashMap::new();
+        for (member, &(tri, corner)) in corners.iter().enumerate() {
+            let group = find_root(&mut parents, member);
+            let (sum, orientation) = group_tangents.entry(group).or_insert((Vec3::ZERO, None));
+
+            let info = &infos[tri];
+            if info.group_with_any {
+                continue;
+            }
+            orientation.get_or_insert(info.orientation_preserving);
+
+            let idx = |k: usize| mesh.indices[triangles[tri] * 3 + k] as usize;
+            let n = normals[idx(corner)];
+            let project = |v: Vec3| normalize_if_not_zero(v - n * n.dot(v));
+
+            let p = positions[idx(corner)];
+            let edge0 = project(positions[idx((corner + 2) % 3)] - p);
+            let edge1 = project(positions[idx((corner + 1) % 3)] - p);
+            let angle = edge0.dot(edge1).max(-1.0).min(1.0).acos();
+
+            *sum += project(info.tangent) * angle;
+        }
+
+        for (member, &(tri, corner)) in corners.iter().enumerate() {
+            let group = find_root(&mut parents, member);
+            let (sum, orientation) = group_tangents[&group];
+
+            let n = normals[mesh.indices[triangles[tri] * 3 + corner] as usize];
+            let tangent = if not_zero(sum.length()) {
+                sum.normalize()
+            } else {
+                any_tangent(n)
+            };
+            let sign = if orientation.unwrap_or(infos[tri].orientation_preserving) {
+                1.0
+            } else {
+                -1.0
+            };
+
+            corner_tangents[tri * 3 + corner] = Some([tangent.x, tangent.y, tangent.z, sign]);
+        }
+    }
+
+    // Triangles with repeated vertices borrow the tangents of others at the same vertices
+    let mut vertex_tangents: HashMap<u32, [f32; 4]> = HashMap::new();
+    for (tri_idx, &tri) in triangles.iter().enumerate() {
+        for corner in 0..3 {
+            if let Some(tangent) = corner_tangents[tri_idx * 3 + corner] {
+                vertex_tangents
+                    .entry(mesh.indices[tri * 3 + corner])
+                    .or_insert(tangent);
+            }
+        }
+    }
+
+    // Vertices keep the first tangent they get, and are copied for each different one
+    let vertex_count = mesh.positions.len();
+    let mut order: Vec<u32> = (0..vertex_count as u32).collect();
+    let mut tangents = mesh.tangents.clone();
+    let mut assigned: Vec<Option<[f32; 4]>> = vec![None; vertex_count];
+    let mut copies: HashMap<(u32, [u32; 4]), u32> = HashMap::new();
+
+    for (tri_idx, &tri) in triangles.iter().enumerate() {
+        for corner in 0..3 {
+            let idx = mesh.indices[tri * 3 + corner];
+            let tangent = corner_tangents[tri_idx * 3 + corner]
+                .or_else(|| vertex_tangents.get(&idx).copied())
+                .unwrap_or_else(|| {
+                    let t = any_tangent(normals[idx as usize]);
+                    [t.x, t.y, t.z, 1.0]
+                });
+
+            match assigned[idx as usize] {
+                None => {
+                    assigned[idx as usize] = Some(tangent);
+                    tangents[idx as usize] = tangent;
+                }
+                Some(existing) if existing == tangent => {}
+                Some(_) => {
+                    mesh.indices[tri * 3 + corner] = *copies
+                        .entry((idx, tangent.map(f32::to_bits)))
+                        .or_insert_with(|| {
+                            order.push(idx);
+                            tangents.push(tangent);
+                            order.len() as u32 - 1
+                        });
+                }
+            }
+        }
+    }
+
+    reorder_vertices(mesh, &order);
+    mesh.tangents = tangents;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::MorphTarget;
+
+    // Two quads with UVs mirrored across their shared edge, a triangle with collinear UVs,
+    // and one with a repeated vertex
+    fn mirrored_mesh() -> TriangleMesh {
+        TriangleMesh {
+            positions: vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [2.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [2.0, 1.0, 0.0],
+                [0.5, 2.0, 0.0],
+            ],
+            normals: vec![[0.0, 0.0, 1.0]; 7],
+            uvs: vec![
+                [0.0, 0.0],
+                [1.0, 0.0],
+                [0.0, 0.0],
+                [0.0, 1.0],
+                [1.0, 1.2],
+                [0.0, 1.0],
+                [0.5, 1.1],
+            ],
+            tangents: vec![[1.0, 0.0, 0.0, 0.0]; 7],
+            indices: vec![0, 1, 4, 0, 4, 3, 1, 2, 5, 1, 5, 4, 3, 4, 6, 2, 5, 5],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn matches_reference_implementation() {
+        // Tangents of each corner of `mirrored_mesh`, as generated by `bevy_mikktspace` 0.10,
+        // a port of the reference implementation
+        let expected: [[f32; 4]; 18] = [
+            [0.99513334, -0.098537646, 0.0, 1.0],
+            [1.0, 0.0, 0.0, 1.0],
+            [0.99513334, -0.098537646, 0.0, 1.0],
+            [0.99513334, -0.098537646, 0.0, 1.0],
+            [0.99513334, -0.098537646, 0.0, 1.0],
+            [0.9805806, -0.19611616, 0.0, 1.0],
+            [-0.9965927, -0.08248055, 0.0, -1.0],
+            [-1.0, 0.0, 0.0, -1.0],
+            [-0.9965927, -0.08248055, 0.0, -1.0],
+            [-0.9965927, -0.08248055, 0.0, -1.0],
+            [-0.9965927, -0.08248055, 0.0, -1.0],
+            [-0.98639387, -0.16439901, 0.0, -1.0],
+            [0.9805806, -0.19611616, 0.0, 1.0],
+            [0.99513334, -0.098537646, 0.0, 1.0],
+            [1.0, 0.0, 0.0, -1.0],
+            [-1.0, 0.0, 0.0, -1.0],
+            [-0.9965927, -0.08248055, 0.0, -1.0],
+            [-0.9965927, -0.08248055, 0.0, -1.0],
+        ];
+
+        let mut mesh = mirrored_mesh();
+        generate_tangents(&mut mesh, &[true; 7]);
+
+        for (corner, expected) in expected.iter().enumerate() {
+            let actual = mesh.tangents[mesh.indices[corner] as usize];
+            assert!(
+                actual
+                    .iter()
+                    .zip(expected)
+                    .all(|(a, b)| (a - b).abs() < 1e-5),
+                "corner {}: {:?} != {:?}",
+                corner,
+                actual,
+                expected
+            );
+        }
+
+        // Vertices along the mirrored edge get a tangent for each side
+        assert_eq!(mesh.positions.len(), 9);
+    }
+
+    #[test]
+    fn split_vertices_keep_their_other_attributes() {
+        let mut mesh = mirrored_mesh();
+        let vertex_count = mesh.positions.len();
+
+        // Unique values for each vertex, to find where they ended up
+        mesh.colors = (0..vertex_count)
+            .map(|i| [i as f32, 0.0, 0.0, 1.0])
+            .collect();
+        mesh.uvs1 = (0..vertex_count).map(|i| [i as f32, 1.0]).collect();
+        mesh.joints = (0..vertex_count).map(|i| [i as u16, 1, 2, 3]).collect();
+        mesh.weights = (0..vertex_count)
+            .map(|i| [i as f32, 0.5, 0.0, 0.0])
+            .collect();
+        mesh.material_ids = (0..vertex_count as u32).collect();
+        mesh.morph_targets = vec![MorphTarget {
+            positions: (0..vertex_count).map(|i| [i as f32, 2.0, 0.0]).collect(),
+            normals: (0..vertex_count).map(|i| [i as f32, 3.0, 0.0]).collect(),
+            tangents: (0..vertex_count).map(|i| [i as f32, 4.0, 0.0]).collect(),
+        }];
+
+        let original = mesh.clone();
+        generate_tangents(&mut mesh, &[true; 7]);
+
+        let new_count = mesh.positions.len();
+        assert!(new_count > vertex_count);
+        assert_eq!(mesh.normals.len(), new_count);
+        assert_eq!(mesh.uvs.len(), new_count);
+        assert_eq!(mesh.tangents.len(), new_count);
+        assert_eq!(mesh.colors.len(), new_count);
+        assert_eq!(mesh.uvs1.len(), new_count);
+        assert_eq!(mesh.joints.len(), new_count);
+        assert_eq!(mesh.weights.len(), new_count);
+        assert_eq!(mesh.material_ids.len(), new_count);
+
+        // Each corner still refers to a copy of the same source vertex
+        for (&new, &old) in mesh.indices.iter().zip(&original.indices) {
+            let (new, old) = (new as usize, old as usize);
+
+            assert_eq!(mesh.positions[new], original.positions[old]);
+            assert_eq!(mesh.uvs[new], original.uvs[old]);
+            assert_eq!(mesh.colors[new], original.colors[old]);
+            assert_eq!(mesh.uvs1[new], original.uvs1[old]);
+            assert_eq!(mesh.joints[new], original.joints[old]);
+            assert_eq!(mesh.weights[new], original.weights[old]);
+            assert_eq!(mesh.material_ids[new], original.material_ids[old]);
+
+            let (target, original_target) = (&mesh.morph_targets[0], &original.morph_targets[0]);
+            assert_eq!(target.positions.len(), new_count);
+            assert_eq!(target.positions[new], original_target.positions[old]);
+            assert_eq!(target.normals[new], original_target.normals[old]);
+            assert_eq!(target.tangents[new], original_target.tangents[old]);
+        }
+    }
+}
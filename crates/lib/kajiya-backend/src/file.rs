@@ -2,109 +2,42 @@ use anyhow::Context as _;
 use bytes::Bytes;
 use hotwatch::Hotwatch;
 use lazy_static::lazy_static;
-use normpath::PathExt;
 use parking_lot::Mutex;
-use std::{collections::HashMap, fs::File, path::PathBuf};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
 use turbosloth::*;
 
+pub use crate::vfs::{
+    canonical_path_from_vfs, normalized_path_from_vfs, set_standard_vfs_mount_points,
+    set_vfs_mount_point,
+};
+use crate::vfs::{normalize_vfs_path, vfs_disk_path, vfs_read, vfs_stat};
+
 lazy_static! {
     pub(crate) static ref FILE_WATCHER: Mutex<Hotwatch> =
         Mutex::new(Hotwatch::new_with_custom_delay(std::time::Duration::from_millis(100)).unwrap());
 }
 
-lazy_static! {
-    static ref VFS_MOUNT_POINTS: Mutex<HashMap<String, PathBuf>> = Mutex::new(
-        vec![
-            ("/kajiya".to_owned(), PathBuf::from(".")),
-            ("/shaders".to_owned(), PathBuf::from("assets/shaders")),
-            (
-                "/rust-shaders-compiled".to_owned(),
-                PathBuf::from("assets/rust-shaders-compiled")
-            ),
-            ("/images".to_owned(), PathBuf::from("assets/images")),
-            ("/baked".to_owned(), PathBuf::from("baked"))
-        ]
-        .into_iter()
-        .collect()
-    );
-}
-
-pub fn set_vfs_mount_point(mount_point: impl Into<String>, path: impl Into<PathBuf>) {
-    VFS_MOUNT_POINTS
+/// Calls `on_change` with the path of every file written to, created in, or moved into
+/// the directory `dir`, or its subdirectories. Each directory may only be watched once.
+pub fn watch_dir_changes(
+    dir: &Path,
+    on_change: impl Fn(&Path) + Send + 'static,
+) -> anyhow::Result<()> {
+    FILE_WATCHER
         .lock()
-        .insert(mount_point.into(), path.into());
-}
-
-pub fn set_standard_vfs_mount_points(kajiya_path: impl Into<PathBuf>) {
-    let kajiya_path = kajiya_path.into();
-    set_vfs_mount_point("/kajiya", &kajiya_path);
-    set_vfs_mount_point("/shaders", kajiya_path.join("assets/shaders"));
-    set_vfs_mount_point(
-        "/rust-shaders-compiled",
-        kajiya_path.join("assets/rust-shaders-compiled"),
-    );
-    set_vfs_mount_point("/images", kajiya_path.join("assets/images"));
-}
-
-pub fn canonical_path_from_vfs(path: impl Into<PathBuf>) -> anyhow::Result<PathBuf> {
-    let path = path.into();
-
-    for (mount_point, mounted_path) in VFS_MOUNT_POINTS.lock().iter() {
-        if let Ok(rel_path) = path.strip_prefix(mount_point) {
-            return mounted_path
-                .join(rel_path)
-                .canonicalize()
-                .with_context(|| {
-                    format!(
-                        "Mounted parent folder: {:?}. Relative path: {:?}",
-                        mounted_path, rel_path
-                    )
-                })
-                .with_context(|| format!("canonicalize {:?}", rel_path));
-        }
-    }
-
-    if path.strip_prefix("/").is_ok() {
-        anyhow::bail!(
-            "No vfs mount point for {:?}. Current mount points: {:#?}",
-            path,
-            VFS_MOUNT_POINTS.lock()
-        );
-    }
-
-    Ok(path)
-}
-
-pub fn normalized_path_from_vfs(path: impl Into<PathBuf>) -> anyhow::Result<PathBuf> {
-    let path = path.into();
-
-    for (mount_point, mounted_path) in VFS_MOUNT_POINTS.lock().iter() {
-        if let Ok(rel_path) = path.strip_prefix(mount_point) {
-            return Ok(mounted_path
-                .join(rel_path)
-                .normalize()
-                .with_context(|| {
-                    format!(
-                        "Mounted parent folder: {:?}. Relative path: {:?}",
-                        mounted_path, rel_path
-                    )
-                })?
-                .as_path()
-                .to_owned());
-        }
-    }
-
-    if path.strip_prefix("/").is_ok() {
-        anyhow::bail!(
-            "No vfs mount point for {:?}. Current mount points: {:#?}",
-            path,
-            VFS_MOUNT_POINTS.lock()
-        );
-    }
-
-    Ok(path)
+        .watch(dir, move |event| match event {
+            hotwatch::Event::Create(path)
+            | hotwatch::Event::Write(path)
+            | hotwatch::Event::Rename(_, path) => on_change(&path),
+            _ => (),
+        })
+        .with_context(|| format!("trying to watch {:?}", dir))
 }
 
+/// Loads a file through the VFS. Files on disk get reloaded when they change.
 #[derive(Clone, Hash)]
 pub struct LoadFile {
     path: PathBuf,
@@ -112,7 +45,11 @@ pub struct LoadFile {
 
 impl LoadFile {
     pub fn new(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
-        let path = canonical_path_from_vfs(path)?;
+        let path = normalize_vfs_path(path.into());
+        if vfs_stat(&path)?.is_dir {
+            anyhow::bail!("LoadFile: {:?} is a directory", path);
+        }
+
         Ok(Self { path })
     }
 }
@@ -122,20 +59,31 @@ impl LazyWorker for LoadFile {
     type Output = anyhow::Result<Bytes>;
 
     async fn run(self, ctx: RunContext) -> Self::Output {
+        let disk_path = match vfs_disk_path(&self.path)? {
+            Some(disk_path) => disk_path
+                .canonicalize()
+                .with_context(|| format!("canonicalize {:?}", disk_path))?,
+            // Other backends, such as asset packs, don't change
+            None => {
+                return vfs_read(&self.path)
+                    .with_context(|| format!("LoadFile: trying to read {:?}", self.path))
+            }
+        };
+
         let invalidation_trigger = ctx.get_invalidation_trigger();
 
         FILE_WATCHER
             .lock()
-            .watch(self.path.clone(), move |event| {
+            .watch(disk_path.clone(), move |event| {
                 if matches!(event, hotwatch::Event::Write(_)) {
                     invalidation_trigger();
                 }
             })
-            .with_context(|| format!("LoadFile: trying to watch {:?}", self.path))?;
+            .with_context(|| format!("LoadFile: trying to watch {:?}", disk_path))?;
 
         let mut buffer = Vec::new();
-        std::io::Read::read_to_end(&mut File::open(&self.path)?, &mut buffer)
-            .with_context(|| format!("LoadFile: trying to read {:?}", self.path))?;
+        std::io::Read::read_to_end(&mut File::open(&disk_path)?, &mut buffer)
+            .with_context(|| format!("LoadFile: trying to read {:?}", disk_path))?;
 
         Ok(Bytes::from(buffer))
     }
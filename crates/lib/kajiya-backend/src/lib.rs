@@ -3,16 +3,21 @@ pub mod chunky_list;
 pub mod dynamic_constants;
 pub mod file;
 pub mod gpu_profiler;
+pub mod pack;
 pub mod pipeline_cache;
 pub mod rust_shader_compiler;
 pub mod shader_compiler;
 pub mod transient_resource_cache;
+pub mod vfs;
 pub mod vulkan;
 
 pub use ash;
-pub use file::{canonical_path_from_vfs, normalized_path_from_vfs, set_vfs_mount_point};
 pub use gpu_allocator;
 pub use rspirv_reflect;
+pub use vfs::{
+    add_vfs_overlay, canonical_path_from_vfs, normalized_path_from_vfs, set_vfs_mount_point,
+    vfs_list, vfs_read, vfs_stat,
+};
 pub use vk_sync;
 pub use vulkan::{
     device::Device,
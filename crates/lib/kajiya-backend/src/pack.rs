@@ -0,0 +1,173 @@
+// Single-file asset packs: an indexed archive of files, mountable through the VFS.
+//
+// Layout, with all integers little-endian:
+//
+// * Header: `PACK_MAGIC`, format version (u32), entry count (u32), offset of the index (u64)
+// * Entry contents, each starting at a multiple of `PACK_ENTRY_ALIGNMENT`
+// * Index: for each entry, its offset (u64), size (u64), path length (u32), and UTF-8 path
+//
+// Entry paths are relative to the root of the pack, and use `/` as the separator.
+// The alignment of entries is enough for baked assets to be used directly from the mapped pack.
+
+use anyhow::Context as _;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+pub const PACK_MAGIC: [u8; 8] = *b"KJYAPACK";
+pub const PACK_FORMAT_VERSION: u32 = 1;
+
+/// Entries are padded to this alignment, which covers the alignment of all flattened assets
+pub const PACK_ENTRY_ALIGNMENT: usize = 16;
+
+const PACK_HEADER_SIZE: usize = 24;
+
+/// A memory-mapped asset pack
+pub struct AssetPack {
+    mmap: memmap2::Mmap,
+    entries: HashMap<String, Range<usize>>,
+}
+
+impl AssetPack {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+        let mmap = unsafe { memmap2::MmapOptions::new().map(&file) }
+            .with_context(|| format!("Could not mmap {:?}", path))?;
+
+        let entries =
+            Self::read_index(&mmap).with_context(|| format!("Invalid asset pack {:?}", path))?;
+
+        Ok(Self { mmap, entries })
+    }
+
+    fn read_index(bytes: &[u8]) -> anyhow::Result<HashMap<String, Range<usize>>> {
+        if bytes.len() < PACK_HEADER_SIZE || bytes[0..8] != PACK_MAGIC {
+            anyhow::bail!("Not an asset pack");
+        }
+
+        let mut reader = PackReader {
+            bytes,
+            offset: PACK_MAGIC.len(),
+        };
+
+        let version = reader.read_u32()?;
+        if version != PACK_FORMAT_VERSION {
+            anyhow::bail!(
+                "Pack format version {}, but version {} is expected",
+                version,
+                PACK_FORMAT_VERSION
+            );
+        }
+
+        let entry_count = reader.read_u32()?;
+        reader.offset = reader.read_u64()? as usize;
+
+        let mut entries = HashMap::new();
+        for _ in 0..entry_count {
+            let offset = reader.read_u64()? as usize;
+            let size = reader.read_u64()? as usize;
+            let path_len = reader.read_u32()? as usize;
+            let path = std::str::from_utf8(reader.read_bytes(path_len)?)
+                .context("Entry path is not UTF-8")?;
+
+            let range = offset..offset.checked_add(size).context("Entry size overflow")?;
+            if range.end > bytes.len() || offset % PACK_ENTRY_ALIGNMENT != 0 {
+                anyhow::bail!("Entry {:?} is out of bounds, or misaligned", path);
+            }
+
+            entries.insert(path.to_owned(), range);
+        }
+
+        Ok(entries)
+    }
+
+    /// Contents of the entry at `path`, relative to the root of the pack
+    pub fn entry(&self, path: &str) -> Option<&[u8]> {
+        self.entries
+            .get(path)
+            .map(|range| &self.mmap[range.clone()])
+    }
+
+    /// Paths and contents of all entries, in no particular order
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.entries
+            .iter()
+            .map(|(path, range)| (path.as_str(), &self.mmap[range.clone()]))
+    }
+}
+
+struct PackReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> PackReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .offset
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.offset..end))
+            .context("Unexpected end of the pack")?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+}
+
+/// Writes an asset pack with the files at `entries`, keyed by their paths inside the pack
+pub fn write_asset_pack(path: &Path, entries: &[(String, PathBuf)]) -> anyhow::Result<()> {
+    let write = || -> anyhow::Result<()> {
+        let mut output = BufWriter::new(File::create(path)?);
+
+        output.write_all(&PACK_MAGIC)?;
+        output.write_all(&PACK_FORMAT_VERSION.to_le_bytes())?;
+        output.write_all(&(entries.len() as u32).to_le_bytes())?;
+        // Offset of the index, patched once known
+        output.write_all(&0u64.to_le_bytes())?;
+
+        let mut offset = PACK_HEADER_SIZE as u64;
+        let mut index = Vec::new();
+
+        for (entry_path, file_path) in entries {
+            let mut file =
+                File::open(file_path).with_context(|| format!("Opening {:?}", file_path))?;
+
+            let padding = offset.wrapping_neg() % PACK_ENTRY_ALIGNMENT as u64;
+            output.write_all(&[0u8; PACK_ENTRY_ALIGNMENT][..padding as usize])?;
+            offset += padding;
+
+            let size = std::io::copy(&mut file, &mut output)
+                .with_context(|| format!("Reading {:?}", file_path))?;
+
+            index.extend_from_slice(&offset.to_le_bytes());
+            index.extend_from_slice(&size.to_le_bytes());
+            index.extend_from_slice(&(entry_path.len() as u32).to_le_bytes());
+            index.extend_from_slice(entry_path.as_bytes());
+
+            offset += size;
+        }
+
+        output.write_all(&index)?;
+
+        output.seek(SeekFrom::Start(16))?;
+        output.write_all(&offset.to_le_bytes())?;
+        output.flush()?;
+
+        Ok(())
+    };
+
+    write().with_context(|| format!("Writing asset pack {:?}", path))
+}
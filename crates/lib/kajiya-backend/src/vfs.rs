@@ -0,0 +1,726 @@
+// Virtual file system used for loading assets and shaders.
+//
+// Backends get mounted at VFS paths such as `/shaders`. Mounts form an overlay stack:
+// a path resolves to the most recently added mount which covers it, and has a file there.
+// That way a game can mount a directory over `/shaders` with just the shaders it replaces.
+
+use anyhow::Context as _;
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use normpath::PathExt;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::pack::AssetPack;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VfsMetadata {
+    pub is_dir: bool,
+    /// Size in bytes; zero for directories
+    pub len: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct VfsDirEntry {
+    pub name: String,
+    pub metadata: VfsMetadata,
+}
+
+/// A source of files for the VFS. Paths are relative to the root of the backend,
+/// use `/` as the separator, and contain no `.` or `..` components.
+/// The root itself is the empty path.
+pub trait VfsBackend: std::fmt::Debug + Send + Sync {
+    fn read(&self, path: &str) -> anyhow::Result<Bytes>;
+
+    /// `None` if there's nothing at `path`
+    fn stat(&self, path: &str) -> Option<VfsMetadata>;
+
+    fn list(&self, dir: &str) -> anyhow::Result<Vec<VfsDirEntry>>;
+
+    /// Where `path` would be on disk, for backends made of plain files.
+    /// Those get watched for changes, and mapped instead of read.
+    fn disk_path(&self, _path: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Files in a directory on disk
+#[derive(Debug)]
+pub struct DirectoryBackend {
+    root: PathBuf,
+}
+
+impl DirectoryBackend {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+fn disk_metadata(metadata: &std::fs::Metadata) -> VfsMetadata {
+    VfsMetadata {
+        is_dir: metadata.is_dir(),
+        len: if metadata.is_dir() { 0 } else { metadata.len() },
+    }
+}
+
+impl VfsBackend for DirectoryBackend {
+    fn read(&self, path: &str) -> anyhow::Result<Bytes> {
+        let path = self.root.join(path);
+        let contents = std::fs::read(&path).with_context(|| format!("Reading {:?}", path))?;
+        Ok(Bytes::from(contents))
+    }
+
+    fn stat(&self, path: &str) -> Option<VfsMetadata> {
+        std::fs::metadata(self.root.join(path))
+            .ok()
+            .map(|metadata| disk_metadata(&metadata))
+    }
+
+    fn list(&self, dir: &str) -> anyhow::Result<Vec<VfsDirEntry>> {
+        let dir = self.root.join(dir);
+
+        let mut entries = Vec::new();
+        for dir_entry in std::fs::read_dir(&dir).with_context(|| format!("Listing {:?}", dir))? {
+            let dir_entry = dir_entry?;
+            entries.push(VfsDirEntry {
+                name: dir_entry.file_name().to_string_lossy().into_owned(),
+                metadata: disk_metadata(&dir_entry.metadata()?),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn disk_path(&self, path: &str) -> Option<PathBuf> {
+        Some(self.root.join(path))
+    }
+}
+
+/// Entries of the directory `dir` among a flat list of file paths and their sizes
+fn list_flat_files<'a>(
+    files: impl Iterator<Item = (&'a str, u64)>,
+    dir: &str,
+) -> anyhow::Result<Vec<VfsDirEntry>> {
+    let mut entries: HashMap<&str, VfsMetadata> = HashMap::new();
+
+    for (path, len) in files {
+        let rel_path = if dir.is_empty() {
+            path
+        } else if let Some(rel_path) = path
+            .strip_prefix(dir)
+            .and_then(|rel_path| rel_path.strip_prefix('/'))
+        {
+            rel_path
+        } else {
+            continue;
+        };
+
+        let metadata = match rel_path.split_once('/') {
+            Some((name, _)) => (
+                name,
+                VfsMetadata {
+                    is_dir: true,
+                    len: 0,
+                },
+            ),
+            None => (rel_path, VfsMetadata { is_dir: false, len }),
+        };
+        entries.insert(metadata.0, metadata.1);
+    }
+
+    if entries.is_empty() && !dir.is_empty() {
+        anyhow::bail!("No directory {:?}", dir);
+    }
+
+    Ok(entries
+        .into_iter()
+        .map(|(name, metadata)| VfsDirEntry {
+            name: name.to_owned(),
+            metadata,
+        })
+        .collect())
+}
+
+/// Stat of `path` among a flat list of file paths; directories exist if they contain files
+fn stat_flat_files<'a>(
+    mut files: impl Iterator<Item = (&'a str, u64)>,
+    path: &str,
+) -> Option<VfsMetadata> {
+    if path.is_empty() {
+        return Some(VfsMetadata {
+            is_dir: true,
+            len: 0,
+        });
+    }
+
+    files.find_map(|(file, len)| {
+        if file == path {
+            Some(VfsMetadata { is_dir: false, len })
+        } else if file.starts_with(path) && file[path.len()..].starts_with('/') {
+            Some(VfsMetadata {
+                is_dir: true,
+                len: 0,
+            })
+        } else {
+            None
+        }
+    })
+}
+
+/// Files kept in memory, which can be added and replaced at any time
+#[derive(Default)]
+pub struct MemoryBackend {
+    files: RwLock<HashMap<String, Bytes>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the file at `path`. Files loaded earlier through `LoadFile` don't get reloaded.
+    pub fn insert(&self, path: impl AsRef<Path>, contents: impl Into<Bytes>) {
+        self.files
+            .write()
+            .insert(normalize_path(path.as_ref()), contents.into());
+    }
+
+    pub fn remove(&self, path: impl AsRef<Path>) -> Option<Bytes> {
+        self.files.write().remove(&normalize_path(path.as_ref()))
+    }
+}
+
+impl std::fmt::Debug for MemoryBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MemoryBackend({} files)", self.files.read().len())
+    }
+}
+
+impl VfsBackend for MemoryBackend {
+    fn read(&self, path: &str) -> anyhow::Result<Bytes> {
+        self.files
+            .read()
+            .get(path)
+            .cloned()
+            .with_context(|| format!("No file {:?} in memory", path))
+    }
+
+    fn stat(&self, path: &str) -> Option<VfsMetadata> {
+        let files = self.files.read();
+        stat_flat_files(
+            files
+                .iter()
+                .map(|(path, contents)| (path.as_str(), contents.len() as u64)),
+            path,
+        )
+    }
+
+    fn list(&self, dir: &str) -> anyhow::Result<Vec<VfsDirEntry>> {
+        let files = self.files.read();
+        list_flat_files(
+            files
+                .iter()
+                .map(|(path, contents)| (path.as_str(), contents.len() as u64)),
+            dir,
+        )
+    }
+}
+
+lazy_static! {
+    // Opened on first use. Never closed, so that their contents can be handed out as `'static`.
+    static ref ASSET_PACKS: Mutex<HashMap<PathBuf, &'static AssetPack>> = Default::default();
+}
+
+fn asset_pack(path: &Path) -> anyhow::Result<&'static AssetPack> {
+    let mut packs = ASSET_PACKS.lock();
+    if let Some(pack) = packs.get(path) {
+        return Ok(pack);
+    }
+
+    let pack: &'static AssetPack = Box::leak(Box::new(AssetPack::open(path)?));
+    packs.insert(path.to_owned(), pack);
+    Ok(pack)
+}
+
+/// A directory inside an asset pack written by `bake pack`. The pack is opened on first use,
+/// and its contents are served without copies.
+#[derive(Debug)]
+pub struct ArchiveBackend {
+    pack_path: PathBuf,
+    // Prefix of the entries in the pack; empty, or ends with `/`
+    dir: String,
+}
+
+impl ArchiveBackend {
+    /// `path` is a pack file, or a directory inside one, such as `demo.pack/shaders`
+    pub fn new(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let (pack_path, dir) = split_pack_path(path)
+            .with_context(|| format!("{:?} isn't inside an asset pack", path))?;
+
+        let mut dir = normalize_path(dir);
+        if !dir.is_empty() {
+            dir.push('/');
+        }
+
+        Ok(Self {
+            pack_path: pack_path.to_owned(),
+            dir,
+        })
+    }
+
+    fn pack_files(&self) -> anyhow::Result<impl Iterator<Item = (&'static str, u64)> + '_> {
+        let pack = asset_pack(&self.pack_path)?;
+        Ok(pack.entries().filter_map(move |(path, contents)| {
+            let rel_path = path.strip_prefix(self.dir.as_str())?;
+            Some((rel_path, contents.len() as u64))
+        }))
+    }
+}
+
+impl VfsBackend for ArchiveBackend {
+    fn read(&self, path: &str) -> anyhow::Result<Bytes> {
+        let pack = asset_pack(&self.pack_path)?;
+        let entry = format!("{}{}", self.dir, path);
+
+        pack.entry(&entry)
+            .map(Bytes::from_static)
+            .with_context(|| format!("No {:?} in the asset pack {:?}", entry, self.pack_path))
+    }
+
+    fn stat(&self, path: &str) -> Option<VfsMetadata> {
+        let files = match self.pack_files() {
+            Ok(files) => files,
+            Err(err) => {
+                log::error!("{:?}", err);
+                return None;
+            }
+        };
+
+        // Most lookups are of files, which don't need a scan
+        if let Some(contents) = asset_pack(&self.pack_path)
+            .ok()?
+            .entry(&format!("{}{}", self.dir, path))
+        {
+            return Some(VfsMetadata {
+                is_dir: false,
+                len: contents.len() as u64,
+            });
+        }
+
+        stat_flat_files(files, path)
+    }
+
+    fn list(&self, dir: &str) -> anyhow::Result<Vec<VfsDirEntry>> {
+        list_flat_files(self.pack_files()?, dir)
+    }
+}
+
+/// Splits a path inside an asset pack into the pack file, and the directory within it
+fn split_pack_path(path: &Path) -> Option<(&Path, &Path)> {
+    let pack = path.ancestors().find(|ancestor| ancestor.is_file())?;
+    Some((pack, path.strip_prefix(pack).unwrap()))
+}
+
+/// Converts a path to the form used by backends: relative, `/`-separated, without `.` or `..`
+fn normalize_path(path: &Path) -> String {
+    let mut components: Vec<String> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_string_lossy().into_owned()),
+            Component::ParentDir => {
+                components.pop();
+            }
+            _ => {}
+        }
+    }
+
+    components.join("/")
+}
+
+struct VfsMount {
+    // Normalized; empty for `/`
+    mount_point: String,
+    backend: Arc<dyn VfsBackend>,
+    // Added with `add_vfs_overlay`, rather than `set_vfs_mount_point`
+    is_overlay: bool,
+}
+
+/// The part of the normalized `path` below `prefix`, if it's inside it
+fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    if prefix.is_empty() {
+        Some(path)
+    } else if path == prefix {
+        Some("")
+    } else {
+        path.strip_prefix(prefix)?.strip_prefix('/')
+    }
+}
+
+impl VfsMount {
+    /// Path relative to the backend if the mount covers `path`, which is normalized
+    fn backend_path<'a>(&self, path: &'a str) -> Option<&'a str> {
+        strip_path_prefix(path, &self.mount_point)
+    }
+}
+
+impl std::fmt::Debug for VfsMount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "/{} -> {:?}", self.mount_point, self.backend)
+    }
+}
+
+fn directory_mount(mount_point: &str, path: impl Into<PathBuf>) -> VfsMount {
+    VfsMount {
+        mount_point: normalize_path(Path::new(mount_point)),
+        backend: Arc::new(DirectoryBackend::new(path)),
+        is_overlay: false,
+    }
+}
+
+lazy_static! {
+    // In the order of priority, from the lowest
+    static ref VFS_MOUNTS: RwLock<Vec<VfsMount>> = RwLock::new(vec![
+        directory_mount("/kajiya", "."),
+        directory_mount("/shaders", "assets/shaders"),
+        directory_mount("/rust-shaders-compiled", "assets/rust-shaders-compiled"),
+        directory_mount("/images", "assets/images"),
+        directory_mount("/baked", "baked"),
+    ]);
+}
+
+/// Replaces the directory or asset pack mounted at `mount_point`. Packs can be mounted whole,
+/// or by a directory inside them, as in `set_vfs_mount_point("/shaders", "demo.pack/shaders")`.
+///
+/// Overlays added at `mount_point` with `add_vfs_overlay` are kept, and still shadow the files
+/// of the new mount.
+pub fn set_vfs_mount_point(mount_point: impl Into<String>, path: impl Into<PathBuf>) {
+    let path = path.into();
+    let backend: Arc<dyn VfsBackend> = match ArchiveBackend::new(&path) {
+        Ok(archive) => Arc::new(archive),
+        Err(_) => Arc::new(DirectoryBackend::new(path)),
+    };
+
+    let mount_point = normalize_path(Path::new(&mount_point.into()));
+
+    let mut mounts = VFS_MOUNTS.write();
+    let is_replaced = |mount: &VfsMount| !mount.is_overlay && mount.mount_point == mount_point;
+
+    // In place of the previous mount, so that overlays added after it stay on top
+    let index = mounts.iter().position(is_replaced).unwrap_or(mounts.len());
+    mounts.retain(|mount| !is_replaced(mount));
+    mounts.insert(
+        index,
+        VfsMount {
+            mount_point,
+            backend,
+            is_overlay: false,
+        },
+    );
+}
+
+/// Mounts `backend` on top of everything mounted so far. Files it has shadow those of
+/// earlier mounts, and the rest are still found in those.
+pub fn add_vfs_overlay(mount_point: impl AsRef<str>, backend: Arc<dyn VfsBackend>) {
+    VFS_MOUNTS.write().push(VfsMount {
+        mount_point: normalize_path(Path::new(mount_point.as_ref())),
+        backend,
+        is_overlay: true,
+    });
+}
+
+pub fn set_standard_vfs_mount_points(kajiya_path: impl Into<PathBuf>) {
+    let kajiya_path = kajiya_path.into();
+    set_vfs_mount_point("/kajiya", &kajiya_path);
+    set_vfs_mount_point("/shaders", kajiya_path.join("assets/shaders"));
+    set_vfs_mount_point(
+        "/rust-shaders-compiled",
+        kajiya_path.join("assets/rust-shaders-compiled"),
+    );
+    set_vfs_mount_point("/images", kajiya_path.join("assets/images"));
+}
+
+/// Whether `path` is in the VFS rather than relative to the working directory
+fn is_vfs_path(path: &Path) -> bool {
+    path.strip_prefix("/").is_ok()
+}
+
+/// Resolves `.` and `..` in VFS paths, so that each file has a single one.
+/// Paths outside the VFS are returned as they are.
+pub(crate) fn normalize_vfs_path(path: PathBuf) -> PathBuf {
+    if is_vfs_path(&path) {
+        PathBuf::from(format!("/{}", normalize_path(&path)))
+    } else {
+        path
+    }
+}
+
+/// The backend holding `path`, and the path relative to it
+fn resolve(path: &Path) -> anyhow::Result<(Arc<dyn VfsBackend>, String, VfsMetadata)> {
+    let normalized = normalize_path(path);
+    let mounts = VFS_MOUNTS.read();
+
+    let mut covered = false;
+    for mount in mounts.iter().rev() {
+        if let Some(backend_path) = mount.backend_path(&normalized) {
+            covered = true;
+            if let Some(metadata) = mount.backend.stat(backend_path) {
+                return Ok((mount.backend.clone(), backend_path.to_owned(), metadata));
+            }
+        }
+    }
+
+    if covered {
+        anyhow::bail!("{:?} not found in the vfs. Mounts: {:#?}", path, mounts);
+    } else {
+        anyhow::bail!(
+            "No vfs mount point for {:?}. Current mount points: {:#?}",
+            path,
+            mounts
+        );
+    }
+}
+
+/// Reads a file from the VFS, or from disk if `path` isn't in the VFS
+pub fn vfs_read(path: impl AsRef<Path>) -> anyhow::Result<Bytes> {
+    let path = path.as_ref();
+    if !is_vfs_path(path) {
+        let contents = std::fs::read(path).with_context(|| format!("Reading {:?}", path))?;
+        return Ok(Bytes::from(contents));
+    }
+
+    let (backend, backend_path, _) = resolve(path)?;
+    backend.read(&backend_path)
+}
+
+pub fn vfs_stat(path: impl AsRef<Path>) -> anyhow::Result<VfsMetadata> {
+    let path = path.as_ref();
+    if !is_vfs_path(path) {
+        let metadata = std::fs::metadata(path).with_context(|| format!("stat {:?}", path))?;
+        return Ok(disk_metadata(&metadata));
+    }
+
+    Ok(resolve(path)?.2)
+}
+
+/// Entries of a VFS directory, merged across all mounts covering it. Where several have
+/// an entry of the same name, the one of the topmost mount is returned.
+pub fn vfs_list(path: impl AsRef<Path>) -> anyhow::Result<Vec<VfsDirEntry>> {
+    let path = path.as_ref();
+    if !is_vfs_path(path) {
+        return DirectoryBackend::new(path).list("");
+    }
+
+    let normalized = normalize_path(path);
+    let mounts = VFS_MOUNTS.read();
+
+    let mut entries: Vec<VfsDirEntry> = Vec::new();
+    let mut found = false;
+
+    let mut add_entry = |entry: VfsDirEntry| {
+        if !entries.iter().any(|e| e.name == entry.name) {
+            entries.push(entry);
+        }
+    };
+
+    for mount in mounts.iter().rev() {
+        if let Some(backend_path) = mount.backend_path(&normalized) {
+            if mount
+                .backend
+                .stat(backend_path)
+                .is_some_and(|metadata| metadata.is_dir)
+            {
+                found = true;
+                for entry in mount.backend.list(backend_path)? {
+                    add_entry(entry);
+                }
+            }
+        } else if let Some(rel_mount_point) = strip_path_prefix(&mount.mount_point, &normalized) {
+            // Mount points below `path` show up as directories
+            found = true;
+            add_entry(VfsDirEntry {
+                name: rel_mount_point.split('/').next().unwrap().to_owned(),
+                metadata: VfsMetadata {
+                    is_dir: true,
+                    len: 0,
+                },
+            });
+        }
+    }
+
+    if !found {
+        anyhow::bail!("No vfs directory {:?}", path);
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// Where a VFS file is on disk; `None` for files served by other backends, such as asset packs.
+/// Paths outside the VFS are returned as they are.
+pub fn vfs_disk_path(path: impl AsRef<Path>) -> anyhow::Result<Option<PathBuf>> {
+    let path = path.as_ref();
+    if !is_vfs_path(path) {
+        return Ok(Some(path.to_owned()));
+    }
+
+    let (backend, backend_path, _) = resolve(path)?;
+    Ok(backend.disk_path(&backend_path))
+}
+
+pub fn canonical_path_from_vfs(path: impl Into<PathBuf>) -> anyhow::Result<PathBuf> {
+    let path = path.into();
+
+    if !is_vfs_path(&path) {
+        return Ok(path);
+    }
+
+    let disk_path =
+        vfs_disk_path(&path)?.with_context(|| format!("{:?} is not a file on disk", path))?;
+
+    disk_path
+        .canonicalize()
+        .with_context(|| format!("canonicalize {:?}", disk_path))
+}
+
+/// Like `canonical_path_from_vfs`, but the path doesn't need to exist.
+/// Resolves to the topmost mount backed by a directory on disk.
+pub fn normalized_path_from_vfs(path: impl Into<PathBuf>) -> anyhow::Result<PathBuf> {
+    let path = path.into();
+
+    if !is_vfs_path(&path) {
+        return Ok(path);
+    }
+
+    let normalized = normalize_path(&path);
+    let mounts = VFS_MOUNTS.read();
+
+    for mount in mounts.iter().rev() {
+        if let Some(disk_path) = mount
+            .backend_path(&normalized)
+            .and_then(|backend_path| mount.backend.disk_path(backend_path))
+        {
+            return Ok(disk_path
+                .normalize()
+                .with_context(|| format!("normalize {:?}", disk_path))?
+                .as_path()
+                .to_owned());
+        }
+    }
+
+    anyhow::bail!(
+        "No vfs mount point on disk for {:?}. Current mount points: {:#?}",
+        path,
+        mounts
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The mounts are global, so each test uses its own mount points
+    fn memory_overlay(mount_point: &str, files: &[(&str, &str)]) {
+        let backend = MemoryBackend::new();
+        for &(path, contents) in files {
+            backend.insert(path, contents.as_bytes().to_vec());
+        }
+        add_vfs_overlay(mount_point, Arc::new(backend));
+    }
+
+    fn read_string(path: &str) -> String {
+        String::from_utf8(vfs_read(path).unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn normalize_path_is_relative_and_resolves_dots() {
+        assert_eq!(normalize_path(Path::new("/")), "");
+        assert_eq!(normalize_path(Path::new("/a/./b/../c")), "a/c");
+        assert_eq!(normalize_path(Path::new("a//b/")), "a/b");
+        assert_eq!(normalize_path(Path::new("/../a")), "a");
+        assert_eq!(
+            normalize_vfs_path(PathBuf::from("/a/b/../c")),
+            PathBuf::from("/a/c")
+        );
+        assert_eq!(
+            normalize_vfs_path(PathBuf::from("a/../b")),
+            PathBuf::from("a/../b")
+        );
+    }
+
+    #[test]
+    fn later_overlays_shadow_earlier_ones() {
+        memory_overlay(
+            "/vfs-test-order",
+            &[
+                ("a.txt", "bottom"),
+                ("b.txt", "bottom"),
+                ("sub/c.txt", "bottom"),
+            ],
+        );
+        memory_overlay("/vfs-test-order", &[("a.txt", "top")]);
+        memory_overlay("/vfs-test-order/sub", &[("c.txt", "sub")]);
+
+        assert_eq!(read_string("/vfs-test-order/a.txt"), "top");
+        assert_eq!(read_string("/vfs-test-order/b.txt"), "bottom");
+        assert_eq!(read_string("/vfs-test-order/./sub/../sub/c.txt"), "sub");
+        assert!(vfs_read("/vfs-test-order/missing.txt").is_err());
+        assert!(vfs_read("/vfs-test-unmounted/a.txt").is_err());
+    }
+
+    #[test]
+    fn vfs_list_merges_mounts() {
+        memory_overlay(
+            "/vfs-test-list",
+            &[("a.txt", "bottom"), ("dir/b.txt", "bottom")],
+        );
+        memory_overlay(
+            "/vfs-test-list",
+            &[("a.txt", "longer top"), ("c.txt", "top")],
+        );
+        memory_overlay("/vfs-test-list/mounted/deeper", &[("d.txt", "deeper")]);
+
+        let entries = vfs_list("/vfs-test-list").unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["a.txt", "c.txt", "dir", "mounted"]);
+
+        // The entry of the topmost mount wins
+        assert_eq!(entries[0].metadata.len, "longer top".len() as u64);
+        assert!(entries[2].metadata.is_dir);
+        assert!(entries[3].metadata.is_dir);
+
+        assert!(vfs_list("/vfs-test-list/a.txt").is_err());
+    }
+
+    #[test]
+    fn set_vfs_mount_point_keeps_overlays() {
+        let dirs: Vec<PathBuf> = (0..2)
+            .map(|i| {
+                let dir = std::env::temp_dir().join(format!(
+                    "kajiya-vfs-test-{}-{}",
+                    std::process::id(),
+                    i
+                ));
+                std::fs::create_dir_all(&dir).unwrap();
+                std::fs::write(dir.join("a.txt"), format!("dir {}", i)).unwrap();
+                std::fs::write(dir.join("b.txt"), format!("dir {}", i)).unwrap();
+                dir
+            })
+            .collect();
+
+        set_vfs_mount_point("/vfs-test-keep", &dirs[0]);
+        memory_overlay("/vfs-test-keep", &[("a.txt", "overlay")]);
+        set_vfs_mount_point("/vfs-test-keep", &dirs[1]);
+
+        assert_eq!(read_string("/vfs-test-keep/a.txt"), "overlay");
+        assert_eq!(read_string("/vfs-test-keep/b.txt"), "dir 1");
+
+        for dir in dirs {
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
@@ -36,14 +36,43 @@ pub struct RayTracingGeometryDesc {
     pub index_buffer: vk::DeviceAddress,
     pub vertex_format: vk::Format,
     pub vertex_stride: usize,
+    /// Address of a row-major 3x4 matrix applied to the vertices, such as for decoding
+    /// quantized positions. Must be 16-byte aligned. Zero for none.
+    pub vertex_transform: vk::DeviceAddress,
     pub parts: Vec<RayTracingGeometryPart>,
+    /// Any-hit shaders are only invoked for non-opaque geometry
+    pub is_opaque: bool,
 }
 
 #[derive(Clone)]
 pub struct RayTracingInstanceDesc {
     pub blas: Arc<RayTracingAcceleration>,
     pub transformation: Affine3A,
-    pub mesh_index: u32,
+    /// Available to shaders as `InstanceID()`; only the low 24 bits are kept
+    pub custom_index: u32,
+    /// Rays only hit instances whose mask has bits in common with the mask of the ray
+    pub mask: u8,
+    /// Disables back-face culling of the instance, for meshes with double-sided materials
+    pub double_sided: bool,
+}
+
+impl RayTracingInstanceDesc {
+    fn geometry_instance_flags(&self) -> ash::vk::GeometryInstanceFlagsKHR {
+        // Opacity is specified per geometry, so that alpha-tested meshes run any-hit shaders
+        let mut flags = ash::vk::GeometryInstanceFlagsKHR::empty();
+
+        if self.double_sided {
+            flags |= ash::vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE;
+        }
+
+        // Facing is determined in object space. Mirrored instances use meshes
+        // with reversed winding, so they need to have it flipped back.
+        if self.transformation.matrix3.determinant() < 0.0 {
+            flags |= ash::vk::GeometryInstanceFlagsKHR::TRIANGLE_FLIP_FACING;
+        }
+
+        flags
+    }
 }
 
 #[derive(Clone)]
@@ -55,6 +84,19 @@ pub struct RayTracingTopAccelerationDesc {
 #[derive(Clone, Debug)]
 pub struct RayTracingBottomAccelerationDesc {
     pub geometries: Vec<RayTracingGeometryDesc>,
+    /// Allows refitting with `refit_ray_tracing_bottom_acceleration` after the vertices move
+    pub allow_update: bool,
+}
+
+impl RayTracingBottomAccelerationDesc {
+    fn build_flags(&self) -> vk::BuildAccelerationStructureFlagsKHR {
+        if self.allow_update {
+            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE
+                | vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE
+        } else {
+            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -104,62 +146,16 @@ impl Device {
     ) -> Result<RayTracingAcceleration> {
         //log::trace!("Creating ray tracing bottom acceleration: {:?}", desc);
 
-        let geometries: Result<Vec<ash::vk::AccelerationStructureGeometryKHR>> = desc
-            .geometries
-            .iter()
-            .map(
-                |desc| -> Result<ash::vk::AccelerationStructureGeometryKHR> {
-                    let part: RayTracingGeometryPart = desc.parts[0];
-
-                    let geometry = ash::vk::AccelerationStructureGeometryKHR::builder()
-                        .geometry_type(ash::vk::GeometryTypeKHR::TRIANGLES)
-                        .geometry(ash::vk::AccelerationStructureGeometryDataKHR {
-                            triangles:
-                                ash::vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
-                                    .vertex_data(ash::vk::DeviceOrHostAddressConstKHR {
-                                        device_address: desc.vertex_buffer,
-                                    })
-                                    .vertex_stride(desc.vertex_stride as _)
-                                    .max_vertex(part.max_vertex)
-                                    .vertex_format(desc.vertex_format)
-                                    .index_data(ash::vk::DeviceOrHostAddressConstKHR {
-                                        device_address: desc.index_buffer,
-                                    })
-                                    .index_type(ash::vk::IndexType::UINT32) // TODO
-                                    .build(),
-                        })
-                        .flags(ash::vk::GeometryFlagsKHR::OPAQUE)
-                        .build();
-
-                    Ok(geometry)
-                },
-            )
-            .collect();
-        let geometries = geometries?;
-
-        let build_range_infos: Vec<ash::vk::AccelerationStructureBuildRangeInfoKHR> = desc
-            .geometries
-            .iter()
-            .map(|desc| {
-                ash::vk::AccelerationStructureBuildRangeInfoKHR::builder()
-                    .primitive_count(desc.parts[0].index_count as u32 / 3)
-                    .build()
-            })
-            .collect();
+        let (geometries, build_range_infos, max_primitive_counts) =
+            bottom_acceleration_geometries(desc)?;
 
         let geometry_info = ash::vk::AccelerationStructureBuildGeometryInfoKHR::builder()
             .ty(ash::vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
-            .flags(ash::vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .flags(desc.build_flags())
             .geometries(geometries.as_slice())
             .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
             .build();
 
-        let max_primitive_counts: Vec<_> = desc
-            .geometries
-            .iter()
-            .map(|desc| desc.parts[0].index_count as u32 / 3)
-            .collect();
-
         // Create bottom-level acceleration structure
 
         let preallocate_bytes = 0;
@@ -212,12 +208,10 @@ impl Device {
 
                 GeometryInstance::new(
                     transform,
-                    desc.mesh_index, /* instance id */
-                    0xff,
+                    desc.custom_index,
+                    desc.mask,
                     0,
-                    /*ash::vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE
-                    | */
-                    ash::vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE,
+                    desc.geometry_instance_flags(),
                     blas_address,
                 )
             })
@@ -425,12 +419,10 @@ impl Device {
 
             GeometryInstance::new(
                 transform,
-                desc.mesh_index, /* instance id */
-                0xff,
+                desc.custom_index,
+                desc.mask,
                 0,
-                /*ash::vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE
-                | */
-                ash::vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE,
+                desc.geometry_instance_flags(),
                 blas_address,
             )
         }));
@@ -482,6 +474,40 @@ impl Device {
         )
     }
 
+    /// Updates a bottom-level acceleration after its vertices have moved. The topology
+    /// must be the same as when it was created, with `allow_update` set.
+    pub fn refit_ray_tracing_bottom_acceleration(
+        &self,
+        cb: vk::CommandBuffer,
+        desc: &RayTracingBottomAccelerationDesc,
+        blas: &RayTracingAcceleration,
+        scratch_buffer: &RayTracingAccelerationScratchBuffer,
+    ) -> Result<()> {
+        assert!(desc.allow_update);
+
+        let (geometries, build_range_infos, max_primitive_counts) =
+            bottom_acceleration_geometries(desc)?;
+
+        let geometry_info = ash::vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(ash::vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+            .flags(desc.build_flags())
+            .geometries(geometries.as_slice())
+            .mode(vk::BuildAccelerationStructureModeKHR::UPDATE)
+            .src_acceleration_structure(blas.raw)
+            .build();
+
+        self.rebuild_ray_tracing_acceleration(
+            cb,
+            geometry_info,
+            &build_range_infos,
+            &max_primitive_counts,
+            blas,
+            scratch_buffer,
+        );
+
+        Ok(())
+    }
+
     fn rebuild_ray_tracing_acceleration(
         &self,
         cb: vk::CommandBuffer,
@@ -817,6 +843,7 @@ pub fn create_ray_tracing_pipeline(
                     assert!(
                         prev_stage == Some(ShaderPipelineStage::RayMiss)
                             || prev_stage == Some(ShaderPipelineStage::RayClosestHit)
+                            || prev_stage == Some(ShaderPipelineStage::RayAnyHit)
                     );
                     hit_entry_count += 1;
 
@@ -842,6 +869,44 @@ pub fn create_ray_tracing_pipeline(
                     shader_stages.push(stage);
                     shader_groups.push(group);
                 }
+                ShaderPipelineStage::RayAnyHit => {
+                    assert!(
+                        prev_stage == Some(ShaderPipelineStage::RayMiss)
+                            || prev_stage == Some(ShaderPipelineStage::RayClosestHit)
+                            || prev_stage == Some(ShaderPipelineStage::RayAnyHit)
+                    );
+
+                    let (module, entry_point) = create_shader_module(desc);
+
+                    entry_points.push(std::ffi::CString::new(entry_point).unwrap());
+                    let entry_point = &**entry_points.last().unwrap();
+
+                    let stage = ash::vk::PipelineShaderStageCreateInfo::builder()
+                        .stage(ash::vk::ShaderStageFlags::ANY_HIT_KHR)
+                        .module(module)
+                        .name(entry_point)
+                        .build();
+
+                    shader_stages.push(stage);
+
+                    // An any-hit shader directly following a closest-hit shader joins its hit group.
+                    // Otherwise it makes a hit group of its own.
+                    if prev_stage == Some(ShaderPipelineStage::RayClosestHit) {
+                        shader_groups.last_mut().unwrap().any_hit_shader = group_idx as _;
+                    } else {
+                        hit_entry_count += 1;
+
+                        let group = ash::vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                            .ty(ash::vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
+                            .general_shader(ash::vk::SHADER_UNUSED_KHR)
+                            .closest_hit_shader(ash::vk::SHADER_UNUSED_KHR)
+                            .any_hit_shader(group_idx as _)
+                            .intersection_shader(ash::vk::SHADER_UNUSED_KHR)
+                            .build();
+
+                        shader_groups.push(group);
+                    }
+                }
                 _ => unimplemented!(),
             }
 
@@ -969,3 +1034,70 @@ impl GeometryInstance {
         self.instance_sbt_offset_and_flags |= flags << 24;
     }
 }
+
+#[allow(clippy::type_complexity)]
+fn bottom_acceleration_geometries(
+    desc: &RayTracingBottomAccelerationDesc,
+) -> Result<(
+    Vec<ash::vk::AccelerationStructureGeometryKHR>,
+    Vec<ash::vk::AccelerationStructureBuildRangeInfoKHR>,
+    Vec<u32>,
+)> {
+    let geometries: Result<Vec<ash::vk::AccelerationStructureGeometryKHR>> = desc
+        .geometries
+        .iter()
+        .map(
+            |desc| -> Result<ash::vk::AccelerationStructureGeometryKHR> {
+                let part: RayTracingGeometryPart = desc.parts[0];
+
+                let geometry = ash::vk::AccelerationStructureGeometryKHR::builder()
+                    .geometry_type(ash::vk::GeometryTypeKHR::TRIANGLES)
+                    .geometry(ash::vk::AccelerationStructureGeometryDataKHR {
+                        triangles: ash::vk::AccelerationStructureGeometryTrianglesDataKHR::builder(
+                        )
+                        .vertex_data(ash::vk::DeviceOrHostAddressConstKHR {
+                            device_address: desc.vertex_buffer,
+                        })
+                        .vertex_stride(desc.vertex_stride as _)
+                        .max_vertex(part.max_vertex)
+                        .vertex_format(desc.vertex_format)
+                        .index_data(ash::vk::DeviceOrHostAddressConstKHR {
+                            device_address: desc.index_buffer,
+                        })
+                        .index_type(ash::vk::IndexType::UINT32) // TODO
+                        .transform_data(ash::vk::DeviceOrHostAddressConstKHR {
+                            device_address: desc.vertex_transform,
+                        })
+                        .build(),
+                    })
+                    .flags(if desc.is_opaque {
+                        ash::vk::GeometryFlagsKHR::OPAQUE
+                    } else {
+                        ash::vk::GeometryFlagsKHR::NO_DUPLICATE_ANY_HIT_INVOCATION
+                    })
+                    .build();
+
+                Ok(geometry)
+            },
+        )
+        .collect();
+    let geometries = geometries?;
+
+    let build_range_infos: Vec<ash::vk::AccelerationStructureBuildRangeInfoKHR> = desc
+        .geometries
+        .iter()
+        .map(|desc| {
+            ash::vk::AccelerationStructureBuildRangeInfoKHR::builder()
+                .primitive_count(desc.parts[0].index_count as u32 / 3)
+                .build()
+        })
+        .collect();
+
+    let max_primitive_counts: Vec<_> = desc
+        .geometries
+        .iter()
+        .map(|desc| desc.parts[0].index_count as u32 / 3)
+        .collect();
+
+    Ok((geometries, build_range_infos, max_primitive_counts))
+}
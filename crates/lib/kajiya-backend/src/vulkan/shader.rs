@@ -486,6 +486,7 @@ pub enum ShaderPipelineStage {
     RayGen,
     RayMiss,
     RayClosestHit,
+    RayAnyHit,
 }
 
 #[derive(Builder, Hash, PartialEq, Eq, Clone, Debug)]
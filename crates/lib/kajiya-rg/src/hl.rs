@@ -16,6 +16,34 @@ use super::{
     Resource, RgComputePipelineHandle, RgRtPipelineHandle,
 };
 
+/// Shaders of one triangle hit group of a ray tracing pipeline.
+/// At least one of the shaders must be specified.
+pub struct RayHitGroup {
+    pub closest_hit: Option<ShaderSource>,
+    pub any_hit: Option<ShaderSource>,
+}
+
+impl RayHitGroup {
+    pub fn closest_hit(source: ShaderSource) -> Self {
+        Self {
+            closest_hit: Some(source),
+            any_hit: None,
+        }
+    }
+
+    pub fn any_hit(source: ShaderSource) -> Self {
+        Self {
+            closest_hit: None,
+            any_hit: Some(source),
+        }
+    }
+
+    pub fn with_any_hit(mut self, source: ShaderSource) -> Self {
+        self.any_hit = Some(source);
+        self
+    }
+}
+
 pub trait ConstBlob {
     fn push_self(
         self: Box<Self>,
@@ -161,12 +189,12 @@ impl<'rg> SimpleRenderPass<'rg, RgRtPipelineHandle> {
         mut pass: PassBuilder<'rg>,
         rgen: ShaderSource,
         miss: impl IntoIterator<Item = ShaderSource>,
-        hit: impl IntoIterator<Item = ShaderSource>,
+        hit: impl IntoIterator<Item = RayHitGroup>,
     ) -> Self {
         let miss = miss.into_iter();
         let hit = hit.into_iter();
 
-        let mut shaders = Vec::with_capacity(1 + miss.size_hint().0 + hit.size_hint().0);
+        let mut shaders = Vec::with_capacity(1 + miss.size_hint().0 + 2 * hit.size_hint().0);
 
         shaders.push(
             PipelineShaderDesc::builder(ShaderPipelineStage::RayGen)
@@ -183,13 +211,32 @@ impl<'rg> SimpleRenderPass<'rg, RgRtPipelineHandle> {
             );
         }
 
-        for source in hit {
-            shaders.push(
-                PipelineShaderDesc::builder(ShaderPipelineStage::RayClosestHit)
-                    .source(source)
-                    .build()
-                    .unwrap(),
+        let mut prev_closest_hit_only = false;
+        for group in hit {
+            // The pipeline would merge an any-hit-only group into the preceding closest-hit one
+            assert!(
+                !(prev_closest_hit_only && group.closest_hit.is_none()),
+                "An any-hit-only group can't directly follow a closest-hit-only group"
             );
+            prev_closest_hit_only = group.closest_hit.is_some() && group.any_hit.is_none();
+
+            if let Some(source) = group.closest_hit {
+                shaders.push(
+                    PipelineShaderDesc::builder(ShaderPipelineStage::RayClosestHit)
+                        .source(source)
+                        .build()
+                        .unwrap(),
+                );
+            }
+
+            if let Some(source) = group.any_hit {
+                shaders.push(
+                    PipelineShaderDesc::builder(ShaderPipelineStage::RayAnyHit)
+                        .source(source)
+                        .build()
+                        .unwrap(),
+                );
+            }
         }
 
         let pipeline = pass.register_ray_tracing_pipeline(
@@ -0,0 +1,366 @@
+use glam::{Affine3A, Quat, Vec3, Vec4};
+use kajiya_asset::mesh::{
+    AnimationInterpolation, AnimationProperty, PackedAnimation, PackedAnimationChannel,
+    PackedScene, PackedSceneNode,
+};
+
+use crate::{
+    world_renderer::WorldRenderer,
+    world_renderer_mmap_adapter::{BakedScene, BakedSceneInstance},
+};
+
+/// Playback state of one clip. Layers are blended by their weights.
+#[derive(Clone, Copy)]
+pub struct AnimationLayer {
+    /// Index into the animations of the scene
+    pub clip: usize,
+    /// In seconds
+    pub time: f32,
+    /// Multiplies the time step; negative values play backwards
+    pub speed: f32,
+    pub weight: f32,
+    pub looping: bool,
+    /// Change of `weight` per second. The layer is removed once it fades out.
+    pub fade_rate: f32,
+}
+
+/// Samples the animation clips of a baked scene, and poses its instances.
+///
+/// Nodes not animated by any layer stay in their rest pose. Where the total weight
+/// of layers animating a property is below one, the rest pose makes up the remainder.
+pub struct AnimationPlayer {
+    scene: &'static PackedScene::Flat,
+    instances: Vec<BakedSceneInstance>,
+    /// Transform of the scene root; instances are placed at `root_transform * node_transform`
+    pub root_transform: Affine3A,
+    pub layers: Vec<AnimationLayer>,
+}
+
+impl AnimationPlayer {
+    pub fn new(scene: &BakedScene, root_transform: Affine3A) -> Self {
+        Self {
+            scene: scene.scene,
+            instances: scene.instances.clone(),
+            root_transform,
+            layers: Vec::new(),
+        }
+    }
+
+    pub fn clip_count(&self) -> usize {
+        self.scene.animations.len()
+    }
+
+    pub fn clip_index(&self, name: &str) -> Option<usize> {
+        self.scene
+            .animations
+            .iter()
+            .position(|animation| animation.name() == name)
+    }
+
+    pub fn clip_name(&self, clip: usize) -> &str {
+        self.scene.animations[clip].name()
+    }
+
+    /// In seconds
+    pub fn clip_duration(&self, clip: usize) -> f32 {
+        clip_duration(&self.scene.animations[clip])
+    }
+
+    /// Adds a looping layer at full weight
+    pub fn play(&mut self, clip: usize) -> &mut AnimationLayer {
+        assert!(clip < self.clip_count(), "no such animation clip");
+
+        self.layers.push(AnimationLayer {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+            weight: 1.0,
+            looping: true,
+            fade_rate: 0.0,
+        });
+        self.layers.last_mut().unwrap()
+    }
+
+    /// Fades out all current layers, and fades in a new one over `duration` seconds
+    pub fn cross_fade(&mut self, clip: usize, duration: f32) -> &mut AnimationLayer {
+        let duration = duration.max(1e-5);
+
+        for layer in &mut self.layers {
+            layer.fade_rate = -layer.weight / duration;
+        }
+
+        let layer = self.play(clip);
+        layer.weight = 0.0;
+        layer.fade_rate = 1.0 / duration;
+        layer
+    }
+
+    pub fn stop(&mut self) {
+        self.layers.clear();
+    }
+
+    /// Moves all layers forward by `dt` seconds
+    pub fn advance(&mut self, dt: f32) {
+        let scene = self.scene;
+
+        for layer in &mut self.layers {
+            let duration = clip_duration(&scene.animations[layer.clip]);
+
+            layer.time += dt * layer.speed;
+            layer.time = if layer.looping && duration > 0.0 {
+                layer.time.rem_euclid(duration)
+            } else {
+                layer.time.clamp(0.0, duration)
+            };
+
+            if layer.fade_rate != 0.0 {
+                layer.weight += dt * layer.fade_rate;
+                if layer.fade_rate > 0.0 && layer.weight >= 1.0 {
+                    layer.weight = 1.0;
+                    layer.fade_rate = 0.0;
+                }
+            }
+        }
+
+        self.layers
+            .retain(|layer| layer.fade_rate >= 0.0 || layer.weight > 0.0);
+    }
+
+    /// Transforms of all nodes in the space of the scene, including its root transform,
+    /// and morph target weights of the meshes of all nodes, in the current pose.
+    /// Animating a root node replaces its own transform, but not the scene's root transform.
+    pub fn sample_pose(&self) -> (Vec<Affine3A>, Vec<Vec<f32>>) {
+        let scene = self.scene;
+        let node_count = scene.nodes.len();
+
+        let mut blend = PoseBlend {
+            translations: vec![(Vec3::ZERO, 0.0); node_count],
+            rotations: vec![(Vec4::ZERO, 0.0); node_count],
+            scales: vec![(Vec3::ZERO, 0.0); node_count],
+            morph_weights: scene
+                .nodes
+                .iter()
+                .map(|node| (vec![0.0; node_morph_target_count(scene, node)], 0.0))
+                .collect(),
+        };
+
+        let mut sample = Vec::new();
+        for layer in &self.layers {
+            if layer.weight <= 0.0 {
+                continue;
+            }
+
+            for channel in scene.animations[layer.clip].channels.iter() {
+                let node_idx = channel.node as usize;
+                let node = &scene.nodes[node_idx];
+
+                let components = match channel.property {
+                    AnimationProperty::TRANSLATION | AnimationProperty::SCALE => 3,
+                    AnimationProperty::ROTATION => 4,
+                    AnimationProperty::MORPH_WEIGHTS => blend.morph_weights[node_idx].0.len(),
+                    _ => 0,
+                };
+
+                if components == 0 {
+                    continue;
+                }
+
+                sample.resize(components, 0.0);
+                if !sample_channel(channel, layer.time, &mut sample) {
+                    continue;
+                }
+
+                let w = layer.weight;
+                match channel.property {
+                    AnimationProperty::TRANSLATION => {
+                        let (sum, total) = &mut blend.translations[node_idx];
+                        *sum += Vec3::new(sample[0], sample[1], sample[2]) * w;
+                        *total += w;
+                    }
+                    AnimationProperty::ROTATION => {
+                        let (sum, total) = &mut blend.rotations[node_idx];
+                        let mut q = Vec4::new(sample[0], sample[1], sample[2], sample[3]);
+
+                        // Keep quaternions in the same hemisphere as the rest pose
+                        if q.dot(Vec4::from(node.rotation)) < 0.0 {
+                            q = -q;
+                        }
+
+                        *sum += q * w;
+                        *total += w;
+                    }
+                    AnimationProperty::SCALE => {
+                        let (sum, total) = &mut blend.scales[node_idx];
+                        *sum += Vec3::new(sample[0], sample[1], sample[2]) * w;
+                        *total += w;
+                    }
+                    AnimationProperty::MORPH_WEIGHTS => {
+                        let (sum, total) = &mut blend.morph_weights[node_idx];
+                        for (sum, value) in sum.iter_mut().zip(sample.iter()) {
+                            *sum += value * w;
+                        }
+                        *total += w;
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+
+        let mut local_transforms: Vec<Affine3A> = Vec::with_capacity(node_count);
+        let mut morph_weights: Vec<Vec<f32>> = Vec::with_capacity(node_count);
+
+        for (node_idx, node) in scene.nodes.iter().enumerate() {
+            let translation =
+                blend_with_rest(blend.translations[node_idx], node.translation.into());
+            let scale = blend_with_rest(blend.scales[node_idx], node.scale.into());
+            let rotation = Quat::from_vec4(
+                blend_with_rest(blend.rotations[node_idx], node.rotation.into()).normalize(),
+            );
+
+            local_transforms.push(Affine3A::from_scale_rotation_translation(
+                scale,
+                rotation,
+                translation,
+            ));
+
+            let (sum, total) = &blend.morph_weights[node_idx];
+            morph_weights.push(if node.mesh == PackedSceneNode::NONE {
+                Vec::new()
+            } else {
+                let rest = scene.meshes[node.mesh as usize].morph_weights.as_slice();
+                sum.iter()
+                    .zip(rest.iter())
+                    .map(|(&sum, &rest)| blend_with_rest((sum, *total), rest))
+                    .collect()
+            });
+        }
+
+        (
+            scene.compose_node_transforms(local_transforms),
+            morph_weights,
+        )
+    }
+
+    /// Sets the transforms, joint matrices, and morph target weights of the scene's instances
+    pub fn apply(&self, world_renderer: &mut WorldRenderer) {
+        let (node_transforms, morph_weights) = self.sample_pose();
+
+        for inst in &self.instances {
+            world_renderer.set_instance_transform(
+                inst.instance,
+                self.root_transform * node_transforms[inst.node],
+            );
+
+            let joint_matrices = self.scene.skin_joint_matrices(inst.node, &node_transforms);
+            if !joint_matrices.is_empty() {
+                world_renderer.set_instance_joint_matrices(inst.instance, &joint_matrices);
+            }
+
+            if !morph_weights[inst.node].is_empty() {
+                world_renderer.set_instance_morph_weights(inst.instance, &morph_weights[inst.node]);
+            }
+        }
+    }
+}
+
+/// Weighted sums of sampled values, and the sum of the weights, per node
+struct PoseBlend {
+    translations: Vec<(Vec3, f32)>,
+    rotations: Vec<(Vec4, f32)>,
+    scales: Vec<(Vec3, f32)>,
+    morph_weights: Vec<(Vec<f32>, f32)>,
+}
+
+fn blend_with_rest<T>((sum, total): (T, f32), rest: T) -> T
+where
+    T: std::ops::Mul<f32, Output = T> + std::ops::Add<Output = T>,
+{
+    if total >= 1.0 {
+        sum * (1.0 / total)
+    } else {
+        sum + rest * (1.0 - total)
+    }
+}
+
+fn node_morph_target_count(scene: &PackedScene::Flat, node: &PackedSceneNode) -> usize {
+    if node.mesh == PackedSceneNode::NONE {
+        0
+    } else {
+        scene.meshes[node.mesh as usize].morph_targets.len()
+    }
+}
+
+fn clip_duration(clip: &PackedAnimation::Flat) -> f32 {
+    clip.channels
+        .iter()
+        .filter_map(|channel| channel.times.as_slice().last().copied())
+        .fold(0.0, f32::max)
+}
+
+/// Writes the value of `channel` at `time` to `out`, which has one element per component.
+/// Returns `false` without writing anything if the channel has no keyframes, or too few values.
+fn sample_channel(channel: &PackedAnimationChannel::Flat, time: f32, out: &mut [f32]) -> bool {
+    let times = channel.times.as_slice();
+    let values = channel.values.as_slice();
+    let n = out.len();
+
+    // Cubic spline keyframes store an in-tangent, a value, and an out-tangent
+    let is_cubic = channel.interpolation == AnimationInterpolation::CUBIC_SPLINE;
+    let stride = if is_cubic { 3 } else { 1 };
+
+    if times.is_empty() || values.len() < times.len() * stride * n {
+        return false;
+    }
+
+    let value = |key: usize| {
+        let start = (key * stride + if is_cubic { 1 } else { 0 }) * n;
+        &values[start..start + n]
+    };
+
+    // Index of the first keyframe after `time`
+    let next = times.partition_point(|&t| t <= time);
+
+    if next == 0 {
+        out.copy_from_slice(value(0));
+        return true;
+    }
+
+    if next == times.len() {
+        out.copy_from_slice(value(times.len() - 1));
+        return true;
+    }
+
+    let prev = next - 1;
+    let dt = times[next] - times[prev];
+    let t = if dt > 0.0 {
+        (time - times[prev]) / dt
+    } else {
+        0.0
+    };
+
+    let (v0, v1) = (value(prev), value(next));
+
+    match channel.interpolation {
+        AnimationInterpolation::STEP => out.copy_from_slice(v0),
+        AnimationInterpolation::CUBIC_SPLINE => {
+            let out_tangent = &values[(prev * 3 + 2) * n..][..n];
+            let in_tangent = &values[(next * 3) * n..][..n];
+            let (t2, t3) = (t * t, t * t * t);
+
+            for i in 0..n {
+                out[i] = (2.0 * t3 - 3.0 * t2 + 1.0) * v0[i]
+                    + (t3 - 2.0 * t2 + t) * dt * out_tangent[i]
+                    + (-2.0 * t3 + 3.0 * t2) * v1[i]
+                    + (t3 - t2) * dt * in_tangent[i];
+            }
+        }
+        // Rotations get normalized after blending, so this is a normalized lerp for those
+        _ => {
+            for i in 0..n {
+                out[i] = v0[i] + (v1[i] - v0[i]) * t;
+            }
+        }
+    }
+
+    true
+}
@@ -3,7 +3,7 @@ use std::sync::Arc;
 use crate::{image_cache::UploadGpuImage, world_renderer::WorldRenderer};
 use kajiya_asset::{
     image::LoadImage,
-    mesh::{TexGamma, TexParams},
+    mesh::{TexChannels, TexCompressionMode, TexGamma, TexMipFilter, TexParams},
 };
 use kajiya_backend::vulkan::RenderBackend;
 #[allow(unused_imports)]
@@ -31,6 +31,9 @@ impl WorldRenderer {
                     params: TexParams {
                         gamma: TexGamma::Linear,
                         use_mips: false,
+                        compression: TexCompressionMode::None,
+                        mip_filter: TexMipFilter::Default,
+                        channels: TexChannels::Rgba,
                     },
                     device: backend.device.clone(),
                 }
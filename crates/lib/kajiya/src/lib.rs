@@ -1,3 +1,4 @@
+pub mod animation;
 pub mod camera;
 pub mod default_world_renderer;
 pub mod frame_desc;
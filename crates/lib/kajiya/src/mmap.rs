@@ -1,23 +1,187 @@
-use std::{collections::HashMap, fs::File, path::PathBuf};
+use std::{
+    any::TypeId,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs::File,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
+use bytes::Bytes;
+use kajiya_asset::mesh::{read_flat_asset, FlatAsset, ValidateContents};
 use parking_lot::Mutex;
 
+struct MappedAsset {
+    // Canonical path of the file, as reported by the watcher
+    disk_path: PathBuf,
+    mmap: memmap2::Mmap,
+    validated: Option<ValidatedAsset>,
+}
+
+struct AssetBytes {
+    bytes: Bytes,
+    validated: Option<ValidatedAsset>,
+}
+
+/// An asset which passed `read_flat_asset`, so that it's only validated once
+#[derive(Clone, Copy)]
+struct ValidatedAsset {
+    type_id: TypeId,
+    // Address of the asset inside the data of its file
+    asset: usize,
+}
+
+impl ValidatedAsset {
+    fn get<T: 'static>(validated: Option<ValidatedAsset>) -> Option<&'static T> {
+        validated
+            .filter(|validated| validated.type_id == TypeId::of::<T>())
+            .map(|validated| unsafe { &*(validated.asset as *const T) })
+    }
+}
+
 lazy_static::lazy_static! {
-    static ref ASSET_MMAPS: Mutex<HashMap<PathBuf, memmap2::Mmap>> = Mutex::new(HashMap::new());
+    // Keyed by the VFS path the asset was requested with
+    static ref ASSET_MMAPS: Mutex<HashMap<PathBuf, MappedAsset>> = Mutex::new(HashMap::new());
+
+    // Mappings of files which have since changed. References handed out earlier still point into them.
+    static ref RETIRED_ASSET_MMAPS: Mutex<Vec<memmap2::Mmap>> = Mutex::new(Vec::new());
+
+    // Assets served by VFS backends other than directories, such as asset packs
+    static ref ASSET_BYTES: Mutex<HashMap<PathBuf, AssetBytes>> = Mutex::new(HashMap::new());
+
+    // Directories with mapped assets in them
+    static ref WATCHED_ASSET_DIRS: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+
+    // Files changed in `WATCHED_ASSET_DIRS`. Written to by the file watcher, which mustn't
+    // take any of the other locks here, as those can be held while adding a watch.
+    static ref CHANGED_ASSET_FILES: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
 }
 
-pub fn mmapped_asset<T, P: Into<std::path::PathBuf>>(path: P) -> anyhow::Result<&'static T> {
+/// Maps a baked asset file into memory, and validates its header and contents
+/// before handing out a reference to the asset. Assets inside asset packs are used in place.
+/// Each file is validated once; later calls return the same reference.
+///
+/// The directories of mapped files are watched; see `take_changed_mmapped_assets`.
+pub fn mmapped_asset<T: FlatAsset + ValidateContents + 'static, P: Into<std::path::PathBuf>>(
+    path: P,
+) -> anyhow::Result<&'static T> {
     let path = path.into();
-    let path = kajiya_backend::canonical_path_from_vfs(&path)
+    let disk_path = kajiya_backend::vfs::vfs_disk_path(&path)
         .with_context(|| format!("Can't mmap asset: file doesn't exist: {:?}", path))?;
 
-    let mut mmaps = ASSET_MMAPS.lock();
-    let data: &[u8] = mmaps.entry(path.clone()).or_insert_with(|| {
-        let file =
-            File::open(&path).unwrap_or_else(|e| panic!("Could not mmap {:?}: {:?}", path, e));
-        unsafe { memmap2::MmapOptions::new().map(&file).unwrap() }
-    });
-    let asset: &T = unsafe { (data.as_ptr() as *const T).as_ref() }.unwrap();
+    // Mappings are never unmapped, so they live as long as the program
+    let data: &'static [u8] = if let Some(disk_path) = disk_path {
+        let mut mmaps = ASSET_MMAPS.lock();
+        let mapped = match mmaps.entry(path.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let disk_path = disk_path
+                    .canonicalize()
+                    .with_context(|| format!("canonicalize {:?}", disk_path))?;
+                watch_asset_dir(&disk_path)?;
+
+                let file = File::open(&disk_path)
+                    .with_context(|| format!("Could not open {:?}", disk_path))?;
+                let mmap = unsafe { memmap2::MmapOptions::new().map(&file) }
+                    .with_context(|| format!("Could not mmap {:?}", disk_path))?;
+                entry.insert(MappedAsset {
+                    disk_path,
+                    mmap,
+                    validated: None,
+                })
+            }
+        };
+
+        if let Some(asset) = ValidatedAsset::get(mapped.validated) {
+            return Ok(asset);
+        }
+
+        unsafe { std::slice::from_raw_parts(mapped.mmap.as_ptr(), mapped.mmap.len()) }
+    } else {
+        let mut asset_bytes = ASSET_BYTES.lock();
+        let loaded = match asset_bytes.entry(path.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(AssetBytes {
+                bytes: kajiya_backend::vfs::vfs_read(&path)?,
+                validated: None,
+            }),
+        };
+
+        if let Some(asset) = ValidatedAsset::get(loaded.validated) {
+            return Ok(asset);
+        }
+
+        unsafe { std::slice::from_raw_parts(loaded.bytes.as_ptr(), loaded.bytes.len()) }
+    };
+
+    // Without holding the locks, as checksumming large files takes a while
+    let asset =
+        read_flat_asset::<T>(data).with_context(|| format!("Invalid baked asset {:?}", path))?;
+    remember_validated_asset(&path, data, asset);
+
     Ok(asset)
 }
+
+/// Records that `asset` in `data` is valid, unless the file at `path` got replaced meanwhile
+fn remember_validated_asset<T: 'static>(path: &Path, data: &[u8], asset: &'static T) {
+    let validated = Some(ValidatedAsset {
+        type_id: TypeId::of::<T>(),
+        asset: asset as *const T as usize,
+    });
+
+    if let Some(mapped) = ASSET_MMAPS.lock().get_mut(path) {
+        if mapped.mmap.as_ptr() == data.as_ptr() {
+            mapped.validated = validated;
+        }
+    } else if let Some(loaded) = ASSET_BYTES.lock().get_mut(path) {
+        if loaded.bytes.as_ptr() == data.as_ptr() {
+            loaded.validated = validated;
+        }
+    }
+}
+
+fn watch_asset_dir(disk_path: &std::path::Path) -> anyhow::Result<()> {
+    let dir = disk_path
+        .parent()
+        .with_context(|| format!("{:?} has no parent directory", disk_path))?;
+
+    let mut watched_dirs = WATCHED_ASSET_DIRS.lock();
+    if !watched_dirs.contains(dir) {
+        kajiya_backend::file::watch_dir_changes(dir, |path| {
+            CHANGED_ASSET_FILES.lock().insert(path.to_owned());
+        })?;
+        watched_dirs.insert(dir.to_owned());
+    }
+
+    Ok(())
+}
+
+/// Returns the paths of mapped assets whose files changed on disk since they were mapped,
+/// as passed to `mmapped_asset`. The next `mmapped_asset` call with any of those paths maps
+/// the new file. References to the previous version of the asset stay valid.
+///
+/// Changed files must be replaced, rather than overwritten in place, as `bake` does.
+/// Writing over a mapped file changes assets in use, or makes them unreadable.
+///
+/// The mappings of previous versions are never unmapped, as references into them are `'static`.
+/// Each reload of a file keeps the address space of its previous version in use for the rest
+/// of the process, which adds up over long editing sessions.
+pub fn take_changed_mmapped_assets() -> Vec<PathBuf> {
+    let changed_files = std::mem::take(&mut *CHANGED_ASSET_FILES.lock());
+    if changed_files.is_empty() {
+        return Vec::new();
+    }
+
+    let mut mmaps = ASSET_MMAPS.lock();
+    let changed_paths: Vec<PathBuf> = mmaps
+        .iter()
+        .filter(|(_, mapped)| changed_files.contains(&mapped.disk_path))
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    let mut retired = RETIRED_ASSET_MMAPS.lock();
+    for path in &changed_paths {
+        retired.push(mmaps.remove(path).unwrap().mmap);
+    }
+
+    changed_paths
+}
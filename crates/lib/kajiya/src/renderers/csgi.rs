@@ -15,7 +15,8 @@ use kajiya_backend::{
     },
 };
 use kajiya_rg::{
-    self as rg, BindRgRef, GetOrCreateTemporal, IntoRenderPassPipelineBinding, SimpleRenderPass,
+    self as rg, BindRgRef, GetOrCreateTemporal, IntoRenderPassPipelineBinding, RayHitGroup,
+    SimpleRenderPass,
 };
 
 use rust_shaders_shared::frame_constants::GiCascadeConstants;
@@ -287,7 +288,11 @@ impl CsgiRenderer {
                     ShaderSource::hlsl("/shaders/rt/gbuffer.rmiss.hlsl"),
                     ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
                 ],
-                [ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl")],
+                [
+                    RayHitGroup::closest_hit(ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl"))
+                        .with_any_hit(ShaderSource::hlsl("/shaders/rt/gbuffer.rahit.hlsl")),
+                    RayHitGroup::any_hit(ShaderSource::hlsl("/shaders/rt/shadow.rahit.hlsl")),
+                ],
             )
             .read_array(&indirect_combined_cascades)
             .read(sky_cube)
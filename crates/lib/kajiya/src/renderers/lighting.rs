@@ -2,7 +2,7 @@ use kajiya_backend::{
     ash::vk,
     vulkan::{image::*, ray_tracing::RayTracingAcceleration, shader::ShaderSource},
 };
-use kajiya_rg::{self as rg, SimpleRenderPass};
+use kajiya_rg::{self as rg, RayHitGroup, SimpleRenderPass};
 
 use super::{rtr::SPATIAL_RESOLVE_OFFSETS, GbufferDepth};
 
@@ -53,7 +53,11 @@ impl LightingRenderer {
                 ShaderSource::hlsl("/shaders/rt/gbuffer.rmiss.hlsl"),
                 ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
             ],
-            [ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl")],
+            [
+                RayHitGroup::closest_hit(ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl"))
+                    .with_any_hit(ShaderSource::hlsl("/shaders/rt/gbuffer.rahit.hlsl")),
+                RayHitGroup::any_hit(ShaderSource::hlsl("/shaders/rt/shadow.rahit.hlsl")),
+            ],
         )
         .read_aspect(&gbuffer_depth.depth, vk::ImageAspectFlags::DEPTH)
         .write(&mut refl0_tex)
@@ -16,6 +16,7 @@ pub mod rtdgi;
 pub mod rtr;
 pub mod shadow_denoise;
 pub mod shadows;
+pub mod skinning;
 pub mod sky;
 pub mod ssgi;
 pub mod taa;
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use glam::{Affine3A, Vec3};
 use kajiya_backend::{
     ash::vk,
     vk_sync::AccessType,
@@ -12,10 +13,68 @@ use crate::world_renderer::MeshInstance;
 
 use super::GbufferDepth;
 
-#[derive(Clone)]
-pub struct UploadedTriMesh {
+#[derive(Clone, Copy)]
+pub struct UploadedMeshLod {
     pub index_buffer_offset: u64,
     pub index_count: u32,
+    /// Triangles with single-sided materials come first in the index buffer, and are drawn
+    /// with back-face culling. The remaining ones are double-sided.
+    pub single_sided_index_count: u32,
+    /// Object-space distance from the full-detail surface
+    pub error: f32,
+}
+
+#[derive(Clone)]
+pub struct UploadedTriMesh {
+    /// Starting with the full-detail one. All LODs share the vertices of the mesh.
+    pub lods: Vec<UploadedMeshLod>,
+    pub bounding_sphere_center: Vec3,
+    pub bounding_sphere_radius: f32,
+}
+
+impl UploadedMeshLod {
+    pub fn has_double_sided_triangles(&self) -> bool {
+        self.single_sided_index_count < self.index_count
+    }
+}
+
+/// Picks the coarsest LOD of each instance whose error stays below a size on screen
+#[derive(Clone, Copy)]
+pub struct MeshLodSelection {
+    pub eye_position: Vec3,
+    /// Height in pixels of an object one unit tall, one unit away from the eye
+    pub pixels_per_unit: f32,
+    /// Zero always selects the full-detail LOD
+    pub max_error_pixels: f32,
+}
+
+impl MeshLodSelection {
+    pub fn select(&self, mesh: &UploadedTriMesh, transform: &Affine3A) -> usize {
+        let scale = transform
+            .matrix3
+            .x_axis
+            .length()
+            .max(transform.matrix3.y_axis.length())
+            .max(transform.matrix3.z_axis.length());
+
+        let center = transform.transform_point3(mesh.bounding_sphere_center);
+        let distance = (center - self.eye_position).length() - mesh.bounding_sphere_radius * scale;
+
+        // The eye is inside the bounds
+        if distance <= 0.0 {
+            return 0;
+        }
+
+        let projected_error =
+            |lod: &UploadedMeshLod| lod.error * scale * self.pixels_per_unit / distance;
+
+        mesh.lods
+            .iter()
+            .take_while(|lod| projected_error(lod) <= self.max_error_pixels)
+            .count()
+            .max(1)
+            - 1
+    }
 }
 
 pub struct RasterMeshesData<'a> {
@@ -23,6 +82,7 @@ pub struct RasterMeshesData<'a> {
     pub instances: &'a [MeshInstance],
     pub vertex_buffer: Arc<Buffer>,
     pub bindless_descriptor_set: vk::DescriptorSet,
+    pub lod_selection: MeshLodSelection,
 }
 
 pub fn raster_meshes(
@@ -34,27 +94,36 @@ pub fn raster_meshes(
 ) {
     let mut pass = rg.add_pass("raster simple");
 
-    let pipeline = pass.register_raster_pipeline(
-        &[
-            PipelineShaderDesc::builder(ShaderPipelineStage::Vertex)
-                // .rust_source("raster_simple::raster_simple_vs")
-                .hlsl_source("/shaders/raster_simple_vs.hlsl")
-                .build()
-                .unwrap(),
-            PipelineShaderDesc::builder(ShaderPipelineStage::Pixel)
-                // .rust_source("raster_simple::raster_simple_fs")
-                .hlsl_source("/shaders/raster_simple_ps.hlsl")
-                .build()
-                .unwrap(),
-        ],
-        RasterPipelineDesc::builder()
-            .render_pass(render_pass.clone())
-            .face_cull(true)
-            .push_constants_bytes(2 * std::mem::size_of::<u32>()),
-    );
+    // Double-sided triangles are drawn by a second pipeline, without back-face culling
+    let [culled_pipeline, unculled_pipeline] = [true, false].map(|face_cull| {
+        pass.register_raster_pipeline(
+            &[
+                PipelineShaderDesc::builder(ShaderPipelineStage::Vertex)
+                    // .rust_source("raster_simple::raster_simple_vs")
+                    .hlsl_source("/shaders/raster_simple_vs.hlsl")
+                    .build()
+                    .unwrap(),
+                PipelineShaderDesc::builder(ShaderPipelineStage::Pixel)
+                    // .rust_source("raster_simple::raster_simple_fs")
+                    .hlsl_source("/shaders/raster_simple_ps.hlsl")
+                    .build()
+                    .unwrap(),
+            ],
+            RasterPipelineDesc::builder()
+                .render_pass(render_pass.clone())
+                .face_cull(face_cull)
+                .push_constants_bytes(2 * std::mem::size_of::<u32>()),
+        )
+    });
 
-    let meshes: Vec<UploadedTriMesh> = mesh_data.meshes.to_vec();
     let instances: Vec<MeshInstance> = mesh_data.instances.to_vec();
+    let instance_lods: Vec<UploadedMeshLod> = instances
+        .iter()
+        .map(|inst| {
+            let mesh = &mesh_data.meshes[inst.mesh.0];
+            mesh.lods[mesh_data.lod_selection.select(mesh, &inst.transformation)]
+        })
+        .collect();
 
     let depth_ref = pass.raster(
         &mut gbuffer_depth.depth,
@@ -129,45 +198,60 @@ pub fn raster_meshes(
 
         api.set_default_view_and_scissor([width, height]);
 
-        let pipeline = api.bind_raster_pipeline(
-            pipeline
-                .into_binding()
-                .descriptor_set(
-                    0,
-                    &[RenderPassBinding::DynamicConstantsStorageBuffer(
-                        instance_transforms_offset,
-                    )],
-                )
-                .raw_descriptor_set(1, bindless_descriptor_set),
-        );
+        for (pipeline, double_sided) in [(culled_pipeline, false), (unculled_pipeline, true)] {
+            let pipeline = api.bind_raster_pipeline(
+                pipeline
+                    .into_binding()
+                    .descriptor_set(
+                        0,
+                        &[RenderPassBinding::DynamicConstantsStorageBuffer(
+                            instance_transforms_offset,
+                        )],
+                    )
+                    .raw_descriptor_set(1, bindless_descriptor_set),
+            );
+
+            unsafe {
+                let raw_device = &api.device().raw;
+                let cb = api.cb;
+
+                for (draw_idx, (instance, lod)) in
+                    instances.iter().zip(instance_lods.iter()).enumerate()
+                {
+                    let (first_index, index_count) = if double_sided {
+                        (
+                            lod.single_sided_index_count,
+                            lod.index_count - lod.single_sided_index_count,
+                        )
+                    } else {
+                        (0, lod.single_sided_index_count)
+                    };
+
+                    if index_count == 0 {
+                        continue;
+                    }
+
+                    raw_device.cmd_bind_index_buffer(
+                        cb.raw,
+                        vertex_buffer.raw,
+                        lod.index_buffer_offset,
+                        vk::IndexType::UINT32,
+                    );
+
+                    let push_constants = (draw_idx as u32, instance.mesh.0 as u32);
+
+                    pipeline.push_constants(
+                        cb.raw,
+                        vk::ShaderStageFlags::ALL_GRAPHICS,
+                        0,
+                        std::slice::from_raw_parts(
+                            &push_constants as *const _ as *const u8,
+                            std::mem::size_of_val(&push_constants),
+                        ),
+                    );
 
-        unsafe {
-            let raw_device = &api.device().raw;
-            let cb = api.cb;
-
-            for (draw_idx, instance) in instances.into_iter().enumerate() {
-                let mesh = &meshes[instance.mesh.0];
-
-                raw_device.cmd_bind_index_buffer(
-                    cb.raw,
-                    vertex_buffer.raw,
-                    mesh.index_buffer_offset,
-                    vk::IndexType::UINT32,
-                );
-
-                let push_constants = (draw_idx as u32, instance.mesh.0 as u32);
-
-                pipeline.push_constants(
-                    cb.raw,
-                    vk::ShaderStageFlags::ALL_GRAPHICS,
-                    0,
-                    std::slice::from_raw_parts(
-                        &push_constants as *const _ as *const u8,
-                        std::mem::size_of_val(&push_constants),
-                    ),
-                );
-
-                raw_device.cmd_draw_indexed(cb.raw, mesh.index_count, 1, 0, 0, 0);
+                    raw_device.cmd_draw_indexed(cb.raw, index_count, 1, first_index, 0, 0);
+                }
             }
         }
 
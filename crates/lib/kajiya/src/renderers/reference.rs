@@ -3,7 +3,7 @@ use kajiya_backend::{
     vulkan::{image::*, ray_tracing::RayTracingAcceleration, shader::ShaderSource},
 };
 use kajiya_rg::{self as rg};
-use rg::{RenderGraph, SimpleRenderPass};
+use rg::{RayHitGroup, RenderGraph, SimpleRenderPass};
 
 pub fn reference_path_trace(
     rg: &mut RenderGraph,
@@ -18,7 +18,11 @@ pub fn reference_path_trace(
             ShaderSource::hlsl("/shaders/rt/gbuffer.rmiss.hlsl"),
             ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
         ],
-        [ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl")],
+        [
+            RayHitGroup::closest_hit(ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl"))
+                .with_any_hit(ShaderSource::hlsl("/shaders/rt/gbuffer.rahit.hlsl")),
+            RayHitGroup::any_hit(ShaderSource::hlsl("/shaders/rt/shadow.rahit.hlsl")),
+        ],
     )
     .write(output_img)
     .raw_descriptor_set(1, bindless_descriptor_set)
@@ -6,7 +6,7 @@ use kajiya_backend::{
     vulkan::{buffer::*, image::*, ray_tracing::RayTracingAcceleration, shader::ShaderSource},
     Device,
 };
-use kajiya_rg::{self as rg, SimpleRenderPass};
+use kajiya_rg::{self as rg, RayHitGroup, SimpleRenderPass};
 
 use super::{csgi, GbufferDepth, PingPongTemporalResource};
 
@@ -252,7 +252,11 @@ impl RtdgiRenderer {
                 ShaderSource::hlsl("/shaders/rt/gbuffer.rmiss.hlsl"),
                 ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
             ],
-            [ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl")],
+            [
+                RayHitGroup::closest_hit(ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl"))
+                    .with_any_hit(ShaderSource::hlsl("/shaders/rt/gbuffer.rahit.hlsl")),
+                RayHitGroup::any_hit(ShaderSource::hlsl("/shaders/rt/shadow.rahit.hlsl")),
+            ],
         )
         .read(&gbuffer_depth.gbuffer)
         .read_aspect(&gbuffer_depth.depth, vk::ImageAspectFlags::DEPTH)
@@ -3,7 +3,7 @@ use kajiya_backend::{
     vulkan::{image::*, ray_tracing::RayTracingAcceleration, shader::ShaderSource},
 };
 use kajiya_rg::{self as rg};
-use rg::{RenderGraph, SimpleRenderPass};
+use rg::{RayHitGroup, RenderGraph, SimpleRenderPass};
 
 use super::GbufferDepth;
 
@@ -23,7 +23,11 @@ pub fn trace_sun_shadow_mask(
             ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
             ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
         ],
-        std::iter::empty(),
+        [
+            // Duplicated because `rt.hlsl` hardcodes hit group index to 1
+            RayHitGroup::any_hit(ShaderSource::hlsl("/shaders/rt/shadow.rahit.hlsl")),
+            RayHitGroup::any_hit(ShaderSource::hlsl("/shaders/rt/shadow.rahit.hlsl")),
+        ],
     )
     .read_aspect(&gbuffer_depth.depth, vk::ImageAspectFlags::DEPTH)
     .read(&gbuffer_depth.geometric_normal)
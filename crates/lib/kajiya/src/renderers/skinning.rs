@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use glam::Affine3A;
+use kajiya_backend::{
+    ash::vk,
+    vk_sync::AccessType,
+    vulkan::{buffer::*, ray_tracing::*},
+};
+use kajiya_rg::{self as rg};
+use rg::{RenderGraph, SimpleRenderPass};
+
+use crate::world_renderer::MeshHandle;
+
+/// Instances of skinned and morphed meshes get their own copy of the vertices,
+/// written to by `skin_meshes`
+pub struct SkinnedInstance {
+    /// The copy of the source mesh used by the instance
+    pub mesh: MeshHandle,
+    /// The mesh the instance was added with
+    pub source_mesh: MeshHandle,
+    pub vertex_count: u32,
+
+    // Bind pose, shared by all instances of the source mesh
+    pub source_vertex_core_offset: u32,
+    pub source_vertex_tangent_offset: u32,
+    pub vertex_joints_offset: u32,
+    pub vertex_weights_offset: u32,
+    pub morph_target_count: u32,
+    pub morph_deltas_offset: u32,
+
+    // Skinned outputs
+    pub vertex_core_offset: u32,
+    pub vertex_prev_core_offset: u32,
+    pub vertex_tangent_offset: u32,
+
+    pub blas_desc: RayTracingBottomAccelerationDesc,
+    pub joint_matrices: Vec<Affine3A>,
+    /// Indexed like the morph targets of the mesh
+    pub morph_weights: Vec<f32>,
+    /// Until the vertices have been skinned once, there are no previous-frame positions
+    pub first_frame: bool,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SkinningConstants {
+    vertex_count: u32,
+    source_vertex_core_offset: u32,
+    source_vertex_tangent_offset: u32,
+    vertex_joints_offset: u32,
+    vertex_weights_offset: u32,
+    morph_target_count: u32,
+    morph_deltas_offset: u32,
+    vertex_core_offset: u32,
+    vertex_prev_core_offset: u32,
+    vertex_tangent_offset: u32,
+    joint_count: u32,
+    first_frame: u32,
+}
+
+pub struct SkinMeshesData<'a> {
+    pub instances: Vec<&'a mut SkinnedInstance>,
+    // Indexed like `UploadedTriMesh::lods`; skinned instance meshes only have the full-detail one
+    pub mesh_blas: &'a [Vec<Arc<RayTracingAcceleration>>],
+    pub accel_scratch: RayTracingAccelerationScratchBuffer,
+}
+
+/// Deforms the vertices of skinned and morphed instances, keeping the previous frame's positions
+/// for motion vectors, and refits their bottom-level acceleration structures.
+///
+/// Needs to run before anything reads the vertices or the top-level acceleration.
+pub fn skin_meshes(
+    rg: &mut RenderGraph,
+    vertex_buffer: &mut rg::Handle<Buffer>,
+    data: SkinMeshesData<'_>,
+) {
+    if data.instances.is_empty() {
+        return;
+    }
+
+    let mut refits: Vec<(
+        RayTracingBottomAccelerationDesc,
+        Arc<RayTracingAcceleration>,
+    )> = Vec::with_capacity(data.instances.len());
+
+    for inst in data.instances {
+        // Without a pose or morph targets, the instance stays in the bind pose it was created with
+        if inst.joint_matrices.is_empty() && inst.morph_weights.is_empty() {
+            continue;
+        }
+
+        // Storage buffers can't be empty
+        let mut joint_matrices: Vec<[f32; 12]> =
+            inst.joint_matrices.iter().map(affine_to_rows).collect();
+        if joint_matrices.is_empty() {
+            joint_matrices.push(affine_to_rows(&Affine3A::IDENTITY));
+        }
+
+        let mut morph_weights = inst.morph_weights.clone();
+        if morph_weights.is_empty() {
+            morph_weights.push(0.0);
+        }
+
+        SimpleRenderPass::new_compute(rg.add_pass("skin mesh"), "/shaders/skin_mesh.hlsl")
+            .write(vertex_buffer)
+            .dynamic_storage_buffer_vec(joint_matrices)
+            .dynamic_storage_buffer_vec(morph_weights)
+            .constants(SkinningConstants {
+                vertex_count: inst.vertex_count,
+                source_vertex_core_offset: inst.source_vertex_core_offset,
+                source_vertex_tangent_offset: inst.source_vertex_tangent_offset,
+                vertex_joints_offset: inst.vertex_joints_offset,
+                vertex_weights_offset: inst.vertex_weights_offset,
+                morph_target_count: inst.morph_weights.len() as u32,
+                morph_deltas_offset: inst.morph_deltas_offset,
+                vertex_core_offset: inst.vertex_core_offset,
+                vertex_prev_core_offset: inst.vertex_prev_core_offset,
+                vertex_tangent_offset: inst.vertex_tangent_offset,
+                joint_count: inst.joint_matrices.len() as u32,
+                first_frame: inst.first_frame as u32,
+            })
+            .dispatch([inst.vertex_count, 1, 1]);
+
+        inst.first_frame = false;
+
+        refits.push((
+            inst.blas_desc.clone(),
+            data.mesh_blas[inst.mesh.0][0].clone(),
+        ));
+    }
+
+    if refits.is_empty() {
+        return;
+    }
+
+    let mut pass = rg.add_pass("refit skinned blas");
+
+    // Makes the skinned vertices visible to all later shader reads. `vk_sync` has no access type
+    // for acceleration structure builds, so the refits get an explicit barrier below.
+    pass.read(vertex_buffer, AccessType::AnyShaderReadOther);
+
+    let accel_scratch = data.accel_scratch;
+
+    pass.render(move |api| {
+        let cb = api.cb;
+
+        unsafe {
+            api.device().raw.cmd_pipeline_barrier(
+                cb.raw,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .build()],
+                &[],
+                &[],
+            );
+        }
+
+        for (blas_desc, blas) in &refits {
+            api.device()
+                .refit_ray_tracing_bottom_acceleration(cb.raw, blas_desc, blas, &accel_scratch)
+                .expect("refit blas");
+        }
+    });
+}
+
+fn affine_to_rows(xform: &Affine3A) -> [f32; 12] {
+    [
+        xform.x_axis.x,
+        xform.y_axis.x,
+        xform.z_axis.x,
+        xform.translation.x,
+        xform.x_axis.y,
+        xform.y_axis.y,
+        xform.z_axis.y,
+        xform.translation.y,
+        xform.x_axis.z,
+        xform.y_axis.z,
+        xform.z_axis.z,
+        xform.translation.z,
+    ]
+}
@@ -15,7 +15,9 @@ impl WorldRenderer {
         rg: &mut rg::TemporalRenderGraph,
         frame_desc: &WorldFrameDesc,
     ) -> rg::Handle<Image> {
-        let tlas = self.prepare_top_level_acceleration(rg);
+        let lod_selection = self.mesh_lod_selection(frame_desc, self.lod_max_error_pixels);
+        let gi_lod_selection = self.mesh_lod_selection(frame_desc, self.gi_lod_max_error_pixels);
+        let tlas = self.prepare_top_level_acceleration(rg, &lod_selection, &gi_lod_selection);
 
         let mut accum_img = rg
             .get_or_create_temporal(
@@ -76,6 +78,7 @@ impl WorldRenderer {
                         instances: self.instances.as_slice(),
                         vertex_buffer: self.vertex_buffer.lock().clone(),
                         bindless_descriptor_set: self.bindless_descriptor_set,
+                        lod_selection,
                     },
                 );
             }
@@ -126,10 +129,11 @@ impl WorldRenderer {
         );
 
         // TODO: don't iter over all the things
-        let any_triangle_lights = self
-            .instances
-            .iter()
-            .any(|inst| !self.mesh_lights[inst.mesh.0].lights.is_empty());
+        let any_triangle_lights = !self.punctual_lights.is_empty()
+            || self
+                .instances
+                .iter()
+                .any(|inst| !self.mesh_lights[inst.mesh.0].lights.is_empty());
 
         let mut rtr = self.rtr.trace(
             rg,
@@ -241,7 +245,9 @@ impl WorldRenderer {
             rg::imageops::clear_color(rg, &mut accum_img, [0.0, 0.0, 0.0, 0.0]);
         }
 
-        let tlas = self.prepare_top_level_acceleration(rg);
+        // Full detail for the reference
+        let lod_selection = self.mesh_lod_selection(frame_desc, 0.0);
+        let tlas = self.prepare_top_level_acceleration(rg, &lod_selection, &lod_selection);
 
         reference_path_trace(rg, &mut accum_img, self.bindless_descriptor_set, &tlas);
 
@@ -5,11 +5,16 @@ use crate::{
     image_lut::{ComputeImageLut, ImageLut},
     renderers::{
         csgi::CsgiRenderer, lighting::LightingRenderer, raster_meshes::*, rtdgi::RtdgiRenderer,
-        rtr::*, shadow_denoise::ShadowDenoiseRenderer, ssgi::*, taa::TaaRenderer,
+        rtr::*, shadow_denoise::ShadowDenoiseRenderer, skinning::*, ssgi::*, taa::TaaRenderer,
     },
+    world_renderer_mmap_adapter::BakedSceneSource,
 };
+use anyhow::Context as _;
 use glam::{Affine3A, Vec2, Vec3};
-use kajiya_asset::mesh::{AssetRef, GpuImage, MeshMaterialFlags, PackedTriMesh, PackedVertex};
+use kajiya_asset::mesh::{
+    AssetRef, GpuImage, MeshMaterial, MeshMaterialAlphaMode, MeshMaterialFlags, PackedTriMesh,
+    PackedVertex, QuantizedVertex, VertexEncodingFlags, MAX_MESH_LODS,
+};
 use kajiya_backend::{
     ash::vk::{self, ImageView},
     dynamic_constants::DynamicConstants,
@@ -26,7 +31,7 @@ use rust_shaders_shared::{
     frame_constants::{FrameConstants, GiCascadeConstants, MAX_CSGI_CASCADE_COUNT},
     view_constants::ViewConstants,
 };
-use std::{collections::HashMap, mem::size_of, sync::Arc};
+use std::{collections::HashMap, mem::size_of, ops::Range, path::PathBuf, sync::Arc};
 use vulkan::buffer::{Buffer, BufferDesc};
 
 #[cfg(feature = "dlss")]
@@ -43,8 +48,24 @@ struct GpuMesh {
 
     mat_data_offset: u32,
     index_offset: u32,
+    vertex_uv1_offset: u32,
+    // Non-zero for skinned and morphed meshes, which move between frames
+    vertex_prev_core_offset: u32,
+
+    // `VertexEncodingFlags`, and the dequantization of positions
+    vertex_encoding: u32,
+    position_offset: [f32; 3],
+    position_scale: [f32; 3],
+
+    // Index offsets of the simplified LODs; zero past the last one
+    lod_index_offsets: [u32; MAX_MESH_LODS],
 }
 
+/// `VkTransformMatrixKHR`, which needs to be 16-byte aligned when used for building a BLAS
+#[repr(C, align(16))]
+#[derive(Copy, Clone)]
+struct BlasVertexTransform([[f32; 4]; 3]);
+
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
 pub struct MeshHandle(pub usize);
 
@@ -55,6 +76,14 @@ const MAX_GPU_MESHES: usize = 1024;
 const VERTEX_BUFFER_CAPACITY: usize = 1024 * 1024 * 512;
 const TLAS_PREALLOCATE_BYTES: usize = 1024 * 1024 * 32;
 
+// The custom index of ray tracing instances holds the mesh index in its low bits,
+// and the LOD above them. Must match `RT_INSTANCE_MESH_INDEX_BITS` in `mesh.hlsl`.
+const RT_INSTANCE_MESH_INDEX_BITS: u32 = 20;
+
+// Must match `RT_INSTANCE_MASK_*` in `rt.hlsl`
+const RT_INSTANCE_MASK_PRIMARY: u8 = 1;
+const RT_INSTANCE_MASK_GI: u8 = 2;
+
 #[derive(Clone, Copy)]
 pub struct InstanceDynamicParameters {
     pub emissive_multiplier: f32,
@@ -68,6 +97,24 @@ impl Default for InstanceDynamicParameters {
     }
 }
 
+/// Inputs for GPU deformation of a mesh with joints and weights, or morph targets
+#[derive(Clone)]
+struct SkinnedMeshSource {
+    mesh: &'static PackedTriMesh::Flat,
+    gpu_mesh: GpuMesh,
+    vertex_joints_offset: u32,
+    vertex_weights_offset: u32,
+    morph_deltas_offset: u32,
+    blas_desc: RayTracingBottomAccelerationDesc,
+}
+
+/// What a mesh was added from, so that it can be replaced with a new version
+#[derive(Clone, Copy)]
+pub(super) struct MeshSource {
+    pub(super) mesh: &'static PackedTriMesh::Flat,
+    pub(super) opts: AddMeshOptions,
+}
+
 #[derive(Clone, Copy)]
 pub struct MeshInstance {
     pub transformation: Affine3A,
@@ -118,14 +165,100 @@ pub struct MeshLightSet {
     pub lights: Vec<TriangleLight>,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PunctualLightKind {
+    Point,
+    /// Emits in a cosine lobe around the light's direction; cone angles are not supported
+    Spot,
+}
+
+/// Light without geometry of its own, such as imported from `KHR_lights_punctual`.
+/// Only triangle lights are supported by the renderer, so these get rendered
+/// as small emissive shapes. Their radiance is uniform, so there's no range either.
+#[derive(Clone, Copy)]
+pub struct PunctualLight {
+    pub kind: PunctualLightKind,
+    pub position: Vec3,
+    /// Spot lights shine along it
+    pub direction: Vec3,
+    /// Radiant intensity along the direction of the light
+    pub intensity: Vec3,
+    /// Size of the emissive shape
+    pub radius: f32,
+}
+
+impl PunctualLight {
+    pub fn triangle_lights(&self) -> Vec<TriangleLight> {
+        let r = self.radius.max(1e-4);
+
+        match self.kind {
+            PunctualLightKind::Point => {
+                // An octahedron has a mean projected area of `sqrt(3) * r^2`
+                let radiance = self.intensity / (3.0f32.sqrt() * r * r);
+
+                let mut res = Vec::with_capacity(8);
+                for &sx in &[-1.0f32, 1.0] {
+                    for &sy in &[-1.0f32, 1.0] {
+                        for &sz in &[-1.0f32, 1.0] {
+                            let x = self.position + Vec3::X * sx * r;
+                            let y = self.position + Vec3::Y * sy * r;
+                            let z = self.position + Vec3::Z * sz * r;
+
+                            // Face outwards
+                            let verts = if sx * sy * sz > 0.0 {
+                                [x, y, z]
+                            } else {
+                                [x, z, y]
+                            };
+
+                            res.push(TriangleLight {
+                                verts: [verts[0].into(), verts[1].into(), verts[2].into()],
+                                radiance: radiance.into(),
+                            });
+                        }
+                    }
+                }
+                res
+            }
+            PunctualLightKind::Spot => {
+                // A square facing along the direction of the light
+                let radiance = self.intensity / (4.0 * r * r);
+                let basis = crate::math::build_orthonormal_basis(self.direction.normalize());
+                let t = basis.x_axis * r;
+                let b = basis.y_axis * r;
+                let c = self.position;
+
+                [
+                    [c - t - b, c + t - b, c + t + b],
+                    [c - t - b, c + t + b, c - t + b],
+                ]
+                .iter()
+                .map(|verts| TriangleLight {
+                    verts: [verts[0].into(), verts[1].into(), verts[2].into()],
+                    radiance: radiance.into(),
+                })
+                .collect()
+            }
+        }
+    }
+}
+
 pub struct WorldRenderer {
-    device: Arc<device::Device>,
+    pub(super) device: Arc<device::Device>,
 
     pub(super) raster_simple_render_pass: Arc<RenderPass>,
     pub(super) bindless_descriptor_set: vk::DescriptorSet,
     pub(super) meshes: Vec<UploadedTriMesh>,
+    // Indexed like `meshes`; `None` for the per-instance copies of skinned meshes
+    pub(super) mesh_sources: Vec<Option<MeshSource>>,
+    // Reloaded when their files change
+    pub(super) baked_scenes: Vec<BakedSceneSource>,
 
     pub(super) mesh_lights: Vec<MeshLightSet>,
+    mesh_skinning: Vec<Option<SkinnedMeshSource>>,
+    pub(super) skinned_instances: HashMap<InstanceHandle, SkinnedInstance>,
+    // Indices into `meshes` of the copies of removed skinned instances, for new ones to reuse
+    free_skinned_mesh_slots: Vec<usize>,
 
     // ----
     // SoA
@@ -138,14 +271,25 @@ pub struct WorldRenderer {
 
     pub(super) vertex_buffer: Mutex<Arc<Buffer>>,
     vertex_buffer_written: u64,
+    // Indexed like `meshes`, for uploads to the same index to reuse
+    mesh_vertex_data: Vec<Range<u64>>,
 
     mesh_buffer: Mutex<Arc<Buffer>>,
 
-    mesh_blas: Vec<Arc<RayTracingAcceleration>>,
+    // Indexed like `UploadedTriMesh::lods`
+    pub(super) mesh_blas: Vec<Vec<Arc<RayTracingAcceleration>>>,
+    // Instances traced by GI rays with a coarser LOD than the rasterized one. Their TLAS
+    // instances come after those of `instances`, and so do their dynamic parameters.
+    rt_gi_only_instances: Vec<usize>,
     tlas: Option<Arc<RayTracingAcceleration>>,
-    accel_scratch: RayTracingAccelerationScratchBuffer,
-
-    bindless_images: Vec<Arc<Image>>,
+    pub(super) accel_scratch: RayTracingAccelerationScratchBuffer,
+
+    bindless_images: HashMap<BindlessImageHandle, Arc<Image>>,
+    // Handles of removed images, for new ones to reuse
+    free_bindless_image_handles: Vec<BindlessImageHandle>,
+    // The images loaded for each mesh added with `add_mesh`, for them to be reloaded in place
+    pub(super) mesh_images:
+        HashMap<MeshHandle, HashMap<AssetRef<GpuImage::Flat>, BindlessImageHandle>>,
     next_bindless_image_id: usize,
     next_instance_handle: usize,
 
@@ -181,6 +325,16 @@ pub struct WorldRenderer {
     pub sun_size_multiplier: f32,
     pub sun_color_multiplier: Vec3,
     pub sky_ambient: Vec3,
+
+    pub punctual_lights: Vec<PunctualLight>,
+
+    /// Rasterized meshes use the coarsest LOD whose error covers at most this many pixels.
+    /// Zero always uses the full-detail meshes.
+    pub lod_max_error_pixels: f32,
+    /// Rays for diffuse GI may use coarser LODs than the rasterized ones, selected with this
+    /// error instead. Their origins are offset to clear the surfaces of the finer LODs.
+    /// Zero traces the rasterized LODs.
+    pub gi_lod_max_error_pixels: f32,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -192,15 +346,15 @@ pub enum RenderMode {
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct BindlessImageHandle(pub u32);
 
-fn load_gpu_image_asset(
+pub(super) fn baked_image_path(asset: AssetRef<GpuImage::Flat>) -> PathBuf {
+    format!("/baked/{:8.8x}.image", asset.identity()).into()
+}
+
+pub(super) fn load_gpu_image_asset(
     device: Arc<kajiya_backend::Device>,
     asset: AssetRef<GpuImage::Flat>,
-) -> Arc<Image> {
-    let asset = crate::mmap::mmapped_asset::<GpuImage::Flat, _>(&format!(
-        "/baked/{:8.8x}.image",
-        asset.identity()
-    ))
-    .unwrap();
+) -> anyhow::Result<Arc<Image>> {
+    let asset = crate::mmap::mmapped_asset::<GpuImage::Flat, _>(baked_image_path(asset))?;
 
     let desc = ImageDesc::new_2d(asset.format, [asset.extent[0], asset.extent[1]])
         .usage(vk::ImageUsageFlags::SAMPLED)
@@ -212,15 +366,36 @@ fn load_gpu_image_asset(
         .enumerate()
         .map(|(mip_level, mip)| ImageSubResourceData {
             data: mip.as_slice(),
-            row_pitch: ((desc.extent[0] as usize) >> mip_level).max(1) * 4,
+            row_pitch: kajiya_asset::image::gpu_image_row_pitch(
+                asset.format,
+                (desc.extent[0] >> mip_level).max(1),
+            ),
             slice_pitch: 0,
         })
         .collect::<Vec<_>>();
 
-    Arc::new(device.create_image(desc, initial_data).unwrap())
+    Ok(Arc::new(device.create_image(desc, initial_data)?))
+}
+
+/// Position, normal, and tangent deltas of each vertex, padded to `float4`,
+/// for one morph target after another. Read by `skin_mesh.hlsl`.
+fn pack_morph_deltas(mesh: &PackedTriMesh::Flat) -> Vec<[f32; 4]> {
+    let pad = |v: &[f32; 3]| [v[0], v[1], v[2], 0.0];
+
+    mesh.morph_targets
+        .iter()
+        .flat_map(|target| {
+            target
+                .positions
+                .iter()
+                .zip(target.normals.iter())
+                .zip(target.tangents.iter())
+                .flat_map(move |((p, n), t)| [pad(p), pad(n), pad(t)])
+        })
+        .collect()
 }
 
-#[derive(Default)]
+#[derive(Clone, Copy, Default)]
 pub struct AddMeshOptions {
     pub use_lights: bool,
 }
@@ -329,21 +504,30 @@ impl WorldRenderer {
             //cube_index_buffer: Arc::new(cube_index_buffer),
             device: backend.device.clone(),
             meshes: Default::default(),
+            mesh_sources: Default::default(),
+            baked_scenes: Default::default(),
             instances: Default::default(),
             instance_handles: Default::default(),
             instance_handle_to_index: Default::default(),
 
             mesh_lights: Default::default(),
+            mesh_skinning: Default::default(),
+            skinned_instances: Default::default(),
+            free_skinned_mesh_slots: Default::default(),
 
             mesh_blas: Default::default(),
+            rt_gi_only_instances: Default::default(),
             tlas: Default::default(),
             accel_scratch,
 
             mesh_buffer: Mutex::new(Arc::new(mesh_buffer)),
             vertex_buffer: Mutex::new(Arc::new(vertex_buffer)),
             vertex_buffer_written: 0,
+            mesh_vertex_data: Default::default(),
             bindless_descriptor_set,
             bindless_images: Default::default(),
+            free_bindless_image_handles: Default::default(),
+            mesh_images: Default::default(),
             image_luts: Default::default(),
 
             next_bindless_image_id: 0,
@@ -378,6 +562,11 @@ impl WorldRenderer {
             sun_size_multiplier: 1.0, // Sun as seen from Earth
             sun_color_multiplier: Vec3::ONE,
             sky_ambient: Vec3::ZERO,
+
+            punctual_lights: Vec::new(),
+
+            lod_max_error_pixels: 1.0,
+            gi_lod_max_error_pixels: 0.0,
         })
     }
 
@@ -405,9 +594,16 @@ impl WorldRenderer {
     }
 
     fn add_bindless_image_view(&mut self, view: ImageView) -> BindlessImageHandle {
-        let handle = BindlessImageHandle(self.next_bindless_image_id as _);
-        self.next_bindless_image_id += 1;
+        let handle = self.free_bindless_image_handles.pop().unwrap_or_else(|| {
+            let handle = BindlessImageHandle(self.next_bindless_image_id as _);
+            self.next_bindless_image_id += 1;
+            handle
+        });
+        self.write_bindless_image_view(handle, view);
+        handle
+    }
 
+    fn write_bindless_image_view(&self, handle: BindlessImageHandle, view: ImageView) {
         let image_info = vk::DescriptorImageInfo::builder()
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
             .image_view(view)
@@ -426,8 +622,6 @@ impl WorldRenderer {
                 .raw
                 .update_descriptor_sets(std::slice::from_ref(&write_descriptor_set), &[]);
         }
-
-        handle
     }
 
     pub fn add_image_lut(&mut self, computer: impl ComputeImageLut + 'static, id: usize) {
@@ -448,27 +642,149 @@ impl WorldRenderer {
     pub fn add_image(&mut self, image: Arc<Image>) -> BindlessImageHandle {
         let handle = self
             .add_bindless_image_view(image.view(self.device.as_ref(), &ImageViewDesc::default()));
-        self.bindless_images.push(image);
+        self.bindless_images.insert(handle, image);
         handle
     }
 
+    /// Makes an image added with `add_image` use the contents of another one. Materials
+    /// referring to its handle show the new image, and the renderer drops the previous one.
+    pub fn replace_image(&mut self, handle: BindlessImageHandle, image: Arc<Image>) {
+        assert!(
+            self.bindless_images.contains_key(&handle),
+            "only images added with `add_image` can be replaced"
+        );
+
+        // Frames in flight may still be sampling the previous image
+        unsafe { self.device.raw.device_wait_idle() }.expect("device_wait_idle");
+
+        self.write_bindless_image_view(
+            handle,
+            image.view(self.device.as_ref(), &ImageViewDesc::default()),
+        );
+        self.bindless_images.insert(handle, image);
+    }
+
+    /// Drops images added with `add_image`, once the frames in flight are done with them.
+    /// Their handles go to the next images added.
+    fn remove_images(&mut self, handles: Vec<BindlessImageHandle>) {
+        if handles.is_empty() {
+            return;
+        }
+
+        unsafe { self.device.raw.device_wait_idle() }.expect("device_wait_idle");
+
+        for handle in handles {
+            self.bindless_images
+                .remove(&handle)
+                .expect("only images added with `add_image` can be removed");
+            self.free_bindless_image_handles.push(handle);
+        }
+    }
+
     pub fn add_mesh(
         &mut self,
         mesh: &'static PackedTriMesh::Flat,
         opts: AddMeshOptions,
-    ) -> MeshHandle {
+    ) -> anyhow::Result<MeshHandle> {
         let mesh_idx = self.meshes.len();
+        self.upload_mesh(mesh_idx, mesh, opts)?;
+        self.mesh_sources.push(Some(MeshSource { mesh, opts }));
+
+        Ok(MeshHandle(mesh_idx))
+    }
+
+    /// Replaces the contents of a mesh added with `add_mesh`, keeping its options. The handle
+    /// stays valid, and instances of the mesh, including skinned ones, show the new version.
+    ///
+    /// The previous vertex data is overwritten if the new one fits in its place, and is not
+    /// reclaimed otherwise. Images used by both versions keep their contents; reload changed
+    /// ones with `replace_image`. Images only the previous version used are removed.
+    pub fn replace_mesh(
+        &mut self,
+        handle: MeshHandle,
+        mesh: &'static PackedTriMesh::Flat,
+    ) -> anyhow::Result<()> {
+        let source = self.mesh_sources[handle.0]
+            .context("only meshes added with `add_mesh` can be replaced")?;
+
+        // Instances of skinned and morphed meshes have their own copies, created when added
+        let was_skinned = self.mesh_skinning[handle.0].is_some();
+        let is_skinned = !mesh.joints.is_empty() || !mesh.morph_targets.is_empty();
+        if was_skinned != is_skinned {
+            anyhow::bail!("the mesh can't gain or lose skinning and morph targets");
+        }
+
+        self.upload_mesh(handle.0, mesh, source.opts)?;
+        self.mesh_sources[handle.0] = Some(MeshSource { mesh, ..source });
+
+        let skinned_instances: Vec<InstanceHandle> = self
+            .skinned_instances
+            .iter()
+            .filter(|(_, skinned)| skinned.source_mesh == handle)
+            .map(|(&inst, _)| inst)
+            .collect();
+
+        for inst in skinned_instances {
+            let prev = self.skinned_instances.remove(&inst).unwrap();
+            let mut skinned = self.upload_skinned_instance_mesh(prev.mesh.0, handle)?;
+
+            skinned.joint_matrices = prev.joint_matrices;
+            for (weight, prev_weight) in skinned.morph_weights.iter_mut().zip(prev.morph_weights) {
+                *weight = prev_weight;
+            }
+
+            self.skinned_instances.insert(inst, skinned);
+        }
+
+        Ok(())
+    }
+
+    /// Uploads the mesh and its images, and stores it at `mesh_idx`, which is either
+    /// that of an existing mesh to replace, or one past the last mesh. Fails without
+    /// changing anything if any of the images can't be loaded, or there's no room for the mesh.
+    ///
+    /// A replaced mesh keeps the handles of the images it still uses, without loading them
+    /// again, and releases the rest.
+    fn upload_mesh(
+        &mut self,
+        mesh_idx: usize,
+        mesh: &'static PackedTriMesh::Flat,
+        opts: AddMeshOptions,
+    ) -> anyhow::Result<()> {
+        if mesh_idx >= MAX_GPU_MESHES {
+            anyhow::bail!("Too many meshes; at most {} are supported", MAX_GPU_MESHES);
+        }
+
         let mut unique_images: Vec<AssetRef<GpuImage::Flat>> = mesh.maps.as_slice().to_vec();
         unique_images.sort();
         unique_images.dedup();
 
+        let prev_images = self
+            .mesh_images
+            .get(&MeshHandle(mesh_idx))
+            .cloned()
+            .unwrap_or_default();
+        let new_images: Vec<AssetRef<GpuImage::Flat>> = unique_images
+            .iter()
+            .copied()
+            .filter(|asset| !prev_images.contains_key(asset))
+            .collect();
+
         let loaded_images = {
             let device = self.device.clone();
             easy_parallel::Parallel::new()
-                .each(unique_images.iter(), |&asset| {
+                .each(new_images.iter(), |&asset| {
                     load_gpu_image_asset(device, asset)
                 })
                 .run()
+                .into_iter()
+                .zip(&new_images)
+                .map(|(image, &asset)| {
+                    image.with_context(|| {
+                        format!("Could not load image {:?}", baked_image_path(asset))
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?
         };
         /*let loaded_images = {
             let device = self.device.clone();
@@ -477,12 +793,150 @@ impl WorldRenderer {
                 .map(|&asset| load_gpu_image_asset(device.clone(), asset))
                 .collect::<Vec<_>>()
         };*/
-        let loaded_images = loaded_images.into_iter().map(|img| self.add_image(img));
-
-        let material_map_to_image: HashMap<AssetRef<GpuImage::Flat>, BindlessImageHandle> =
-            unique_images.into_iter().zip(loaded_images).collect();
 
         let mut materials = mesh.materials.as_slice().to_vec();
+
+        // If using emissives as lights, flag it in the material parameters
+        if opts.use_lights {
+            for mat in materials.iter_mut() {
+                mat.flags |= MeshMaterialFlags::MESH_MATERIAL_FLAG_EMISSIVE_USED_AS_LIGHT;
+            }
+        }
+
+        // Triangles with double-sided materials are baked after the single-sided ones
+        let single_sided_index_count = |indices: &[u32]| {
+            indices
+                .chunks_exact(3)
+                .take_while(|tri| {
+                    let material =
+                        &materials[mesh.material_ids.as_slice()[tri[0] as usize] as usize];
+                    material.flags & MeshMaterialFlags::MESH_MATERIAL_FLAG_DOUBLE_SIDED == 0
+                })
+                .count()
+                * 3
+        };
+
+        // Indexed like `UploadedTriMesh::lods`
+        let single_sided_index_counts: Vec<usize> = std::iter::once(mesh.indices.as_slice())
+            .chain(mesh.lods.iter().map(|lod| lod.indices.as_slice()))
+            .map(single_sided_index_count)
+            .collect();
+
+        let (bounds_min, bounds_max) = (0..mesh.vertex_count())
+            .map(|idx| Vec3::from(mesh.vertex_position(idx)))
+            .fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), p| (min.min(p), max.max(p)),
+            );
+        let bounding_sphere_center = (bounds_min + bounds_max) * 0.5;
+        let bounding_sphere_radius = (bounds_max - bounds_min).length() * 0.5;
+
+        // Offsets are relative to the start of the vertex data until it is allocated
+        let mut buffer_builder = BufferBuilder::new();
+        let vertex_index_offset = buffer_builder.append(mesh.indices.as_slice()) as u32;
+        let lod_index_offsets: Vec<u32> = mesh
+            .lods
+            .iter()
+            .map(|lod| buffer_builder.append(lod.indices.as_slice()) as u32)
+            .collect();
+        // Each stream is either stored as floats, or quantized, as described by the encoding
+        let vertex_encoding = mesh.vertex_encoding;
+        let is_encoded = |flag: u32| vertex_encoding.flags & flag != 0;
+        let quantized_positions = vertex_encoding.flags & VertexEncodingFlags::POSITION_MASK;
+
+        let vertex_core_offset = (if quantized_positions == VertexEncodingFlags::POSITION_F32 {
+            buffer_builder.append(mesh.verts.as_slice())
+        } else {
+            buffer_builder.append(mesh.quantized_verts.as_slice())
+        }) as u32;
+        let (vertex_uv_offset, vertex_uv1_offset) = if is_encoded(VertexEncodingFlags::UV_F16) {
+            (
+                buffer_builder.append(mesh.quantized_uvs.as_slice()),
+                buffer_builder.append(mesh.quantized_uvs1.as_slice()),
+            )
+        } else {
+            (
+                buffer_builder.append(mesh.uvs.as_slice()),
+                buffer_builder.append(mesh.uvs1.as_slice()),
+            )
+        };
+        let vertex_mat_offset = buffer_builder.append(mesh.material_ids.as_slice()) as u32;
+        let vertex_aux_offset = (if !is_encoded(VertexEncodingFlags::COLOR_RGBA8) {
+            buffer_builder.append(mesh.colors.as_slice())
+        } else {
+            buffer_builder.append(mesh.quantized_colors.as_slice())
+        }) as u32;
+        let vertex_tangent_offset = (if !is_encoded(VertexEncodingFlags::TANGENT_OCTAHEDRAL) {
+            buffer_builder.append(mesh.tangents.as_slice())
+        } else {
+            buffer_builder.append(mesh.quantized_tangents.as_slice())
+        }) as u32;
+
+        // The BLAS reads quantized positions directly, and applies their dequantization
+        let (vertex_format, vertex_stride, vertex_transform_offset) = match quantized_positions {
+            VertexEncodingFlags::POSITION_F32 => (
+                vk::Format::R32G32B32_SFLOAT,
+                size_of::<PackedVertex>(),
+                None,
+            ),
+            _ => {
+                let [sx, sy, sz] = vertex_encoding.position_scale;
+                let [ox, oy, oz] = vertex_encoding.position_offset;
+                let transform = BlasVertexTransform([
+                    [sx, 0.0, 0.0, ox],
+                    [0.0, sy, 0.0, oy],
+                    [0.0, 0.0, sz, oz],
+                ]);
+
+                (
+                    if quantized_positions == VertexEncodingFlags::POSITION_F16 {
+                        vk::Format::R16G16B16A16_SFLOAT
+                    } else {
+                        vk::Format::R16G16B16A16_SNORM
+                    },
+                    size_of::<QuantizedVertex>(),
+                    Some(buffer_builder.append(vec![transform])),
+                )
+            }
+        };
+
+        // Only used by the skinning pass
+        let is_skinned = !mesh.joints.is_empty();
+        let (vertex_joints_offset, vertex_weights_offset) = if is_skinned {
+            (
+                buffer_builder.append(mesh.joints.as_slice()) as u32,
+                buffer_builder.append(mesh.weights.as_slice()) as u32,
+            )
+        } else {
+            (0, 0)
+        };
+
+        let has_morph_targets = !mesh.morph_targets.is_empty();
+        let morph_deltas_offset = if has_morph_targets {
+            buffer_builder.append(pack_morph_deltas(mesh)) as u32
+        } else {
+            0
+        };
+
+        // The materials go last, as the handles of their images are only assigned once
+        // there's room for the mesh
+        let mat_data_offset = {
+            let alignment = std::mem::align_of::<MeshMaterial>() as u64;
+            (buffer_builder.current_offset() + alignment - 1) & !(alignment - 1)
+        };
+        let vertex_data_size =
+            mat_data_offset + (materials.len() * size_of::<MeshMaterial>()) as u64;
+        let vertex_data_offset = self.allocate_vertex_data(mesh_idx, vertex_data_size)?;
+
+        let (mut material_map_to_image, unused_images): (HashMap<_, _>, HashMap<_, _>) =
+            prev_images
+                .into_iter()
+                .partition(|(asset, _)| unique_images.contains(asset));
+        self.remove_images(unused_images.into_values().collect());
+        for (asset, image) in new_images.into_iter().zip(loaded_images) {
+            material_map_to_image.insert(asset, self.add_image(image));
+        }
+
         {
             let mesh_map_gpu_ids: Vec<BindlessImageHandle> = mesh
                 .maps
@@ -497,80 +951,91 @@ impl WorldRenderer {
                 }
             }
         }
-
-        // If using emissives as lights, flag it in the material parameters
-        if opts.use_lights {
-            for mat in materials.iter_mut() {
-                mat.flags |= MeshMaterialFlags::MESH_MATERIAL_FLAG_EMISSIVE_USED_AS_LIGHT;
-            }
-        }
-
-        let vertex_data_offset = self.vertex_buffer_written as u32;
-
-        let mut buffer_builder = BufferBuilder::new();
-        let vertex_index_offset =
-            buffer_builder.append(mesh.indices.as_slice()) as u32 + vertex_data_offset;
-        let vertex_core_offset =
-            buffer_builder.append(mesh.verts.as_slice()) as u32 + vertex_data_offset;
-        let vertex_uv_offset =
-            buffer_builder.append(mesh.uvs.as_slice()) as u32 + vertex_data_offset;
-        let vertex_mat_offset =
-            buffer_builder.append(mesh.material_ids.as_slice()) as u32 + vertex_data_offset;
-        let vertex_aux_offset =
-            buffer_builder.append(mesh.colors.as_slice()) as u32 + vertex_data_offset;
-        let vertex_tangent_offset =
-            buffer_builder.append(mesh.tangents.as_slice()) as u32 + vertex_data_offset;
-        let mat_data_offset = buffer_builder.append(materials) as u32 + vertex_data_offset;
-
-        let total_buffer_size = buffer_builder.current_offset();
-        let mut vertex_buffer = self.vertex_buffer.lock();
-        buffer_builder.upload(
-            self.device.as_ref(),
-            Arc::get_mut(&mut *vertex_buffer).expect("refs may not be retained"),
-            self.vertex_buffer_written,
-        );
-        self.vertex_buffer_written += total_buffer_size;
-
-        let mesh_buffer_dst = unsafe {
-            let mut mesh_buffer = self.mesh_buffer.lock();
-            let mesh_buffer = Arc::get_mut(&mut *mesh_buffer).expect("refs may not be retained");
-            let mesh_buffer_dst =
-                mesh_buffer.allocation.mapped_ptr().unwrap().as_ptr() as *mut GpuMesh;
-            std::slice::from_raw_parts_mut(mesh_buffer_dst, MAX_GPU_MESHES)
+        self.mesh_images.insert(MeshHandle(mesh_idx), material_map_to_image);
+
+        assert_eq!(buffer_builder.append(materials), mat_data_offset);
+        let base_da = self.upload_vertex_data(buffer_builder, vertex_data_offset);
+        let mat_data_offset = mat_data_offset as u32;
+
+        let vertex_data_offset = vertex_data_offset as u32;
+        let rebase = |offset: u32| offset + vertex_data_offset;
+        let vertex_index_offset = rebase(vertex_index_offset);
+        let lod_index_offsets: Vec<u32> = lod_index_offsets.into_iter().map(rebase).collect();
+        let vertex_core_offset = rebase(vertex_core_offset);
+        let vertex_uv_offset = rebase(vertex_uv_offset as u32);
+        let vertex_uv1_offset = rebase(vertex_uv1_offset as u32);
+        let vertex_mat_offset = rebase(vertex_mat_offset);
+        let vertex_aux_offset = rebase(vertex_aux_offset);
+        let vertex_tangent_offset = rebase(vertex_tangent_offset);
+        let mat_data_offset = rebase(mat_data_offset);
+        let (vertex_joints_offset, vertex_weights_offset) = if is_skinned {
+            (rebase(vertex_joints_offset), rebase(vertex_weights_offset))
+        } else {
+            (0, 0)
+        };
+        let morph_deltas_offset = if has_morph_targets {
+            rebase(morph_deltas_offset)
+        } else {
+            0
         };
 
-        let base_da = vertex_buffer.device_address(&self.device);
-        let vertex_buffer_da = base_da + vertex_core_offset as u64;
-        let index_buffer_da = base_da + vertex_index_offset as u64;
+        let blas_desc = RayTracingBottomAccelerationDesc {
+            geometries: vec![RayTracingGeometryDesc {
+                geometry_type: RayTracingGeometryType::Triangle,
+                vertex_buffer: base_da + vertex_core_offset as u64,
+                index_buffer: base_da + vertex_index_offset as u64,
+                vertex_format,
+                vertex_stride,
+                vertex_transform: vertex_transform_offset
+                    .map_or(0, |offset| base_da + u64::from(vertex_data_offset) + offset),
+                parts: vec![RayTracingGeometryPart {
+                    index_count: mesh.indices.len(),
+                    index_offset: 0,
+                    max_vertex: mesh
+                        .indices
+                        .as_slice()
+                        .iter()
+                        .copied()
+                        .max()
+                        .expect("mesh must not be empty"),
+                }],
+                // Alpha-tested and transmissive materials need the any-hit shaders to run
+                is_opaque: mesh.materials.as_slice().iter().all(|mat| {
+                    mat.alpha_mode == MeshMaterialAlphaMode::MESH_MATERIAL_ALPHA_MODE_OPAQUE
+                        && mat.transmission == 0.0
+                }),
+            }],
+            allow_update: false,
+        };
 
-        let blas = self
-            .device
-            .create_ray_tracing_bottom_acceleration(
-                &RayTracingBottomAccelerationDesc {
-                    geometries: vec![RayTracingGeometryDesc {
-                        geometry_type: RayTracingGeometryType::Triangle,
-                        vertex_buffer: vertex_buffer_da,
-                        index_buffer: index_buffer_da,
-                        vertex_format: vk::Format::R32G32B32_SFLOAT,
-                        vertex_stride: size_of::<PackedVertex>(),
-                        parts: vec![RayTracingGeometryPart {
-                            index_count: mesh.indices.len(),
-                            index_offset: 0,
-                            max_vertex: mesh
-                                .indices
-                                .as_slice()
-                                .iter()
-                                .copied()
-                                .max()
-                                .expect("mesh must not be empty"),
-                        }],
-                    }],
-                },
-                &self.accel_scratch,
+        // The simplified LODs keep all vertices, so only the indices differ
+        let blas: Vec<Arc<RayTracingAcceleration>> = std::iter::once(blas_desc.clone())
+            .chain(
+                mesh.lods
+                    .iter()
+                    .zip(&lod_index_offsets)
+                    .map(|(lod, &offset)| {
+                        let mut lod_desc = blas_desc.clone();
+                        lod_desc.geometries[0].index_buffer = base_da + offset as u64;
+                        lod_desc.geometries[0].parts[0].index_count = lod.indices.len();
+                        lod_desc
+                    }),
             )
-            .expect("blas");
+            .map(|desc| {
+                Arc::new(
+                    self.device
+                        .create_ray_tracing_bottom_acceleration(&desc, &self.accel_scratch)
+                        .expect("blas"),
+                )
+            })
+            .collect();
+
+        let mut gpu_lod_index_offsets = [0; MAX_MESH_LODS];
+        for (dst, &offset) in gpu_lod_index_offsets.iter_mut().zip(&lod_index_offsets) {
+            *dst = offset;
+        }
 
-        mesh_buffer_dst[mesh_idx] = GpuMesh {
+        let gpu_mesh = GpuMesh {
             vertex_core_offset,
             vertex_uv_offset,
             vertex_mat_offset,
@@ -578,14 +1043,51 @@ impl WorldRenderer {
             vertex_tangent_offset,
             mat_data_offset,
             index_offset: vertex_index_offset,
+            vertex_uv1_offset,
+            vertex_prev_core_offset: 0,
+            vertex_encoding: vertex_encoding.flags,
+            position_offset: vertex_encoding.position_offset,
+            position_scale: vertex_encoding.position_scale,
+            lod_index_offsets: gpu_lod_index_offsets,
         };
+        self.write_gpu_mesh(mesh_idx, gpu_mesh);
 
-        self.meshes.push(UploadedTriMesh {
+        let full_detail_lod = UploadedMeshLod {
             index_buffer_offset: vertex_index_offset as u64,
             index_count: mesh.indices.len() as _,
-        });
+            single_sided_index_count: single_sided_index_counts[0] as _,
+            error: 0.0,
+        };
 
-        self.mesh_blas.push(Arc::new(blas));
+        let uploaded_mesh = UploadedTriMesh {
+            lods: std::iter::once(full_detail_lod)
+                .chain(
+                    mesh.lods
+                        .iter()
+                        .zip(lod_index_offsets)
+                        .zip(&single_sided_index_counts[1..])
+                        .map(
+                            |((lod, offset), &single_sided_index_count)| UploadedMeshLod {
+                                index_buffer_offset: offset as u64,
+                                index_count: lod.indices.len() as _,
+                                single_sided_index_count: single_sided_index_count as _,
+                                error: lod.error,
+                            },
+                        ),
+                )
+                .collect(),
+            bounding_sphere_center,
+            bounding_sphere_radius,
+        };
+
+        let skinning = (is_skinned || has_morph_targets).then(|| SkinnedMeshSource {
+            mesh,
+            gpu_mesh,
+            vertex_joints_offset,
+            vertex_weights_offset,
+            morph_deltas_offset,
+            blas_desc,
+        });
 
         let mesh_lights = if opts.use_lights {
             let emissive_materials = mesh
@@ -601,9 +1103,9 @@ impl WorldRenderer {
                     continue;
                 }
 
-                let v0 = mesh.verts[indices[0] as usize].pos;
-                let v1 = mesh.verts[indices[1] as usize].pos;
-                let v2 = mesh.verts[indices[2] as usize].pos;
+                let v0 = mesh.vertex_position(indices[0] as usize);
+                let v1 = mesh.vertex_position(indices[1] as usize);
+                let v2 = mesh.vertex_position(indices[2] as usize);
                 let radiance = mesh.materials[mat_idx].emissive;
 
                 mesh_lights.push(TriangleLight {
@@ -617,13 +1119,206 @@ impl WorldRenderer {
             Vec::new()
         };
 
-        self.mesh_lights.push(MeshLightSet {
-            lights: mesh_lights,
+        self.store_mesh(
+            mesh_idx,
+            uploaded_mesh,
+            blas,
+            skinning,
+            MeshLightSet {
+                lights: mesh_lights,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Stores a mesh at `mesh_idx`, replacing the one there, or adding a new one past the last
+    fn store_mesh(
+        &mut self,
+        mesh_idx: usize,
+        mesh: UploadedTriMesh,
+        blas: Vec<Arc<RayTracingAcceleration>>,
+        skinning: Option<SkinnedMeshSource>,
+        lights: MeshLightSet,
+    ) {
+        if mesh_idx == self.meshes.len() {
+            self.meshes.push(mesh);
+            self.mesh_blas.push(blas);
+            self.mesh_skinning.push(skinning);
+            self.mesh_lights.push(lights);
+        } else {
+            self.meshes[mesh_idx] = mesh;
+            self.mesh_blas[mesh_idx] = blas;
+            self.mesh_skinning[mesh_idx] = skinning;
+            self.mesh_lights[mesh_idx] = lights;
+        }
+    }
+
+    /// Finds room in the vertex buffer for `size` bytes of data of the mesh at `mesh_idx`,
+    /// and returns its offset. The previous data of the mesh is overwritten if the new one
+    /// fits in its place. Otherwise the data goes to the end of the buffer, and the previous
+    /// range is never reused.
+    fn allocate_vertex_data(&mut self, mesh_idx: usize, size: u64) -> anyhow::Result<u64> {
+        if let Some(prev) = self.mesh_vertex_data.get(mesh_idx) {
+            if size <= prev.end - prev.start {
+                // Frames in flight may still be reading the previous data
+                unsafe { self.device.raw.device_wait_idle() }?;
+                return Ok(prev.start);
+            }
+        }
+
+        let start = self.vertex_buffer_written;
+        let end = start + size;
+        if end > VERTEX_BUFFER_CAPACITY as u64 {
+            anyhow::bail!(
+                "Out of vertex buffer space: {} bytes needed, {} left of {} MB",
+                size,
+                VERTEX_BUFFER_CAPACITY as u64 - start,
+                VERTEX_BUFFER_CAPACITY / (1024 * 1024)
+            );
+        }
+
+        // Keep the start of each upload aligned, as needed by `BlasVertexTransform`
+        self.vertex_buffer_written = (end + 15) & !15;
+
+        if mesh_idx == self.mesh_vertex_data.len() {
+            self.mesh_vertex_data.push(start..end);
+        } else {
+            self.mesh_vertex_data[mesh_idx] = start..end;
+        }
+
+        Ok(start)
+    }
+
+    /// Uploads to `offset` in the vertex buffer, and returns the device address of the buffer
+    fn upload_vertex_data(
+        &mut self,
+        buffer_builder: BufferBuilder,
+        offset: u64,
+    ) -> vk::DeviceAddress {
+        let mut vertex_buffer = self.vertex_buffer.lock();
+        buffer_builder.upload(
+            self.device.as_ref(),
+            Arc::get_mut(&mut *vertex_buffer).expect("refs may not be retained"),
+            offset,
+        );
+
+        vertex_buffer.device_address(&self.device)
+    }
+
+    fn write_gpu_mesh(&self, mesh_idx: usize, gpu_mesh: GpuMesh) {
+        assert!(mesh_idx < MAX_GPU_MESHES, "too many meshes");
+
+        let mesh_buffer_dst = unsafe {
+            let mut mesh_buffer = self.mesh_buffer.lock();
+            let mesh_buffer = Arc::get_mut(&mut *mesh_buffer).expect("refs may not be retained");
+            let mesh_buffer_dst =
+                mesh_buffer.allocation.mapped_ptr().unwrap().as_ptr() as *mut GpuMesh;
+            std::slice::from_raw_parts_mut(mesh_buffer_dst, MAX_GPU_MESHES)
+        };
+
+        mesh_buffer_dst[mesh_idx] = gpu_mesh;
+    }
+
+    /// Creates a copy of a skinned or morphed mesh for a new instance. It shares everything but the vertex
+    /// positions, normals, and tangents with the source, and has its own refittable BLAS.
+    fn add_skinned_instance_mesh(
+        &mut self,
+        source_mesh: MeshHandle,
+    ) -> anyhow::Result<SkinnedInstance> {
+        let mesh_idx = self.free_skinned_mesh_slots.pop().unwrap_or_else(|| {
+            self.mesh_sources.push(None);
+            self.meshes.len()
         });
+        self.upload_skinned_instance_mesh(mesh_idx, source_mesh)
+    }
+
+    /// Uploads the copy of a skinned or morphed mesh for an instance, and stores it at `mesh_idx`,
+    /// like `upload_mesh` does.
+    fn upload_skinned_instance_mesh(
+        &mut self,
+        mesh_idx: usize,
+        source_mesh: MeshHandle,
+    ) -> anyhow::Result<SkinnedInstance> {
+        if mesh_idx >= MAX_GPU_MESHES {
+            anyhow::bail!("Too many meshes; at most {} are supported", MAX_GPU_MESHES);
+        }
+
+        let source = self.mesh_skinning[source_mesh.0]
+            .clone()
+            .expect("mesh is not skinned");
+
+        // Start with the bind pose
+        let mut buffer_builder = BufferBuilder::new();
+        let vertex_core_offset = buffer_builder.append(source.mesh.verts.as_slice()) as u32;
+        let vertex_prev_core_offset = buffer_builder.append(source.mesh.verts.as_slice()) as u32;
+        let vertex_tangent_offset = buffer_builder.append(source.mesh.tangents.as_slice()) as u32;
+
+        let vertex_data_offset =
+            self.allocate_vertex_data(mesh_idx, buffer_builder.current_offset())?;
+        let base_da = self.upload_vertex_data(buffer_builder, vertex_data_offset);
+
+        let vertex_data_offset = vertex_data_offset as u32;
+        let vertex_core_offset = vertex_core_offset + vertex_data_offset;
+        let vertex_prev_core_offset = vertex_prev_core_offset + vertex_data_offset;
+        let vertex_tangent_offset = vertex_tangent_offset + vertex_data_offset;
+
+        let mut blas_desc = source.blas_desc.clone();
+        blas_desc.geometries[0].vertex_buffer = base_da + vertex_core_offset as u64;
+        blas_desc.allow_update = true;
+
+        let blas = self
+            .device
+            .create_ray_tracing_bottom_acceleration(&blas_desc, &self.accel_scratch)
+            .expect("blas");
 
-        MeshHandle(mesh_idx)
+        self.write_gpu_mesh(
+            mesh_idx,
+            GpuMesh {
+                vertex_core_offset,
+                vertex_tangent_offset,
+                vertex_prev_core_offset,
+                ..source.gpu_mesh
+            },
+        );
+
+        // Only the full-detail BLAS gets refit, so the rasterizer has to match it
+        let mut uploaded_mesh = self.meshes[source_mesh.0].clone();
+        uploaded_mesh.lods.truncate(1);
+
+        // Emissive triangles stay in the bind pose
+        let lights = self.mesh_lights[source_mesh.0].lights.clone();
+        self.store_mesh(
+            mesh_idx,
+            uploaded_mesh,
+            vec![Arc::new(blas)],
+            None,
+            MeshLightSet { lights },
+        );
+
+        Ok(SkinnedInstance {
+            mesh: MeshHandle(mesh_idx),
+            source_mesh,
+            vertex_count: source.mesh.verts.len() as u32,
+            source_vertex_core_offset: source.gpu_mesh.vertex_core_offset,
+            source_vertex_tangent_offset: source.gpu_mesh.vertex_tangent_offset,
+            vertex_joints_offset: source.vertex_joints_offset,
+            vertex_weights_offset: source.vertex_weights_offset,
+            morph_target_count: source.mesh.morph_targets.len() as u32,
+            morph_deltas_offset: source.morph_deltas_offset,
+            vertex_core_offset,
+            vertex_prev_core_offset,
+            vertex_tangent_offset,
+            blas_desc,
+            joint_matrices: Vec::new(),
+            morph_weights: source.mesh.morph_weights.as_slice().to_vec(),
+            first_frame: true,
+        })
     }
 
+    /// Instances of skinned meshes are rendered in their bind pose until
+    /// `set_instance_joint_matrices` is called. Instances of meshes with morph targets
+    /// start with the default weights of the mesh.
     pub fn add_instance(&mut self, mesh: MeshHandle, transform: Affine3A) -> InstanceHandle {
         let handle = self.next_instance_handle;
         self.next_instance_handle += 1;
@@ -631,6 +1326,17 @@ impl WorldRenderer {
 
         let index = self.instances.len();
 
+        let mesh = if self.mesh_skinning[mesh.0].is_some() {
+            let skinned = self
+                .add_skinned_instance_mesh(mesh)
+                .expect("could not upload the vertices of the skinned instance");
+            let mesh = skinned.mesh;
+            self.skinned_instances.insert(handle, skinned);
+            mesh
+        } else {
+            mesh
+        };
+
         self.instances.push(MeshInstance {
             transformation: transform,
             prev_transformation: transform,
@@ -646,7 +1352,16 @@ impl WorldRenderer {
         handle
     }
 
+    /// The mesh copy of a skinned instance goes to the next skinned instance added,
+    /// which reuses its vertex data if it fits.
     pub fn remove_instance(&mut self, inst: InstanceHandle) {
+        if let Some(skinned) = self.skinned_instances.remove(&inst) {
+            let mesh_idx = skinned.mesh.0;
+            self.mesh_blas[mesh_idx].clear();
+            self.mesh_lights[mesh_idx].lights.clear();
+            self.free_skinned_mesh_slots.push(mesh_idx);
+        }
+
         let index = self
             .instance_handle_to_index
             .remove(&inst)
@@ -666,6 +1381,42 @@ impl WorldRenderer {
         self.instances[index].transformation = transform;
     }
 
+    /// Poses a skinned instance. Each matrix transforms from the space of the mesh
+    /// in its bind pose to that of the instance, and is indexed by the joint indices
+    /// of the mesh vertices.
+    pub fn set_instance_joint_matrices(
+        &mut self,
+        inst: InstanceHandle,
+        joint_matrices: &[Affine3A],
+    ) {
+        let skinned = self
+            .skinned_instances
+            .get_mut(&inst)
+            .expect("instance is not skinned");
+
+        skinned.joint_matrices.clear();
+        skinned.joint_matrices.extend_from_slice(joint_matrices);
+    }
+
+    /// Sets the weights of the morph targets of an instance's mesh. Missing weights are zero,
+    /// and extra ones are ignored.
+    pub fn set_instance_morph_weights(&mut self, inst: InstanceHandle, weights: &[f32]) {
+        let skinned = self
+            .skinned_instances
+            .get_mut(&inst)
+            .expect("instance has no morph targets");
+
+        let count = skinned.morph_target_count as usize;
+        skinned.morph_weights.clear();
+        skinned.morph_weights.extend(
+            weights
+                .iter()
+                .copied()
+                .chain(std::iter::repeat(0.0))
+                .take(count),
+        );
+    }
+
     pub fn get_instance_dynamic_parameters(
         &self,
         inst: InstanceHandle,
@@ -692,9 +1443,12 @@ impl WorldRenderer {
                         .instances
                         .iter()
                         .map(|inst| RayTracingInstanceDesc {
-                            blas: self.mesh_blas[inst.mesh.0].clone(),
+                            blas: self.mesh_blas[inst.mesh.0][0].clone(),
                             transformation: inst.transformation,
-                            mesh_index: inst.mesh.0 as u32,
+                            custom_index: inst.mesh.0 as u32,
+                            mask: 0xff,
+                            double_sided: self.meshes[inst.mesh.0].lods[0]
+                                .has_double_sided_triangles(),
                         })
                         .collect::<Vec<_>>(),
                     preallocate_bytes: TLAS_PREALLOCATE_BYTES,
@@ -711,23 +1465,79 @@ impl WorldRenderer {
         self.frame_idx = 0;
     }
 
+    pub(super) fn mesh_lod_selection(
+        &self,
+        frame_desc: &WorldFrameDesc,
+        max_error_pixels: f32,
+    ) -> MeshLodSelection {
+        MeshLodSelection {
+            eye_position: frame_desc.camera_matrices.eye_position(),
+            pixels_per_unit: frame_desc.camera_matrices.view_to_clip.y_axis.y
+                * frame_desc.render_extent[1] as f32
+                * 0.5,
+            max_error_pixels,
+        }
+    }
+
+    /// Rebuilds the TLAS with the LODs picked by `lod_selection`, which should match the
+    /// rasterized ones. Instances for which `gi_lod_selection` picks a coarser LOD get
+    /// a second TLAS instance with it, only visible to GI rays.
     pub(super) fn prepare_top_level_acceleration(
         &mut self,
         rg: &mut rg::TemporalRenderGraph,
+        lod_selection: &MeshLodSelection,
+        gi_lod_selection: &MeshLodSelection,
     ) -> rg::Handle<RayTracingAcceleration> {
         let mut tlas = rg.import(
             self.tlas.as_ref().unwrap().clone(),
             vk_sync::AccessType::AnyShaderReadOther,
         );
 
+        let instance_desc = |inst: &MeshInstance, lod: usize, mask: u8| RayTracingInstanceDesc {
+            blas: self.mesh_blas[inst.mesh.0][lod].clone(),
+            transformation: inst.transformation,
+            custom_index: inst.mesh.0 as u32 | ((lod as u32) << RT_INSTANCE_MESH_INDEX_BITS),
+            mask,
+            double_sided: self.meshes[inst.mesh.0].lods[lod].has_double_sided_triangles(),
+        };
+
+        let instance_lods: Vec<(usize, usize)> = self
+            .instances
+            .iter()
+            .map(|inst| {
+                let mesh = &self.meshes[inst.mesh.0];
+                let lod = lod_selection.select(mesh, &inst.transformation);
+                let gi_lod = gi_lod_selection.select(mesh, &inst.transformation).max(lod);
+                (lod, gi_lod)
+            })
+            .collect();
+
+        self.rt_gi_only_instances = instance_lods
+            .iter()
+            .enumerate()
+            .filter(|(_, (lod, gi_lod))| gi_lod != lod)
+            .map(|(idx, _)| idx)
+            .collect();
+
         let instances = self
             .instances
             .iter()
-            .map(|inst| RayTracingInstanceDesc {
-                blas: self.mesh_blas[inst.mesh.0].clone(),
-                transformation: inst.transformation,
-                mesh_index: inst.mesh.0 as u32,
+            .zip(&instance_lods)
+            .map(|(inst, &(lod, gi_lod))| {
+                let mask = if lod == gi_lod {
+                    RT_INSTANCE_MASK_PRIMARY | RT_INSTANCE_MASK_GI
+                } else {
+                    RT_INSTANCE_MASK_PRIMARY
+                };
+                instance_desc(inst, lod, mask)
             })
+            .chain(self.rt_gi_only_instances.iter().map(|&idx| {
+                instance_desc(
+                    &self.instances[idx],
+                    instance_lods[idx].1,
+                    RT_INSTANCE_MASK_GI,
+                )
+            }))
             .collect::<Vec<_>>();
 
         let mut pass = rg.add_pass("rebuild tlas");
@@ -768,6 +1578,8 @@ impl WorldRenderer {
         rg: &mut rg::TemporalRenderGraph,
         frame_desc: &WorldFrameDesc,
     ) -> rg::Handle<Image> {
+        self.reload_changed_baked_assets();
+
         rg.predefined_descriptor_set_layouts.insert(
             1,
             rg::PredefinedDescriptorSet {
@@ -779,6 +1591,23 @@ impl WorldRenderer {
             image_lut.compute_if_needed(rg);
         }
 
+        if !self.skinned_instances.is_empty() {
+            let mut vertex_buffer = rg.import(
+                self.vertex_buffer.lock().clone(),
+                vk_sync::AccessType::AnyShaderReadOther,
+            );
+
+            skin_meshes(
+                rg,
+                &mut vertex_buffer,
+                SkinMeshesData {
+                    instances: self.skinned_instances.values_mut().collect(),
+                    mesh_blas: &self.mesh_blas,
+                    accel_scratch: self.accel_scratch.clone(),
+                },
+            );
+        }
+
         match self.render_mode {
             RenderMode::Standard => {
                 self.taa.current_supersample_offset = self.supersample_offsets
@@ -860,6 +1689,11 @@ impl WorldRenderer {
                             .scale_radiance(emissive_multiplier)
                     })
             })
+            .chain(
+                self.punctual_lights
+                    .iter()
+                    .flat_map(PunctualLight::triangle_lights),
+            )
             .collect();
 
         // Initialize constants for the maximum allowed cascade count, even if we're not using them,
@@ -882,6 +1716,17 @@ impl WorldRenderer {
 
         let real_sun_angular_radius = 0.53f32.to_radians() * 0.5;
 
+        // Surfaces of the rasterized and GI LODs are within the sum of their errors
+        let gi_ray_lod_bias =
+            if self.render_mode == RenderMode::Standard && !self.rt_gi_only_instances.is_empty() {
+                let gi_lod_selection =
+                    self.mesh_lod_selection(frame_desc, self.gi_lod_max_error_pixels);
+                (self.lod_max_error_pixels + gi_lod_selection.max_error_pixels)
+                    / gi_lod_selection.pixels_per_unit
+            } else {
+                0.0
+            };
+
         let globals_offset = dynamic_constants.push(&FrameConstants {
             view_constants,
             sun_direction: frame_desc.sun_direction.extend(0.0),
@@ -893,14 +1738,23 @@ impl WorldRenderer {
             sky_ambient: self.sky_ambient.extend(0.0),
             triangle_light_count: triangle_lights.len() as _,
             world_gi_scale: self.world_gi_scale,
-            pad0: 0,
+            gi_ray_lod_bias,
             pad1: 0,
             pad2: 0,
             gi_cascades,
         });
 
-        let instance_dynamic_parameters_offset = dynamic_constants
-            .push_from_iter(self.instances.iter().map(|inst| inst.dynamic_parameters));
+        // Indexed like the TLAS instances
+        let instance_dynamic_parameters_offset = dynamic_constants.push_from_iter(
+            self.instances
+                .iter()
+                .chain(
+                    self.rt_gi_only_instances
+                        .iter()
+                        .map(|&idx| &self.instances[idx]),
+                )
+                .map(|inst| inst.dynamic_parameters),
+        );
 
         let triangle_lights_offset: u32 =
             dynamic_constants.push_from_iter(triangle_lights.into_iter());
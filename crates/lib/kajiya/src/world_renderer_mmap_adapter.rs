@@ -1,16 +1,172 @@
-use kajiya_asset::mesh::PackedTriMesh;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
-use crate::world_renderer::{AddMeshOptions, MeshHandle, WorldRenderer};
+use glam::Affine3A;
+use kajiya_asset::mesh::{AssetRef, GpuImage, PackedScene, PackedSceneNode, PackedTriMesh};
+
+use crate::world_renderer::{
+    baked_image_path, load_gpu_image_asset, AddMeshOptions, BindlessImageHandle, InstanceHandle,
+    MeshHandle, WorldRenderer,
+};
+
+/// Meshes and instances added to the world from a baked scene
+pub struct BakedScene {
+    pub scene: &'static PackedScene::Flat,
+    /// Indexed like the meshes of the scene
+    pub meshes: Vec<MeshHandle>,
+    /// One per scene node which has a mesh, in node order
+    pub instances: Vec<BakedSceneInstance>,
+}
+
+#[derive(Clone, Copy)]
+pub struct BakedSceneInstance {
+    pub instance: InstanceHandle,
+    /// Index into the nodes of the scene
+    pub node: usize,
+    /// Transform of the node relative to the scene root. To move the scene,
+    /// set the instance transform to `root_transform * node_transform`.
+    pub node_transform: Affine3A,
+}
+
+/// A baked scene added to the world, and the meshes created for it
+pub(crate) struct BakedSceneSource {
+    path: PathBuf,
+    meshes: Vec<MeshHandle>,
+}
 
 impl WorldRenderer {
-    pub fn add_baked_mesh(
+    /// Adds the meshes of a baked scene, and an instance for each node which has a mesh.
+    ///
+    /// When the scene file is baked again, the meshes are replaced with the new versions, as long
+    /// as the number of meshes stays the same. Images baked again are reloaded in place. `BakedScene::scene`
+    /// keeps pointing at the version which was added, and the nodes and their instances don't change.
+    pub fn add_baked_scene(
         &mut self,
         path: impl Into<std::path::PathBuf>,
         opts: AddMeshOptions,
-    ) -> anyhow::Result<MeshHandle> {
-        Ok(self.add_mesh(
-            crate::mmap::mmapped_asset::<PackedTriMesh::Flat, _>(path)?,
-            opts,
-        ))
+        transform: Affine3A,
+    ) -> anyhow::Result<BakedScene> {
+        let path = path.into();
+        let scene = crate::mmap::mmapped_asset::<PackedScene::Flat, _>(&path)?;
+
+        let meshes: Vec<MeshHandle> = scene
+            .meshes
+            .iter()
+            .map(|mesh| self.add_mesh(mesh, opts))
+            .collect::<anyhow::Result<_>>()?;
+
+        self.baked_scenes.push(BakedSceneSource {
+            path,
+            meshes: meshes.clone(),
+        });
+
+        let node_transforms = scene.node_world_transforms();
+
+        let instances = scene
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.mesh != PackedSceneNode::NONE)
+            .map(|(node_idx, node)| {
+                let node_transform = node_transforms[node_idx];
+                let instance =
+                    self.add_instance(meshes[node.mesh as usize], transform * node_transform);
+
+                // Skinned meshes start in the rest pose of the scene
+                if !scene.meshes[node.mesh as usize].joints.is_empty() {
+                    let joint_matrices = scene.skin_joint_matrices(node_idx, &node_transforms);
+                    if !joint_matrices.is_empty() {
+                        self.set_instance_joint_matrices(instance, &joint_matrices);
+                    }
+                }
+
+                BakedSceneInstance {
+                    instance,
+                    node: node_idx,
+                    node_transform,
+                }
+            })
+            .collect();
+
+        Ok(BakedScene {
+            scene,
+            meshes,
+            instances,
+        })
+    }
+}
+
+impl WorldRenderer {
+    /// Replaces the meshes of baked scenes whose files changed, and reloads changed images in place
+    pub(crate) fn reload_changed_baked_assets(&mut self) {
+        let changed_paths: HashSet<PathBuf> = crate::mmap::take_changed_mmapped_assets()
+            .into_iter()
+            .collect();
+        if changed_paths.is_empty() {
+            return;
+        }
+
+        let mut replaced_meshes: HashMap<MeshHandle, &'static PackedTriMesh::Flat> = HashMap::new();
+
+        for baked_scene in &self.baked_scenes {
+            if !changed_paths.contains(&baked_scene.path) {
+                continue;
+            }
+
+            let scene = match crate::mmap::mmapped_asset::<PackedScene::Flat, _>(&baked_scene.path)
+            {
+                Ok(scene) => scene,
+                Err(err) => {
+                    log::warn!("Could not reload {:?}: {:#}", baked_scene.path, err);
+                    continue;
+                }
+            };
+
+            if scene.meshes.len() != baked_scene.meshes.len() {
+                log::warn!(
+                    "Not reloading {:?}: it has {} meshes instead of {}",
+                    baked_scene.path,
+                    scene.meshes.len(),
+                    baked_scene.meshes.len()
+                );
+                continue;
+            }
+
+            log::info!("Reloading {:?}", baked_scene.path);
+            replaced_meshes.extend(baked_scene.meshes.iter().copied().zip(scene.meshes.iter()));
+        }
+
+        // Changed images are loaded once for all the handles they were added at. Replaced meshes
+        // keep the handles of the images they still use, so this goes first.
+        let mut reloaded_images: HashMap<AssetRef<GpuImage::Flat>, Vec<BindlessImageHandle>> =
+            HashMap::new();
+        for images in self.mesh_images.values() {
+            for (&asset, &image) in images {
+                if changed_paths.contains(&baked_image_path(asset)) {
+                    reloaded_images.entry(asset).or_default().push(image);
+                }
+            }
+        }
+
+        for (asset, handles) in reloaded_images {
+            let path = baked_image_path(asset);
+            match load_gpu_image_asset(self.device.clone(), asset) {
+                Ok(image) => {
+                    log::info!("Reloading {:?}", path);
+                    for handle in handles {
+                        self.replace_image(handle, image.clone());
+                    }
+                }
+                Err(err) => log::warn!("Could not reload {:?}: {:#}", path, err),
+            }
+        }
+
+        for (handle, mesh) in replaced_meshes {
+            if let Err(err) = self.replace_mesh(handle, mesh) {
+                log::warn!("Could not reload mesh {}: {:#}", handle.0, err);
+            }
+        }
     }
 }
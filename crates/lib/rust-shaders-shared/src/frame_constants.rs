@@ -31,7 +31,8 @@ pub struct FrameConstants {
     pub sky_ambient: Vec4,
 
     pub world_gi_scale: f32,
-    pub pad0: u32,
+    // Offset of GI ray origins per unit of distance from the eye, clearing the coarser GI LODs
+    pub gi_ray_lod_bias: f32,
     pub pad1: u32,
     pub pad2: u32,
 
@@ -1,24 +1,55 @@
 use crate::util::*;
 use macaw::*;
 
-#[cfg(target_arch = "spirv")]
-use spirv_std::float::{f16x2_to_vec2, vec2_to_f16x2};
 #[cfg(target_arch = "spirv")]
 use spirv_std::num_traits::Float;
 
+// Mirrors `gbuffer.hlsl`
+
 #[repr(C)]
 #[derive(Clone)]
 pub struct GbufferDataPacked {
     pub v: UVec4,
 }
 
-#[derive(Default)]
 pub struct GbufferData {
     pub albedo: Vec3,
     pub emissive: Vec3,
     pub normal: Vec3,
     pub roughness: f32,
     pub metalness: f32,
+
+    /// Specular reflectance at normal incidence of the non-metallic part
+    pub dielectric_f0: f32,
+
+    /// Strength and roughness of a dielectric coating layered on top
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+
+    /// Fraction of the non-metallic part which lets light through, tinted by the albedo
+    pub transmission: f32,
+
+    /// Intensity and roughness of the retro-reflective fuzz of cloth-like materials
+    pub sheen: f32,
+    pub sheen_roughness: f32,
+}
+
+impl Default for GbufferData {
+    fn default() -> Self {
+        Self {
+            albedo: Vec3::ZERO,
+            emissive: Vec3::ZERO,
+            normal: Vec3::ZERO,
+            roughness: 0.0,
+            metalness: 0.0,
+            dielectric_f0: 0.04,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            transmission: 0.0,
+            sheen: 0.0,
+            sheen_roughness: 0.0,
+        }
+    }
 }
 
 pub fn roughness_to_perceptual_roughness(r: f32) -> f32 {
@@ -29,20 +60,51 @@ pub fn perceptual_roughness_to_roughness(r: f32) -> f32 {
     r * r
 }
 
+fn pack_unorm_rounded(val: f32, bit_count: u32) -> u32 {
+    (val.clamp(0.0, 1.0) * ((1u32 << bit_count) - 1) as f32).round() as u32
+}
+
+fn unpack_unorm(pckd: u32, bit_count: u32) -> f32 {
+    let max_val = (1u32 << bit_count) - 1;
+    (pckd & max_val) as f32 / max_val as f32
+}
+
+// The top 16 bits of `v.z` hold at most one optional layer: its kind in the top two bits,
+// and two 7-bit parameters below. Clearcoat takes precedence over transmission, and that over sheen.
+const LAYER_NONE: u32 = 0;
+const LAYER_CLEARCOAT: u32 = 1;
+const LAYER_TRANSMISSION: u32 = 2;
+const LAYER_SHEEN: u32 = 3;
+
 impl GbufferData {
-    #[allow(clippy::unused_self)]
     pub fn pack(&self) -> GbufferDataPacked {
-        #[cfg(not(target_arch = "spirv"))]
-        return GbufferDataPacked { v: UVec4::ZERO };
-        #[cfg(target_arch = "spirv")]
+        let layer = if self.clearcoat > 0.0 {
+            (LAYER_CLEARCOAT << 14)
+                | pack_unorm_rounded(self.clearcoat, 7)
+                | (pack_unorm_rounded(
+                    roughness_to_perceptual_roughness(self.clearcoat_roughness),
+                    7,
+                ) << 7)
+        } else if self.transmission > 0.0 {
+            (LAYER_TRANSMISSION << 14) | pack_unorm_rounded(self.transmission, 7)
+        } else if self.sheen > 0.0 {
+            (LAYER_SHEEN << 14)
+                | pack_unorm_rounded(self.sheen, 7)
+                | (pack_unorm_rounded(roughness_to_perceptual_roughness(self.sheen_roughness), 7)
+                    << 7)
+        } else {
+            LAYER_NONE << 14
+        };
+
         GbufferDataPacked {
             v: UVec4::new(
-                pack_color_888(self.albedo),
+                // F0 is stored in the otherwise unused top byte, with more precision for the common low values.
+                pack_color_888(self.albedo)
+                    | (pack_unorm_rounded(self.dielectric_f0.sqrt(), 8) << 24),
                 pack_normal_11_10_11(self.normal).to_bits(),
-                vec2_to_f16x2(vec2(
-                    roughness_to_perceptual_roughness(self.roughness),
-                    self.metalness,
-                )),
+                pack_unorm_rounded(roughness_to_perceptual_roughness(self.roughness), 8)
+                    | (pack_unorm_rounded(self.metalness, 8) << 8)
+                    | (layer << 16),
                 float3_to_rgb9e5(self.emissive),
             ),
         }
@@ -51,18 +113,38 @@ impl GbufferData {
 
 impl GbufferDataPacked {
     pub fn unpack(&self) -> GbufferData {
-        #[cfg(not(target_arch = "spirv"))]
-        let roughness_metalness: Vec2 = Vec2::new(0.0, 0.0);
-        #[cfg(target_arch = "spirv")]
-        let roughness_metalness: Vec2 = f16x2_to_vec2(self.v.z);
+        let f0_sqrt = unpack_unorm(self.v.x >> 24, 8);
+
+        let layer = self.v.z >> 16;
+        let layer_kind = layer >> 14;
+        let layer_param0 = unpack_unorm(layer, 7);
+        let layer_param1 = unpack_unorm(layer >> 7, 7);
 
-        GbufferData {
+        let mut res = GbufferData {
             albedo: self.unpack_albedo(),
             emissive: rgb9e5_to_float3(self.v.w),
             normal: self.unpack_normal(),
-            roughness: perceptual_roughness_to_roughness(roughness_metalness.x),
-            metalness: roughness_metalness.y,
+            roughness: perceptual_roughness_to_roughness(unpack_unorm(self.v.z, 8)),
+            metalness: unpack_unorm(self.v.z >> 8, 8),
+            dielectric_f0: f0_sqrt * f0_sqrt,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            transmission: 0.0,
+            sheen: 0.0,
+            sheen_roughness: 0.0,
+        };
+
+        if layer_kind == LAYER_CLEARCOAT {
+            res.clearcoat = layer_param0;
+            res.clearcoat_roughness = perceptual_roughness_to_roughness(layer_param1);
+        } else if layer_kind == LAYER_TRANSMISSION {
+            res.transmission = layer_param0;
+        } else if layer_kind == LAYER_SHEEN {
+            res.sheen = layer_param0;
+            res.sheen_roughness = perceptual_roughness_to_roughness(layer_param1);
         }
+
+        res
     }
 
     pub fn unpack_normal(&self) -> Vec3 {
@@ -11,6 +11,12 @@ pub struct MeshDescriptor {
     pub vertex_tangent_offset: u32,
     pub mat_data_offset: u32,
     pub index_offset: u32,
+    pub vertex_uv1_offset: u32,
+    pub vertex_prev_core_offset: u32, // non-zero for skinned meshes
+    pub vertex_encoding: u32,         // `VertexEncodingFlags` of `kajiya-asset`
+    pub position_offset: [f32; 3],
+    pub position_scale: [f32; 3],
+    pub lod_index_offsets: [u32; 8], // zero past the last simplified LOD
 }
 
 #[repr(C, align(16))]
@@ -102,11 +108,12 @@ impl MaterialDescriptor {
             data[offset + 6],
             data[offset + 7],
         ));
-        let roughness_mult = f32::from_bits(data[offset + 8]);
-        let metalness_factor = f32::from_bits(data[offset + 9]);
-        let emissive = load_vec4(data, offset + 10);
-        let flags = data[offset + 15];
-        let map_transforms = load_map_transforms(data, offset + 16);
+        // The fifth map, for clearcoat, is not used here
+        let roughness_mult = f32::from_bits(data[offset + 9]);
+        let metalness_factor = f32::from_bits(data[offset + 10]);
+        let emissive = load_vec4(data, offset + 11);
+        let flags = data[offset + 16];
+        let map_transforms = load_map_transforms(data, offset + 17);
 
         Self {
             base_color_mult,
@@ -1,5 +1,5 @@
-use macaw::{UVec4, Vec2, Vec3};
-use rust_shaders_shared::util;
+use macaw::{UVec4, Vec3};
+use rust_shaders_shared::gbuffer::GbufferData;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -12,20 +12,17 @@ pub struct GBufferData {
 }
 
 impl GBufferData {
+    // Packed like `gbuffer.hlsl`, with the default dielectric F0 and no layer
     pub fn pack(self) -> UVec4 {
-        let mut res: UVec4 = Default::default();
-
-        res.x = util::pack_color_888(self.albedo);
-        res.y = util::pack_normal_11_10_11(self.normal) as u32;
-
-        let roughness_metalness = Vec2::new(
-            util::roughness_to_perceptual_roughness(self.roughness),
-            self.metalness,
-        );
-
-        res.z = spirv_std::float::vec2_to_f16x2(roughness_metalness);
-        res.w = util::float3_to_rgb9e5(self.emissive);
-
-        res
+        GbufferData {
+            albedo: self.albedo,
+            emissive: self.emissive,
+            normal: self.normal,
+            roughness: self.roughness,
+            metalness: self.metalness,
+            ..Default::default()
+        }
+        .pack()
+        .v
     }
 }